@@ -6,21 +6,26 @@ use std::path::PathBuf;
 fn secp256k1_build() {
     // Actual build
     let mut base_config = cc::Build::new();
+    target_config(&mut base_config);
     base_config
         .include("nostrdb/deps/secp256k1/")
         .include("nostrdb/deps/secp256k1/include")
         .include("nostrdb/deps/secp256k1/src")
-        .flag_if_supported("-Wno-unused-function") // some ecmult stuff is defined but not used upstream
-        .flag_if_supported("-Wno-unused-parameter") // patching out printf causes this warning
         //.define("SECP256K1_API", Some(""))
         .define("ENABLE_MODULE_ECDH", Some("1"))
         .define("ENABLE_MODULE_SCHNORRSIG", Some("1"))
         .define("ENABLE_MODULE_EXTRAKEYS", Some("1"));
     //.define("ENABLE_MODULE_ELLSWIFT", Some("1"))
 
-    // WASM headers and size/align defines.
-    if env::var("CARGO_CFG_TARGET_ARCH").unwrap() == "wasm32" {
-        base_config.include("wasm/wasm-sysroot").file("wasm/wasm.c");
+    if !is_msvc(&base_config) {
+        base_config
+            .flag_if_supported("-Wno-unused-function") // some ecmult stuff is defined but not used upstream
+            .flag_if_supported("-Wno-unused-parameter"); // patching out printf causes this warning
+    }
+
+    if wasm_config(&mut base_config) {
+        // libc shims, shared with the nostrdb build
+        base_config.file("wasm/wasm.c");
     }
 
     // secp256k1
@@ -31,7 +36,7 @@ fn secp256k1_build() {
         .file("nostrdb/deps/secp256k1/src/secp256k1.c");
 
     if env::var("PROFILE").unwrap() == "debug" {
-        base_config.flag("-O1");
+        base_config.opt_level(1);
     }
 
     if base_config.try_compile("libsecp256k1.a").is_err() {
@@ -43,9 +48,128 @@ fn secp256k1_build() {
     }
 }
 
+/// MSVC takes different flags than gcc and clang, and errors on some of
+/// theirs
+fn is_msvc(build: &Build) -> bool {
+    build.get_compiler().is_like_msvc()
+}
+
+fn target_os() -> String {
+    env::var("CARGO_CFG_TARGET_OS").unwrap_or_default()
+}
+
+/// Whether the user already told cc which C compiler to use for this target
+fn compiler_configured(target: &str) -> bool {
+    [
+        format!("CC_{target}"),
+        format!("CC_{}", target.replace('-', "_")),
+        "TARGET_CC".to_string(),
+        "CC".to_string(),
+    ]
+    .iter()
+    .any(|var| env::var_os(var).is_some())
+}
+
+/// The NDK's clang and llvm-ar for an android target, found through
+/// `ANDROID_NDK_HOME` (or `ANDROID_NDK_ROOT`). The NDK only ships
+/// compilers with the API level in their name, eg.
+/// `aarch64-linux-android21-clang`, which cc doesn't look for on its own.
+fn ndk_tools(target: &str) -> Option<(PathBuf, PathBuf)> {
+    println!("cargo:rerun-if-env-changed=ANDROID_NDK_HOME");
+    println!("cargo:rerun-if-env-changed=ANDROID_NDK_ROOT");
+    println!("cargo:rerun-if-env-changed=ANDROID_PLATFORM");
+
+    let ndk = env::var_os("ANDROID_NDK_HOME").or_else(|| env::var_os("ANDROID_NDK_ROOT"))?;
+
+    // this runs on the host, so cfg! describes the machine doing the build
+    let host = if cfg!(target_os = "macos") {
+        "darwin-x86_64"
+    } else if cfg!(windows) {
+        "windows-x86_64"
+    } else {
+        "linux-x86_64"
+    };
+    let bin = PathBuf::from(ndk)
+        .join("toolchains/llvm/prebuilt")
+        .join(host)
+        .join("bin");
+
+    // armv7 rust targets are armv7a in clang's naming
+    let triple = target.replace("armv7-", "armv7a-");
+    let api = env::var("ANDROID_PLATFORM").unwrap_or_else(|_| "21".to_string());
+    let api = api.trim_start_matches("android-");
+    let (script, exe) = if cfg!(windows) {
+        (".cmd", ".exe")
+    } else {
+        ("", "")
+    };
+
+    let clang = bin.join(format!("{triple}{api}-clang{script}"));
+    let ar = bin.join(format!("llvm-ar{exe}"));
+    clang.exists().then_some((clang, ar))
+}
+
+/// Compiler and flags that depend on the target rather than on what is
+/// being built
+fn target_config(build: &mut Build) {
+    let target = env::var("TARGET").unwrap();
+
+    if target_os() == "android" && !compiler_configured(&target) {
+        if let Some((clang, ar)) = ndk_tools(&target) {
+            build.compiler(clang).archiver(ar);
+        }
+    }
+
+    if target_os() == "ios" {
+        // bitcode is deprecated and the app store rejects it, but older cc
+        // versions still embed it for ios
+        build.flag_if_supported("-fno-embed-bitcode");
+    }
+}
+
+/// WASM headers and size/align defines, for wasm targets without a libc.
+/// Returns whether they were needed.
+///
+/// This only gets nostrdb's parsers and secp256k1 building. lmdb needs a
+/// shared mmap and nostrdb needs threads, so wasm builds can make, parse
+/// and verify notes but can't open a database.
+fn wasm_config(build: &mut Build) -> bool {
+    let needed = env::var("CARGO_CFG_TARGET_ARCH").unwrap() == "wasm32" && target_os() != "wasi";
+    if needed {
+        build.include("wasm/wasm-sysroot");
+    }
+    needed
+}
+
+/// Whether a cargo feature is enabled for this build
+fn feature(name: &str) -> bool {
+    let var = format!("CARGO_FEATURE_{}", name.to_uppercase().replace('-', "_"));
+    env::var_os(var).is_some()
+}
+
+/// Add an include dir and link search path for a system library, if the
+/// packager pointed us at a non-standard location, eg. `LMDB_INCLUDE_DIR`
+/// and `LMDB_LIB_DIR`
+fn system_lib_dirs(build: &mut Build, prefix: &str) {
+    println!("cargo:rerun-if-env-changed={prefix}_INCLUDE_DIR");
+    println!("cargo:rerun-if-env-changed={prefix}_LIB_DIR");
+
+    if let Some(dir) = env::var_os(format!("{prefix}_INCLUDE_DIR")) {
+        build.include(dir);
+    }
+    if let Some(dir) = env::var_os(format!("{prefix}_LIB_DIR")) {
+        let dir = PathBuf::from(dir);
+        println!("cargo:rustc-link-search=native={}", dir.display());
+    }
+}
+
 fn main() {
+    let system_lmdb = feature("system-lmdb");
+    let system_secp256k1 = feature("system-secp256k1");
+
     // Compile the C file
     let mut build = Build::new();
+    target_config(&mut build);
 
     build
         .files([
@@ -80,31 +204,62 @@ fn main() {
             "nostrdb/deps/flatcc/src/runtime/builder.c",
             "nostrdb/deps/flatcc/src/runtime/emitter.c",
             "nostrdb/deps/flatcc/src/runtime/refmap.c",
-            "nostrdb/deps/lmdb/mdb.c",
-            "nostrdb/deps/lmdb/midl.c",
         ])
-        .include("nostrdb/deps/lmdb")
         .include("nostrdb/deps/flatcc/include")
-        .include("nostrdb/deps/secp256k1/include")
         .include("nostrdb/ccan")
-        .include("nostrdb/src")
-        // Add other include paths
-        //.flag("-Wall")
-        .flag("-Wno-sign-compare")
-        .flag("-Wno-misleading-indentation")
-        .flag("-Wno-unused-function")
-        .flag("-Wno-unused-parameter");
+        .include("nostrdb/src");
+    // Add other include paths
+    //.flag("-Wall")
     //.flag("-Werror")
     //.flag("-g")
 
+    if is_msvc(&build) {
+        // designated initializers and friends
+        build.flag("/std:c11");
+    } else {
+        build
+            .flag("-Wno-sign-compare")
+            .flag("-Wno-misleading-indentation")
+            .flag("-Wno-unused-function")
+            .flag("-Wno-unused-parameter");
+    }
+
+    if wasm_config(&mut build) {
+        println!("cargo:warning=wasm32 builds can't open a database, Ndb::new always fails");
+    }
+
+    if system_lmdb {
+        system_lib_dirs(&mut build, "LMDB");
+    } else {
+        build
+            .file("nostrdb/deps/lmdb/mdb.c")
+            .file("nostrdb/deps/lmdb/midl.c")
+            .include("nostrdb/deps/lmdb");
+
+        // bionic doesn't have robust mutexes
+        if target_os() == "android" {
+            build.define("MDB_USE_ROBUST", Some("0"));
+        }
+    }
+
+    // a system secp256k1 has to be built with the schnorrsig and extrakeys
+    // modules
+    if system_secp256k1 {
+        system_lib_dirs(&mut build, "SECP256K1");
+    } else {
+        build.include("nostrdb/deps/secp256k1/include");
+    }
+
     if env::var("PROFILE").unwrap() == "debug" {
-        build.flag("-DDEBUG");
-        build.flag("-O1");
+        build.define("DEBUG", None);
+        build.opt_level(1);
     }
 
     build.compile("libnostrdb.a");
 
-    secp256k1_build();
+    if !system_secp256k1 {
+        secp256k1_build();
+    }
 
     // Re-run the build script if any of the C files or headers change
     for file in &["nostrdb/src/nostrdb.c", "nostrdb/src/nostrdb.h"] {
@@ -112,6 +267,21 @@ fn main() {
     }
 
     println!("cargo:rustc-link-lib=secp256k1");
+    if system_lmdb {
+        println!("cargo:rustc-link-lib=lmdb");
+    } else if target_os() == "windows" {
+        // lmdb maps the database with the native NT section apis
+        println!("cargo:rustc-link-lib=ntdll");
+        println!("cargo:rustc-link-lib=advapi32");
+    }
+
+    // android 15 devices can use 16KB pages, and refuse to load libraries
+    // aligned for 4KB ones. This only covers our own binaries and tests,
+    // apps have to pass it when linking their shared library.
+    if target_os() == "android" {
+        println!("cargo:rustc-link-arg-bins=-Wl,-z,max-page-size=16384");
+        println!("cargo:rustc-link-arg-tests=-Wl,-z,max-page-size=16384");
+    }
 
     // Print out the path to the compiled library
     let out_path = PathBuf::from(env::var("OUT_DIR").unwrap());
@@ -0,0 +1,124 @@
+use crate::{Ndb, NoteKey, Result, Transaction};
+
+/// Notes written to each database at a time
+const ARCHIVE_BATCH: usize = 1024;
+
+/// Where [Ndb::archive_into] put the notes
+///
+/// [Ndb::archive_into]: crate::Ndb::archive_into
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct ArchiveStats {
+    /// Notes copied to the new hot database
+    pub hot: usize,
+
+    /// Notes copied to the archive
+    pub archived: usize,
+}
+
+/// Copy every note into `hot` or `archive`, by age. Replaceable notes stay
+/// hot whatever their age, the latest profile or contact list can be
+/// years old.
+pub(crate) fn split(ndb: &Ndb, cutoff: u64, hot: &Ndb, archive: &Ndb) -> Result<ArchiveStats> {
+    let txn = Transaction::new(ndb)?;
+    let mut stats = ArchiveStats::default();
+    let (mut hot_batch, mut archive_batch) = (String::new(), String::new());
+
+    for (_, note) in ndb.iter_notes(&txn, NoteKey::new(1)) {
+        let archived = note.created_at() < cutoff && !note.kind_enum().is_replaceable();
+        let (batch, db, count) = if archived {
+            (&mut archive_batch, archive, &mut stats.archived)
+        } else {
+            (&mut hot_batch, hot, &mut stats.hot)
+        };
+
+        batch.push_str(r#"["EVENT","archive","#);
+        batch.push_str(&note.json()?);
+        batch.push_str("]\n");
+        *count += 1;
+        if *count % ARCHIVE_BATCH == 0 {
+            db.process_events(batch)?;
+            batch.clear();
+        }
+    }
+
+    for (batch, db) in [(hot_batch, hot), (archive_batch, archive)] {
+        if !batch.is_empty() {
+            db.process_events(&batch)?;
+        }
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util, Config, Error, Filter, NoteBuilder};
+
+    #[test]
+    fn archive_works() {
+        let db = "target/testdbs/archive_src";
+        let hot = "target/testdbs/archive_hot";
+        let cold = "target/testdbs/archive_cold";
+        for dir in [db, hot, cold] {
+            test_util::cleanup_db(dir);
+        }
+
+        let note = |kind, created_at| {
+            NoteBuilder::new()
+                .kind(kind)
+                .content("{}")
+                .created_at(created_at)
+                .sign(&[1u8; 32])
+                .build()
+                .expect("note")
+        };
+        let (old, profile, new) = (note(1, 1000), note(0, 1000), note(1, 3000));
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            for note in [&old, &profile, &new] {
+                let json = note.json().expect("json");
+                ndb.process_event(&format!("[\"EVENT\",\"archive\",{json}]"))
+                    .expect("process ok");
+            }
+        }
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let stats = ndb
+                .archive_into(2000, hot, cold, &Config::new())
+                .expect("archive");
+            assert_eq!(
+                stats,
+                ArchiveStats {
+                    hot: 2,
+                    archived: 1
+                }
+            );
+            assert_eq!(
+                ndb.archive_into(2000, hot, hot, &Config::new()),
+                Err(Error::DbInUse)
+            );
+        }
+
+        let ndb = Ndb::new(hot, &Config::new()).expect("ndb");
+        let txn = Transaction::new(&ndb).expect("txn");
+        let filter = Filter::new().kinds([1]).build();
+        assert_eq!(ndb.query(&txn, &[filter], 10).expect("query").len(), 1);
+        assert!(ndb.get_note_by_id(&txn, old.id()).is_err());
+
+        assert_eq!(
+            ndb.get_archived_note_by_id(old.id()).err(),
+            Some(Error::NotFound)
+        );
+
+        ndb.attach_archive(cold).expect("attach");
+        let found = ndb
+            .get_archived_note_by_id(old.id())
+            .expect("archived note");
+        assert_eq!(found.created_at(), 1000);
+        assert!(ndb.get_note_by_id(&txn, old.id()).is_err());
+        assert!(ndb.get_archived_note_by_id(new.id()).is_err());
+        assert!(ndb.detach_archive());
+        assert!(ndb.get_archived_note_by_id(old.id()).is_err());
+    }
+}
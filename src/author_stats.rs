@@ -0,0 +1,255 @@
+use crate::util::{decode_hex32, encode_hex, lock};
+use crate::{Kind, Ndb, Note, NoteKey, Reaction, Transaction, ZapReceipt};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// The snapshot file in the database directory
+pub(crate) const STATS_FILE: &str = "author_stats";
+
+/// Write a snapshot once this many notes were counted since the last one
+const SNAPSHOT_EVERY: u64 = 10_000;
+
+/// What a pubkey has done and received, as counted from the stored notes.
+/// See [Ndb::author_stats].
+///
+/// [Ndb::author_stats]: crate::Ndb::author_stats
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct AuthorStats {
+    /// Notes by this author that were stored
+    pub notes: u64,
+
+    /// The created_at of their newest note
+    pub last_active: Option<u64>,
+
+    /// Reactions (kind 7) to their notes
+    pub reactions_received: u64,
+
+    /// The invoice amounts of zap receipts naming them as the recipient
+    pub zap_msats_received: u64,
+}
+
+/// Per author counters, kept up to date the way custom indexes are: note
+/// keys only go up, so only notes written since the last lookup have to be
+/// counted. The counters are written to a snapshot next to the database
+/// now and then, so reopening doesn't count everything again.
+#[derive(Debug)]
+pub(crate) struct AuthorStatsRollup {
+    path: PathBuf,
+    state: Mutex<Option<RollupState>>,
+}
+
+#[derive(Debug, Default)]
+struct RollupState {
+    by_pubkey: HashMap<[u8; 32], AuthorStats>,
+
+    /// The last note key that was counted
+    through: u64,
+
+    /// `through` as of the last snapshot
+    saved_through: u64,
+}
+
+impl AuthorStatsRollup {
+    pub(crate) fn new(db_dir: &Path) -> Self {
+        AuthorStatsRollup {
+            path: db_dir.join(STATS_FILE),
+            state: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn get(&self, ndb: &Ndb, txn: &Transaction, pubkey: &[u8; 32]) -> AuthorStats {
+        let mut state = lock(&self.state);
+        let state = state.get_or_insert_with(|| self.load());
+        state.catch_up(ndb, txn);
+        if state.through >= state.saved_through + SNAPSHOT_EVERY {
+            // losing a snapshot only means counting those notes again
+            let _ = self.save(state);
+        }
+        state.by_pubkey.get(pubkey).copied().unwrap_or_default()
+    }
+
+    /// Read the last snapshot. A missing or damaged one starts the counts
+    /// over.
+    fn load(&self) -> RollupState {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(_) => return RollupState::default(),
+        };
+        let mut lines = BufReader::new(file).lines();
+        let through = match lines.next().and_then(|line| line.ok()?.parse().ok()) {
+            Some(through) => through,
+            None => return RollupState::default(),
+        };
+
+        let mut by_pubkey = HashMap::new();
+        for line in lines {
+            let parsed = line.ok().and_then(|line| parse_line(&line));
+            match parsed {
+                Some((pubkey, stats)) => by_pubkey.insert(pubkey, stats),
+                None => return RollupState::default(),
+            };
+        }
+        RollupState {
+            by_pubkey,
+            through,
+            saved_through: through,
+        }
+    }
+
+    fn save(&self, state: &mut RollupState) -> std::io::Result<()> {
+        let mut out = format!("{}\n", state.through);
+        for (pubkey, stats) in &state.by_pubkey {
+            let last_active = stats
+                .last_active
+                .map_or("-".to_string(), |at| at.to_string());
+            out.push_str(&format!(
+                "{} {} {last_active} {} {}\n",
+                encode_hex(pubkey),
+                stats.notes,
+                stats.reactions_received,
+                stats.zap_msats_received
+            ));
+        }
+
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, out)?;
+        fs::rename(&tmp, &self.path)?;
+        state.saved_through = state.through;
+        Ok(())
+    }
+}
+
+fn parse_line(line: &str) -> Option<([u8; 32], AuthorStats)> {
+    let mut fields = line.split(' ');
+    let pubkey = decode_hex32(fields.next()?)?;
+    let notes = fields.next()?.parse().ok()?;
+    let last_active = match fields.next()? {
+        "-" => None,
+        at => Some(at.parse().ok()?),
+    };
+    let stats = AuthorStats {
+        notes,
+        last_active,
+        reactions_received: fields.next()?.parse().ok()?,
+        zap_msats_received: fields.next()?.parse().ok()?,
+    };
+    Some((pubkey, stats))
+}
+
+impl RollupState {
+    fn catch_up(&mut self, ndb: &Ndb, txn: &Transaction) {
+        for (note_key, note) in ndb.iter_notes(txn, NoteKey::new(self.through + 1)) {
+            self.count(note);
+            self.through = note_key.as_u64();
+        }
+    }
+
+    fn count(&mut self, note: Note) {
+        let author = self.by_pubkey.entry(*note.pubkey()).or_default();
+        author.notes += 1;
+        author.last_active = author.last_active.max(Some(note.created_at()));
+
+        match note.kind_enum() {
+            Kind::Reaction => {
+                if let Some(target) = Reaction::new(note).and_then(|r| r.target_author()) {
+                    self.by_pubkey
+                        .entry(*target)
+                        .or_default()
+                        .reactions_received += 1;
+                }
+            }
+            Kind::ZapReceipt => {
+                let zap =
+                    ZapReceipt::new(note).and_then(|r| Some((r.recipient()?, r.amount_msats()?)));
+                if let Some((recipient, msats)) = zap {
+                    self.by_pubkey
+                        .entry(*recipient)
+                        .or_default()
+                        .zap_msats_received += msats;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util, Config, NoteBuilder};
+
+    #[test]
+    fn author_stats_work() {
+        let db = "target/testdbs/author_stats";
+        test_util::cleanup_db(db);
+
+        let (alice, bob) = ([1u8; 32], [2u8; 32]);
+        let post = NoteBuilder::new()
+            .kind(1)
+            .content("hello")
+            .created_at(1000)
+            .sign(&alice)
+            .build()
+            .expect("note");
+        let alice_pubkey = crate::util::encode_hex(post.pubkey());
+        let reaction = NoteBuilder::new()
+            .kind(7)
+            .content("+")
+            .created_at(1100)
+            .start_tag()
+            .tag_str("e")
+            .tag_str(&crate::util::encode_hex(post.id()))
+            .start_tag()
+            .tag_str("p")
+            .tag_str(&alice_pubkey)
+            .sign(&bob)
+            .build()
+            .expect("note");
+        let receipt = NoteBuilder::new()
+            .kind(9735)
+            .content("")
+            .created_at(1200)
+            .start_tag()
+            .tag_str("p")
+            .tag_str(&alice_pubkey)
+            .start_tag()
+            .tag_str("bolt11")
+            .tag_str("lnbc21u1pjqaaa")
+            .sign(&[3u8; 32])
+            .build()
+            .expect("note");
+
+        let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+        for note in [&post, &reaction, &receipt] {
+            let json = note.json().expect("json");
+            ndb.process_event(&format!("[\"EVENT\",\"stats\",{json}]"))
+                .expect("process ok");
+        }
+        ndb.close().expect("close");
+
+        let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+        let txn = Transaction::new(&ndb).expect("txn");
+        let expected = AuthorStats {
+            notes: 1,
+            last_active: Some(1000),
+            reactions_received: 1,
+            zap_msats_received: 2_100_000,
+        };
+        assert_eq!(ndb.author_stats(&txn, post.pubkey()), expected);
+        assert_eq!(ndb.author_stats(&txn, reaction.pubkey()).notes, 1);
+        assert_eq!(ndb.author_stats(&txn, &[9u8; 32]), AuthorStats::default());
+
+        // the snapshot picks up where it left off
+        let rollup = AuthorStatsRollup::new(Path::new(db));
+        rollup.get(&ndb, &txn, post.pubkey());
+        let mut state = lock(&rollup.state);
+        let state = state.as_mut().expect("state");
+        rollup.save(state).expect("save");
+        let loaded = AuthorStatsRollup::new(Path::new(db)).load();
+        assert_eq!(loaded.through, state.through);
+        assert_eq!(loaded.by_pubkey.get(post.pubkey()), Some(&expected));
+    }
+}
@@ -0,0 +1,201 @@
+//! Inspect and manage a nostrdb directory from the shell.
+//!
+//! Build with `cargo build --features cli`.
+
+use nostrdb::{Config, Filter, IntegrityOptions, Ndb, NoteKey, Transaction};
+use std::io::{self, BufWriter, Write};
+use std::{env, process};
+
+type CliResult = Result<(), Box<dyn std::error::Error>>;
+
+const USAGE: &str = "usage: ndb [-d <dbdir>] <command>
+
+commands:
+  stat                        show database statistics
+  query <filter json>         print notes matching a filter, one per line
+  search [-l <limit>] <text>  full text search over note contents
+  import <file>               import newline-delimited relay messages
+  export                      print every note, one per line
+  check [-s]                  check the indexes, and signatures with -s
+  profile <pubkey>            show the profile for a hex pubkey";
+
+fn usage() -> ! {
+    eprintln!("{USAGE}");
+    process::exit(1)
+}
+
+fn decode_hex32(s: &str) -> Option<[u8; 32]> {
+    if s.len() != 64 || !s.is_ascii() {
+        return None;
+    }
+
+    let mut out = [0u8; 32];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(out)
+}
+
+fn stat(ndb: &Ndb) -> CliResult {
+    let stat = ndb.stat()?;
+
+    println!(
+        "{:<24} {:>12} {:>14} {:>14}",
+        "db", "count", "keys", "values"
+    );
+    for (name, counts) in &stat.dbs {
+        println!(
+            "{:<24} {:>12} {:>14} {:>14}",
+            name, counts.count, counts.key_size, counts.value_size
+        );
+    }
+
+    println!();
+    println!(
+        "{:<24} {:>12} {:>14} {:>14}",
+        "kind", "count", "keys", "values"
+    );
+    let other = ("other", stat.other_kinds);
+    for (name, counts) in stat.kinds.iter().chain(std::iter::once(&other)) {
+        println!(
+            "{:<24} {:>12} {:>14} {:>14}",
+            name, counts.count, counts.key_size, counts.value_size
+        );
+    }
+
+    Ok(())
+}
+
+fn query(ndb: &Ndb, filter_json: &str) -> CliResult {
+    let filter = Filter::from_json(filter_json)?;
+    let limit = filter
+        .limit()
+        .map_or(1000, |l| l.min(i32::MAX as u64) as i32);
+    let txn = Transaction::new(ndb)?;
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    for result in ndb.query(&txn, &[filter], limit)? {
+        writeln!(out, "{}", result.note.json()?)?;
+    }
+    Ok(())
+}
+
+fn search(ndb: &Ndb, text: &str, limit: i32) -> CliResult {
+    let txn = Transaction::new(ndb)?;
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    for key in ndb.text_search(&txn, text, limit)? {
+        writeln!(out, "{}", ndb.get_note_by_key(&txn, key)?.json()?)?;
+    }
+    Ok(())
+}
+
+fn import(ndb: &Ndb, path: &str) -> CliResult {
+    let lines = ndb.import_file(path)?;
+    eprintln!("queued {lines} lines");
+    Ok(())
+}
+
+fn export(ndb: &Ndb) -> CliResult {
+    let txn = Transaction::new(ndb)?;
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    for (_, note) in ndb.iter_notes(&txn, NoteKey::new(1)) {
+        writeln!(out, "{}", note.json()?)?;
+    }
+    Ok(())
+}
+
+fn check(ndb: &Ndb, verify_signatures: bool) -> CliResult {
+    let txn = Transaction::new(ndb)?;
+    let mut options = IntegrityOptions::new();
+    options.set_verify_signatures(verify_signatures);
+
+    let report = ndb.check_integrity(&txn, &options)?;
+    for problem in &report.problems {
+        println!("note {}: {:?}", problem.note.as_u64(), problem.issue);
+    }
+    for check in &report.inconclusive {
+        println!(
+            "note {}: couldn't check {}",
+            check.note.as_u64(),
+            check.index
+        );
+    }
+    eprintln!(
+        "checked {} notes, {} problems, {} inconclusive",
+        report.notes_checked,
+        report.problems.len(),
+        report.inconclusive.len()
+    );
+
+    if !report.is_ok() {
+        return Err("database is inconsistent".into());
+    }
+    Ok(())
+}
+
+fn profile(ndb: &Ndb, pubkey: &str) -> CliResult {
+    let pubkey = decode_hex32(pubkey).ok_or("pubkey must be 64 hex characters")?;
+    let txn = Transaction::new(ndb)?;
+    let record = ndb.get_profile_by_pubkey(&txn, &pubkey)?;
+    let profile = record.record().profile().ok_or("profile record is empty")?;
+
+    let fields = [
+        ("name", profile.name()),
+        ("display_name", profile.display_name()),
+        ("about", profile.about()),
+        ("picture", profile.picture()),
+        ("banner", profile.banner()),
+        ("website", profile.website()),
+        ("nip05", profile.nip05()),
+        ("lud16", profile.lud16()),
+        ("lud06", profile.lud06()),
+    ];
+    for (name, value) in fields {
+        if let Some(value) = value {
+            println!("{name:<14} {value}");
+        }
+    }
+    Ok(())
+}
+
+fn run(args: &[String]) -> CliResult {
+    let mut args = args;
+    let mut dbdir = ".";
+    if let [flag, dir, rest @ ..] = args {
+        if flag == "-d" {
+            dbdir = dir;
+            args = rest;
+        }
+    }
+
+    let ndb = Ndb::new(dbdir, &Config::new())?;
+
+    match args {
+        [cmd] if cmd == "stat" => stat(&ndb),
+        [cmd, filter] if cmd == "query" => query(&ndb, filter),
+        [cmd, text] if cmd == "search" => search(&ndb, text, 32),
+        [cmd, flag, limit, text] if cmd == "search" && flag == "-l" => {
+            search(&ndb, text, limit.parse()?)
+        }
+        [cmd, path] if cmd == "import" => import(&ndb, path),
+        [cmd] if cmd == "export" => export(&ndb),
+        [cmd] if cmd == "check" => check(&ndb, false),
+        [cmd, flag] if cmd == "check" && flag == "-s" => check(&ndb, true),
+        [cmd, pubkey] if cmd == "profile" => profile(&ndb, pubkey),
+        _ => usage(),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.is_empty() {
+        usage();
+    }
+
+    if let Err(err) = run(&args) {
+        eprintln!("ndb: {err}");
+        process::exit(1);
+    }
+}
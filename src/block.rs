@@ -1,4 +1,8 @@
-use crate::{bindings, Note, Transaction};
+use crate::util::nip30::{self, EmojiBlockIter};
+use crate::util::nip92;
+use crate::util::url;
+use crate::{bindings, Imeta, Note, Transaction, UrlKind};
+use std::hash::{Hash, Hasher};
 
 #[derive(Debug)]
 pub struct Blocks<'a> {
@@ -19,7 +23,11 @@ pub struct BlockIter<'a> {
     txn: Option<&'a Transaction>,
 }
 
-#[derive(Debug, Eq, PartialEq)]
+/// Compares and hashes by nostrdb's number, like [Kind]
+///
+/// [Kind]: crate::Kind
+#[derive(Debug, Clone, Copy, Eq)]
+#[non_exhaustive]
 pub enum BlockType {
     Hashtag,
     Text,
@@ -27,9 +35,16 @@ pub enum BlockType {
     MentionBech32,
     Url,
     Invoice,
+
+    /// A block type this version doesn't know about, from a newer nostrdb
+    Unknown(u32),
 }
 
-#[derive(Debug, Eq, PartialEq)]
+/// Compares and hashes by nostrdb's number, like [Kind]
+///
+/// [Kind]: crate::Kind
+#[derive(Debug, Clone, Copy, Eq)]
+#[non_exhaustive]
 pub enum Bech32Type {
     Event,
     Pubkey,
@@ -38,8 +53,12 @@ pub enum Bech32Type {
     Relay,
     Addr,
     Secret,
+
+    /// A bech32 type this version doesn't know about, from a newer nostrdb
+    Unknown(u32),
 }
 
+#[non_exhaustive]
 pub enum Mention<'a> {
     Pubkey(&'a bindings::bech32_npub),
     Event(&'a bindings::bech32_nevent),
@@ -48,6 +67,9 @@ pub enum Mention<'a> {
     Relay(&'a bindings::bech32_nrelay),
     Secret(&'a bindings::bech32_nsec),
     Addr(&'a bindings::bech32_naddr),
+
+    /// A mention of a bech32 type this version can't decode
+    Unknown(u32),
 }
 
 impl bindings::ndb_str_block {
@@ -61,6 +83,14 @@ impl bindings::ndb_str_block {
     }
 }
 
+impl bindings::ndb_relays {
+    /// The relay urls in a bech32 entity's TLV relay list
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        let len = (self.num_relays.max(0) as usize).min(self.relays.len());
+        self.relays[..len].iter().map(|relay| relay.as_str())
+    }
+}
+
 impl bindings::bech32_nrelay {
     pub fn as_str(&self) -> &str {
         self.relay.as_str()
@@ -71,6 +101,10 @@ impl bindings::bech32_nprofile {
     pub fn pubkey(&self) -> &[u8; 32] {
         unsafe { &*(self.pubkey as *const [u8; 32]) }
     }
+
+    pub fn relays(&self) -> impl Iterator<Item = &str> {
+        self.relays.iter()
+    }
 }
 
 impl bindings::bech32_npub {
@@ -85,6 +119,21 @@ impl bindings::bech32_note {
     }
 }
 
+impl bindings::bech32_naddr {
+    pub fn pubkey(&self) -> &[u8; 32] {
+        unsafe { &*(self.pubkey as *const [u8; 32]) }
+    }
+
+    /// The `d` tag identifier of the addressed note
+    pub fn identifier(&self) -> &str {
+        self.identifier.as_str()
+    }
+
+    pub fn relays(&self) -> impl Iterator<Item = &str> {
+        self.relays.iter()
+    }
+}
+
 impl bindings::bech32_nevent {
     pub fn id(&self) -> &[u8; 32] {
         unsafe { &*(self.event_id as *const [u8; 32]) }
@@ -98,6 +147,10 @@ impl bindings::bech32_nevent {
             Some(&*(self.pubkey as *const [u8; 32]))
         }
     }
+
+    pub fn relays(&self) -> impl Iterator<Item = &str> {
+        self.relays.iter()
+    }
 }
 
 impl<'a> Mention<'a> {
@@ -111,6 +164,7 @@ impl<'a> Mention<'a> {
                 Bech32Type::Relay => Mention::Relay(&bech32.__bindgen_anon_1.nrelay),
                 Bech32Type::Secret => Mention::Secret(&bech32.__bindgen_anon_1.nsec),
                 Bech32Type::Addr => Mention::Addr(&bech32.__bindgen_anon_1.naddr),
+                Bech32Type::Unknown(typ) => Mention::Unknown(typ),
             }
         }
     }
@@ -126,7 +180,72 @@ impl Bech32Type {
             5 => Bech32Type::Relay,
             6 => Bech32Type::Addr,
             7 => Bech32Type::Secret,
-            _ => panic!("Invalid bech32 type"),
+            typ => Bech32Type::Unknown(typ),
+        }
+    }
+
+    /// nostrdb's number for this type
+    pub fn as_u32(&self) -> u32 {
+        match self {
+            Bech32Type::Note => 1,
+            Bech32Type::Pubkey => 2,
+            Bech32Type::Profile => 3,
+            Bech32Type::Event => 4,
+            Bech32Type::Relay => 5,
+            Bech32Type::Addr => 6,
+            Bech32Type::Secret => 7,
+            Bech32Type::Unknown(typ) => *typ,
+        }
+    }
+}
+
+impl PartialEq for Bech32Type {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_u32() == other.as_u32()
+    }
+}
+
+impl Hash for Bech32Type {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_u32().hash(state)
+    }
+}
+
+impl PartialEq for BlockType {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_u32() == other.as_u32()
+    }
+}
+
+impl Hash for BlockType {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_u32().hash(state)
+    }
+}
+
+impl BlockType {
+    pub fn from_u32(typ: bindings::ndb_block_type) -> BlockType {
+        match typ {
+            1 => BlockType::Hashtag,
+            2 => BlockType::Text,
+            3 => BlockType::MentionIndex,
+            4 => BlockType::MentionBech32,
+            5 => BlockType::Url,
+            6 => BlockType::Invoice,
+            typ => BlockType::Unknown(typ),
+        }
+    }
+
+    /// nostrdb's number for this type
+    pub fn as_u32(&self) -> u32 {
+        match self {
+            BlockType::Hashtag => 1,
+            BlockType::Text => 2,
+            BlockType::MentionIndex => 3,
+            BlockType::MentionBech32 => 4,
+            BlockType::Url => 5,
+            BlockType::Invoice => 6,
+            BlockType::Unknown(typ) => *typ,
         }
     }
 }
@@ -174,21 +293,49 @@ impl<'a> Block<'a> {
         }
     }
 
+    /// Classify the target of a [BlockType::Url] block as an image, video,
+    /// audio or webpage link. Returns None for non-url blocks.
+    pub fn url_kind(&self) -> Option<UrlKind> {
+        if self.blocktype() != BlockType::Url {
+            return None;
+        }
+        Some(url::url_kind(self.as_str()))
+    }
+
+    /// The host of a [BlockType::Url] block, eg: `github.com`
+    pub fn url_host(&self) -> Option<&'a str> {
+        if self.blocktype() != BlockType::Url {
+            return None;
+        }
+        url::url_host(self.as_str())
+    }
+
+    /// The NIP-92 `imeta` metadata for a [BlockType::Url] block, looked up
+    /// in the tags of the note the block came from
+    pub fn imeta(&self, note: &Note<'a>) -> Option<Imeta<'a>> {
+        if self.blocktype() != BlockType::Url {
+            return None;
+        }
+        nip92::note_imeta(note, self.as_str())
+    }
+
     fn c_bech32(&self) -> &'a bindings::nostr_bech32 {
         unsafe { &(*self.as_ptr()).block.mention_bech32.bech32 }
     }
 
+    /// The kind of block. Blocks of types added in newer versions of
+    /// nostrdb are [BlockType::Unknown] rather than an error, so their text
+    /// can still be shown with [Block::as_str].
     pub fn blocktype(&self) -> BlockType {
-        let typ = unsafe { bindings::ndb_get_block_type(self.as_ptr()) };
-        match typ {
-            1 => BlockType::Hashtag,
-            2 => BlockType::Text,
-            3 => BlockType::MentionIndex,
-            4 => BlockType::MentionBech32,
-            5 => BlockType::Url,
-            6 => BlockType::Invoice,
-            _ => panic!("Invalid blocktype {}", typ),
+        BlockType::from_u32(unsafe { bindings::ndb_get_block_type(self.as_ptr()) })
+    }
+
+    /// The type of a [BlockType::MentionBech32] block's entity
+    pub fn bech32_type(&self) -> Option<Bech32Type> {
+        if self.blocktype() != BlockType::MentionBech32 {
+            return None;
         }
+        Some(Bech32Type::from_ctype(self.c_bech32().type_))
     }
 }
 
@@ -216,6 +363,12 @@ impl<'a> Blocks<'a> {
         }
     }
 
+    /// Iterate over blocks with NIP-30 `:shortcode:` emoji split out of
+    /// text blocks, resolved against the note's `emoji` tags
+    pub fn iter_emojis(&self, note: &Note<'a>) -> EmojiBlockIter<'a> {
+        EmojiBlockIter::new(self.iter(note), nip30::note_emojis(note))
+    }
+
     pub fn as_ptr(&self) -> *mut bindings::ndb_blocks {
         self.ptr
     }
@@ -313,6 +466,18 @@ mod tests {
     use crate::test_util;
     use crate::{Config, Ndb};
 
+    #[test]
+    fn unknown_types_work() {
+        for typ in 1..=7 {
+            assert_eq!(BlockType::from_u32(typ).as_u32(), typ);
+            assert_eq!(Bech32Type::from_ctype(typ).as_u32(), typ);
+        }
+        assert_eq!(BlockType::from_u32(6), BlockType::Invoice);
+        assert_eq!(BlockType::from_u32(42), BlockType::Unknown(42));
+        assert_eq!(Bech32Type::from_ctype(42), Bech32Type::Unknown(42));
+        assert_eq!(BlockType::Unknown(1), BlockType::Hashtag);
+    }
+
     #[test]
     fn note_blocks_work() {
         let db = "target/testdbs/note_blocks";
@@ -368,6 +533,8 @@ mod tests {
                     4 => {
                         assert_eq!(block.blocktype(), BlockType::Url);
                         assert_eq!(block.as_str(), "https://github.com/damus-io");
+                        assert_eq!(block.url_kind(), Some(UrlKind::Webpage));
+                        assert_eq!(block.url_host(), Some("github.com"));
                     }
 
                     _ => assert!(false),
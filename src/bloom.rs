@@ -0,0 +1,137 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Bits per expected id, about a 1% false positive rate with [HASHES]
+const BITS_PER_ID: usize = 10;
+const HASHES: u64 = 7;
+
+/// How often [Ndb::process_event] could skip a duplicate, see
+/// [Config::set_duplicate_filter]
+///
+/// [Ndb::process_event]: crate::Ndb::process_event
+/// [Config::set_duplicate_filter]: crate::Config::set_duplicate_filter
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct DuplicateStats {
+    /// Events that were already stored, and were dropped before parsing
+    pub hits: u64,
+
+    /// Events the filter had never seen, passed on to nostrdb
+    pub misses: u64,
+
+    /// Events the filter thought it had seen, but that weren't stored
+    /// yet. These cost a lookup and are passed on as usual. If there are
+    /// many, the filter is too small for the database.
+    pub false_positives: u64,
+}
+
+/// A bloom filter of the note ids that were processed. It can't say for
+/// sure that an id was seen, only that it wasn't, so a maybe is checked
+/// against the database before an event is dropped.
+#[derive(Debug)]
+pub(crate) struct DuplicateFilter {
+    bits: Vec<AtomicU64>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    false_positives: AtomicU64,
+}
+
+impl DuplicateFilter {
+    pub(crate) fn new(capacity: usize) -> Self {
+        let words = (capacity.max(1) * BITS_PER_ID).div_ceil(64);
+        DuplicateFilter {
+            bits: (0..words).map(|_| AtomicU64::new(0)).collect(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            false_positives: AtomicU64::new(0),
+        }
+    }
+
+    /// Ids are already sha256 hashes, so the bit positions are taken
+    /// straight from them
+    fn positions(&self, id: &[u8; 32]) -> impl Iterator<Item = u64> {
+        let h1 = u64::from_le_bytes(id[..8].try_into().unwrap_or_default());
+        let h2 = u64::from_le_bytes(id[8..16].try_into().unwrap_or_default()) | 1;
+        let nbits = self.bits.len() as u64 * 64;
+        (0..HASHES).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % nbits)
+    }
+
+    pub(crate) fn maybe_contains(&self, id: &[u8; 32]) -> bool {
+        self.positions(id).all(|bit| {
+            let word = self.bits[(bit / 64) as usize].load(Ordering::Relaxed);
+            word & (1 << (bit % 64)) != 0
+        })
+    }
+
+    pub(crate) fn insert(&self, id: &[u8; 32]) {
+        for bit in self.positions(id) {
+            self.bits[(bit / 64) as usize].fetch_or(1 << (bit % 64), Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn false_positive(&self) {
+        self.false_positives.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn stats(&self) -> DuplicateStats {
+        DuplicateStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            false_positives: self.false_positives.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util, Config, Ndb, Transaction};
+    use std::time::Duration;
+
+    #[test]
+    fn duplicate_filter_works() {
+        let event = test_util::hello_event();
+        let filter = DuplicateFilter::new(100);
+        let ids: Vec<[u8; 32]> = (0..100u8).map(|i| [i.wrapping_mul(37); 32]).collect();
+        assert!(!filter.maybe_contains(&ids[0]));
+        for id in &ids {
+            filter.insert(id);
+        }
+        assert!(ids.iter().all(|id| filter.maybe_contains(id)));
+
+        let db = "target/testdbs/duplicate_filter";
+        test_util::cleanup_db(db);
+        let mut config = Config::new();
+        config.set_duplicate_filter(1000);
+        let ndb = Ndb::new(db, &config).expect("ndb");
+
+        ndb.process_event(&event).expect("process ok");
+        let id = crate::util::decode_hex32(
+            "702555e52e82cc24ad517ba78c21879f6e47a7c0692b9b20df147916ae8731a3",
+        )
+        .expect("id");
+        // only stored notes are skipped, wait for it to be written
+        let mut tries = 0;
+        loop {
+            let txn = Transaction::new(&ndb).expect("txn");
+            if ndb.get_notekey_by_id(&txn, &id).is_ok() {
+                break;
+            }
+            tries += 1;
+            assert!(tries < 500, "note never written");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        ndb.process_event(&event).expect("process ok");
+        ndb.process_event(&event).expect("process ok");
+        let stats = ndb.duplicate_stats().expect("stats");
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.hits, 2);
+    }
+}
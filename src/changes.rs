@@ -0,0 +1,354 @@
+use crate::util::{decode_hex32, first_tag_str};
+use crate::{
+    Deletion, Filter, Kind, Ndb, Note, NoteKey, Result, Subscription, SubscriptionSet, Transaction,
+};
+
+/// How many stored versions of a replaceable note to look through
+const VERSION_LIMIT: i32 = 16;
+
+/// Something that happened to the notes matching a [ChangeFeed]'s filters
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Change {
+    /// A new note was written
+    Added(NoteKey),
+
+    /// A stored note was deleted by its author with a NIP-09 deletion
+    /// request. nostrdb keeps the note, so anything derived from it should
+    /// be dropped by the consumer.
+    Deleted { note: NoteKey, deletion: NoteKey },
+
+    /// A replaceable note was superseded by a newer version. `new` is also
+    /// delivered as [Change::Added] if it is new to the feed.
+    Replaced { old: NoteKey, new: NoteKey },
+}
+
+/// A subscription that reports deletions and replaced notes as well as new
+/// ones, so caches and views built on top of the database can stay in sync
+/// with it. Construct one with [Ndb::subscribe_changes]. Everything is
+/// unsubscribed when this is dropped.
+///
+/// Notes that arrive already deleted, or older than a stored version of
+/// the same replaceable note, are not reported at all.
+///
+/// nostrdb never removes notes itself, so there are no pruning changes.
+#[derive(Debug)]
+pub struct ChangeFeed {
+    ndb: Ndb,
+    filters: Vec<Filter>,
+    set: SubscriptionSet,
+    notes: Subscription,
+
+    /// Written notes that were polled but not read yet
+    pending: Vec<(Subscription, NoteKey)>,
+}
+
+impl ChangeFeed {
+    pub(crate) fn new(ndb: &Ndb, filters: &[Filter]) -> Result<Self> {
+        let mut set = SubscriptionSet::new(ndb);
+        let notes = set.subscribe(filters)?;
+
+        // deletion requests rarely match the feed's own filters
+        let deletions = Filter::new().kinds([Kind::Deletion.into()]).build();
+        set.subscribe(&[deletions])?;
+
+        Ok(ChangeFeed {
+            ndb: ndb.clone(),
+            filters: filters.to_vec(),
+            set,
+            notes,
+            pending: vec![],
+        })
+    }
+
+    pub fn filters(&self) -> &[Filter] {
+        &self.filters
+    }
+
+    /// Get the changes from up to `max_notes` written notes without
+    /// blocking. Notes written after `txn` began are kept for a later poll.
+    pub fn poll(&mut self, txn: &Transaction, max_notes: u32) -> Result<Vec<Change>> {
+        let notes = self.set.poll_for_notes(max_notes);
+        self.pending.extend(notes);
+        self.changes(txn)
+    }
+
+    /// Wait until there are changes. The notes are read with a transaction
+    /// of its own, so no other transaction can be open on this thread; if
+    /// one is, this fails and the notes stay queued for the next poll.
+    pub async fn wait(&mut self, max_notes: u32) -> Result<Vec<Change>> {
+        loop {
+            if !self.pending.is_empty() {
+                let changes = {
+                    let txn = Transaction::new(&self.ndb)?;
+                    self.changes(&txn)?
+                };
+                if !changes.is_empty() {
+                    return Ok(changes);
+                }
+            }
+            let notes = self.set.wait_for_notes(max_notes).await?;
+            self.pending.extend(notes);
+        }
+    }
+
+    fn changes(&mut self, txn: &Transaction) -> Result<Vec<Change>> {
+        let mut changes = vec![];
+        let mut later = vec![];
+        let pending = std::mem::take(&mut self.pending);
+        for (sub, key) in &pending {
+            let note = if let Ok(note) = self.ndb.get_note_by_key(txn, *key) {
+                note
+            } else {
+                later.push((*sub, *key));
+                continue;
+            };
+
+            let looked_up = if *sub == self.notes {
+                self.added(txn, &note, *key, &mut changes)
+            } else {
+                self.deleted(txn, &note, *key, &mut changes)
+            };
+            if let Err(err) = looked_up {
+                // nothing was reported, so try them all again next time
+                self.pending = pending;
+                return Err(err);
+            }
+        }
+        self.pending = later;
+
+        Ok(changes)
+    }
+
+    fn matches(&self, note: &Note) -> bool {
+        self.filters.iter().any(|f| f.matches(note))
+    }
+
+    fn added(
+        &self,
+        txn: &Transaction,
+        note: &Note,
+        key: NoteKey,
+        changes: &mut Vec<Change>,
+    ) -> Result<()> {
+        if self.is_deleted(txn, note)? {
+            return Ok(());
+        }
+
+        if !note.kind_enum().is_replaceable() {
+            changes.push(Change::Added(key));
+            return Ok(());
+        }
+
+        match self.previous_version(txn, note)? {
+            Version::Stale => {}
+            Version::Replaces(old) => {
+                changes.push(Change::Added(key));
+                changes.push(Change::Replaced { old, new: key });
+            }
+            Version::First => changes.push(Change::Added(key)),
+        }
+        Ok(())
+    }
+
+    /// Whether a deletion request for this note was already written
+    fn is_deleted(&self, txn: &Transaction, note: &Note) -> Result<bool> {
+        let filter = Filter::new()
+            .kinds([Kind::Deletion.into()])
+            .authors([note.pubkey()])
+            .event(note.id())
+            .build();
+        Ok(!self.ndb.query(txn, &[filter], 1)?.is_empty())
+    }
+
+    /// Find the version of a replaceable note that `note` replaces
+    fn previous_version(&self, txn: &Transaction, note: &Note) -> Result<Version> {
+        let filter = Filter::new()
+            .kinds([note.kind() as u64])
+            .authors([note.pubkey()]);
+        let filter = if note.kind_enum().is_addressable() {
+            let identifier = first_tag_str(note, "d").unwrap_or("");
+            filter.tags([identifier.to_string()], 'd')
+        } else {
+            filter
+        }
+        .build();
+
+        let mut previous: Option<Note> = None;
+        for result in self.ndb.query(txn, &[filter], VERSION_LIMIT)? {
+            if result.note.id() == note.id() {
+                continue;
+            }
+            if newer(&result.note, note) {
+                return Ok(Version::Stale);
+            }
+            let replace = match &previous {
+                Some(p) => newer(&result.note, p),
+                None => true,
+            };
+            if replace {
+                previous = Some(result.note);
+            }
+        }
+
+        Ok(match previous.and_then(|p| p.key()) {
+            Some(old) => Version::Replaces(old),
+            None => Version::First,
+        })
+    }
+
+    fn deleted(
+        &self,
+        txn: &Transaction,
+        deletion: &Note,
+        key: NoteKey,
+        changes: &mut Vec<Change>,
+    ) -> Result<()> {
+        let targets = Deletion::new(deletion.clone()).map_or(vec![], |deletion| {
+            let ids = deletion.ids().into_iter();
+            let notes = ids.filter_map(|id| self.ndb.get_note_by_id(txn, id).ok());
+            let addressed = deletion.addresses().into_iter();
+            notes
+                .chain(addressed.filter_map(|coord| self.addressed(txn, coord)))
+                .collect()
+        });
+
+        for note in targets {
+            // only authors can delete their notes, and an address deletion
+            // doesn't cover versions written after it
+            if note.pubkey() != deletion.pubkey()
+                || note.created_at() > deletion.created_at()
+                || !self.matches(&note)
+            {
+                continue;
+            }
+
+            if let Some(note_key) = note.key() {
+                let change = Change::Deleted {
+                    note: note_key,
+                    deletion: key,
+                };
+                if !changes.contains(&change) {
+                    changes.push(change);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The latest note at a `kind:pubkey:identifier` coordinate
+    fn addressed<'a>(&self, txn: &'a Transaction, coord: &str) -> Option<Note<'a>> {
+        let mut parts = coord.splitn(3, ':');
+        let kind = parts.next()?.parse().ok()?;
+        let pubkey = decode_hex32(parts.next()?)?;
+        let identifier = parts.next().unwrap_or("");
+        self.ndb
+            .get_note_by_address(txn, kind, &pubkey, identifier)
+            .ok()
+    }
+}
+
+enum Version {
+    /// There is no other version stored
+    First,
+
+    /// A newer version is already stored
+    Stale,
+
+    Replaces(NoteKey),
+}
+
+/// Whether `a` wins over `b` as the current version of a replaceable note.
+/// Ties go to the lowest id.
+fn newer(a: &Note, b: &Note) -> bool {
+    (a.created_at(), std::cmp::Reverse(a.id())) > (b.created_at(), std::cmp::Reverse(b.id()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util, Config, NoteBuilder};
+
+    fn write(ndb: &Ndb, note: Note) {
+        let json = note.json().expect("json");
+        ndb.process_event(&format!("[\"EVENT\",\"changes\",{json}]"))
+            .expect("process ok");
+    }
+
+    fn added(change: Change) -> NoteKey {
+        match change {
+            Change::Added(key) => key,
+            other => panic!("expected an added note, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn change_feed_works() {
+        let db = "target/testdbs/change_feed";
+        test_util::cleanup_db(db);
+
+        let seckey = [7u8; 32];
+        let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+        let mut feed = ndb
+            .subscribe_changes(&[Filter::new().kinds([1, 10002]).build()])
+            .expect("feed");
+
+        let note = NoteBuilder::new()
+            .kind(1)
+            .content("hello")
+            .created_at(1)
+            .sign(&seckey)
+            .build()
+            .expect("note");
+        let id = hex::encode(note.id());
+        write(&ndb, note);
+        let changes = feed.wait(10).await.expect("changes");
+        assert_eq!(changes.len(), 1);
+        let note_key = added(changes[0]);
+
+        let relays = |created_at| {
+            NoteBuilder::new()
+                .kind(10002)
+                .content("")
+                .created_at(created_at)
+                .sign(&seckey)
+                .build()
+                .expect("relays")
+        };
+        write(&ndb, relays(10));
+        let changes = feed.wait(10).await.expect("changes");
+        assert_eq!(changes.len(), 1);
+        let old = added(changes[0]);
+
+        write(&ndb, relays(20));
+        let changes = feed.wait(10).await.expect("changes");
+        assert_eq!(changes.len(), 2);
+        let new = added(changes[0]);
+        assert_eq!(changes[1], Change::Replaced { old, new });
+
+        // an old version showing up late is already superseded
+        write(&ndb, relays(15));
+
+        let deletion = NoteBuilder::new()
+            .kind(Kind::Deletion.into())
+            .content("")
+            .created_at(30)
+            .start_tag()
+            .tag_str("e")
+            .tag_str(&id)
+            .sign(&seckey)
+            .build()
+            .expect("deletion");
+        write(&ndb, deletion);
+        let changes = feed.wait(10).await.expect("changes");
+        assert_eq!(changes.len(), 1);
+        assert!(matches!(changes[0], Change::Deleted { note, .. } if note == note_key));
+        {
+            let txn = Transaction::new(&ndb).expect("txn");
+            assert!(feed.poll(&txn, 10).expect("poll").is_empty());
+        }
+
+        drop(feed);
+        assert_eq!(ndb.subscription_count(), 0);
+    }
+}
@@ -1,9 +1,46 @@
 use crate::bindings;
+use crate::ingest::CreatedAtBounds;
+use crate::readers::{ReaderInfo, ReaderWarning};
+use crate::verify::DEFAULT_VERIFY_BATCH_SIZE;
+use std::sync::Arc;
+use std::time::Duration;
 
 pub struct Config {
     pub config: bindings::ndb_config,
+
+    /// Threads dedicated to checking ids and signatures. 0 leaves it to
+    /// nostrdb's ingester threads
+    verify_threads: usize,
+    verify_batch_size: usize,
+    verify_queue_depth: usize,
+    max_event_size: usize,
+    long_reader_warning: Option<(Duration, ReaderWarning)>,
+    record_sources: bool,
+    record_first_seen: bool,
+    duplicate_filter: usize,
+    created_at_bounds: CreatedAtBounds,
+    delegation_index: bool,
+    tag_index: bool,
+    #[cfg(feature = "language")]
+    language_index: bool,
 }
 
+/// LMDB on Windows grows the data file to the full map size as soon as it
+/// opens, instead of as the database fills up, so default to a smaller map
+/// there
+#[cfg(all(windows, target_pointer_width = "64"))]
+const MAX_DEFAULT_MAPSIZE: usize = 1024 * 1024 * 1024 * 16;
+
+/// iOS apps can't map much more than this without the extended virtual
+/// addressing entitlement
+#[cfg(all(target_os = "ios", target_pointer_width = "64"))]
+const MAX_DEFAULT_MAPSIZE: usize = 1024 * 1024 * 1024 * 4;
+
+/// nostrdb's default doesn't fit in a 32-bit address space, eg. on older
+/// android devices
+#[cfg(target_pointer_width = "32")]
+const MAX_DEFAULT_MAPSIZE: usize = 1024 * 1024 * 1024;
+
 impl Default for Config {
     fn default() -> Self {
         Config::new()
@@ -26,7 +63,30 @@ impl Config {
             bindings::ndb_default_config(&mut config);
         }
 
-        Config { config }
+        #[cfg(any(windows, target_os = "ios", target_pointer_width = "32"))]
+        {
+            // the default overflows to 0 where size_t is 32 bits
+            if config.mapsize == 0 || config.mapsize > MAX_DEFAULT_MAPSIZE {
+                config.mapsize = MAX_DEFAULT_MAPSIZE;
+            }
+        }
+
+        Config {
+            config,
+            verify_threads: 0,
+            verify_batch_size: DEFAULT_VERIFY_BATCH_SIZE,
+            verify_queue_depth: 0,
+            max_event_size: 0,
+            long_reader_warning: None,
+            record_sources: false,
+            record_first_seen: false,
+            duplicate_filter: 0,
+            created_at_bounds: CreatedAtBounds::default(),
+            delegation_index: false,
+            tag_index: false,
+            #[cfg(feature = "language")]
+            language_index: false,
+        }
     }
 
     //
@@ -47,11 +107,246 @@ impl Config {
         self
     }
 
+    /// Don't upgrade the database schema when opening it, eg. to check
+    /// [Ndb::db_version] before letting nostrdb migrate it
+    ///
+    /// [Ndb::db_version]: crate::Ndb::db_version
+    pub fn skip_migrations(&mut self, skip: bool) -> &mut Self {
+        let no_migrate = bindings::NDB_FLAG_NOMIGRATE as i32;
+
+        if skip {
+            self.config.flags |= no_migrate;
+        } else {
+            self.config.flags &= !no_migrate;
+        }
+
+        self
+    }
+
     pub fn set_ingester_threads(&mut self, threads: i32) -> &mut Self {
         self.config.ingester_threads = threads;
         self
     }
 
+    /// The maximum size of the database in bytes. On Windows the data file
+    /// takes up this much disk space from the start.
+    pub fn set_mapsize(&mut self, bytes: usize) -> &mut Self {
+        self.config.mapsize = bytes;
+        self
+    }
+
+    /// Verify ids and signatures of ingested events on a dedicated pool of
+    /// this many threads, instead of on the ingester threads. Useful when
+    /// verification is the bottleneck, eg. bulk imports on many-core
+    /// machines. 0, the default, leaves verification to nostrdb. Has no
+    /// effect if validation is skipped.
+    pub fn set_verify_threads(&mut self, threads: usize) -> &mut Self {
+        self.verify_threads = threads;
+        self
+    }
+
+    /// How many queued events a verify thread takes at a time. Bigger
+    /// batches mean less contention on the queue.
+    pub fn set_verify_batch_size(&mut self, size: usize) -> &mut Self {
+        self.verify_batch_size = size.max(1);
+        self
+    }
+
+    /// The most events waiting for the verify threads at once. Once it's
+    /// full, [Ndb::process_event] and friends block until there's room,
+    /// which caps memory use when events arrive faster than they can be
+    /// verified. 0, the default, doesn't limit it. Only applies with
+    /// [Config::set_verify_threads]: nostrdb's own ingester queue is a
+    /// fixed size in C and can't be changed from here.
+    ///
+    /// [Ndb::process_event]: crate::Ndb::process_event
+    pub fn set_verify_queue_depth(&mut self, depth: usize) -> &mut Self {
+        self.verify_queue_depth = depth;
+        self
+    }
+
+    /// The biggest event, in bytes of json, that [Ndb::process_event] and
+    /// friends take. Bigger ones are rejected with [Rejection::TooLarge]
+    /// before they're queued, so a small device never buffers them. 0, the
+    /// default, doesn't limit it.
+    ///
+    /// This doesn't let bigger events in: nostrdb's writer has its own
+    /// scratch buffer, sized in C, and drops events that don't fit it
+    /// whatever this is set to. Resizing that buffer needs an API upstream
+    /// nostrdb doesn't have yet.
+    ///
+    /// [Ndb::process_event]: crate::Ndb::process_event
+    /// [Rejection::TooLarge]: crate::Rejection::TooLarge
+    pub fn set_max_event_size(&mut self, bytes: usize) -> &mut Self {
+        self.max_event_size = bytes;
+        self
+    }
+
+    /// Call `warning` when a read transaction has been open for longer than
+    /// `threshold`. LMDB can't reuse pages that an open reader can still
+    /// see, so a forgotten transaction makes the database file keep
+    /// growing. It's called once per transaction, from a background thread
+    /// that looks at least every 10ms, so thresholds below that, including
+    /// zero, are only noticed that often. See also [Ndb::reader_info].
+    ///
+    /// [Ndb::reader_info]: crate::Ndb::reader_info
+    pub fn set_long_reader_warning<F>(&mut self, threshold: Duration, warning: F) -> &mut Self
+    where
+        F: Fn(&ReaderInfo) + Send + Sync + 'static,
+    {
+        self.long_reader_warning = Some((threshold, Arc::new(warning)));
+        self
+    }
+
+    /// Remember which relay or other source each note came from, as passed
+    /// to [Ndb::process_event_from], so it can be looked up with
+    /// [Note::sources]. Sources of events nostrdb rejects aren't kept.
+    /// Off by default.
+    ///
+    /// [Ndb::process_event_from]: crate::Ndb::process_event_from
+    /// [Note::sources]: crate::Note::sources
+    pub fn set_record_sources(&mut self, record: bool) -> &mut Self {
+        self.record_sources = record;
+        self
+    }
+
+    /// Remember when each note was first received by [Ndb::process_event]
+    /// and friends, see [Note::first_seen] and [Ndb::query_by_first_seen].
+    /// Off by default.
+    ///
+    /// [Ndb::process_event]: crate::Ndb::process_event
+    /// [Ndb::query_by_first_seen]: crate::Ndb::query_by_first_seen
+    /// [Note::first_seen]: crate::Note::first_seen
+    pub fn set_record_first_seen(&mut self, record: bool) -> &mut Self {
+        self.record_first_seen = record;
+        self
+    }
+
+    /// Index NIP-26 delegated notes by delegator, for
+    /// [Ndb::query_by_effective_author]. Off by default. Delegations are
+    /// validated at ingest either way.
+    ///
+    /// [Ndb::query_by_effective_author]: crate::Ndb::query_by_effective_author
+    pub fn set_delegation_index(&mut self, index: bool) -> &mut Self {
+        self.delegation_index = index;
+        self
+    }
+
+    /// Index the values of single letter tags for prefix scans with
+    /// [Ndb::iter_tag]. Off by default. nostrdb's own tag index can't be
+    /// scanned from here, so this is held in memory, with every tag value
+    /// in the database in it.
+    ///
+    /// [Ndb::iter_tag]: crate::Ndb::iter_tag
+    pub fn set_tag_index(&mut self, index: bool) -> &mut Self {
+        self.tag_index = index;
+        self
+    }
+
+    /// Index text notes by their detected language, for
+    /// [Ndb::query_language]. Off by default.
+    ///
+    /// [Ndb::query_language]: crate::Ndb::query_language
+    #[cfg(feature = "language")]
+    pub fn set_language_index(&mut self, index: bool) -> &mut Self {
+        self.language_index = index;
+        self
+    }
+
+    /// Keep a bloom filter of the ids of processed events, sized for about
+    /// `capacity` ids, so [Ndb::process_event] can drop events that are
+    /// already stored before parsing or verifying them. Worth it when the
+    /// same events arrive from many relays. 0, the default, turns it off.
+    /// See [Ndb::duplicate_stats].
+    ///
+    /// [Ndb::process_event]: crate::Ndb::process_event
+    /// [Ndb::duplicate_stats]: crate::Ndb::duplicate_stats
+    pub fn set_duplicate_filter(&mut self, capacity: usize) -> &mut Self {
+        self.duplicate_filter = capacity;
+        self
+    }
+
+    /// Reject events dated more than `secs` after the current time with
+    /// [Rejection::TooNew]. Off by default. Far future timestamps would
+    /// otherwise sit at the top of every newest first query, and ahead of
+    /// every `until` cursor. They can't be clamped instead, changing
+    /// created_at would invalidate the id and signature.
+    ///
+    /// [Rejection::TooNew]: crate::Rejection::TooNew
+    pub fn set_max_future_drift(&mut self, secs: u64) -> &mut Self {
+        self.created_at_bounds.max_future_drift = Some(secs);
+        self
+    }
+
+    /// Reject events dated before `ts`, in unix seconds, with
+    /// [Rejection::TooOld], eg. the bogus epoch 0 timestamps some clients
+    /// send. 0, the default, accepts everything.
+    ///
+    /// Batches passed to [Ndb::process_events] and [Ndb::import_file] can't
+    /// report per event errors, so events outside either bound are dropped
+    /// from them.
+    ///
+    /// [Rejection::TooOld]: crate::Rejection::TooOld
+    /// [Ndb::process_events]: crate::Ndb::process_events
+    /// [Ndb::import_file]: crate::Ndb::import_file
+    pub fn set_min_created_at(&mut self, ts: u64) -> &mut Self {
+        self.created_at_bounds.min = ts;
+        self
+    }
+
+    pub fn verify_threads(&self) -> usize {
+        self.verify_threads
+    }
+
+    pub fn verify_batch_size(&self) -> usize {
+        self.verify_batch_size
+    }
+
+    pub fn verify_queue_depth(&self) -> usize {
+        self.verify_queue_depth
+    }
+
+    pub fn max_event_size(&self) -> usize {
+        self.max_event_size
+    }
+
+    pub(crate) fn long_reader_warning(&self) -> Option<(Duration, ReaderWarning)> {
+        self.long_reader_warning.clone()
+    }
+
+    pub(crate) fn records_sources(&self) -> bool {
+        self.record_sources
+    }
+
+    pub(crate) fn records_first_seen(&self) -> bool {
+        self.record_first_seen
+    }
+
+    pub(crate) fn duplicate_filter(&self) -> usize {
+        self.duplicate_filter
+    }
+
+    pub(crate) fn created_at_bounds(&self) -> CreatedAtBounds {
+        self.created_at_bounds
+    }
+
+    pub(crate) fn indexes_delegations(&self) -> bool {
+        self.delegation_index
+    }
+
+    pub(crate) fn indexes_tags(&self) -> bool {
+        self.tag_index
+    }
+
+    #[cfg(feature = "language")]
+    pub(crate) fn indexes_language(&self) -> bool {
+        self.language_index
+    }
+
+    pub(crate) fn skips_validation(&self) -> bool {
+        self.config.flags & bindings::NDB_FLAG_SKIP_NOTE_VERIFY as i32 != 0
+    }
+
     // Add other setter methods as needed
 
     // Internal method to get a raw pointer to the config, used in Ndb
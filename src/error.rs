@@ -3,14 +3,26 @@ use std::fmt;
 #[derive(Debug, Eq, PartialEq)]
 pub enum Error {
     DbOpenFailed,
+    DbInUse,
     NotFound,
     DecodeError,
     QueryError,
     NoteProcessFailed,
     TransactionFailed,
     SubscriptionError,
+    SubscriptionOverflow,
     BufferOverflow,
+    IoError,
     Filter(FilterError),
+    Note(NoteError),
+    Rejected(Rejection),
+
+    /// More notes matched than the call reads when it isn't given a
+    /// limit, eg. [Ndb::MAX_NEGENTROPY_ITEMS]. Holds the cap. Returned
+    /// instead of a result that would silently be missing notes.
+    ///
+    /// [Ndb::MAX_NEGENTROPY_ITEMS]: crate::Ndb::MAX_NEGENTROPY_ITEMS
+    LimitExceeded(usize),
 }
 
 impl Error {
@@ -23,6 +35,34 @@ impl Error {
 pub enum FilterError {
     FieldAlreadyExists,
     FieldAlreadyStarted,
+
+    /// A field with no values, eg. `"kinds": []`, which nostrdb would
+    /// otherwise treat as matching nothing or everything depending on the
+    /// field. Holds the field's json name.
+    EmptyField(String),
+
+    /// `since`, `until` and `limit` take exactly one value
+    ExpectedOneValue {
+        field: &'static str,
+        count: i32,
+    },
+
+    SinceAfterUntil {
+        since: u64,
+        until: u64,
+    },
+
+    /// The limit is above [Filter::MAX_LIMIT]
+    ///
+    /// [Filter::MAX_LIMIT]: crate::Filter::MAX_LIMIT
+    LimitTooLarge(u64),
+
+    /// An id or pubkey that isn't 64 hex characters
+    InvalidHexId(String),
+
+    /// More values than fit in nostrdb's filter buffer. Holds the field's
+    /// json name.
+    TooManyElements(String),
 }
 
 impl FilterError {
@@ -35,11 +75,116 @@ impl FilterError {
     }
 }
 
+/// What is wrong with an event given to [Note::from_json]
+///
+/// [Note::from_json]: crate::Note::from_json
+#[derive(Debug, Eq, PartialEq)]
+pub enum NoteError {
+    NotAnObject,
+    MissingField(&'static str),
+
+    /// `id`, `pubkey` or `sig` isn't the right length of hex
+    InvalidHex(&'static str),
+
+    /// A field has the wrong type, eg. a `kind` in quotes
+    InvalidField(&'static str),
+
+    /// The id isn't the hash of the event
+    IdMismatch,
+    InvalidSignature,
+
+    /// nostrdb couldn't parse the event, for some other reason
+    Malformed,
+}
+
+/// Why [Ndb::process_event_with] turned an event away. Displayed with the
+/// NIP-01 prefixes, so it can be sent back in an `OK` message as is.
+///
+/// [Ndb::process_event_with]: crate::Ndb::process_event_with
+#[derive(Debug, Eq, PartialEq)]
+pub enum Rejection {
+    /// A NIP-70 protected event from someone other than the authenticated
+    /// pubkey
+    Protected,
+
+    /// The [IngestOptions::set_policy] hook said no
+    ///
+    /// [IngestOptions::set_policy]: crate::IngestOptions::set_policy
+    Policy,
+
+    /// The note mentions a word muted with [IngestOptions::set_muted_words]
+    ///
+    /// [IngestOptions::set_muted_words]: crate::IngestOptions::set_muted_words
+    Muted,
+
+    /// Created before [Config::set_min_created_at]
+    ///
+    /// [Config::set_min_created_at]: crate::Config::set_min_created_at
+    TooOld,
+
+    /// Created further in the future than [Config::set_max_future_drift]
+    ///
+    /// [Config::set_max_future_drift]: crate::Config::set_max_future_drift
+    TooNew,
+
+    /// A NIP-26 delegation tag with a bad token, or that doesn't cover the
+    /// note
+    Delegation,
+
+    /// Bigger than [Config::set_max_event_size]
+    ///
+    /// [Config::set_max_event_size]: crate::Config::set_max_event_size
+    TooLarge,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::Protected => {
+                write!(
+                    f,
+                    "auth-required: this event may only be published by its author"
+                )
+            }
+            Rejection::Policy => write!(f, "blocked: not accepted by this relay"),
+            Rejection::Muted => write!(f, "blocked: contains muted words"),
+            Rejection::TooOld => write!(f, "invalid: created_at is too far in the past"),
+            Rejection::TooNew => write!(f, "invalid: created_at is too far in the future"),
+            Rejection::Delegation => write!(f, "invalid: bad delegation"),
+            Rejection::TooLarge => write!(f, "invalid: event is too large"),
+        }
+    }
+}
+
+impl fmt::Display for NoteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NoteError::NotAnObject => write!(f, "not a json object"),
+            NoteError::MissingField(field) => write!(f, "missing {field}"),
+            NoteError::InvalidHex(field) => write!(f, "{field} is not valid hex"),
+            NoteError::InvalidField(field) => write!(f, "{field} has the wrong type"),
+            NoteError::IdMismatch => write!(f, "id doesn't match the event"),
+            NoteError::InvalidSignature => write!(f, "invalid signature"),
+            NoteError::Malformed => write!(f, "malformed event"),
+        }
+    }
+}
+
 impl fmt::Display for FilterError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             FilterError::FieldAlreadyExists => write!(f, "field already exists"),
             FilterError::FieldAlreadyStarted => write!(f, "field already started"),
+            FilterError::EmptyField(field) => write!(f, "{field} has no values"),
+            FilterError::ExpectedOneValue { field, count } => {
+                write!(f, "{field} takes one value, got {count}")
+            }
+            FilterError::SinceAfterUntil { since, until } => {
+                write!(f, "since {since} is after until {until}")
+            }
+            FilterError::LimitTooLarge(limit) => write!(f, "limit {limit} is too large"),
+            FilterError::InvalidHexId(id) => write!(f, "invalid hex id {id:?}"),
+            FilterError::TooManyElements(field) => write!(f, "{field} has too many values"),
         }
     }
 }
@@ -48,14 +193,20 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Error::DbOpenFailed => write!(f, "Open failed"),
+            Error::DbInUse => write!(f, "Database still in use"),
             Error::NotFound => write!(f, "Not found"),
             Error::QueryError => write!(f, "Query failed"),
             Error::DecodeError => write!(f, "Decode error"),
             Error::NoteProcessFailed => write!(f, "Note process failed"),
             Error::TransactionFailed => write!(f, "Transaction failed"),
             Error::SubscriptionError => write!(f, "Subscription failed"),
+            Error::SubscriptionOverflow => write!(f, "Subscription overflowed"),
             Error::BufferOverflow => write!(f, "Buffer overflow"),
+            Error::IoError => write!(f, "I/O error"),
             Error::Filter(filter_err) => write!(f, "Filter: {filter_err}"),
+            Error::Note(note_err) => write!(f, "Note: {note_err}"),
+            Error::Rejected(rejection) => write!(f, "Rejected: {rejection}"),
+            Error::LimitExceeded(max) => write!(f, "More than {max} notes matched"),
         }
     }
 }
@@ -1,3 +1,4 @@
+use crate::util::decode_hex32;
 use crate::{bindings, Error, FilterError, Note, Result};
 use std::ffi::CString;
 use std::os::raw::c_char;
@@ -105,6 +106,10 @@ impl Default for bindings::ndb_filter {
 }
 
 impl Filter {
+    /// The largest limit [FilterBuilder::try_build] accepts. Every result
+    /// up to the limit is held in memory at once.
+    pub const MAX_LIMIT: u64 = 100_000;
+
     #[allow(clippy::new_ret_no_self)]
     pub fn new() -> FilterBuilder {
         FilterBuilder {
@@ -188,6 +193,55 @@ impl Filter {
         unsafe { &*(self.as_ptr()) }.num_elements
     }
 
+    /// Check for filters that nostrdb would accept but that can't be what
+    /// was meant: fields without values, `since` after `until`, and limits
+    /// above [Filter::MAX_LIMIT]. Useful for filters that came from
+    /// [Filter::from_json].
+    pub fn validate(&self) -> Result<()> {
+        let data = self.to_ref();
+        for index in 0..data.num_elements {
+            let elements = if let Some(elements) = data.elements(index) {
+                elements
+            } else {
+                continue;
+            };
+
+            let count = elements.count();
+            let single = match elements.fieldtype() {
+                FilterFieldType::Since => Some("since"),
+                FilterFieldType::Until => Some("until"),
+                FilterFieldType::Limit => Some("limit"),
+                _ => None,
+            };
+
+            match single {
+                Some(field) if count != 1 => {
+                    return Err(Error::filter(FilterError::ExpectedOneValue {
+                        field,
+                        count,
+                    }));
+                }
+                None if count == 0 => {
+                    return Err(Error::filter(FilterError::EmptyField(field_name(elements))));
+                }
+                _ => {}
+            }
+        }
+
+        if let (Some(since), Some(until)) = (self.since(), self.until()) {
+            if since > until {
+                return Err(Error::filter(FilterError::SinceAfterUntil { since, until }));
+            }
+        }
+
+        match self.limit() {
+            Some(limit) if limit > Self::MAX_LIMIT => {
+                Err(Error::filter(FilterError::LimitTooLarge(limit)))
+            }
+            _ => Ok(()),
+        }
+    }
+
     pub fn limit_mut(self, limit: u64) -> Self {
         for field in self.mut_iter() {
             if let MutFilterField::Limit(val) = field {
@@ -283,6 +337,48 @@ impl Filter {
         // 1mb buffer
         self.json_with_bufsize(1024usize * 1024usize)
     }
+
+    /// A `["REQ",<sub_id>,<filter>]` message for asking a relay for the
+    /// notes matching this filter
+    pub fn to_req(&self, sub_id: &str) -> Result<String> {
+        Filter::req(sub_id, std::slice::from_ref(self))
+    }
+
+    /// A `["REQ",<sub_id>,<filters>...]` message for a subscription with
+    /// several filters
+    pub fn req(sub_id: &str, filters: &[Filter]) -> Result<String> {
+        let mut req = format!("[\"REQ\",{}", json_string(sub_id));
+        for filter in filters {
+            req.push(',');
+            req.push_str(&filter.json()?);
+        }
+        req.push(']');
+        Ok(req)
+    }
+
+    /// The `["CLOSE",<sub_id>]` message that ends a subscription started
+    /// with [Filter::to_req]
+    pub fn close(sub_id: &str) -> String {
+        format!("[\"CLOSE\",{}]", json_string(sub_id))
+    }
+}
+
+pub(crate) fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
 }
 
 impl Default for FilterBuilder {
@@ -545,6 +641,72 @@ impl FilterBuilder {
         self
     }
 
+    /// Add a field of ids, failing with [FilterError::TooManyElements]
+    /// once nostrdb's filter buffer is full instead of panicking like
+    /// [FilterBuilder::ids] and friends
+    fn try_id_field<'a>(
+        mut self,
+        field: &str,
+        start: fn(&mut Self) -> Result<()>,
+        ids: impl IntoIterator<Item = &'a [u8; 32]>,
+    ) -> Result<Self> {
+        start(&mut self)?;
+        for id in ids {
+            if self.add_id_element(id).is_err() {
+                // frees the buffer
+                drop(self.build());
+                return Err(Error::filter(FilterError::TooManyElements(
+                    field.to_string(),
+                )));
+            }
+        }
+        self.end_field();
+        Ok(self)
+    }
+
+    /// Like [FilterBuilder::ids], for ids in hex
+    pub fn ids_hex<'a, I>(self, ids: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let ids = decode_ids(ids)?;
+        self.try_id_field("ids", Self::start_ids_field, &ids)
+    }
+
+    /// Only match notes by pubkeys scoring at least `min` in `scores`, eg.
+    /// to restrict a timeline to the user's extended network. This is the
+    /// filter's `authors` field, so it can't be combined with
+    /// [FilterBuilder::authors]. Scores don't change with the filter, get
+    /// new ones from [Ndb::trust_scores] to pick up follow changes.
+    ///
+    /// Fails with [FilterError::TooManyElements] if the network is too big
+    /// for one filter. Raise `min`, or split the pubkeys from
+    /// [TrustScores::at_least] across several filters.
+    ///
+    /// [Ndb::trust_scores]: crate::Ndb::trust_scores
+    /// [TrustScores::at_least]: crate::TrustScores::at_least
+    #[cfg(feature = "wot")]
+    pub fn min_trust(self, scores: &crate::TrustScores, min: f64) -> Result<Self> {
+        self.try_id_field("authors", Self::start_authors_field, &scores.at_least(min))
+    }
+
+    /// Like [FilterBuilder::authors], for pubkeys in hex
+    pub fn authors_hex<'a, I>(self, authors: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let authors = decode_ids(authors)?;
+        self.try_id_field("authors", Self::start_authors_field, &authors)
+    }
+
+    /// Build the filter, failing with a [FilterError] if it doesn't pass
+    /// [Filter::validate]
+    pub fn try_build(&mut self) -> Result<Filter> {
+        let filter = self.build();
+        filter.validate()?;
+        Ok(filter)
+    }
+
     pub fn build(&mut self) -> Filter {
         unsafe {
             bindings::ndb_filter_end(self.as_mut_ptr());
@@ -560,6 +722,27 @@ impl Drop for Filter {
     }
 }
 
+fn decode_ids<'a>(ids: impl IntoIterator<Item = &'a str>) -> Result<Vec<[u8; 32]>> {
+    ids.into_iter()
+        .map(|id| {
+            decode_hex32(id).ok_or_else(|| Error::filter(FilterError::InvalidHexId(id.to_string())))
+        })
+        .collect()
+}
+
+/// The json name of a field, eg. `kinds` or `#e`
+fn field_name(elements: FilterElements) -> String {
+    match elements.fieldtype() {
+        FilterFieldType::Ids => "ids".to_string(),
+        FilterFieldType::Authors => "authors".to_string(),
+        FilterFieldType::Kinds => "kinds".to_string(),
+        FilterFieldType::Tags => format!("#{}", elements.tag()),
+        FilterFieldType::Since => "since".to_string(),
+        FilterFieldType::Until => "until".to_string(),
+        FilterFieldType::Limit => "limit".to_string(),
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct MutFilterIter<'a> {
     filter: &'a bindings::ndb_filter,
@@ -1078,6 +1261,73 @@ impl<'a> FilterElemIter<'a> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn filter_to_req_works() {
+        let filter = Filter::new().kinds([1]).limit(10).build();
+        let json = filter.json().expect("json");
+
+        assert_eq!(
+            filter.to_req("sub\"1").expect("req"),
+            format!(r#"["REQ","sub\"1",{json}]"#)
+        );
+        assert_eq!(
+            Filter::req("s", &[filter.clone(), filter]).expect("req"),
+            format!(r#"["REQ","s",{json},{json}]"#)
+        );
+        assert_eq!(Filter::close("s"), r#"["CLOSE","s"]"#);
+    }
+
+    #[test]
+    fn filter_validation_works() {
+        let id = "fb165be22c7b2518b749aabb7140c73f0887fe84475c82785700663be85ba859";
+        let filter = Filter::new()
+            .ids_hex([id])
+            .expect("hex")
+            .kinds([1])
+            .since(1)
+            .until(2)
+            .try_build()
+            .expect("valid");
+        assert_eq!(filter.limit(), None);
+
+        assert_eq!(
+            Filter::new().authors_hex(["fb16"]).err(),
+            Some(Error::filter(FilterError::InvalidHexId("fb16".to_string())))
+        );
+
+        // far more than nostrdb's filter buffer holds
+        let many: Vec<String> = (0..100_000u32).map(|i| format!("{i:064x}")).collect();
+        assert_eq!(
+            Filter::new()
+                .authors_hex(many.iter().map(String::as_str))
+                .err(),
+            Some(Error::filter(FilterError::TooManyElements(
+                "authors".to_string()
+            )))
+        );
+        assert_eq!(
+            Filter::new().kinds([]).try_build().err(),
+            Some(Error::filter(FilterError::EmptyField("kinds".to_string())))
+        );
+        assert_eq!(
+            Filter::new().tags([], 't').try_build().err(),
+            Some(Error::filter(FilterError::EmptyField("#t".to_string())))
+        );
+        assert_eq!(
+            Filter::new().since(3).until(2).try_build().err(),
+            Some(Error::filter(FilterError::SinceAfterUntil {
+                since: 3,
+                until: 2
+            }))
+        );
+        assert_eq!(
+            Filter::new().limit(Filter::MAX_LIMIT + 1).try_build().err(),
+            Some(Error::filter(FilterError::LimitTooLarge(
+                Filter::MAX_LIMIT + 1
+            )))
+        );
+    }
+
     #[test]
     fn filter_limit_iter_works() {
         let filter = Filter::new().limit(42).build();
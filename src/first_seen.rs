@@ -0,0 +1,193 @@
+use crate::sidecar::{Sidecar, SidecarIndex};
+use crate::{Ndb, Transaction};
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The log of arrival times, in the database directory
+pub(crate) const FIRST_SEEN_FILE: &str = "first_seen.log";
+
+/// When each stored note was first received, see
+/// [Config::set_record_first_seen]. The time an event arrived only counts
+/// once nostrdb has stored it, so a forged copy that got in first can't
+/// date the real note; see [Sidecar].
+///
+/// [Config::set_record_first_seen]: crate::Config::set_record_first_seen
+#[derive(Debug)]
+pub(crate) struct FirstSeen {
+    log: Sidecar<FirstSeenIndex>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct FirstSeenIndex {
+    by_id: HashMap<[u8; 32], u64>,
+
+    /// The same, in the order the notes were received
+    by_time: BTreeSet<(u64, [u8; 32])>,
+}
+
+impl SidecarIndex for FirstSeenIndex {
+    type Value = u64;
+
+    fn parse(value: &str) -> Option<u64> {
+        value.parse().ok()
+    }
+
+    fn format(value: &u64) -> String {
+        value.to_string()
+    }
+
+    fn insert(&mut self, id: [u8; 32], at: u64) -> bool {
+        if let Some(&earlier) = self.by_id.get(&id) {
+            if earlier <= at {
+                return false;
+            }
+            self.by_time.remove(&(earlier, id));
+        }
+        self.by_id.insert(id, at);
+        self.by_time.insert((at, id));
+        true
+    }
+
+    fn has(&self, id: &[u8; 32], _at: &u64) -> bool {
+        // it can only have arrived later than it already did
+        self.by_id.contains_key(id)
+    }
+
+    fn redundant(_old: &u64, _new: &u64) -> bool {
+        true
+    }
+
+    fn for_each(&self, mut f: impl FnMut(&[u8; 32], &u64)) {
+        for (at, id) in &self.by_time {
+            f(id, at);
+        }
+    }
+}
+
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+impl FirstSeen {
+    pub(crate) fn new(db_dir: &Path) -> Self {
+        FirstSeen {
+            log: Sidecar::new(db_dir.join(FIRST_SEEN_FILE)),
+        }
+    }
+
+    /// Remember that events with these ids and signatures were just handed
+    /// to nostrdb at `at`
+    pub(crate) fn record(
+        &self,
+        events: impl IntoIterator<Item = ([u8; 32], [u8; 64])>,
+        at: u64,
+    ) -> std::io::Result<()> {
+        self.log
+            .record(events.into_iter().map(|(id, sig)| (id, sig, at)))
+    }
+
+    pub(crate) fn get(&self, ndb: &Ndb, txn: &Transaction, id: &[u8; 32]) -> Option<u64> {
+        self.log
+            .with(ndb, txn, |index| index.by_id.get(id).copied())
+            .flatten()
+    }
+
+    /// Visit the notes first seen after `since`, most recently seen first,
+    /// until `visit` returns false
+    pub(crate) fn newest(
+        &self,
+        ndb: &Ndb,
+        txn: &Transaction,
+        since: Option<u64>,
+        mut visit: impl FnMut(&[u8; 32], u64) -> bool,
+    ) {
+        let from = since.map_or(0, |since| since.saturating_add(1));
+        self.log.with(ndb, txn, |index| {
+            for (at, id) in index.by_time.range((from, [0u8; 32])..).rev() {
+                if !visit(id, *at) {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{test_util, Config, Filter, Ndb, NoteBuilder, Transaction};
+
+    #[test]
+    fn first_seen_works() {
+        let db = "target/testdbs/first_seen";
+        test_util::cleanup_db(db);
+
+        let mut config = Config::new();
+        config.set_record_first_seen(true);
+        let new = NoteBuilder::new()
+            .kind(1)
+            .content("new")
+            .created_at(2000)
+            .sign(&[1u8; 32])
+            .build()
+            .expect("note");
+        // arrives later, but claims to be older
+        let late = NoteBuilder::new()
+            .kind(1)
+            .content("late")
+            .created_at(1000)
+            .sign(&[1u8; 32])
+            .build()
+            .expect("note");
+
+        let msg = |note: &crate::Note| {
+            let json = note.json().expect("json");
+            format!("[\"EVENT\",\"seen\",{json}]")
+        };
+
+        // a copy with a bad signature gets there first, but nostrdb drops
+        // it, so it doesn't say when the note was first seen
+        let forged = {
+            let real = msg(&new);
+            let sig = crate::util::encode_hex(new.sig());
+            real.replace(&sig, &crate::util::encode_hex(&[7u8; 64]))
+        };
+        {
+            let ndb = Ndb::new(db, &config).expect("ndb");
+            ndb.process_event(&forged).expect("process ok");
+        }
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+
+        let before = super::now();
+        for note in [&new, &late] {
+            let ndb = Ndb::new(db, &config).expect("ndb");
+            ndb.process_event(&msg(note)).expect("process ok");
+        }
+
+        let ndb = Ndb::new(db, &config).expect("ndb");
+        let txn = Transaction::new(&ndb).expect("txn");
+        let filter = Filter::new().kinds([1]).build();
+        let results = ndb
+            .query_by_first_seen(&txn, std::slice::from_ref(&filter), None, 10)
+            .expect("query");
+        let contents: Vec<&str> = results.iter().map(|r| r.note.content()).collect();
+        assert_eq!(contents, vec!["late", "new"]);
+
+        assert!(results
+            .iter()
+            .all(|r| r.note.first_seen(&txn).is_some_and(|seen| seen >= before)));
+        let newest = ndb
+            .query_by_first_seen(&txn, std::slice::from_ref(&filter), None, 1)
+            .expect("query");
+        assert_eq!(newest.len(), 1);
+        assert_eq!(newest[0].note.content(), "late");
+
+        let seen = results[0].note.first_seen(&txn).expect("first seen");
+        assert!(ndb
+            .query_by_first_seen(&txn, &[filter], Some(seen), 10)
+            .expect("query")
+            .is_empty());
+    }
+}
@@ -0,0 +1,280 @@
+use crate::util::lock;
+use crate::{Filter, Kind, Ndb, NdbStrVariant, Note, NoteKey, Result, Subscription, Transaction};
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+/// The most queued contact lists applied per lookup
+const APPLY_BATCH: u32 = 4096;
+
+/// How many contact list changes are remembered for [GraphState::changed_since]
+#[cfg(feature = "wot")]
+const CHANGE_LOG: usize = 4096;
+
+/// Who follows who, according to the latest stored contact list (kind 3)
+/// of each author. Built from the kind index the first time it is needed,
+/// then kept up to date from a subscription to new contact lists, so
+/// lookups never scan the stored lists. See [Ndb::follows_of].
+///
+/// [Ndb::follows_of]: crate::Ndb::follows_of
+#[derive(Debug, Default)]
+pub(crate) struct FollowGraph {
+    state: Mutex<Option<GraphState>>,
+}
+
+#[derive(Debug)]
+pub(crate) struct GraphState {
+    sub: Subscription,
+
+    /// Written contact lists that weren't visible to the transaction they
+    /// were applied with yet
+    pending: Vec<NoteKey>,
+
+    lists: HashMap<[u8; 32], ContactList>,
+    followers: HashMap<[u8; 32], HashSet<[u8; 32]>>,
+
+    /// Bumped whenever a contact list changes, so anything derived from
+    /// the graph knows when to recompute
+    #[cfg(feature = "wot")]
+    generation: u64,
+
+    /// The authors of the latest changes, by generation
+    #[cfg(feature = "wot")]
+    changes: std::collections::VecDeque<(u64, [u8; 32])>,
+}
+
+#[derive(Debug)]
+struct ContactList {
+    created_at: u64,
+    id: [u8; 32],
+    follows: HashSet<[u8; 32]>,
+}
+
+impl FollowGraph {
+    /// Run `f` on the graph as of `txn`, building or catching it up first
+    pub(crate) fn with<T>(
+        &self,
+        ndb: &Ndb,
+        txn: &Transaction,
+        f: impl FnOnce(&GraphState) -> T,
+    ) -> Result<T> {
+        let mut state = lock(&self.state);
+
+        // notes were dropped from the subscription, start over
+        if let Some(graph) = state.as_ref() {
+            if graph.sub.overflowed(ndb) {
+                let _ = ndb.unsubscribe(graph.sub);
+                *state = None;
+            }
+        }
+
+        let graph = match &mut *state {
+            Some(graph) => graph,
+            None => state.insert(GraphState::build(ndb, txn)?),
+        };
+        graph.catch_up(ndb, txn);
+        Ok(f(graph))
+    }
+}
+
+impl GraphState {
+    fn build(ndb: &Ndb, txn: &Transaction) -> Result<Self> {
+        // subscribe before reading the stored lists, so nothing written in
+        // between is missed. lists seen twice are harmless.
+        let filter = Filter::new().kinds([Kind::Contacts.into()]).build();
+        let sub = ndb.subscribe(&[filter])?;
+
+        let mut graph = GraphState {
+            sub,
+            pending: vec![],
+            lists: HashMap::new(),
+            followers: HashMap::new(),
+            #[cfg(feature = "wot")]
+            generation: 0,
+            #[cfg(feature = "wot")]
+            changes: Default::default(),
+        };
+        for (_, note) in ndb.iter_kind(txn, Kind::Contacts.into(), None, None) {
+            graph.apply(&note);
+        }
+        Ok(graph)
+    }
+
+    fn catch_up(&mut self, ndb: &Ndb, txn: &Transaction) {
+        let mut keys = std::mem::take(&mut self.pending);
+        keys.extend(ndb.poll_for_notes(self.sub, APPLY_BATCH));
+
+        for key in keys {
+            match ndb.get_note_by_key(txn, key) {
+                Ok(note) => self.apply(&note),
+                Err(_) => self.pending.push(key),
+            }
+        }
+    }
+
+    /// Use a contact list if it is newer than the one we have for its
+    /// author. Ties go to the lowest id, like other replaceable notes.
+    fn apply(&mut self, note: &Note) {
+        let author = *note.pubkey();
+        if let Some(current) = self.lists.get(&author) {
+            let current = (current.created_at, std::cmp::Reverse(&current.id));
+            if current >= (note.created_at(), std::cmp::Reverse(note.id())) {
+                return;
+            }
+        }
+
+        let follows = contact_pubkeys(note);
+        let old = self.lists.insert(
+            author,
+            ContactList {
+                created_at: note.created_at(),
+                id: *note.id(),
+                follows: follows.clone(),
+            },
+        );
+
+        if let Some(old) = old {
+            for followed in old.follows.difference(&follows) {
+                if let Some(followers) = self.followers.get_mut(followed) {
+                    followers.remove(&author);
+                }
+            }
+        }
+        for followed in follows {
+            self.followers.entry(followed).or_default().insert(author);
+        }
+
+        #[cfg(feature = "wot")]
+        {
+            self.generation += 1;
+            if self.changes.len() == CHANGE_LOG {
+                self.changes.pop_front();
+            }
+            self.changes.push_back((self.generation, author));
+        }
+    }
+
+    pub(crate) fn follows(&self, pubkey: &[u8; 32]) -> Option<&HashSet<[u8; 32]>> {
+        self.lists.get(pubkey).map(|list| &list.follows)
+    }
+
+    pub(crate) fn followers(&self, pubkey: &[u8; 32]) -> Option<&HashSet<[u8; 32]>> {
+        self.followers.get(pubkey)
+    }
+
+    #[cfg(feature = "wot")]
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// The authors whose contact lists changed after `generation`. None if
+    /// that's too long ago to tell.
+    #[cfg(feature = "wot")]
+    pub(crate) fn changed_since(&self, generation: u64) -> Option<Vec<[u8; 32]>> {
+        let oldest = self.changes.front().map_or(self.generation, |c| c.0 - 1);
+        if generation < oldest {
+            return None;
+        }
+        let changes = self.changes.iter().filter(|c| c.0 > generation);
+        Some(changes.map(|c| c.1).collect())
+    }
+}
+
+/// The pubkeys in a contact list's `p` tags
+fn contact_pubkeys(note: &Note) -> HashSet<[u8; 32]> {
+    let mut pubkeys = HashSet::new();
+    for tag in note.tags() {
+        if tag.count() < 2 || tag.get_unchecked(0).variant().str() != Some("p") {
+            continue;
+        }
+        if let NdbStrVariant::Id(pubkey) = tag.get_unchecked(1).variant() {
+            pubkeys.insert(*pubkey);
+        }
+    }
+    pubkeys
+}
+
+/// A sorted copy of a set of pubkeys, so results don't depend on hashing
+pub(crate) fn sorted<'a>(pubkeys: impl IntoIterator<Item = &'a [u8; 32]>) -> Vec<[u8; 32]> {
+    let mut pubkeys: Vec<[u8; 32]> = pubkeys.into_iter().copied().collect();
+    pubkeys.sort_unstable();
+    pubkeys
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{test_util, Config, Ndb, Note, NoteBuilder, Transaction};
+    use std::time::Duration;
+
+    fn contacts(seckey: &[u8; 32], created_at: u64, follows: &[[u8; 32]]) -> Note<'static> {
+        let mut builder = NoteBuilder::new()
+            .kind(3)
+            .content("")
+            .created_at(created_at);
+        for pubkey in follows {
+            builder = builder
+                .start_tag()
+                .tag_str("p")
+                .tag_str(&hex::encode(pubkey));
+        }
+        builder.sign(seckey).build().expect("contacts")
+    }
+
+    fn write(ndb: &Ndb, note: Note) {
+        let json = note.json().expect("json");
+        ndb.process_event(&format!("[\"EVENT\",\"follows\",{json}]"))
+            .expect("process ok");
+    }
+
+    fn pubkey(seckey: &[u8; 32]) -> [u8; 32] {
+        *contacts(seckey, 0, &[]).pubkey()
+    }
+
+    #[test]
+    fn follow_graph_works() {
+        let db = "target/testdbs/follow_graph";
+        test_util::cleanup_db(db);
+
+        let (alice, bob, carol) = ([1u8; 32], [2u8; 32], [3u8; 32]);
+        let (a, b, c) = (pubkey(&alice), pubkey(&bob), pubkey(&carol));
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            write(&ndb, contacts(&alice, 10, &[b, c]));
+            write(&ndb, contacts(&bob, 10, &[c]));
+        }
+
+        let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+        {
+            let txn = Transaction::new(&ndb).expect("txn");
+            let mut both = vec![b, c];
+            both.sort();
+            assert_eq!(ndb.follows_of(&txn, &a).expect("follows"), both);
+
+            let mut followers = vec![a, b];
+            followers.sort();
+            assert_eq!(ndb.followers_of(&txn, &c).expect("followers"), followers);
+            assert_eq!(
+                ndb.follow_intersection(&txn, &a, &b).expect("intersection"),
+                vec![c]
+            );
+            assert!(ndb.follows_of(&txn, &c).expect("follows").is_empty());
+        }
+
+        // a newer list replaces the old one, an older one is ignored. both
+        // arrive through the graph's subscription
+        write(&ndb, contacts(&bob, 5, &[a]));
+        write(&ndb, contacts(&alice, 20, &[b]));
+        let mut tries = 0;
+        loop {
+            let txn = Transaction::new(&ndb).expect("txn");
+            if ndb.follows_of(&txn, &a).expect("follows") == vec![b] {
+                assert_eq!(ndb.followers_of(&txn, &c).expect("followers"), vec![b]);
+                assert!(ndb.followers_of(&txn, &a).expect("followers").is_empty());
+                break;
+            }
+            tries += 1;
+            assert!(tries < 500, "contact list never applied");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
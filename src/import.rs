@@ -0,0 +1,298 @@
+use crate::ingest::Admission;
+use crate::verify::{self, Verifier};
+use crate::{Error, Ndb, Result};
+use std::borrow::Cow;
+use std::fs::File;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// How much of a file each import thread hands to nostrdb at a time
+pub(crate) const IMPORT_CHUNK_SIZE: usize = 32 * 1024 * 1024;
+
+/// A read-only view of a whole file. On unix this is an mmap, so pages are
+/// read in as the ingester gets to them instead of all up front.
+pub(crate) struct MappedFile {
+    #[cfg(unix)]
+    ptr: *mut libc::c_void,
+    #[cfg(unix)]
+    len: usize,
+    #[cfg(not(unix))]
+    data: Vec<u8>,
+}
+
+// the mapping is read only and never changes once created
+unsafe impl Send for MappedFile {}
+unsafe impl Sync for MappedFile {}
+
+impl MappedFile {
+    #[cfg(unix)]
+    pub(crate) fn open(path: &Path) -> Result<Self> {
+        use std::os::unix::io::AsRawFd;
+
+        let file = File::open(path).map_err(|_| Error::IoError)?;
+        let len = file.metadata().map_err(|_| Error::IoError)?.len() as usize;
+        if len == 0 {
+            return Ok(MappedFile {
+                ptr: std::ptr::null_mut(),
+                len,
+            });
+        }
+
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(Error::IoError);
+        }
+
+        // we only ever read forwards through the file. this is only a hint
+        unsafe { libc::madvise(ptr, len, libc::MADV_SEQUENTIAL) };
+
+        Ok(MappedFile { ptr, len })
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn open(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path).map_err(|_| Error::IoError)?;
+        Ok(MappedFile { data })
+    }
+}
+
+impl Deref for MappedFile {
+    type Target = [u8];
+
+    #[cfg(unix)]
+    fn deref(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+
+    #[cfg(not(unix))]
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+#[cfg(unix)]
+impl Drop for MappedFile {
+    fn drop(&mut self) {
+        if self.len != 0 {
+            unsafe { libc::munmap(self.ptr, self.len) };
+        }
+    }
+}
+
+/// Split newline-delimited data into chunks of roughly `size` bytes that
+/// each end on a line boundary
+pub(crate) fn line_chunks(data: &[u8], size: usize) -> Vec<&[u8]> {
+    let mut chunks = vec![];
+    let mut start = 0;
+
+    while start < data.len() {
+        let mut end = (start + size.max(1)).min(data.len());
+        if end < data.len() {
+            end = data[end..]
+                .iter()
+                .position(|b| *b == b'\n')
+                .map_or(data.len(), |i| end + i + 1);
+        }
+        chunks.push(&data[start..end]);
+        start = end;
+    }
+
+    chunks
+}
+
+fn count_lines(chunk: &[u8]) -> usize {
+    chunk
+        .split(|b| *b == b'\n')
+        .filter(|line| line.iter().any(|b| !b.is_ascii_whitespace()))
+        .count()
+}
+
+/// The lines of a chunk that pass admission, and how many lines were
+/// dropped for not being utf-8. A bad line doesn't let the rest of its
+/// chunk skip the checks.
+fn admit<'a>(admission: &Admission, chunk: &'a [u8]) -> (Cow<'a, [u8]>, usize) {
+    if let Ok(text) = std::str::from_utf8(chunk) {
+        let kept = match admission.retain(text) {
+            Cow::Borrowed(kept) => Cow::Borrowed(kept.as_bytes()),
+            Cow::Owned(kept) => Cow::Owned(kept.into_bytes()),
+        };
+        return (kept, 0);
+    }
+
+    let mut text = String::with_capacity(chunk.len());
+    let mut bad = 0;
+    for line in chunk.split(|b| *b == b'\n').filter(|line| !line.is_empty()) {
+        match std::str::from_utf8(line) {
+            Ok(line) => {
+                text.push_str(line);
+                text.push('\n');
+            }
+            Err(_) => bad += 1,
+        }
+    }
+    let kept = admission.retain(&text).into_owned().into_bytes();
+    (Cow::Owned(kept), bad)
+}
+
+/// Verify each line of a chunk, handing the valid ones to nostrdb. Returns
+/// how many were handed over.
+fn verify_chunk(ndb: &Ndb, chunk: &[u8], verifier: &mut Verifier) -> usize {
+    let mut queued = 0;
+
+    for line in chunk.split(|b| *b == b'\n') {
+        let line = if let Ok(line) = std::str::from_utf8(line) {
+            line.trim()
+        } else {
+            continue;
+        };
+        if line.is_empty() || !verifier.verify_message(line) {
+            continue;
+        }
+
+        if ndb.ingest_unchecked(line.as_bytes()) {
+            queued += 1;
+        }
+    }
+
+    queued
+}
+
+/// Queue every line of `data` for ingestion, feeding chunks to nostrdb from
+/// several threads. nostrdb copies each event before queueing it, so
+/// `data` only has to live until this returns.
+///
+/// Id and signature checks normally happen on nostrdb's ingester threads as
+/// the chunks arrive. With `verify`, each thread checks its own chunks and
+/// only hands over the valid events, for databases using a verify pool.
+pub(crate) fn process_chunks(
+    ndb: &Ndb,
+    data: &[u8],
+    chunk_size: usize,
+    verify: bool,
+) -> Result<usize> {
+    let chunks = line_chunks(data, chunk_size);
+    let threads = thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(chunks.len())
+        .max(1);
+
+    let admission = ndb.admission();
+    let next = AtomicUsize::new(0);
+    let lines = AtomicUsize::new(0);
+    let failed = AtomicUsize::new(0);
+
+    let work = || loop {
+        let i = next.fetch_add(1, Ordering::Relaxed);
+        let chunk = if let Some(chunk) = chunks.get(i) {
+            chunk
+        } else {
+            break;
+        };
+        let (chunk, bad) = admit(&admission, chunk);
+        let chunk = chunk.as_ref();
+        failed.fetch_add(bad, Ordering::Relaxed);
+
+        if verify {
+            match verify::with_verifier(|v| verify_chunk(ndb, chunk, v)) {
+                Some(n) => lines.fetch_add(n, Ordering::Relaxed),
+                None => failed.fetch_add(1, Ordering::Relaxed),
+            };
+            continue;
+        }
+
+        if !ndb.ingest_lines_unchecked(chunk) {
+            failed.fetch_add(1, Ordering::Relaxed);
+        } else {
+            lines.fetch_add(count_lines(chunk), Ordering::Relaxed);
+        }
+    };
+
+    // no point in a thread when there's only one to use, and some targets
+    // (wasm) can't spawn them at all
+    if threads == 1 {
+        work();
+    } else {
+        thread::scope(|s| {
+            for _ in 0..threads {
+                s.spawn(work);
+            }
+        });
+    }
+
+    if failed.load(Ordering::Relaxed) != 0 {
+        return Err(Error::NoteProcessFailed);
+    }
+
+    Ok(lines.load(Ordering::Relaxed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ingest::CreatedAtBounds;
+    use crate::{test_util, Config, Filter, Transaction};
+
+    #[test]
+    fn line_chunks_work() {
+        let data = b"aaaa\nbb\ncccccc\nd";
+        let chunks = line_chunks(data, 3);
+        assert_eq!(chunks, vec![&b"aaaa\n"[..], b"bb\ncccccc\n", b"d"]);
+        assert_eq!(chunks.concat(), data);
+        assert!(line_chunks(b"", 3).is_empty());
+        assert_eq!(count_lines(b"a\n\n  \nb\n"), 2);
+    }
+
+    #[test]
+    fn admit_works() {
+        let admission = Admission {
+            created_at: CreatedAtBounds::default(),
+            delegations: false,
+            max_size: 8,
+        };
+        let (kept, bad) = admit(&admission, b"short\nmuch too long\n");
+        assert_eq!((kept.as_ref(), bad), (&b"short\n"[..], 0));
+
+        // the rest of a chunk with a bad line is still checked
+        let (kept, bad) = admit(&admission, b"short\n\xff\xfe\nmuch too long\nok");
+        assert_eq!((kept.as_ref(), bad), (&b"short\nok\n"[..], 1));
+    }
+
+    #[test]
+    fn import_file_works() {
+        let db = "target/testdbs/import_file";
+        test_util::cleanup_db(db);
+        let _ = std::fs::create_dir_all(db);
+
+        let path = Path::new(db).join("dump.jsonl");
+        std::fs::write(&path, format!("{}\n", test_util::hello_event())).expect("write dump");
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            assert_eq!(ndb.import_file(&path).expect("import"), 1);
+        }
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let txn = Transaction::new(&ndb).expect("txn");
+            let filter = Filter::new().kinds([1]).build();
+            let res = ndb.query(&txn, &[filter], 10).expect("query");
+            assert_eq!(res.len(), 1);
+        }
+
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,244 @@
+use crate::query::sort_newest_first;
+use crate::util::lock;
+use crate::{Ndb, Note, NoteKey, QueryResult, Transaction};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::{Bound, RangeBounds};
+use std::sync::{Arc, Mutex};
+
+/// Derives the keys a note is indexed under, see [Ndb::register_index]
+///
+/// [Ndb::register_index]: crate::Ndb::register_index
+pub(crate) type KeyFn = Box<dyn Fn(&Note) -> Vec<Vec<u8>> + Send + Sync>;
+
+/// An application defined index. Nothing is saved: the keys are kept in
+/// memory, so the first query after opening the database derives them
+/// from every stored note, in time and memory proportional to the whole
+/// database. After that it is caught up with the note table whenever it is
+/// queried. Note keys are handed out in increasing order, so only notes
+/// written since the last query have to be looked at.
+pub(crate) struct CustomIndex {
+    derive: KeyFn,
+    state: Mutex<IndexState>,
+}
+
+#[derive(Default)]
+struct IndexState {
+    keys: BTreeMap<Vec<u8>, BTreeSet<NoteKey>>,
+
+    /// The last note key that was indexed
+    through: u64,
+}
+
+/// The custom indexes registered on a database
+#[derive(Default)]
+pub(crate) struct Indexes {
+    indexes: Mutex<HashMap<String, Arc<CustomIndex>>>,
+}
+
+impl std::fmt::Debug for Indexes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names: Vec<String> = lock(&self.indexes).keys().cloned().collect();
+        f.debug_struct("Indexes").field("names", &names).finish()
+    }
+}
+
+impl Indexes {
+    /// Add an index, replacing any index with the same name
+    pub(crate) fn register(&self, name: &str, derive: KeyFn) {
+        let index = CustomIndex {
+            derive,
+            state: Mutex::new(IndexState::default()),
+        };
+        lock(&self.indexes).insert(name.to_string(), Arc::new(index));
+    }
+
+    pub(crate) fn unregister(&self, name: &str) -> bool {
+        lock(&self.indexes).remove(name).is_some()
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<Arc<CustomIndex>> {
+        lock(&self.indexes).get(name).cloned()
+    }
+}
+
+impl CustomIndex {
+    /// Up to `limit` notes with an index key in `range`, newest first.
+    /// Notes under several matching keys are only returned once.
+    pub(crate) fn range<'a, 'k>(
+        &self,
+        ndb: &Ndb,
+        txn: &'a Transaction,
+        range: impl RangeBounds<&'k [u8]>,
+        limit: usize,
+    ) -> Vec<QueryResult<'a>> {
+        let bounds: (Bound<&[u8]>, Bound<&[u8]>) =
+            (range.start_bound().cloned(), range.end_bound().cloned());
+
+        self.catch_up(ndb, txn);
+        let note_keys: BTreeSet<NoteKey> = {
+            let state = lock(&self.state);
+            state
+                .keys
+                .range::<[u8], _>(bounds)
+                .flat_map(|(_, keys)| keys.iter().copied())
+                .collect()
+        };
+
+        // the index can be ahead of this transaction if a newer one caught
+        // it up, those notes aren't visible here and don't count towards
+        // the limit
+        let mut results: Vec<QueryResult<'a>> = note_keys
+            .into_iter()
+            .filter_map(|note_key| QueryResult::from_key(ndb, txn, note_key))
+            .collect();
+        sort_newest_first(&mut results);
+        results.truncate(limit);
+        results
+    }
+
+    /// The notes with an index key starting with `prefix`, eg. a geohash
+    /// cell and everything inside it
+    pub(crate) fn prefix<'a>(
+        &self,
+        ndb: &Ndb,
+        txn: &'a Transaction,
+        prefix: &[u8],
+        limit: usize,
+    ) -> Vec<QueryResult<'a>> {
+        match prefix_end(prefix) {
+            Some(end) => self.range(ndb, txn, prefix..end.as_slice(), limit),
+            None => self.range(ndb, txn, prefix.., limit),
+        }
+    }
+
+    /// Index the notes written since the last catch up. The notes are read
+    /// without holding the lock, so queries of other threads aren't held up
+    /// by a first build.
+    fn catch_up(&self, ndb: &Ndb, txn: &Transaction) {
+        let from = lock(&self.state).through + 1;
+        let derived: Vec<(NoteKey, Vec<Vec<u8>>)> = ndb
+            .iter_notes(txn, NoteKey::new(from))
+            .map(|(note_key, note)| (note_key, (self.derive)(&note)))
+            .collect();
+
+        let mut state = lock(&self.state);
+        for (note_key, keys) in derived {
+            // another thread may have caught up past these already
+            if note_key.as_u64() <= state.through {
+                continue;
+            }
+            for key in keys {
+                state.keys.entry(key).or_default().insert(note_key);
+            }
+            state.through = note_key.as_u64();
+        }
+    }
+}
+
+/// The smallest key after every key starting with `prefix`, None if there
+/// isn't one
+fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::first_tag_str;
+    use crate::{test_util, Config, NoteBuilder, OwnedTransaction};
+
+    #[test]
+    fn custom_index_works() {
+        let db = "target/testdbs/custom_index";
+        test_util::cleanup_db(db);
+
+        let seckey = [3u8; 32];
+        let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+        let checkin = |geohash: &str, created_at| {
+            NoteBuilder::new()
+                .kind(1)
+                .content("here")
+                .created_at(created_at)
+                .start_tag()
+                .tag_str("g")
+                .tag_str(geohash)
+                .sign(&seckey)
+                .build()
+                .expect("note")
+        };
+        for (geohash, created_at) in [("u4pruyd", 1), ("u4prvq", 2), ("9q8yy", 3)] {
+            let json = checkin(geohash, created_at).json().expect("json");
+            ndb.process_event(&format!("[\"EVENT\",\"index\",{json}]"))
+                .expect("process ok");
+        }
+        ndb.close().expect("close");
+
+        let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+        ndb.register_index("geohash", |note| {
+            first_tag_str(note, "g")
+                .map(|g| vec![g.as_bytes().to_vec()])
+                .unwrap_or_default()
+        });
+
+        {
+            let txn = Transaction::new(&ndb).expect("txn");
+            let results = ndb
+                .index_prefix(&txn, "geohash", b"u4pr", 10)
+                .expect("index");
+            assert_eq!(results.len(), 2);
+            assert_eq!(results[0].note.created_at(), 2);
+            let newest = ndb
+                .index_prefix(&txn, "geohash", b"u4pr", 1)
+                .expect("index");
+            assert_eq!(newest[0].note.created_at(), 2);
+
+            let results = ndb
+                .index_range(&txn, "geohash", b"9".as_slice()..b"u".as_slice(), 10)
+                .expect("index");
+            assert_eq!(results.len(), 1);
+            assert_eq!(results[0].note.created_at(), 3);
+        }
+
+        // a newer snapshot catching the index up doesn't eat into an older
+        // one's limit
+        let old = OwnedTransaction::new(&ndb).expect("owned txn");
+        let json = checkin("u4pruzz", 4).json().expect("json");
+        ndb.process_event(&format!("[\"EVENT\",\"index\",{json}]"))
+            .expect("process ok");
+        let mut tries = 0;
+        loop {
+            let txn = Transaction::new(&ndb).expect("txn");
+            let results = ndb
+                .index_prefix(&txn, "geohash", b"u4pr", 10)
+                .expect("index");
+            if results.len() == 3 {
+                break;
+            }
+            tries += 1;
+            assert!(tries < 500, "note never written");
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let seen = old
+            .with(|ndb, txn| {
+                let results = ndb.index_prefix(txn, "geohash", b"u4pr", 1).expect("index");
+                results
+                    .iter()
+                    .map(|r| r.note.created_at())
+                    .collect::<Vec<_>>()
+            })
+            .expect("with");
+        assert_eq!(seen, vec![2]);
+
+        let txn = Transaction::new(&ndb).expect("txn");
+        assert_eq!(prefix_end(b"a\xff"), Some(b"b".to_vec()));
+        assert_eq!(prefix_end(b"\xff"), None);
+        assert!(ndb.index_prefix(&txn, "language", b"en", 10).is_err());
+    }
+}
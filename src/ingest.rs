@@ -0,0 +1,274 @@
+use crate::first_seen;
+use crate::json;
+use crate::util::nip26;
+use crate::verify::event_object;
+use crate::{Error, MutedWords, Note, Rejection, Result};
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::Arc;
+
+/// Decides whether an event is accepted, given the pubkey the connection
+/// it arrived on authenticated as, if any. See [IngestOptions::set_policy].
+pub type PublishPolicy = Arc<dyn Fn(&Note, Option<&[u8; 32]>) -> bool + Send + Sync>;
+
+/// Checks applied by [Ndb::process_event_with] before an event is queued,
+/// for embedders that accept events from clients the way a relay does.
+///
+/// By default NIP-70 protected events (with a `["-"]` tag) are only
+/// accepted from a connection that authenticated (NIP-42) as their author.
+///
+/// [Ndb::process_event_with]: crate::Ndb::process_event_with
+#[derive(Clone, Default)]
+pub struct IngestOptions {
+    authed: Option<[u8; 32]>,
+    allow_protected: bool,
+    policy: Option<PublishPolicy>,
+    muted_words: MutedWords,
+}
+
+impl fmt::Debug for IngestOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IngestOptions")
+            .field("authed", &self.authed)
+            .field("allow_protected", &self.allow_protected)
+            .field("policy", &self.policy.is_some())
+            .field("muted_words", &self.muted_words)
+            .finish()
+    }
+}
+
+impl IngestOptions {
+    pub fn new() -> Self {
+        IngestOptions::default()
+    }
+
+    /// The event arrived over a connection that authenticated as `pubkey`
+    pub fn set_authed(&mut self, pubkey: &[u8; 32]) -> &mut Self {
+        self.authed = Some(*pubkey);
+        self
+    }
+
+    /// Accept protected events from anyone, eg. when they come from a relay
+    /// that already checked them
+    pub fn set_allow_protected(&mut self, allow: bool) -> &mut Self {
+        self.allow_protected = allow;
+        self
+    }
+
+    /// Reject events `policy` returns false for, eg. to only take events
+    /// from authenticated members. Runs after the NIP-70 check.
+    pub fn set_policy<F>(&mut self, policy: F) -> &mut Self
+    where
+        F: Fn(&Note, Option<&[u8; 32]>) -> bool + Send + Sync + 'static,
+    {
+        self.policy = Some(Arc::new(policy));
+        self
+    }
+
+    /// Don't store notes that mention any of these words or phrases
+    pub fn set_muted_words(&mut self, muted_words: MutedWords) -> &mut Self {
+        self.muted_words = muted_words;
+        self
+    }
+
+    pub fn authed(&self) -> Option<&[u8; 32]> {
+        self.authed.as_ref()
+    }
+
+    pub fn allows_protected(&self) -> bool {
+        self.allow_protected
+    }
+
+    /// Whether the event in a relay message may be ingested.
+    /// Ids and signatures are checked later, as usual.
+    pub(crate) fn check(&self, msg: &str) -> Result<()> {
+        let event = event_object(msg).ok_or(Error::DecodeError)?;
+        let note = Note::from_json_unverified(event)?;
+
+        if note.is_protected() && !self.allow_protected && self.authed() != Some(note.pubkey()) {
+            return Err(Error::Rejected(Rejection::Protected));
+        }
+        if self.muted_words.mutes(&note) {
+            return Err(Error::Rejected(Rejection::Muted));
+        }
+        if let Some(policy) = &self.policy {
+            if !policy(&note, self.authed()) {
+                return Err(Error::Rejected(Rejection::Policy));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What [Ndb::process_event] checks before queueing an event, whatever
+/// [IngestOptions] it came with
+///
+/// [Ndb::process_event]: crate::Ndb::process_event
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Admission {
+    pub(crate) created_at: CreatedAtBounds,
+
+    /// Check NIP-26 delegation tags, off when validation is skipped
+    pub(crate) delegations: bool,
+
+    /// The longest message taken, 0 for no limit. See
+    /// [Config::set_max_event_size]
+    ///
+    /// [Config::set_max_event_size]: crate::Config::set_max_event_size
+    pub(crate) max_size: usize,
+}
+
+impl Admission {
+    pub(crate) fn check(&self, msg: &str, now: u64) -> std::result::Result<(), Rejection> {
+        if self.max_size != 0 && msg.len() > self.max_size {
+            return Err(Rejection::TooLarge);
+        }
+        self.created_at.check(msg, now)?;
+        // only parse the events that could have a delegation tag
+        if self.delegations && msg.contains("\"delegation\"") {
+            let delegated =
+                event_object(msg).and_then(|event| Note::from_json_unverified(event).ok());
+            if delegated.is_some_and(|note| !nip26::check(&note)) {
+                return Err(Rejection::Delegation);
+            }
+        }
+        Ok(())
+    }
+
+    /// The lines of `ldjson` that pass. Borrowed when none are cut.
+    pub(crate) fn retain<'a>(&self, ldjson: &'a str) -> Cow<'a, str> {
+        if self.created_at.is_unbounded() && !self.delegations && self.max_size == 0 {
+            return Cow::Borrowed(ldjson);
+        }
+        let now = first_seen::now();
+        if ldjson.lines().all(|line| self.check(line, now).is_ok()) {
+            return Cow::Borrowed(ldjson);
+        }
+
+        let mut kept = String::with_capacity(ldjson.len());
+        for line in ldjson.lines().filter(|line| self.check(line, now).is_ok()) {
+            kept.push_str(line);
+            kept.push('\n');
+        }
+        Cow::Owned(kept)
+    }
+}
+
+/// The created_at range [Ndb::process_event] accepts, see
+/// [Config::set_max_future_drift] and [Config::set_min_created_at]
+///
+/// [Ndb::process_event]: crate::Ndb::process_event
+/// [Config::set_max_future_drift]: crate::Config::set_max_future_drift
+/// [Config::set_min_created_at]: crate::Config::set_min_created_at
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub(crate) struct CreatedAtBounds {
+    pub(crate) min: u64,
+    pub(crate) max_future_drift: Option<u64>,
+}
+
+impl CreatedAtBounds {
+    pub(crate) fn is_unbounded(&self) -> bool {
+        self.min == 0 && self.max_future_drift.is_none()
+    }
+
+    /// Whether the event in a relay message was created in range. Messages
+    /// without a readable created_at are left for nostrdb to reject.
+    pub(crate) fn check(&self, msg: &str, now: u64) -> std::result::Result<(), Rejection> {
+        if self.is_unbounded() {
+            return Ok(());
+        }
+        let created_at = match json::created_at(msg) {
+            Some(created_at) => created_at,
+            None => return Ok(()),
+        };
+        if created_at < self.min {
+            return Err(Rejection::TooOld);
+        }
+        match self.max_future_drift {
+            Some(drift) if created_at > now.saturating_add(drift) => Err(Rejection::TooNew),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util, Config, Ndb, NoteBuilder};
+
+    #[test]
+    fn ingest_options_work() {
+        let db = "target/testdbs/ingest_options";
+        test_util::cleanup_db(db);
+        let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+
+        let seckey = [1u8; 32];
+        let note = NoteBuilder::new()
+            .kind(1)
+            .content("members only")
+            .start_tag()
+            .tag_str("-")
+            .sign(&seckey)
+            .build()
+            .expect("note");
+        assert!(note.is_protected());
+        let msg = format!("[\"EVENT\",\"ingest\",{}]", note.json().expect("json"));
+
+        let mut options = IngestOptions::new();
+        assert_eq!(
+            ndb.process_event_with(&msg, &options),
+            Err(Error::Rejected(Rejection::Protected))
+        );
+        options.set_authed(&[2u8; 32]);
+        assert_eq!(
+            ndb.process_event_with(&msg, &options),
+            Err(Error::Rejected(Rejection::Protected))
+        );
+        options.set_authed(note.pubkey());
+        assert_eq!(ndb.process_event_with(&msg, &options), Ok(()));
+
+        options.set_policy(|note, _| note.kind() != 1);
+        assert_eq!(
+            ndb.process_event_with(&msg, &options),
+            Err(Error::Rejected(Rejection::Policy))
+        );
+    }
+
+    #[test]
+    fn created_at_bounds_work() {
+        let db = "target/testdbs/created_at_bounds";
+        test_util::cleanup_db(db);
+        let mut config = Config::new();
+        config
+            .set_max_future_drift(15 * 60)
+            .set_min_created_at(1_000_000_000);
+        let ndb = Ndb::new(db, &config).expect("ndb");
+
+        let now = first_seen::now();
+        let msg = |created_at: u64| {
+            let note = NoteBuilder::new()
+                .kind(1)
+                .content("when")
+                .created_at(created_at)
+                .sign(&[1u8; 32])
+                .build()
+                .expect("note");
+            format!("[\"EVENT\",\"when\",{}]", note.json().expect("json"))
+        };
+
+        assert_eq!(
+            ndb.process_event(&msg(0)),
+            Err(Error::Rejected(Rejection::TooOld))
+        );
+        // 2100-01-01
+        assert_eq!(
+            ndb.process_event(&msg(4_102_444_800)),
+            Err(Error::Rejected(Rejection::TooNew))
+        );
+        assert_eq!(ndb.process_event(&msg(now + 60)), Ok(()));
+
+        let batch = [msg(now), msg(1), msg(now + 86400)].join("\n");
+        let kept = ndb.admission().retain(&batch);
+        assert_eq!(kept.lines().collect::<Vec<_>>(), vec![msg(now)]);
+    }
+}
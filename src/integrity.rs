@@ -0,0 +1,355 @@
+use crate::verify;
+use crate::{Filter, FilterBuilder, Ndb, NdbStrVariant, Note, NoteKey, Result, Transaction};
+use std::fmt;
+
+/// The secondary indexes nostrdb keeps for notes
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum NoteIndex {
+    /// Note id to note key
+    Id,
+    /// Kind and created_at
+    Kind,
+    /// Lookups by author. nostrdb has no table of its own for these, so
+    /// this checks that an `authors` query finds the note.
+    Author,
+    /// Single letter tags and created_at
+    Tags,
+    /// Words in the content of text notes
+    Fulltext,
+    /// Parsed content blocks of text notes
+    Blocks,
+}
+
+impl NoteIndex {
+    pub const ALL: [NoteIndex; 6] = [
+        NoteIndex::Id,
+        NoteIndex::Kind,
+        NoteIndex::Author,
+        NoteIndex::Tags,
+        NoteIndex::Fulltext,
+        NoteIndex::Blocks,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            NoteIndex::Id => "id",
+            NoteIndex::Kind => "kind",
+            NoteIndex::Author => "author",
+            NoteIndex::Tags => "tags",
+            NoteIndex::Fulltext => "fulltext",
+            NoteIndex::Blocks => "blocks",
+        }
+    }
+}
+
+impl fmt::Display for NoteIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// What [Ndb::check_integrity] looks at
+#[derive(Debug, Clone)]
+pub struct IntegrityOptions {
+    indexes: Vec<NoteIndex>,
+    verify_signatures: bool,
+}
+
+impl Default for IntegrityOptions {
+    fn default() -> Self {
+        IntegrityOptions::new()
+    }
+}
+
+impl IntegrityOptions {
+    /// Check every index, but don't re-verify signatures
+    pub fn new() -> Self {
+        IntegrityOptions {
+            indexes: NoteIndex::ALL.to_vec(),
+            verify_signatures: false,
+        }
+    }
+
+    pub fn set_indexes(&mut self, indexes: &[NoteIndex]) -> &mut Self {
+        self.indexes = indexes.to_vec();
+        self
+    }
+
+    /// Also recompute every note's id and check its signature. This is
+    /// much slower than the index checks.
+    pub fn set_verify_signatures(&mut self, verify: bool) -> &mut Self {
+        self.verify_signatures = verify;
+        self
+    }
+
+    pub fn indexes(&self) -> &[NoteIndex] {
+        &self.indexes
+    }
+
+    pub fn verify_signatures(&self) -> bool {
+        self.verify_signatures
+    }
+
+    fn checks(&self, index: NoteIndex) -> bool {
+        self.indexes.contains(&index)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum IntegrityIssue {
+    /// The index has no entry for the note
+    MissingFromIndex(NoteIndex),
+
+    /// The id index maps the note's id to a different note
+    IdMismatch(NoteKey),
+
+    /// The stored id or signature doesn't match the note
+    InvalidSignature,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct IntegrityProblem {
+    pub note: NoteKey,
+    pub issue: IntegrityIssue,
+}
+
+/// A check of one note that couldn't tell whether the index has it,
+/// because the lookup filled up with other notes first
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct InconclusiveCheck {
+    pub note: NoteKey,
+    pub index: NoteIndex,
+}
+
+/// The result of [Ndb::check_integrity]
+#[derive(Debug, Clone, Default)]
+pub struct IntegrityReport {
+    /// How many notes were in the primary store
+    pub notes_checked: usize,
+
+    /// Every inconsistency found, in note key order
+    pub problems: Vec<IntegrityProblem>,
+
+    /// Checks that couldn't be made, in note key order. These notes may
+    /// still be missing from those indexes.
+    pub inconclusive: Vec<InconclusiveCheck>,
+}
+
+impl IntegrityReport {
+    /// Whether no problems were found. Check [IntegrityReport::is_complete]
+    /// too before trusting the indexes.
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    /// Whether every check could be made
+    pub fn is_complete(&self) -> bool {
+        self.inconclusive.is_empty()
+    }
+
+    /// Whether any problems were found with `index`
+    pub fn index_damaged(&self, index: NoteIndex) -> bool {
+        self.problems
+            .iter()
+            .any(|p| p.issue == IntegrityIssue::MissingFromIndex(index))
+    }
+}
+
+/// Index lookups return at most this many notes. A lookup that fills up
+/// without finding the note can't tell us anything, so it is reported as
+/// inconclusive.
+const LOOKUP_LIMIT: i32 = 256;
+
+/// Whether an index lookup for the note found it. None if the results were
+/// truncated before we could tell.
+fn lookup_finds(
+    ndb: &Ndb,
+    txn: &Transaction,
+    builder: FilterBuilder,
+    key: NoteKey,
+    created_at: u64,
+) -> Result<Option<bool>> {
+    let filter = builder.since(created_at).until(created_at).build();
+
+    let results = ndb.query(txn, &[filter], LOOKUP_LIMIT)?;
+    if results.iter().any(|r| r.note_key == key) {
+        return Ok(Some(true));
+    }
+    Ok((results.len() < LOOKUP_LIMIT as usize).then_some(false))
+}
+
+/// Single letter tags with a value, which is what the tag index covers
+fn indexed_tags<'a>(note: &Note<'a>) -> Vec<(char, NdbStrVariant<'a>)> {
+    let mut tags = vec![];
+    for tag in note.tags() {
+        if tag.count() < 2 {
+            continue;
+        }
+
+        let name = if let Some(name) = tag.get_unchecked(0).variant().str() {
+            name
+        } else {
+            continue;
+        };
+        let mut chars = name.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            tags.push((c, tag.get_unchecked(1).variant()));
+        }
+    }
+    tags
+}
+
+fn tag_filter(name: char, value: &NdbStrVariant) -> Result<FilterBuilder> {
+    let mut builder = Filter::new();
+    builder.start_tag_field(name)?;
+    match value {
+        NdbStrVariant::Id(id) => builder.add_id_element(id)?,
+        NdbStrVariant::Str(s) => builder.add_str_element(s)?,
+    }
+    builder.end_field();
+    Ok(builder)
+}
+
+/// The word we search the fulltext index for: the longest plain one, so
+/// that it matches as few other notes as possible
+fn search_word(content: &str) -> Option<&str> {
+    content
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.len() > 2 && word.is_ascii())
+        .max_by_key(|word| word.len())
+}
+
+fn check_note(
+    ndb: &Ndb,
+    txn: &Transaction,
+    key: NoteKey,
+    note: &Note,
+    options: &IntegrityOptions,
+    report: &mut IntegrityReport,
+) -> Result<()> {
+    let problems = &mut report.problems;
+    let mut problem = |issue| problems.push(IntegrityProblem { note: key, issue });
+    let inconclusive = &mut report.inconclusive;
+    let mut unknown = |index| inconclusive.push(InconclusiveCheck { note: key, index });
+    let text_note = note.kind() == 1;
+
+    if options.checks(NoteIndex::Id) {
+        match ndb.get_notekey_by_id(txn, note.id()) {
+            Ok(found) if found == key.as_u64() => {}
+            Ok(found) => problem(IntegrityIssue::IdMismatch(NoteKey::new(found))),
+            Err(_) => problem(IntegrityIssue::MissingFromIndex(NoteIndex::Id)),
+        }
+    }
+
+    let lookups = [
+        (NoteIndex::Kind, Filter::new().kinds([note.kind() as u64])),
+        (NoteIndex::Author, Filter::new().authors([note.pubkey()])),
+    ];
+    for (index, builder) in lookups {
+        if !options.checks(index) {
+            continue;
+        }
+        match lookup_finds(ndb, txn, builder, key, note.created_at())? {
+            Some(true) => {}
+            Some(false) => problem(IntegrityIssue::MissingFromIndex(index)),
+            None => unknown(index),
+        }
+    }
+
+    if options.checks(NoteIndex::Tags) {
+        let mut unsure = false;
+        for (name, value) in indexed_tags(note) {
+            let builder = tag_filter(name, &value)?;
+            match lookup_finds(ndb, txn, builder, key, note.created_at())? {
+                Some(true) => {}
+                Some(false) => {
+                    problem(IntegrityIssue::MissingFromIndex(NoteIndex::Tags));
+                    unsure = false;
+                    break;
+                }
+                None => unsure = true,
+            }
+        }
+        if unsure {
+            unknown(NoteIndex::Tags);
+        }
+    }
+
+    if text_note && options.checks(NoteIndex::Fulltext) {
+        if let Some(word) = search_word(note.content()) {
+            let found = ndb.text_search(txn, word, LOOKUP_LIMIT)?;
+            if !found.contains(&key) {
+                if found.len() < LOOKUP_LIMIT as usize {
+                    problem(IntegrityIssue::MissingFromIndex(NoteIndex::Fulltext));
+                } else {
+                    unknown(NoteIndex::Fulltext);
+                }
+            }
+        }
+    }
+
+    if text_note && options.checks(NoteIndex::Blocks) && ndb.get_blocks_by_key(txn, key).is_err() {
+        problem(IntegrityIssue::MissingFromIndex(NoteIndex::Blocks));
+    }
+
+    if options.verify_signatures {
+        // notes in a transaction are read only, so verify a copy
+        let valid = note
+            .json()
+            .ok()
+            .and_then(|json| verify::with_verifier(|v| v.verify_message(&json)))
+            .unwrap_or(false);
+        if !valid {
+            problem(IntegrityIssue::InvalidSignature);
+        }
+    }
+
+    Ok(())
+}
+
+/// Walk the primary note store and check each note against the indexes
+/// picked in `options`
+pub(crate) fn check(
+    ndb: &Ndb,
+    txn: &Transaction,
+    options: &IntegrityOptions,
+) -> Result<IntegrityReport> {
+    let mut report = IntegrityReport::default();
+
+    for (key, note) in ndb.iter_notes(txn, NoteKey::new(1)) {
+        report.notes_checked += 1;
+        check_note(ndb, txn, key, &note, options, &mut report)?;
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util, Config};
+
+    #[test]
+    fn check_integrity_works() {
+        let db = "target/testdbs/check_integrity";
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            ndb.process_event(&test_util::hello_event())
+                .expect("process ok");
+        }
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let txn = Transaction::new(&ndb).expect("txn");
+            let mut options = IntegrityOptions::new();
+            options.set_verify_signatures(true);
+
+            let report = ndb.check_integrity(&txn, &options).expect("check");
+            assert_eq!(report.notes_checked, 1);
+            assert!(report.is_ok(), "{:?}", report.problems);
+            assert!(report.is_complete(), "{:?}", report.inconclusive);
+        }
+    }
+}
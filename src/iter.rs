@@ -0,0 +1,436 @@
+use crate::{Error, Filter, Ndb, Note, NoteKey, Result, Transaction};
+use std::collections::{HashSet, VecDeque};
+
+/// How many notes [QueryIter] asks for at a time
+const QUERY_PAGE_SIZE: u64 = 256;
+
+/// How many keys past the end of the note table [NoteIter] checks for
+/// notes in debug builds
+const GAP_PROBE: u64 = 16;
+
+/// Iterates over every stored note in insertion (primary key) order.
+/// Construct one with [Ndb::iter_notes].
+///
+/// The bindings have no cursor over the note table, so this looks notes
+/// up by key, one after the other, and stops at the first key without a
+/// note. That only reaches every note because nostrdb's writer hands out
+/// keys one after the other and never deletes notes, so there are no gaps.
+/// Debug builds check a few keys past the end to catch a nostrdb that
+/// does. The oplog, custom indexes, author stats, integrity checks,
+/// archiving and the CLI export all walk the notes this way.
+pub struct NoteIter<'a> {
+    ndb: Ndb,
+    txn: &'a Transaction,
+    next_key: u64,
+}
+
+impl<'a> NoteIter<'a> {
+    pub(crate) fn new(ndb: &Ndb, txn: &'a Transaction, start_key: NoteKey) -> Self {
+        // note keys start at 1
+        let next_key = start_key.as_u64().max(1);
+        NoteIter {
+            ndb: ndb.clone(),
+            txn,
+            next_key,
+        }
+    }
+
+    /// The key of the next note this iterator will try to yield
+    pub fn next_key(&self) -> NoteKey {
+        NoteKey::new(self.next_key)
+    }
+}
+
+impl<'a> Iterator for NoteIter<'a> {
+    type Item = (NoteKey, Note<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // note keys are assigned sequentially by the writer, so the first
+        // missing key is the end of the note table
+        let key = NoteKey::new(self.next_key);
+        let note = match self.ndb.get_note_by_key(self.txn, key) {
+            Ok(note) => note,
+            Err(_) => {
+                debug_assert!(
+                    (1..=GAP_PROBE).all(|i| self
+                        .ndb
+                        .get_note_by_key(self.txn, NoteKey::new(self.next_key + i))
+                        .is_err()),
+                    "notes stored after missing note key {}",
+                    self.next_key
+                );
+                return None;
+            }
+        };
+        self.next_key += 1;
+        Some((key, note))
+    }
+}
+
+/// A position in a newest first walk: the created_at reached so far, and
+/// the notes at that created_at that were already passed. Notes sharing a
+/// second are in no useful order, so the second alone can't say where to
+/// pick up again.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct QueryCursor {
+    until: Option<u64>,
+    seen: HashSet<NoteKey>,
+}
+
+impl QueryCursor {
+    /// Nothing newer than `until`, and nothing passed yet
+    fn until(until: Option<u64>) -> Self {
+        QueryCursor {
+            until,
+            seen: HashSet::new(),
+        }
+    }
+
+    fn passed(&self, created_at: u64, note_key: NoteKey) -> bool {
+        Some(created_at) == self.until && self.seen.contains(&note_key)
+    }
+
+    /// Move past a note. Notes have to be passed newest first.
+    pub(crate) fn pass(&mut self, created_at: u64, note_key: NoteKey) {
+        if Some(created_at) != self.until {
+            self.until = Some(created_at);
+            self.seen.clear();
+        }
+        self.seen.insert(note_key);
+    }
+}
+
+/// Iterates over all the notes matching a filter, newest first. Construct
+/// one with [Ndb::iter_kind] and friends.
+///
+/// The bindings have no cursor over nostrdb's indexes, so this pages
+/// through [Ndb::query] results. Between pages it keeps the created_at of
+/// the oldest note so far plus the notes already yielded at that
+/// created_at, so a second with more notes than fit on a page is still
+/// walked to the end: the next page asks for that second again with room
+/// for the notes after the ones already seen.
+pub struct QueryIter<'a> {
+    ndb: Ndb,
+    txn: &'a Transaction,
+    filter: Filter,
+    cursor: QueryCursor,
+    page: VecDeque<(NoteKey, Note<'a>)>,
+    done: bool,
+
+    /// Why the walk stopped before the end, see [QueryIter::error]
+    error: Option<Error>,
+}
+
+impl<'a> QueryIter<'a> {
+    pub(crate) fn new(ndb: &Ndb, txn: &'a Transaction, filter: Filter) -> Self {
+        Self::resume(ndb, txn, filter, QueryCursor::default())
+    }
+
+    /// Walk on from where `cursor` is, eg. the oldest note a feed has
+    /// loaded so far
+    pub(crate) fn resume(
+        ndb: &Ndb,
+        txn: &'a Transaction,
+        filter: Filter,
+        cursor: QueryCursor,
+    ) -> Self {
+        let cursor = match (filter.until(), cursor.until) {
+            (Some(until), Some(at)) if until >= at => cursor,
+            (Some(until), _) => QueryCursor::until(Some(until)),
+            (None, _) => cursor,
+        };
+        QueryIter {
+            ndb: ndb.clone(),
+            txn,
+            filter,
+            cursor,
+            page: VecDeque::new(),
+            done: false,
+            error: None,
+        }
+    }
+
+    /// The query that failed, if the walk stopped before the last matching
+    /// note
+    pub fn error(&self) -> Option<&Error> {
+        self.error.as_ref()
+    }
+
+    /// The query error that stopped the walk early, if there was one and
+    /// it hasn't been taken yet
+    pub(crate) fn take_error(&mut self) -> Result<()> {
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Every remaining note, or the error that cut the walk short
+    pub(crate) fn try_collect(mut self) -> Result<Vec<Note<'a>>> {
+        let notes = self.by_ref().map(|(_, note)| note).collect();
+        self.take_error()?;
+        Ok(notes)
+    }
+
+    fn fetch_page(&mut self) -> Result<()> {
+        // everything already seen at `until` comes back again, so make
+        // room for a page past it
+        let limit = (self.cursor.seen.len() as u64 + QUERY_PAGE_SIZE).min(i32::MAX as u64);
+        let mut filter = self.filter.clone().limit_mut(limit);
+        if let Some(until) = self.cursor.until {
+            filter = filter.until_mut(until);
+        }
+
+        let mut results = self.ndb.query(self.txn, &[filter], limit as i32)?;
+        let full = results.len() as u64 >= limit;
+        results.sort_by(|a, b| {
+            b.note
+                .created_at()
+                .cmp(&a.note.created_at())
+                .then(b.note_key.cmp(&a.note_key))
+        });
+
+        for result in results {
+            if !self
+                .cursor
+                .passed(result.note.created_at(), result.note_key)
+            {
+                self.page.push_back((result.note_key, result.note));
+            }
+        }
+        for (note_key, note) in &self.page {
+            self.cursor.pass(note.created_at(), *note_key);
+        }
+
+        // a short page means nothing is left at or before `until`
+        if !full || self.page.is_empty() {
+            self.done = true;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Iterator for QueryIter<'a> {
+    type Item = (NoteKey, Note<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.page.is_empty() && !self.done {
+            if let Err(err) = self.fetch_page() {
+                self.error = Some(err);
+                self.done = true;
+            }
+        }
+
+        self.page.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::QUERY_PAGE_SIZE;
+    use crate::{test_util, Config, Filter, Ndb, NoteBuilder, Transaction};
+    use std::collections::HashSet;
+
+    #[test]
+    fn iter_kind_walks_a_crowded_second() {
+        let db = "target/testdbs/iter_kind_crowded";
+        test_util::cleanup_db(db);
+
+        // more notes in one second than fit on a page, with one on
+        // either side of it
+        let crowded = QUERY_PAGE_SIZE as usize + 44;
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let mut events = String::new();
+            for i in 0..crowded + 2 {
+                let created_at = match i {
+                    0 => 1001,
+                    1 => 999,
+                    _ => 1000,
+                };
+                let note = NoteBuilder::new()
+                    .kind(1)
+                    .content(&format!("note {i}"))
+                    .created_at(created_at)
+                    .sign(&[1u8; 32])
+                    .build()
+                    .expect("note");
+                let json = note.json().expect("json");
+                events.push_str(&format!("[\"EVENT\",\"s\",{json}]\n"));
+            }
+            ndb.process_events(&events).expect("process ok");
+        }
+
+        let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+        let txn = Transaction::new(&ndb).expect("txn");
+        let notes: Vec<_> = ndb.iter_kind(&txn, 1, None, None).collect();
+        assert_eq!(notes.len(), crowded + 2);
+        let keys: HashSet<_> = notes.iter().map(|(note_key, _)| *note_key).collect();
+        assert_eq!(keys.len(), notes.len());
+        assert_eq!(notes[0].1.created_at(), 1001);
+        assert_eq!(notes[crowded + 1].1.created_at(), 999);
+    }
+
+    #[test]
+    fn iter_kind_works() {
+        let db = "target/testdbs/iter_kind";
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            ndb.process_event(&test_util::hello_event())
+                .expect("process ok");
+        }
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let txn = Transaction::new(&ndb).expect("txn");
+
+            let notes: Vec<_> = ndb.iter_kind(&txn, 1, None, None).collect();
+            assert_eq!(notes.len(), 1);
+            assert_eq!(notes[0].1.content(), "hello, world");
+
+            assert_eq!(ndb.iter_kind(&txn, 1, Some(1702675562), None).count(), 0);
+            assert_eq!(ndb.iter_kind(&txn, 1, None, Some(1702675560)).count(), 0);
+            assert_eq!(ndb.iter_kind(&txn, 0, None, None).count(), 0);
+        }
+    }
+
+    #[test]
+    fn iter_author_kind_works() {
+        let db = "target/testdbs/iter_author_kind";
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            ndb.process_event(&test_util::hello_event())
+                .expect("process ok");
+        }
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let txn = Transaction::new(&ndb).expect("txn");
+            let author: [u8; 32] = [
+                0x32, 0xbf, 0x91, 0x59, 0x04, 0xbf, 0xde, 0x2d, 0x13, 0x6b, 0xa4, 0x5d, 0xde, 0x32,
+                0xc8, 0x8f, 0x4a, 0xca, 0x86, 0x37, 0x83, 0x99, 0x9f, 0xae, 0xa2, 0xe8, 0x47, 0xa8,
+                0xfa, 0xfd, 0x2f, 0x15,
+            ];
+
+            let notes: Vec<_> = ndb.iter_author_kind(&txn, &author, 1, None, None).collect();
+            assert_eq!(notes.len(), 1);
+            assert_eq!(notes[0].1.pubkey(), &author);
+
+            assert_eq!(
+                ndb.iter_author_kind(&txn, &author, 0, None, None).count(),
+                0
+            );
+            assert_eq!(
+                ndb.iter_author_kind(&txn, &[0; 32], 1, None, None).count(),
+                0
+            );
+        }
+    }
+
+    #[test]
+    fn iter_author_kind_spans_pages() {
+        let db = "target/testdbs/iter_author_kind_pages";
+        test_util::cleanup_db(db);
+
+        let mine = QUERY_PAGE_SIZE * 2 + 10;
+        let author = {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let mut events = String::new();
+            let mut author = [0u8; 32];
+            for created_at in 1..=mine {
+                // someone else posting in every other second
+                for seckey in [[1u8; 32], [2u8; 32]] {
+                    if seckey[0] == 2 && created_at % 2 == 0 {
+                        continue;
+                    }
+                    let note = NoteBuilder::new()
+                        .kind(1)
+                        .content("paged")
+                        .created_at(created_at)
+                        .sign(&seckey)
+                        .build()
+                        .expect("note");
+                    if seckey[0] == 1 {
+                        author = *note.pubkey();
+                    }
+                    let json = note.json().expect("json");
+                    events.push_str(&format!("[\"EVENT\",\"s\",{json}]\n"));
+                }
+            }
+            ndb.process_events(&events).expect("process ok");
+            author
+        };
+
+        let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+        let txn = Transaction::new(&ndb).expect("txn");
+        let notes: Vec<_> = ndb.iter_author_kind(&txn, &author, 1, None, None).collect();
+        assert_eq!(notes.len() as u64, mine);
+        assert!(notes.iter().all(|(_, note)| note.pubkey() == &author));
+        assert!(notes
+            .windows(2)
+            .all(|w| w[0].1.created_at() > w[1].1.created_at()));
+    }
+
+    #[test]
+    fn iter_time_range_spans_a_crowded_second() {
+        let db = "target/testdbs/iter_time_range_crowded";
+        test_util::cleanup_db(db);
+
+        // two kinds sharing one second, more of them than fit on a page
+        let crowded = QUERY_PAGE_SIZE as usize + 20;
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let mut events = String::new();
+            for i in 0..crowded {
+                let note = NoteBuilder::new()
+                    .kind(if i % 2 == 0 { 1 } else { 7 })
+                    .content(&format!("{i}"))
+                    .created_at(5000)
+                    .sign(&[3u8; 32])
+                    .build()
+                    .expect("note");
+                let json = note.json().expect("json");
+                events.push_str(&format!("[\"EVENT\",\"s\",{json}]\n"));
+            }
+            ndb.process_events(&events).expect("process ok");
+        }
+
+        let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+        let txn = Transaction::new(&ndb).expect("txn");
+        let keys: HashSet<_> = ndb
+            .iter_time_range(&txn, Some(5000), Some(5000))
+            .map(|(note_key, _)| note_key)
+            .collect();
+        assert_eq!(keys.len(), crowded);
+        assert_eq!(
+            ndb.count(&txn, &[Filter::new().kinds([7]).build()]),
+            Ok(crowded as u64 / 2)
+        );
+    }
+
+    #[test]
+    fn iter_time_range_works() {
+        let db = "target/testdbs/iter_time_range";
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            ndb.process_event(&test_util::hello_event())
+                .expect("process ok");
+        }
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let txn = Transaction::new(&ndb).expect("txn");
+
+            let in_range = ndb.iter_time_range(&txn, Some(1702675561), Some(1702675561));
+            assert_eq!(in_range.count(), 1);
+            assert_eq!(ndb.iter_time_range(&txn, None, None).count(), 1);
+            assert_eq!(ndb.iter_time_range(&txn, Some(1702675562), None).count(), 0);
+        }
+    }
+}
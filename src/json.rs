@@ -0,0 +1,176 @@
+//! Just enough json scanning to say what is wrong with an event that
+//! nostrdb's parser rejected, or to pick out an event id without parsing
+//! the whole note. nostrdb only reports that parsing failed.
+
+use crate::util::{decode_hex32, decode_hex64};
+
+/// The members of a json object as (key, raw value) pairs. Keys are
+/// returned without their quotes and values exactly as written. None if
+/// `json` isn't a well formed object.
+pub(crate) fn object_members(json: &str) -> Option<Vec<(&str, &str)>> {
+    let mut rest = json.trim().strip_prefix('{')?.trim_start();
+    let mut members = vec![];
+
+    if let Some(after) = rest.strip_prefix('}') {
+        return after.trim().is_empty().then_some(members);
+    }
+
+    loop {
+        let (key, after) = string(rest)?;
+        let after = after.trim_start().strip_prefix(':')?.trim_start();
+        let (value, after) = value(after)?;
+        members.push((key, value));
+
+        let after = after.trim_start();
+        if let Some(after) = after.strip_prefix(',') {
+            rest = after.trim_start();
+        } else {
+            let after = after.strip_prefix('}')?;
+            return after.trim().is_empty().then_some(members);
+        }
+    }
+}
+
+/// The elements of a json array, exactly as written. None if `json` isn't
+/// a well formed array.
+pub(crate) fn array_elements(json: &str) -> Option<Vec<&str>> {
+    let mut rest = json.trim().strip_prefix('[')?.trim_start();
+    let mut elements = vec![];
+
+    if let Some(after) = rest.strip_prefix(']') {
+        return after.trim().is_empty().then_some(elements);
+    }
+
+    loop {
+        let (element, after) = value(rest)?;
+        elements.push(element);
+
+        let after = after.trim_start();
+        if let Some(after) = after.strip_prefix(',') {
+            rest = after.trim_start();
+        } else {
+            let after = after.strip_prefix(']')?;
+            return after.trim().is_empty().then_some(elements);
+        }
+    }
+}
+
+/// The id of the event in an `["EVENT","subid",{...}]` relay message
+pub(crate) fn event_id(msg: &str) -> Option<[u8; 32]> {
+    decode_hex32(string(event_member(msg, "id")?)?.0)
+}
+
+/// The id and signature of the event in an `["EVENT","subid",{...}]`
+/// relay message
+pub(crate) fn event_id_sig(msg: &str) -> Option<([u8; 32], [u8; 64])> {
+    let elements = array_elements(msg)?;
+    let members = object_members(elements.get(2)?)?;
+    let member = |name: &str| {
+        let (_, value) = members.iter().find(|(key, _)| *key == name)?;
+        string(value).map(|(s, _)| s)
+    };
+    Some((decode_hex32(member("id")?)?, decode_hex64(member("sig")?)?))
+}
+
+/// The created_at of the event in an `["EVENT","subid",{...}]` relay
+/// message
+pub(crate) fn created_at(msg: &str) -> Option<u64> {
+    event_member(msg, "created_at")?.parse().ok()
+}
+
+/// The raw value of `name` in the event of a relay message
+fn event_member<'a>(msg: &'a str, name: &str) -> Option<&'a str> {
+    let elements = array_elements(msg)?;
+    let event = elements.get(2)?;
+    let (_, value) = object_members(event)?
+        .into_iter()
+        .find(|(key, _)| *key == name)?;
+    Some(value)
+}
+
+/// The contents of the json string at the start of `s`, and what follows it
+fn string(s: &str) -> Option<(&str, &str)> {
+    let body = s.strip_prefix('"')?;
+    let mut escaped = false;
+    for (i, c) in body.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => return Some((&body[..i], &body[i + 1..])),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The raw json value at the start of `s`, and what follows it
+fn value(s: &str) -> Option<(&str, &str)> {
+    let end = match s.chars().next()? {
+        '"' => s.len() - string(s)?.1.len(),
+        '{' | '[' => nested_end(s)?,
+        _ => s
+            .find(|c: char| c == ',' || c == '}' || c == ']' || c.is_whitespace())
+            .unwrap_or(s.len()),
+    };
+    (end > 0).then(|| (&s[..end], &s[end..]))
+}
+
+/// Where the object or array at the start of `s` ends
+fn nested_end(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut rest = s;
+    while let Some(c) = rest.chars().next() {
+        if c == '"' {
+            rest = string(rest)?.1;
+            continue;
+        }
+
+        match c {
+            '{' | '[' => depth += 1,
+            '}' | ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(s.len() - rest.len() + 1);
+                }
+            }
+            _ => {}
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn object_members_work() {
+        let members = object_members(r#" {"a": "x,}\"", "b":[1,{"c":"]"}], "d" : 12 ,"e":{}} "#)
+            .expect("object");
+        assert_eq!(
+            members,
+            vec![
+                ("a", r#""x,}\"""#),
+                ("b", r#"[1,{"c":"]"}]"#),
+                ("d", "12"),
+                ("e", "{}")
+            ]
+        );
+
+        assert_eq!(object_members("{}"), Some(vec![]));
+        assert_eq!(object_members(r#"{"a":1"#), None);
+        assert_eq!(object_members(r#"{"a":1} x"#), None);
+        assert_eq!(object_members("[]"), None);
+
+        assert_eq!(
+            array_elements(r#"[1, "a]", {"b":[]}]"#),
+            Some(vec!["1", r#""a]""#, r#"{"b":[]}"#])
+        );
+        assert_eq!(
+            event_id(r#"["EVENT","s",{"id":"702555e52e82cc24ad517ba78c21879f6e47a7c0692b9b20df147916ae8731a3"}]"#).map(|id| id[0]),
+            Some(0x70)
+        );
+        assert_eq!(event_id(r#"["EOSE","s"]"#), None);
+    }
+}
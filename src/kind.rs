@@ -0,0 +1,211 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+/// Note kinds with names, for matching on [Note::kind_enum] instead of
+/// magic numbers. Anything else is [Kind::Custom].
+///
+/// Kinds compare and hash by number, so `Kind::Custom(1) ==
+/// Kind::TextNote`. Build them with [Kind::from_u32] to get the named
+/// variant, which is the one `match` arms see.
+///
+/// [Note::kind_enum]: crate::Note::kind_enum
+#[derive(Debug, Clone, Copy, Eq)]
+#[non_exhaustive]
+pub enum Kind {
+    Metadata,
+    TextNote,
+    RecommendRelay,
+    Contacts,
+
+    /// NIP-04 encrypted direct message
+    Dm,
+
+    /// NIP-09 deletion request
+    Deletion,
+    Repost,
+    Reaction,
+    BadgeAward,
+    Seal,
+
+    /// NIP-17 private direct message
+    PrivateDm,
+    GenericRepost,
+    ChannelMessage,
+    GiftWrap,
+    Report,
+    Label,
+
+    /// NIP-75 zap goal
+    ZapGoal,
+    ZapRequest,
+    ZapReceipt,
+    Highlight,
+    MuteList,
+    PinList,
+    RelayList,
+    BookmarkList,
+
+    /// NIP-51 sets, named with a `d` tag
+    FollowSet,
+    RelaySet,
+    BookmarkSet,
+    ProfileBadges,
+    BadgeDefinition,
+    LongForm,
+
+    Custom(u32),
+}
+
+impl Kind {
+    /// The named variant for `kind`, if there is one
+    pub fn from_u32(kind: u32) -> Self {
+        match kind {
+            0 => Kind::Metadata,
+            1 => Kind::TextNote,
+            2 => Kind::RecommendRelay,
+            3 => Kind::Contacts,
+            4 => Kind::Dm,
+            5 => Kind::Deletion,
+            6 => Kind::Repost,
+            7 => Kind::Reaction,
+            8 => Kind::BadgeAward,
+            13 => Kind::Seal,
+            14 => Kind::PrivateDm,
+            16 => Kind::GenericRepost,
+            42 => Kind::ChannelMessage,
+            1059 => Kind::GiftWrap,
+            1984 => Kind::Report,
+            1985 => Kind::Label,
+            9041 => Kind::ZapGoal,
+            9734 => Kind::ZapRequest,
+            9735 => Kind::ZapReceipt,
+            9802 => Kind::Highlight,
+            10000 => Kind::MuteList,
+            10001 => Kind::PinList,
+            10002 => Kind::RelayList,
+            10003 => Kind::BookmarkList,
+            30000 => Kind::FollowSet,
+            30002 => Kind::RelaySet,
+            30003 => Kind::BookmarkSet,
+            30008 => Kind::ProfileBadges,
+            30009 => Kind::BadgeDefinition,
+            30023 => Kind::LongForm,
+            kind => Kind::Custom(kind),
+        }
+    }
+
+    pub fn as_u32(self) -> u32 {
+        match self {
+            Kind::Metadata => 0,
+            Kind::TextNote => 1,
+            Kind::RecommendRelay => 2,
+            Kind::Contacts => 3,
+            Kind::Dm => 4,
+            Kind::Deletion => 5,
+            Kind::Repost => 6,
+            Kind::Reaction => 7,
+            Kind::BadgeAward => 8,
+            Kind::Seal => 13,
+            Kind::PrivateDm => 14,
+            Kind::GenericRepost => 16,
+            Kind::ChannelMessage => 42,
+            Kind::GiftWrap => 1059,
+            Kind::Report => 1984,
+            Kind::Label => 1985,
+            Kind::ZapGoal => 9041,
+            Kind::ZapRequest => 9734,
+            Kind::ZapReceipt => 9735,
+            Kind::Highlight => 9802,
+            Kind::MuteList => 10000,
+            Kind::PinList => 10001,
+            Kind::RelayList => 10002,
+            Kind::BookmarkList => 10003,
+            Kind::FollowSet => 30000,
+            Kind::RelaySet => 30002,
+            Kind::BookmarkSet => 30003,
+            Kind::ProfileBadges => 30008,
+            Kind::BadgeDefinition => 30009,
+            Kind::LongForm => 30023,
+            Kind::Custom(kind) => kind,
+        }
+    }
+
+    /// Only the latest note of this kind from each author counts
+    pub fn is_replaceable(self) -> bool {
+        let kind = self.as_u32();
+        kind == 0 || kind == 3 || (10000..20000).contains(&kind) || self.is_addressable()
+    }
+
+    /// Only the latest note of this kind from each author with the same
+    /// `d` tag counts
+    pub fn is_addressable(self) -> bool {
+        (30000..40000).contains(&self.as_u32())
+    }
+
+    /// Relays don't store notes of this kind
+    pub fn is_ephemeral(self) -> bool {
+        (20000..30000).contains(&self.as_u32())
+    }
+}
+
+impl PartialEq for Kind {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_u32() == other.as_u32()
+    }
+}
+
+impl Hash for Kind {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_u32().hash(state)
+    }
+}
+
+impl From<u32> for Kind {
+    fn from(kind: u32) -> Self {
+        Kind::from_u32(kind)
+    }
+}
+
+impl From<Kind> for u32 {
+    fn from(kind: Kind) -> Self {
+        kind.as_u32()
+    }
+}
+
+impl From<Kind> for u64 {
+    fn from(kind: Kind) -> Self {
+        kind.as_u32() as u64
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_u32())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kind_roundtrip_works() {
+        for kind in 0..40000 {
+            assert_eq!(Kind::from_u32(kind).as_u32(), kind);
+        }
+        assert_eq!(Kind::from(9735), Kind::ZapReceipt);
+        assert_eq!(Kind::from(12345), Kind::Custom(12345));
+
+        assert!(Kind::Metadata.is_replaceable());
+        assert!(Kind::RelayList.is_replaceable());
+        assert!(Kind::LongForm.is_addressable());
+        assert!(Kind::LongForm.is_replaceable());
+        assert!(!Kind::TextNote.is_replaceable());
+        assert!(Kind::Custom(20001).is_ephemeral());
+
+        // an unnormalized custom kind is still the kind it stands for
+        assert_eq!(Kind::Custom(1), Kind::TextNote);
+        let kinds: std::collections::HashSet<Kind> = [Kind::Custom(7), Kind::Reaction].into();
+        assert_eq!(kinds.len(), 1);
+    }
+}
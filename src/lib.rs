@@ -3,6 +3,7 @@
 #[allow(non_snake_case)]
 #[allow(unused)]
 #[allow(clippy::upper_case_acronyms)]
+#[allow(clippy::missing_safety_doc)]
 mod bindings;
 
 #[allow(unused)]
@@ -11,35 +12,118 @@ mod bindings;
 #[allow(clippy::missing_safety_doc)]
 mod ndb_profile;
 
+mod archive;
+mod author_stats;
 mod block;
+mod bloom;
+mod changes;
 mod config;
 mod error;
 mod filter;
+mod first_seen;
+mod follows;
+mod import;
+mod index;
+mod ingest;
+mod integrity;
+mod iter;
+mod json;
+mod kind;
+mod media;
+mod migrate;
+mod mute;
 mod ndb;
 mod ndb_str;
+mod negentropy;
 mod note;
+mod note_cache;
+mod oplog;
+mod persistent;
 mod profile;
 mod query;
+pub mod raw;
+mod readers;
+#[cfg(feature = "relay")]
+mod relay;
+mod response;
 mod result;
+#[cfg(feature = "nostr")]
+mod rust_nostr;
+mod sidecar;
+mod snapshot;
+mod sources;
+mod stat;
 mod subscription;
 mod tags;
+mod timeline;
 mod transaction;
 mod util;
+mod verify;
+#[cfg(feature = "wot")]
+mod wot;
 
+pub use archive::ArchiveStats;
+pub use author_stats::AuthorStats;
 pub use block::{Block, BlockType, Blocks, Mention};
+pub use bloom::DuplicateStats;
+pub use changes::{Change, ChangeFeed};
 pub use config::Config;
-pub use error::{Error, FilterError};
+pub use error::{Error, FilterError, NoteError, Rejection};
 pub use filter::{Filter, FilterBuilder};
+pub use ingest::{IngestOptions, PublishPolicy};
+pub use integrity::{
+    InconclusiveCheck, IntegrityIssue, IntegrityOptions, IntegrityProblem, IntegrityReport,
+    NoteIndex,
+};
+pub use iter::{NoteIter, QueryIter};
+pub use kind::Kind;
+pub use media::MediaMeta;
+pub use migrate::{MigrationContext, MigrationProgress, Migrator};
+pub use mute::MutedWords;
 pub use ndb::Ndb;
 pub use ndb_profile::{NdbProfile, NdbProfileRecord};
 pub use ndb_str::{NdbStr, NdbStrVariant};
+pub use negentropy::{Negentropy, Reconciliation};
 pub use note::{Note, NoteBuildOptions, NoteBuilder, NoteKey};
-pub use profile::{ProfileKey, ProfileRecord};
-pub use query::QueryResult;
+pub use note_cache::{CachedNote, NoteCache};
+pub use oplog::OplogEntry;
+pub use persistent::{PersistentSubscription, Watermark};
+pub use profile::{ProfileKey, ProfileRecord, ProfileWatch};
+pub use query::{CancelToken, PartialResults, QueryDiff, QueryOptions, QueryResult};
+pub use readers::ReaderInfo;
+#[cfg(feature = "relay")]
+pub use relay::RelayPool;
+pub use response::{count_message, eose_message, event_message, ResponseIter};
 pub use result::Result;
-pub use subscription::Subscription;
+pub use stat::{Stat, StatCounts};
+pub use subscription::{
+    BackfillSubscription, OverflowPolicy, Subscription, SubscriptionConfig, SubscriptionEvent,
+    SubscriptionSet, SubscriptionStats,
+};
 pub use tags::{Tag, TagIter, Tags, TagsIter};
-pub use transaction::Transaction;
+pub use timeline::{Timeline, TimelineEntry};
+pub use transaction::{OwnedTransaction, Transaction};
+#[cfg(feature = "language")]
+pub use util::language::detect_language;
+pub use util::nip09::Deletion;
 pub use util::nip10::{Marker, NoteIdRef, NoteIdRefBuf, NoteReply, NoteReplyBuf};
+pub use util::nip18::Repost;
+pub use util::nip19::{encode_nevent, encode_nprofile};
+pub use util::nip23::{Article, CodeSegment, ProseBlocks};
+pub use util::nip25::Reaction;
+pub use util::nip26::{note_delegation, sign_delegation, Delegation, DelegationConditions};
+pub use util::nip30::{Emoji, EmojiBlock, EmojiBlockIter};
+pub use util::nip32::{Label, LabelTarget, LabelsByNamespace};
+pub use util::nip36::ContentWarnings;
+pub use util::nip51::{List, LIST_KINDS};
+pub use util::nip57::{bolt11_msats, note_zap_splits, split_amount, ZapReceipt, ZapSplit};
+pub use util::nip75::ZapGoal;
+pub use util::nip84::{Highlight, HighlightSource};
+pub use util::nip92::{note_imetas, Imeta};
+pub use util::relay_hints::{note_hinted_relays, note_relay_hints, HintTarget, RelayHint};
+pub use util::url::UrlKind;
+pub use verify::verify_events;
+#[cfg(feature = "wot")]
+pub use wot::{Trust, TrustOptions, TrustScores};
 
 mod test_util;
@@ -0,0 +1,257 @@
+use crate::util::lock;
+use crate::{Error, Result};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// The log of media metadata by url, in the database directory
+pub(crate) const MEDIA_FILE: &str = "media.log";
+
+/// In place of the etag on a line that removes a url. Etags are quoted.
+const REMOVED: &str = "-";
+
+/// Rewrite the log once it has this many more lines than entries
+const COMPACT_SLACK: usize = 4096;
+
+/// What a client remembers about a profile picture or other media url
+/// between fetches, see [Ndb::set_media_meta]
+///
+/// [Ndb::set_media_meta]: crate::Ndb::set_media_meta
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct MediaMeta {
+    /// For `If-None-Match` on the next fetch
+    pub etag: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+
+    /// When the url was last fetched or checked, in unix seconds
+    pub last_checked: u64,
+
+    /// Fetches that failed in a row, eg. for backing off dead links
+    pub failures: u32,
+}
+
+impl MediaMeta {
+    fn to_line(&self, url: &str) -> String {
+        let opt = |n: Option<u32>| n.map_or(String::new(), |n| n.to_string());
+        format!(
+            "{url}\t{}\t{}\t{}\t{}\t{}\n",
+            self.etag.as_deref().unwrap_or(""),
+            opt(self.width),
+            opt(self.height),
+            self.last_checked,
+            self.failures
+        )
+    }
+
+    /// A line from the log. A url followed by [REMOVED] means it was
+    /// removed.
+    fn from_line(line: &str) -> Option<(&str, Option<MediaMeta>)> {
+        let mut fields = line.split('\t');
+        let url = fields.next()?;
+        let etag = fields.next()?;
+        if etag == REMOVED {
+            return Some((url, None));
+        }
+        let opt = |s: &str| {
+            if s.is_empty() {
+                Ok(None)
+            } else {
+                s.parse().map(Some)
+            }
+        };
+
+        let meta = MediaMeta {
+            etag: (!etag.is_empty()).then(|| etag.to_string()),
+            width: opt(fields.next()?).ok()?,
+            height: opt(fields.next()?).ok()?,
+            last_checked: fields.next()?.parse().ok()?,
+            failures: fields.next()?.parse().ok()?,
+        };
+        Some((url, Some(meta)))
+    }
+}
+
+/// Media bookkeeping for clients, keyed by url. nostrdb has no way to
+/// store anything but notes in its LMDB environment, so this is a
+/// separate log of `<url>\t<fields>` lines, the latest line for a url
+/// winning. Writes to it aren't part of any LMDB transaction, and a crash
+/// can leave it out of step with the notes. It is read back the first
+/// time it is needed and rewritten when it gets much longer than what it
+/// holds.
+#[derive(Debug)]
+pub(crate) struct MediaStore {
+    path: PathBuf,
+    state: Mutex<Option<MediaState>>,
+}
+
+#[derive(Debug)]
+struct MediaState {
+    entries: HashMap<String, MediaMeta>,
+    lines: usize,
+    file: File,
+}
+
+/// Tabs and newlines would break the log apart
+fn valid(s: &str) -> bool {
+    !s.is_empty() && !s.contains(['\t', '\n', '\r'])
+}
+
+impl MediaStore {
+    pub(crate) fn new(db_dir: &Path) -> Self {
+        MediaStore {
+            path: db_dir.join(MEDIA_FILE),
+            state: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn get(&self, url: &str) -> Option<MediaMeta> {
+        let mut state = lock(&self.state);
+        let state = self.load(&mut state).ok()?;
+        state.entries.get(url).cloned()
+    }
+
+    pub(crate) fn set(&self, url: &str, meta: &MediaMeta) -> Result<()> {
+        let bad_etag = meta
+            .etag
+            .as_deref()
+            .is_some_and(|etag| !valid(etag) || etag == REMOVED);
+        if !valid(url) || bad_etag {
+            return Err(Error::DecodeError);
+        }
+
+        let mut state = lock(&self.state);
+        let state = self.load(&mut state).map_err(|_| Error::IoError)?;
+        if state.entries.get(url) == Some(meta) {
+            return Ok(());
+        }
+        state.append(&meta.to_line(url))?;
+        state.entries.insert(url.to_string(), meta.clone());
+        self.maybe_compact(state)
+    }
+
+    pub(crate) fn remove(&self, url: &str) -> Result<bool> {
+        let mut state = lock(&self.state);
+        let state = self.load(&mut state).map_err(|_| Error::IoError)?;
+        if !state.entries.contains_key(url) {
+            return Ok(false);
+        }
+        state.append(&format!("{url}\t{REMOVED}\n"))?;
+        state.entries.remove(url);
+        self.maybe_compact(state)?;
+        Ok(true)
+    }
+
+    fn load<'a>(&self, state: &'a mut Option<MediaState>) -> std::io::Result<&'a mut MediaState> {
+        if let Some(state) = state {
+            return Ok(state);
+        }
+
+        let mut entries = HashMap::new();
+        let mut lines = 0;
+        if let Ok(file) = File::open(&self.path) {
+            for line in BufReader::new(file).lines() {
+                let line = line?;
+                // a line cut short by a crash is skipped
+                let (url, meta) = match MediaMeta::from_line(&line) {
+                    Some(parsed) => parsed,
+                    None => continue,
+                };
+                lines += 1;
+                match meta {
+                    Some(meta) => entries.insert(url.to_string(), meta),
+                    None => entries.remove(url),
+                };
+            }
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        Ok(state.insert(MediaState {
+            entries,
+            lines,
+            file,
+        }))
+    }
+
+    /// Write out just the current entries and swap them in for the log
+    fn maybe_compact(&self, state: &mut MediaState) -> Result<()> {
+        if state.lines < state.entries.len() + COMPACT_SLACK {
+            return Ok(());
+        }
+
+        let tmp = self.path.with_extension("log.tmp");
+        let mut lines = String::new();
+        for (url, meta) in &state.entries {
+            lines.push_str(&meta.to_line(url));
+        }
+        fs::write(&tmp, lines)
+            .and_then(|_| fs::rename(&tmp, &self.path))
+            .map_err(|_| Error::IoError)?;
+
+        state.file = OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .map_err(|_| Error::IoError)?;
+        state.lines = state.entries.len();
+        Ok(())
+    }
+}
+
+impl MediaState {
+    fn append(&mut self, line: &str) -> Result<()> {
+        self.file
+            .write_all(line.as_bytes())
+            .map_err(|_| Error::IoError)?;
+        self.lines += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util, Config, Ndb};
+
+    #[test]
+    fn media_meta_works() {
+        let db = "target/testdbs/media_meta";
+        test_util::cleanup_db(db);
+
+        let pfp = "https://example.com/pfp.png";
+        let meta = MediaMeta {
+            etag: Some("\"abc\"".to_string()),
+            width: Some(400),
+            height: Some(400),
+            last_checked: 1700000000,
+            failures: 0,
+        };
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            assert_eq!(ndb.media_meta(pfp), None);
+            ndb.set_media_meta(pfp, &meta).expect("set");
+
+            let dead = MediaMeta {
+                failures: 3,
+                ..MediaMeta::default()
+            };
+            ndb.set_media_meta("https://example.com/gone.jpg", &dead)
+                .expect("set");
+            assert!(ndb
+                .remove_media_meta("https://example.com/gone.jpg")
+                .expect("remove"));
+            assert_eq!(
+                ndb.set_media_meta("https://example.com/a\tb", &dead),
+                Err(Error::DecodeError)
+            );
+        }
+
+        let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+        assert_eq!(ndb.media_meta(pfp), Some(meta));
+        assert_eq!(ndb.media_meta("https://example.com/gone.jpg"), None);
+    }
+}
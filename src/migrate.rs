@@ -0,0 +1,215 @@
+use crate::{Error, Ndb, Result};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Where the version of the application's own migrations is kept, next to
+/// the lmdb files. nostrdb tracks its schema version itself.
+pub(crate) const APP_VERSION_FILE: &str = "app_version";
+
+type MigrationFn = Box<dyn Fn(&Ndb, &mut MigrationContext) -> Result<()>>;
+
+struct Migration {
+    version: u32,
+    name: &'static str,
+    run: MigrationFn,
+}
+
+/// Where a migration run is, for progress UI
+#[derive(Debug, Clone, Copy)]
+pub struct MigrationProgress {
+    /// The version the running step migrates to
+    pub version: u32,
+    pub name: &'static str,
+
+    /// Which of the pending steps this is, starting at 0
+    pub step: usize,
+    pub steps: usize,
+
+    /// How far through this step we are, from 0 to 1
+    pub fraction: f32,
+}
+
+/// Handed to each migration step while it runs
+pub struct MigrationContext<'a> {
+    progress: MigrationProgress,
+    dry_run: bool,
+    report: &'a mut dyn FnMut(MigrationProgress),
+}
+
+impl MigrationContext<'_> {
+    /// Whether this is a dry run. Steps should check what they would do and
+    /// fail if they can't, but not write anything.
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Report how far through the step we are, from 0 to 1
+    pub fn progress(&mut self, fraction: f32) {
+        self.progress.fraction = fraction.clamp(0.0, 1.0);
+        (self.report)(self.progress);
+    }
+}
+
+/// Upgrades an application's data in a nostrdb database through a series
+/// of numbered steps. Each step runs once, in version order, and the
+/// version reached is recorded after every step, so an interrupted run
+/// picks up where it stopped.
+///
+/// nostrdb migrates its own schema when the database is opened. Open with
+/// [Config::skip_migrations] to look at [Ndb::db_version] first.
+///
+/// [Config::skip_migrations]: crate::Config::skip_migrations
+#[derive(Default)]
+pub struct Migrator {
+    steps: Vec<Migration>,
+    dry_run: bool,
+}
+
+impl Migrator {
+    pub fn new() -> Self {
+        Migrator::default()
+    }
+
+    /// Register the step that brings the data up to `version`
+    pub fn step<F>(&mut self, version: u32, name: &'static str, run: F) -> &mut Self
+    where
+        F: Fn(&Ndb, &mut MigrationContext) -> Result<()> + 'static,
+    {
+        self.steps.push(Migration {
+            version,
+            name,
+            run: Box::new(run),
+        });
+        self.steps.sort_by_key(|step| step.version);
+        self
+    }
+
+    /// Run the steps without recording any versions, see
+    /// [MigrationContext::dry_run]
+    pub fn set_dry_run(&mut self, dry_run: bool) -> &mut Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// The newest version any registered step migrates to
+    pub fn latest_version(&self) -> u32 {
+        self.steps.last().map_or(0, |step| step.version)
+    }
+
+    /// The steps that haven't run on this database yet, as (version, name)
+    pub fn pending(&self, ndb: &Ndb) -> Result<Vec<(u32, &'static str)>> {
+        let current = ndb.app_version()?;
+        Ok(self
+            .steps
+            .iter()
+            .filter(|step| step.version > current)
+            .map(|step| (step.version, step.name))
+            .collect())
+    }
+
+    /// Run every pending step. Returns the version the data is at
+    /// afterwards, which on a dry run is the one it would be at.
+    pub fn run(&self, ndb: &Ndb, mut report: impl FnMut(MigrationProgress)) -> Result<u32> {
+        let current = ndb.app_version()?;
+        let pending: Vec<&Migration> = self
+            .steps
+            .iter()
+            .filter(|step| step.version > current)
+            .collect();
+
+        let mut version = current;
+        for (i, step) in pending.iter().enumerate() {
+            let mut ctx = MigrationContext {
+                progress: MigrationProgress {
+                    version: step.version,
+                    name: step.name,
+                    step: i,
+                    steps: pending.len(),
+                    fraction: 0.0,
+                },
+                dry_run: self.dry_run,
+                report: &mut report,
+            };
+
+            ctx.progress(0.0);
+            (step.run)(ndb, &mut ctx)?;
+            ctx.progress(1.0);
+
+            version = step.version;
+            if !self.dry_run {
+                write_app_version(ndb.path(), version)?;
+            }
+        }
+
+        Ok(version)
+    }
+}
+
+/// The version recorded in the database directory `dir`. 0 if no migration
+/// has ever run.
+pub(crate) fn read_app_version(dir: &Path) -> Result<u32> {
+    match fs::read_to_string(dir.join(APP_VERSION_FILE)) {
+        Ok(version) => version.trim().parse().map_err(|_| Error::DecodeError),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(0),
+        Err(_) => Err(Error::IoError),
+    }
+}
+
+fn write_app_version(dir: &Path, version: u32) -> Result<()> {
+    // write then rename, so a crash never leaves a half written version
+    let path = dir.join(APP_VERSION_FILE);
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, version.to_string()).map_err(|_| Error::IoError)?;
+    fs::rename(&tmp, &path).map_err(|_| Error::IoError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util, Config};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn migrator_works() {
+        let db = "target/testdbs/migrator";
+        test_util::cleanup_db(db);
+        let _ = fs::remove_file(Path::new(db).join(APP_VERSION_FILE));
+
+        let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+        assert!(ndb.db_version().is_ok());
+        assert_eq!(ndb.app_version(), Ok(0));
+
+        let ran = Rc::new(RefCell::new(vec![]));
+        let mut migrator = Migrator::new();
+        for version in [2, 1] {
+            let ran = ran.clone();
+            migrator.step(version, "step", move |_, ctx| {
+                if !ctx.dry_run() {
+                    ran.borrow_mut().push(version);
+                }
+                ctx.progress(0.5);
+                Ok(())
+            });
+        }
+
+        migrator.set_dry_run(true);
+        let mut reports = 0;
+        assert_eq!(migrator.run(&ndb, |_| reports += 1), Ok(2));
+        assert_eq!(reports, 6);
+        assert_eq!(ndb.app_version(), Ok(0));
+        assert!(ran.borrow().is_empty());
+
+        migrator.set_dry_run(false);
+        assert_eq!(migrator.pending(&ndb).expect("pending").len(), 2);
+        assert_eq!(migrator.run(&ndb, |_| {}), Ok(2));
+        assert_eq!(*ran.borrow(), vec![1, 2]);
+        assert_eq!(ndb.app_version(), Ok(2));
+
+        // nothing left to do
+        assert!(migrator.pending(&ndb).expect("pending").is_empty());
+        assert_eq!(migrator.run(&ndb, |_| {}), Ok(2));
+        assert_eq!(ran.borrow().len(), 2);
+    }
+}
@@ -0,0 +1,132 @@
+use crate::{List, Note};
+
+/// Words and phrases to hide notes by, eg. from a NIP-51 mute list. See
+/// [QueryOptions::set_muted_words], [SubscriptionConfig::set_muted_words]
+/// and [IngestOptions::set_muted_words].
+///
+/// Text is split into lowercase words at anything that isn't a letter or
+/// digit, so muting `nostr` hides `#nostr` and `Nostr!` too. A phrase only
+/// matches its words in order.
+///
+/// This isn't nostrdb's fulltext tokenizer, which the bindings can't call,
+/// so a muted word doesn't hide exactly what a search for it finds:
+/// - an apostrophe splits a word, `don't` is `don` and `t`
+/// - text without spaces, like Chinese or Japanese, is one word per run
+/// - only whole words match, while search also finds words starting with
+///   the one searched for
+///
+/// [QueryOptions::set_muted_words]: crate::QueryOptions::set_muted_words
+/// [SubscriptionConfig::set_muted_words]: crate::SubscriptionConfig::set_muted_words
+/// [IngestOptions::set_muted_words]: crate::IngestOptions::set_muted_words
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct MutedWords {
+    phrases: Vec<Vec<String>>,
+}
+
+/// Split text into lowercase words: anything that isn't a letter or digit
+/// separates them
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+impl MutedWords {
+    pub fn new() -> Self {
+        MutedWords::default()
+    }
+
+    /// The `word` items of a NIP-51 mute list. Private items can't be read
+    /// here, add them once decrypted.
+    pub fn from_list(list: &List) -> Self {
+        let mut muted = MutedWords::new();
+        for word in list.words() {
+            muted.add(word);
+        }
+        muted
+    }
+
+    /// Mute a word or phrase. Ones with no words in them are ignored.
+    pub fn add(&mut self, phrase: &str) -> &mut Self {
+        let phrase: Vec<String> = words(phrase).collect();
+        if !phrase.is_empty() && !self.phrases.contains(&phrase) {
+            self.phrases.push(phrase);
+        }
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.phrases.is_empty()
+    }
+
+    /// Whether any muted word or phrase appears in `text`
+    pub fn matches(&self, text: &str) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+        let text: Vec<String> = words(text).collect();
+        self.phrases.iter().any(|phrase| {
+            text.windows(phrase.len())
+                .any(|window| window == phrase.as_slice())
+        })
+    }
+
+    /// Whether a note should be hidden, going by its content
+    pub(crate) fn mutes(&self, note: &Note) -> bool {
+        self.matches(note.content())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_util, Config, Error, Filter, IngestOptions, Ndb, NoteBuilder, QueryOptions, Rejection,
+        Transaction,
+    };
+
+    #[test]
+    fn muted_words_work() {
+        let mut muted = MutedWords::new();
+        muted.add("Bitcoin").add("good morning").add("  !! ");
+        assert!(muted.matches("#bitcoin to the moon"));
+        assert!(muted.matches("GOOD morning, nostr!"));
+        assert!(!muted.matches("morning good"));
+        assert!(!muted.matches("bitcoiners"));
+        assert!(MutedWords::new().add("don").matches("don't"));
+        assert!(!MutedWords::new().matches("bitcoin"));
+
+        let db = "target/testdbs/muted_words";
+        test_util::cleanup_db(db);
+        let msg = |content: &str| {
+            let note = NoteBuilder::new()
+                .kind(1)
+                .content(content)
+                .sign(&[1u8; 32])
+                .build()
+                .expect("note");
+            format!("[\"EVENT\",\"mute\",{}]", note.json().expect("json"))
+        };
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let mut options = IngestOptions::new();
+            options.set_muted_words(muted.clone());
+            assert_eq!(
+                ndb.process_event_with(&msg("buy bitcoin"), &options),
+                Err(Error::Rejected(Rejection::Muted))
+            );
+            ndb.process_event(&msg("good morning everyone"))
+                .expect("process ok");
+            ndb.process_event(&msg("gm everyone")).expect("process ok");
+        }
+
+        let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+        let txn = Transaction::new(&ndb).expect("txn");
+        let mut options = QueryOptions::new();
+        options.set_muted_words(muted);
+        let filters = [Filter::new().kinds([1]).limit(1).build()];
+        let results = ndb.query_with_options(&txn, &filters, 10, &options);
+        let contents: Vec<&str> = results.results.iter().map(|r| r.note.content()).collect();
+        assert_eq!(contents, vec!["gm everyone"]);
+    }
+}
@@ -1,19 +1,107 @@
+use crate::util::lock;
 use std::ffi::CString;
 use std::ptr;
 
+use crate::archive;
+use crate::author_stats::AuthorStatsRollup;
+use crate::bloom::DuplicateFilter;
+use crate::changes::ChangeFeed;
+use crate::filter::FilterField;
+use crate::first_seen::{self, FirstSeen};
+use crate::follows::{self, FollowGraph};
+use crate::import::{self, MappedFile};
+use crate::index::Indexes;
+use crate::ingest::Admission;
+use crate::integrity;
+use crate::json;
+use crate::media::MediaStore;
+use crate::migrate;
+use crate::oplog;
+use crate::persistent::SavedSubscriptions;
+use crate::query;
+use crate::readers::{self, Readers};
+use crate::snapshot;
+use crate::sources::Sources;
+use crate::subscription::{self, SubRegistry, SubState};
+use crate::util::nip19;
+use crate::util::nip32::{self, LabelTarget, LabelsByNamespace};
+use crate::util::nip51;
+use crate::util::nip65;
+use crate::util::relay_hints;
+use crate::verify::VerifyPool;
 use crate::{
-    bindings, Blocks, Config, Error, Filter, Note, NoteKey, ProfileKey, ProfileRecord, QueryResult,
-    Result, Subscription, Transaction,
+    bindings, ArchiveStats, Article, AuthorStats, BackfillSubscription, Blocks, Config,
+    DuplicateStats, Error, Filter, FilterBuilder, Highlight, HighlightSource, IngestOptions,
+    IntegrityOptions, IntegrityReport, Kind, List, MediaMeta, Negentropy, Note, NoteIter, NoteKey,
+    OplogEntry, PartialResults, PersistentSubscription, ProfileKey, ProfileRecord, ProfileWatch,
+    QueryDiff, QueryIter, QueryOptions, QueryResult, ReaderInfo, ResponseIter, Result, Stat,
+    Subscription, SubscriptionConfig, Timeline, Transaction, ZapGoal, ZapReceipt,
 };
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::os::raw::c_int;
-use std::path::Path;
-use std::sync::Arc;
+use std::ops::RangeBounds;
+use std::os::raw::{c_char, c_void};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock, Weak};
+use std::thread;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{Duration, Instant};
+#[cfg(not(target_arch = "wasm32"))]
 use tokio::task; // Make sure to import the task module
 
 #[derive(Debug)]
 struct NdbRef {
     ndb: *mut bindings::ndb,
+
+    /// Where the database lives, as registered in [open_dbs]
+    path: PathBuf,
+
+    /// Filters and queued notes for each live subscription
+    subs: Arc<SubRegistry>,
+
+    /// Moves notes from nostrdb's subscription queues into our inboxes
+    drainer: Option<thread::JoinHandle<()>>,
+
+    /// Checks events before they reach nostrdb, see [Config::set_verify_threads]
+    verify: Option<Arc<VerifyPool>>,
+
+    /// Open read transactions, see [Ndb::reader_info]
+    readers: Arc<Readers>,
+
+    /// See [Ndb::register_index]
+    indexes: Indexes,
+
+    /// See [Ndb::follows_of]
+    follows: FollowGraph,
+
+    /// See [Ndb::trust_scores]
+    #[cfg(feature = "wot")]
+    trust: crate::wot::TrustCache,
+
+    /// See [Config::set_record_sources]
+    sources: Option<Sources>,
+
+    /// See [Config::set_record_first_seen]
+    first_seen: Option<FirstSeen>,
+
+    /// See [Config::set_duplicate_filter]
+    duplicates: Option<DuplicateFilter>,
+
+    /// See [Config::set_max_future_drift] and [Note::delegator]
+    admission: Admission,
+
+    /// See [Ndb::set_media_meta]
+    media: MediaStore,
+
+    /// See [Ndb::author_stats]
+    author_stats: AuthorStatsRollup,
+
+    /// See [Ndb::attach_archive]
+    archive: Mutex<Option<Ndb>>,
+
+    /// See [Ndb::resume_subscription]
+    saved_subs: SavedSubscriptions,
 }
 
 /// It's safe to have multi-threaded references to this because thread safety
@@ -22,14 +110,102 @@ unsafe impl Send for NdbRef {}
 unsafe impl Sync for NdbRef {}
 
 /// The database is automatically closed when [Ndb] is [Drop]ped.
+impl NdbRef {
+    /// Flush and close the database. Fails with [Error::NoteProcessFailed]
+    /// if events that passed verification never reached nostrdb. Does
+    /// nothing the second time.
+    fn close(&mut self) -> Result<()> {
+        // events still being verified have to reach nostrdb before it
+        // flushes its queues
+        let lost = self.verify.take().map_or(0, |verify| verify.shutdown());
+
+        // the drain thread polls nostrdb, so it has to stop first
+        self.subs.shutdown();
+        if let Some(drainer) = self.drainer.take() {
+            let _ = drainer.join();
+        }
+
+        if !self.ndb.is_null() {
+            // keep the path registered until the environment is fully
+            // closed, so a concurrent open waits instead of opening it a
+            // second time. Other paths can open and close in the meantime
+            let open = open_dbs();
+            lock(&open.dbs).insert(self.path.clone(), OpenDb::Closing);
+
+            unsafe {
+                bindings::ndb_destroy(self.ndb);
+            }
+            self.ndb = ptr::null_mut();
+
+            open.settle(&self.path);
+        }
+
+        if lost > 0 {
+            return Err(Error::NoteProcessFailed);
+        }
+        Ok(())
+    }
+}
+
 impl Drop for NdbRef {
     fn drop(&mut self) {
-        unsafe {
-            bindings::ndb_destroy(self.ndb);
+        let _ = self.close();
+    }
+}
+
+#[derive(Debug)]
+enum OpenDb {
+    Open(Weak<NdbRef>),
+
+    /// Being opened or closed by another thread, which will remove or
+    /// replace the entry and signal [OpenDbs::changed] when it's done
+    Opening,
+    Closing,
+}
+
+/// The databases open in this process. LMDB doesn't allow opening the same
+/// environment twice in one process, so opening a path that is already open
+/// shares the existing one.
+struct OpenDbs {
+    dbs: Mutex<HashMap<PathBuf, OpenDb>>,
+
+    /// Signalled whenever a database finishes opening or closing
+    changed: Condvar,
+}
+
+impl OpenDbs {
+    /// Drop the entry for a path that finished closing or failed to open,
+    /// and wake anyone waiting on it
+    fn settle(&self, path: &Path) {
+        let mut dbs = lock(&self.dbs);
+        if !matches!(dbs.get(path), Some(OpenDb::Open(_))) {
+            dbs.remove(path);
         }
+        drop(dbs);
+        self.changed.notify_all();
     }
 }
 
+/// Takes the [OpenDb::Opening] entry back out if opening fails part way
+struct PendingOpen<'a> {
+    open: &'a OpenDbs,
+    path: &'a Path,
+}
+
+impl Drop for PendingOpen<'_> {
+    fn drop(&mut self) {
+        self.open.settle(self.path);
+    }
+}
+
+fn open_dbs() -> &'static OpenDbs {
+    static OPEN_DBS: OnceLock<OpenDbs> = OnceLock::new();
+    OPEN_DBS.get_or_init(|| OpenDbs {
+        dbs: Mutex::new(HashMap::new()),
+        changed: Condvar::new(),
+    })
+}
+
 /// A nostrdb context. Construct one of these with [Ndb::new].
 #[derive(Debug, Clone)]
 pub struct Ndb {
@@ -37,9 +213,36 @@ pub struct Ndb {
 }
 
 impl Ndb {
+    /// The maximum number of notes in a [Ndb::negentropy] snapshot when
+    /// the filter doesn't have a limit. More than this is an error.
+    pub const MAX_NEGENTROPY_ITEMS: i32 = 100_000;
+
+    /// The most relays put in an [Ndb::nprofile] or [Ndb::nevent] link
+    pub const MAX_SHARE_RELAYS: usize = 3;
+
+    /// What [Ndb::notifications] looks for when no kinds are given
+    pub const NOTIFICATION_KINDS: [Kind; 5] = [
+        Kind::TextNote,
+        Kind::Repost,
+        Kind::Reaction,
+        Kind::GenericRepost,
+        Kind::ZapReceipt,
+    ];
+
     /// Construct a new nostrdb context. Takes a directory where the database
     /// is/will be located and a nostrdb config.
+    ///
+    /// If the database is already open in this process, the returned handle
+    /// shares it, and `config` is ignored.
+    ///
+    /// Always fails with [Error::DbOpenFailed] on wasm32. lmdb needs a
+    /// shared memory map, and nostrdb and these bindings need threads, so
+    /// only notes, filters and the parsers work there.
     pub fn new(db_dir: &str, config: &Config) -> Result<Self> {
+        if cfg!(target_arch = "wasm32") {
+            return Err(Error::DbOpenFailed);
+        }
+
         let db_dir_cstr = match CString::new(db_dir) {
             Ok(cstr) => cstr,
             Err(_) => return Err(Error::DbOpenFailed),
@@ -51,36 +254,655 @@ impl Ndb {
             let _ = fs::create_dir_all(path);
         }
 
-        let result = unsafe { bindings::ndb_init(&mut ndb, db_dir_cstr.as_ptr(), config.as_ptr()) };
+        let key = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        let open = open_dbs();
+        let mut dbs = lock(&open.dbs);
+        loop {
+            let existing = match dbs.get(&key) {
+                Some(OpenDb::Open(db)) => Some(db.upgrade()),
+                Some(OpenDb::Opening | OpenDb::Closing) => Some(None),
+                None => None,
+            };
+            match existing {
+                Some(Some(refs)) => return Ok(Ndb { refs }),
+                // another thread is opening it, or the last handle was
+                // just dropped, so wait for that to finish
+                Some(None) => dbs = open.changed.wait(dbs).unwrap_or_else(|e| e.into_inner()),
+                None => break,
+            }
+        }
+
+        // the slow part is done without the lock, so opening or closing one
+        // database doesn't hold up the others
+        dbs.insert(key.clone(), OpenDb::Opening);
+        drop(dbs);
+        let pending = PendingOpen { open, path: &key };
+
+        // subscription inboxes are managed on our side, so we need to hear
+        // about every write that matches a subscription. A callback already
+        // set on the config is still called, after ours
+        let subs = Arc::new(SubRegistry::chained(
+            config.config.sub_cb,
+            config.config.sub_cb_ctx,
+        ));
+        let verify_threads = if config.skips_validation() {
+            0
+        } else {
+            config.verify_threads()
+        };
+        let verify_batch_size = config.verify_batch_size();
+        let verify_queue_depth = config.verify_queue_depth();
+        let long_reader_warning = config.long_reader_warning();
+        let sources = config.records_sources().then(|| Sources::new(&key));
+        let first_seen = config.records_first_seen().then(|| FirstSeen::new(&key));
+        let duplicates = match config.duplicate_filter() {
+            0 => None,
+            capacity => Some(DuplicateFilter::new(capacity)),
+        };
+        let admission = Admission {
+            created_at: config.created_at_bounds(),
+            delegations: !config.skips_validation(),
+            max_size: config.max_event_size(),
+        };
+        let delegation_index = config.indexes_delegations();
+        let tag_index = config.indexes_tags();
+        #[cfg(feature = "language")]
+        let language_index = config.indexes_language();
+        let mut config = config.config;
+        if verify_threads > 0 {
+            // our verify pool does it instead. Everything reaching nostrdb
+            // has to go through the pool then, or through a caller of
+            // Ndb::ingest_unchecked that verified it itself
+            config.flags |= bindings::NDB_FLAG_SKIP_NOTE_VERIFY as i32;
+        }
+        unsafe {
+            bindings::ndb_config_set_subscription_callback(
+                &mut config,
+                Some(subscription::sub_callback),
+                Arc::as_ptr(&subs) as *mut c_void,
+            );
+        }
+
+        let result = unsafe { bindings::ndb_init(&mut ndb, db_dir_cstr.as_ptr(), &config) };
 
         if result == 0 {
             return Err(Error::DbOpenFailed);
         }
 
-        let refs = Arc::new(NdbRef { ndb });
+        let drainer = match subscription::spawn_drainer(subs.clone(), ndb) {
+            Ok(drainer) => drainer,
+            Err(_) => {
+                unsafe { bindings::ndb_destroy(ndb) };
+                return Err(Error::DbOpenFailed);
+            }
+        };
+
+        let verify = if verify_threads > 0 {
+            match VerifyPool::spawn(ndb, verify_threads, verify_batch_size, verify_queue_depth) {
+                Ok(pool) => Some(pool),
+                Err(_) => {
+                    subs.shutdown();
+                    let _ = drainer.join();
+                    unsafe { bindings::ndb_destroy(ndb) };
+                    return Err(Error::DbOpenFailed);
+                }
+            }
+        } else {
+            None
+        };
+
+        // the watcher exits by itself once the database is closed, but it
+        // isn't needed to use the database, so failing to start it isn't
+        // fatal
+        let readers = Arc::new(Readers::default());
+        if let Some((threshold, warning)) = long_reader_warning {
+            let _ = readers::spawn_watcher(&readers, threshold, warning);
+        }
+
+        let refs = Arc::new(NdbRef {
+            ndb,
+            path: key.clone(),
+            subs,
+            drainer: Some(drainer),
+            verify,
+            readers,
+            indexes: Indexes::default(),
+            follows: FollowGraph::default(),
+            #[cfg(feature = "wot")]
+            trust: Default::default(),
+            sources,
+            first_seen,
+            duplicates,
+            admission,
+            media: MediaStore::new(&key),
+            author_stats: AuthorStatsRollup::new(&key),
+            archive: Mutex::new(None),
+            saved_subs: SavedSubscriptions::new(&key),
+        });
+        if delegation_index {
+            let derive = Box::new(crate::util::nip26::index_keys);
+            refs.indexes.register(Self::DELEGATION_INDEX, derive);
+        }
+        if tag_index {
+            let derive = Box::new(crate::tags::index_keys);
+            refs.indexes.register(Self::TAG_INDEX, derive);
+        }
+        #[cfg(feature = "language")]
+        if language_index {
+            let derive = Box::new(crate::util::language::index_keys);
+            refs.indexes.register(Self::LANGUAGE_INDEX, derive);
+        }
+        lock(&open.dbs).insert(key.clone(), OpenDb::Open(Arc::downgrade(&refs)));
+        drop(pending);
         Ok(Ndb { refs })
     }
 
+    /// Close the database. nostrdb finishes writing everything already
+    /// queued by [Ndb::process_event] before its threads exit, so once this
+    /// returns the notes are on disk. Dropping the last handle does the same
+    /// thing, but gives no way of knowing when it has happened, or whether
+    /// it went wrong.
+    ///
+    /// Fails with [Error::NoteProcessFailed] if events that passed the
+    /// [verify threads][Config::set_verify_threads] never reached nostrdb.
+    /// The database is closed either way. nostrdb doesn't report notes its
+    /// writer fails to store, so those can't be caught here.
+    ///
+    /// Fails with [Error::DbInUse], leaving the database open, if anything
+    /// else still holds it. Besides clones of this [Ndb], and other
+    /// [Ndb::new]s of the same path, these keep it open until they're
+    /// dropped: [Transaction], [OwnedTransaction], [NoteIter],
+    /// [SubscriptionSet], [BackfillSubscription], [PersistentSubscription],
+    /// [ChangeFeed], [ProfileWatch], [Timeline], `RelayPool`, a pending
+    /// [Ndb::wait_for_notes] and another database this one is
+    /// [attached][Ndb::attach_archive] to.
+    ///
+    /// [OwnedTransaction]: crate::OwnedTransaction
+    /// [SubscriptionSet]: crate::SubscriptionSet
+    pub fn close(self) -> Result<()> {
+        match Arc::try_unwrap(self.refs) {
+            Ok(mut refs) => refs.close(),
+            Err(_) => Err(Error::DbInUse),
+        }
+    }
+
     /// Ingest a relay-sent event in the form `["EVENT","subid", {"id:"...}]`
     /// This function returns immediately and doesn't provide any information on
     /// if ingestion was successful or not.
+    ///
+    /// Events dated outside [Config::set_min_created_at] and
+    /// [Config::set_max_future_drift], or with a NIP-26 delegation that
+    /// doesn't hold up, fail with [Error::Rejected] though.
     pub fn process_event(&self, json: &str) -> Result<()> {
-        // Convert the Rust string to a C-style string
-        let c_json = CString::new(json).expect("CString::new failed");
-        let c_json_ptr = c_json.as_ptr();
+        let id = json::event_id(json);
+        if self.is_stored_duplicate(id.as_ref()) {
+            return Ok(());
+        }
+        let now = first_seen::now();
+        self.refs
+            .admission
+            .check(json, now)
+            .map_err(Error::Rejected)?;
+
+        self.queue_event(json)?;
+        self.record_first_seen([json], now)
+    }
 
-        // Get the length of the string
-        let len = json.len() as libc::c_int;
+    /// Hand an admitted event to the verify pool or nostrdb
+    fn queue_event(&self, json: &str) -> Result<()> {
+        if let Some(verify) = &self.refs.verify {
+            if !verify.push(json.to_string()) {
+                return Err(Error::NoteProcessFailed);
+            }
+            return Ok(());
+        }
 
-        let res = unsafe { bindings::ndb_process_event(self.as_ptr(), c_json_ptr, len) };
+        if !self.ingest_unchecked(json.as_bytes()) {
+            return Err(Error::NoteProcessFailed);
+        }
 
-        if res == 0 {
+        Ok(())
+    }
+
+    /// Hand one event to nostrdb's ingester as it is. False if nostrdb
+    /// didn't take it.
+    ///
+    /// With [Config::set_verify_threads], nostrdb doesn't check ids and
+    /// signatures itself, so only pass events that were just verified.
+    /// This and [Ndb::ingest_lines_unchecked] are the only ways events
+    /// reach nostrdb besides the verify pool.
+    pub(crate) fn ingest_unchecked(&self, msg: &[u8]) -> bool {
+        let len = msg.len() as libc::c_int;
+        unsafe {
+            bindings::ndb_process_event(self.as_ptr(), msg.as_ptr() as *const c_char, len) != 0
+        }
+    }
+
+    /// Like [Ndb::ingest_unchecked], for newline-delimited events
+    pub(crate) fn ingest_lines_unchecked(&self, ldjson: &[u8]) -> bool {
+        unsafe {
+            bindings::ndb_process_events(
+                self.as_ptr(),
+                ldjson.as_ptr() as *const c_char,
+                ldjson.len(),
+            ) != 0
+        }
+    }
+
+    /// Like [Ndb::process_event], but turn the event away with
+    /// [Error::Rejected] if it doesn't pass `options`, eg. a NIP-70
+    /// protected event from an unauthenticated connection
+    pub fn process_event_with(&self, json: &str, options: &IngestOptions) -> Result<()> {
+        options.check(json)?;
+        self.process_event(json)
+    }
+
+    /// Like [Ndb::process_event], also remembering that the event came
+    /// from `source`, eg. a relay url, if [Config::set_record_sources] is
+    /// on. The source only counts once the note is stored. See
+    /// [Note::sources].
+    pub fn process_event_from(&self, json: &str, source: &str) -> Result<()> {
+        self.process_event(json)?;
+        if let (Some(sources), Some((id, sig))) = (&self.refs.sources, json::event_id_sig(json)) {
+            sources
+                .record(id, sig, source)
+                .map_err(|_| Error::IoError)?;
+        }
+        Ok(())
+    }
+
+    /// See [Note::sources]
+    pub(crate) fn note_sources(&self, txn: &Transaction, id: &[u8; 32]) -> Vec<String> {
+        match &self.refs.sources {
+            Some(sources) => sources.get(self, txn, id),
+            None => vec![],
+        }
+    }
+
+    /// Whether the event was stored already, going by the duplicate filter
+    fn is_stored_duplicate(&self, id: Option<&[u8; 32]>) -> bool {
+        let (duplicates, id) = match (&self.refs.duplicates, id) {
+            (Some(duplicates), Some(id)) => (duplicates, id),
+            _ => return false,
+        };
+
+        if !duplicates.maybe_contains(id) {
+            duplicates.insert(id);
+            duplicates.miss();
+            return false;
+        }
+
+        // fails if the caller has a transaction open on this thread, then
+        // nostrdb can sort it out
+        let stored =
+            Transaction::new(self).is_ok_and(|txn| self.get_notekey_by_id(&txn, id).is_ok());
+        if stored {
+            duplicates.hit();
+        } else {
+            duplicates.false_positive();
+        }
+        stored
+    }
+
+    /// How the duplicate filter has been doing, if the database was opened
+    /// with [Config::set_duplicate_filter]
+    pub fn duplicate_stats(&self) -> Option<DuplicateStats> {
+        self.refs.duplicates.as_ref().map(DuplicateFilter::stats)
+    }
+
+    /// Note when the events in these relay messages were handed to
+    /// nostrdb. They only count once they're stored.
+    fn record_first_seen<'a>(
+        &self,
+        msgs: impl IntoIterator<Item = &'a str>,
+        at: u64,
+    ) -> Result<()> {
+        match &self.refs.first_seen {
+            Some(first_seen) => first_seen
+                .record(msgs.into_iter().filter_map(json::event_id_sig), at)
+                .map_err(|_| Error::IoError),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn admission(&self) -> Admission {
+        self.refs.admission
+    }
+
+    /// See [Note::first_seen]
+    pub(crate) fn note_first_seen(&self, txn: &Transaction, id: &[u8; 32]) -> Option<u64> {
+        self.refs.first_seen.as_ref()?.get(self, txn, id)
+    }
+
+    /// Up to `limit` notes matching any of the filters, most recently
+    /// received first rather than by created_at, eg. for showing what's
+    /// new since the last visit when notes arrive with old timestamps.
+    /// Only notes first seen after `since` are included. Needs
+    /// [Config::set_record_first_seen], notes received without it are
+    /// never returned.
+    pub fn query_by_first_seen<'a>(
+        &self,
+        txn: &'a Transaction,
+        filters: &[Filter],
+        since: Option<u64>,
+        limit: usize,
+    ) -> Result<Vec<QueryResult<'a>>> {
+        let first_seen = match &self.refs.first_seen {
+            Some(first_seen) => first_seen,
+            None => return Ok(vec![]),
+        };
+
+        let mut found = vec![];
+        first_seen.newest(self, txn, since, |id, at| {
+            // keep going to the end of the second the last one came in
+            if found.len() >= limit && found.last().is_some_and(|(last, _)| *last != at) {
+                return false;
+            }
+            let result = self
+                .get_notekey_by_id(txn, id)
+                .ok()
+                .and_then(|key| QueryResult::from_key(self, txn, NoteKey::new(key)));
+            if let Some(result) = result {
+                if filters.iter().any(|filter| filter.matches(&result.note)) {
+                    found.push((at, result));
+                }
+            }
+            true
+        });
+
+        // within the same second, the note written last first
+        found.sort_by_key(|(at, result)| Reverse((*at, result.note_key)));
+        found.truncate(limit);
+        Ok(found.into_iter().map(|(_, result)| result).collect())
+    }
+
+    /// Ingest newline-delimited relay messages, eg. a relay dump. Like
+    /// [Ndb::process_event] this returns before the notes are written.
+    pub fn process_events(&self, ldjson: &str) -> Result<()> {
+        let ldjson = &*self.refs.admission.retain(ldjson);
+        let now = first_seen::now();
+
+        if let Some(verify) = &self.refs.verify {
+            let lines = ldjson.lines().filter(|line| !line.trim().is_empty());
+            if !verify.push_all(lines.map(str::to_string)) {
+                return Err(Error::NoteProcessFailed);
+            }
+            return self.record_first_seen(ldjson.lines(), now);
+        }
+
+        if !self.ingest_lines_unchecked(ldjson.as_bytes()) {
             return Err(Error::NoteProcessFailed);
         }
 
+        self.record_first_seen(ldjson.lines(), now)
+    }
+
+    /// Ingest a newline-delimited dump of relay messages straight from a
+    /// file without reading it into memory first, for multi-gigabyte relay
+    /// dumps. Returns the number of lines queued. Like
+    /// [Ndb::process_events] this returns before the notes are written.
+    /// Lines that aren't utf-8 are skipped, and fail the import with
+    /// [Error::NoteProcessFailed] once the rest is queued.
+    pub fn import_file(&self, path: impl AsRef<Path>) -> Result<usize> {
+        let file = MappedFile::open(path.as_ref())?;
+        let verify = self.refs.verify.is_some();
+        import::process_chunks(self, &file, import::IMPORT_CHUNK_SIZE, verify)
+    }
+
+    /// Walk every stored note and check that nostrdb's secondary indexes
+    /// agree with it, optionally re-verifying signatures. Useful after a
+    /// crash or a failed migration. This reads every note and does several
+    /// index lookups for each, so expect it to take a while on big
+    /// databases.
+    pub fn check_integrity(
+        &self,
+        txn: &Transaction,
+        options: &IntegrityOptions,
+    ) -> Result<IntegrityReport> {
+        integrity::check(self, txn, options)
+    }
+
+    /// Split the notes into two new databases by age, to keep the one in
+    /// everyday use small: notes created before `cutoff` are copied to
+    /// `archive_dir`, the rest to `hot_dir`. Replaceable notes, like
+    /// profiles, stay hot however old they are.
+    ///
+    /// nostrdb can't delete notes, so this database is left as it is.
+    /// Switch over to `hot_dir` once this returns, and
+    /// [attach][Ndb::attach_archive] `archive_dir` to it to look up old
+    /// notes.
+    pub fn archive_into(
+        &self,
+        cutoff: u64,
+        hot_dir: &str,
+        archive_dir: &str,
+        config: &Config,
+    ) -> Result<ArchiveStats> {
+        let canonical = |dir| fs::canonicalize(dir).unwrap_or_else(|_| PathBuf::from(dir));
+        let (hot_path, archive_path) = (canonical(hot_dir), canonical(archive_dir));
+        if hot_path == self.refs.path || archive_path == self.refs.path || hot_path == archive_path
+        {
+            return Err(Error::DbInUse);
+        }
+
+        // closing the new databases waits for their writers to finish
+        let hot = Ndb::new(hot_dir, config)?;
+        let archive = Ndb::new(archive_dir, config)?;
+        let stats = archive::split(self, cutoff, &hot, &archive)?;
+        drop(hot);
+        drop(archive);
+        Ok(stats)
+    }
+
+    /// Open the database at `archive_dir` for
+    /// [Ndb::get_archived_note_by_id], see [Ndb::archive_into].
+    ///
+    /// nostrdb can't open a database read-only, so it is opened without
+    /// migrations and this handle only ever reads from it. The archive
+    /// stays open until it is detached or this database closes. To query
+    /// it, open `archive_dir` with [Ndb::new] instead.
+    pub fn attach_archive(&self, archive_dir: &str) -> Result<()> {
+        let mut config = Config::new();
+        config.skip_migrations(true).set_ingester_threads(1);
+        let archive = Ndb::new(archive_dir, &config)?;
+        if Arc::ptr_eq(&archive.refs, &self.refs) {
+            return Err(Error::DbInUse);
+        }
+        *lock(&self.refs.archive) = Some(archive);
         Ok(())
     }
 
+    /// Close the attached archive, once any lookup in it finishes. Returns
+    /// false if none was attached.
+    pub fn detach_archive(&self) -> bool {
+        lock(&self.refs.archive).take().is_some()
+    }
+
+    /// Get a note from the attached archive, see [Ndb::attach_archive].
+    /// [Ndb::get_note_by_id] never looks there. The note is copied out,
+    /// since the archive's transaction ends here, so it has no note key.
+    pub fn get_archived_note_by_id(&self, id: &[u8; 32]) -> Result<Note<'static>> {
+        let archive = lock(&self.refs.archive).clone().ok_or(Error::NotFound)?;
+        let txn = Transaction::new(&archive)?;
+        let note = archive.get_note_by_id(&txn, id)?;
+        note.copy_owned()
+    }
+
+    /// Up to `limit` writes made after the one numbered `seq`, oldest first,
+    /// for replicating this database into another without a full resync.
+    /// Start from 0, then pass the last [OplogEntry::seq] seen.
+    pub fn changes_since<'a>(
+        &self,
+        txn: &'a Transaction,
+        seq: u64,
+        limit: usize,
+    ) -> Vec<OplogEntry<'a>> {
+        oplog::changes_since(self, txn, seq, limit)
+    }
+
+    /// Write entries exported from another database with
+    /// [OplogEntry::to_json], one per line. Returns the highest sequence
+    /// number applied, to resume from with [Ndb::changes_since] on the
+    /// source.
+    pub fn apply_changes(&self, ldjson: &str) -> Result<Option<u64>> {
+        oplog::apply(self, ldjson)
+    }
+
+    /// Write every note to `path` as nostrdb stores it. Returns the number
+    /// of notes written. Load it with [Ndb::import_snapshot].
+    ///
+    /// Only the export skips json. Importing still goes through json, so
+    /// moving a database this way isn't much faster than writing the
+    /// notes out as json and loading them with [Ndb::import_file]. See
+    /// [Ndb::import_snapshot].
+    pub fn export_snapshot(&self, txn: &Transaction, path: impl AsRef<Path>) -> Result<usize> {
+        snapshot::export(self, txn, path.as_ref())
+    }
+
+    /// Queue the notes in a snapshot made by [Ndb::export_snapshot].
+    /// Returns the number of notes queued. Fails with [Error::DecodeError]
+    /// if the file isn't a snapshot.
+    ///
+    /// This is not a binary import. nostrdb's writer only takes json, and
+    /// the bindings have no way to write packed notes directly. So every
+    /// note is turned back into json here and parsed again by the
+    /// ingester, the same as an ldjson import. The only step it saves is
+    /// parsing on the export side. Verifying signatures is most of the
+    /// cost, so open the database with [Config::skip_validation] when the
+    /// snapshot is trusted. Notes are read as they were packed, so only
+    /// import snapshots made by this library.
+    pub fn import_snapshot(&self, path: impl AsRef<Path>) -> Result<usize> {
+        snapshot::import(self, path.as_ref())
+    }
+
+    /// The schema version of the nostrdb database itself. This opens its
+    /// own read transaction, so it can't be called while this thread holds
+    /// a [Transaction].
+    pub fn db_version(&self) -> Result<u32> {
+        let version = unsafe { bindings::ndb_db_version(self.as_ptr()) };
+        u32::try_from(version).map_err(|_| Error::QueryError)
+    }
+
+    /// The version last reached by a [Migrator] on this database. 0 if none
+    /// has ever run.
+    ///
+    /// [Migrator]: crate::Migrator
+    pub fn app_version(&self) -> Result<u32> {
+        migrate::read_app_version(self.path())
+    }
+
+    /// The database directory
+    pub fn path(&self) -> &Path {
+        &self.refs.path
+    }
+
+    /// How much is stored in each of nostrdb's databases. This opens its
+    /// own read transaction, so it can't be called while this thread holds
+    /// a [Transaction].
+    pub fn stat(&self) -> Result<Stat> {
+        let mut stat = std::mem::MaybeUninit::<bindings::ndb_stat>::zeroed();
+        let res = unsafe { bindings::ndb_stat(self.as_ptr(), stat.as_mut_ptr()) };
+        if res == 0 {
+            return Err(Error::QueryError);
+        }
+
+        Ok(Stat::new(unsafe { &stat.assume_init() }))
+    }
+
+    /// Full text search over note contents, newest first. Returns the keys
+    /// of at most `limit` matching notes.
+    pub fn text_search(&self, txn: &Transaction, query: &str, limit: i32) -> Result<Vec<NoteKey>> {
+        let query = CString::new(query).map_err(|_| Error::DecodeError)?;
+        let mut config = bindings::ndb_text_search_config {
+            order: bindings::ndb_search_order_NDB_ORDER_DESCENDING,
+            limit,
+        };
+        let mut results = std::mem::MaybeUninit::<bindings::ndb_text_search_results>::zeroed();
+
+        let res = unsafe {
+            bindings::ndb_text_search(
+                txn.as_mut_ptr(),
+                query.as_ptr(),
+                results.as_mut_ptr(),
+                &mut config,
+            )
+        };
+        if res == 0 {
+            return Err(Error::QueryError);
+        }
+
+        let results = unsafe { results.assume_init() };
+        let num = (results.num_results.max(0) as usize).min(results.results.len());
+
+        // a note shows up once for every matching word
+        let mut keys: Vec<NoteKey> = vec![];
+        for result in &results.results[..num] {
+            let key = NoteKey::new(result.key.note_id);
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+        Ok(keys)
+    }
+
+    /// Run a query again and say how its results changed since `prev`, the
+    /// keys of the last results in order (eg. from [QueryDiff::keys]), so
+    /// a list view only has to update the rows that changed. Results are
+    /// newest first without duplicates, like [Ndb::query_with_options].
+    ///
+    /// [QueryDiff::keys]: crate::QueryDiff::keys
+    pub fn diff_query<'a>(
+        &self,
+        txn: &'a Transaction,
+        prev: &[NoteKey],
+        filters: &[Filter],
+        max_results: i32,
+    ) -> Result<QueryDiff<'a>> {
+        let mut results = self.query(txn, filters, max_results)?;
+        query::sort_newest_first(&mut results);
+        results.truncate(max_results.max(0) as usize);
+        Ok(query::diff(prev, results))
+    }
+
+    /// Like [Ndb::text_search], but gives up if `options` says to stop.
+    /// nostrdb runs a text search as a single call returning a bounded
+    /// number of results, so it can only be stopped before it starts.
+    pub fn text_search_with_options(
+        &self,
+        txn: &Transaction,
+        query: &str,
+        limit: i32,
+        options: &QueryOptions,
+    ) -> Result<PartialResults<NoteKey>> {
+        if options.interrupted() {
+            return Ok(PartialResults {
+                results: vec![],
+                cancelled: true,
+            });
+        }
+
+        Ok(PartialResults {
+            results: self.text_search(txn, query, limit)?,
+            cancelled: false,
+        })
+    }
+
+    /// Query with a deadline or [CancelToken], so a slow filter over a big
+    /// database can't hold up the caller. If the query is stopped, whatever
+    /// was found so far is returned with [PartialResults::cancelled] set.
+    /// Results are newest first.
+    ///
+    /// [CancelToken]: crate::CancelToken
+    pub fn query_with_options<'a>(
+        &self,
+        txn: &'a Transaction,
+        filters: &[Filter],
+        max_results: i32,
+        options: &QueryOptions,
+    ) -> PartialResults<QueryResult<'a>> {
+        query::query_with_options(self, txn, filters, max_results, options)
+    }
+
     pub fn query<'a>(
         &self,
         txn: &'a Transaction,
@@ -116,7 +938,12 @@ impl Ndb {
     }
 
     pub fn unsubscribe(&self, sub: Subscription) -> Result<()> {
-        let r = unsafe { bindings::ndb_unsubscribe(self.as_ptr(), sub.id()) };
+        let current = self
+            .subs()
+            .remove(&sub.id())
+            .map_or(sub.id(), |state| state.current);
+        self.refs.subs.notify();
+        let r = unsafe { bindings::ndb_unsubscribe(self.as_ptr(), current) };
 
         if r == 0 {
             Err(Error::SubscriptionError)
@@ -126,6 +953,410 @@ impl Ndb {
     }
 
     pub fn subscribe(&self, filters: &[Filter]) -> Result<Subscription> {
+        self.subscribe_with_config(filters, &SubscriptionConfig::default())
+    }
+
+    /// Subscribe with a custom inbox capacity and overflow policy
+    pub fn subscribe_with_config(
+        &self,
+        filters: &[Filter],
+        config: &SubscriptionConfig,
+    ) -> Result<Subscription> {
+        let mut subs = self.subs();
+        let id = self.raw_subscribe(filters)?;
+        subs.insert(id, SubState::new(id, filters.to_vec(), config.clone()));
+        Ok(Subscription::new(id))
+    }
+
+    /// Subscribe, and deliver up to `limit` matching stored notes (newest
+    /// first) before any live ones. Notes written while the stored notes
+    /// are being looked up are delivered exactly once.
+    pub fn subscribe_with_backfill(
+        &self,
+        filters: &[Filter],
+        limit: i32,
+    ) -> Result<BackfillSubscription> {
+        self.subscribe_with_stored(filters, |ndb, txn| {
+            let mut results = ndb.query(txn, filters, limit)?;
+            results.sort_by_key(|r| Reverse(r.note.created_at()));
+            Ok(results.into_iter().map(|r| r.note_key).collect())
+        })
+    }
+
+    /// Subscribe, and deliver the stored notes `stored` looks up before
+    /// any live ones. The lookup gets a snapshot begun after subscribing,
+    /// see [Ndb::with_fresh_snapshot].
+    pub(crate) fn subscribe_with_stored(
+        &self,
+        filters: &[Filter],
+        stored: impl FnOnce(&Ndb, &Transaction) -> Result<Vec<NoteKey>>,
+    ) -> Result<BackfillSubscription> {
+        // subscribe first so that nothing written during the query is missed
+        let sub = self.subscribe(filters)?;
+
+        let stored = match self.with_fresh_snapshot(stored) {
+            Ok(stored) => stored,
+            Err(err) => {
+                let _ = self.unsubscribe(sub);
+                return Err(err);
+            }
+        };
+
+        let keys: HashSet<u64> = stored.iter().map(|k| k.as_u64()).collect();
+        if let Some(state) = self.subs().get_mut(&sub.id()) {
+            state.exclude(&keys);
+        }
+
+        Ok(BackfillSubscription::new(self, sub, stored))
+    }
+
+    /// Run `lookup` against a snapshot begun now, on a thread of its own.
+    /// Stored notes for a new subscription have to come from a snapshot
+    /// newer than the subscription, which a transaction the caller already
+    /// holds is not, and this thread can't begin a second one while it
+    /// holds it.
+    fn with_fresh_snapshot<R: Send>(
+        &self,
+        lookup: impl FnOnce(&Ndb, &Transaction) -> Result<R>,
+    ) -> Result<R> {
+        let lookup = AssertSend(lookup);
+        thread::scope(|scope| {
+            scope
+                .spawn(move || {
+                    let lookup = lookup.into_inner();
+                    let txn = Transaction::new(self)?;
+                    lookup(self, &txn)
+                })
+                .join()
+                .unwrap_or(Err(Error::TransactionFailed))
+        })
+    }
+
+    /// Subscribe under a stable `name`, saved in the database directory
+    /// along with how far it got, so it can be picked up again with
+    /// [Ndb::resume_subscription] after a restart. Starts from the newest
+    /// stored note, and replaces anything saved under the same name. Fails
+    /// with [Error::DecodeError] if the name is empty or contains a tab or
+    /// newline.
+    pub fn register_subscription(
+        &self,
+        name: &str,
+        filters: &[Filter],
+    ) -> Result<PersistentSubscription> {
+        PersistentSubscription::register(self, name, filters)
+    }
+
+    /// Resubscribe to the subscription saved under `name`, delivering the
+    /// notes that matched since it last delivered one before going live.
+    /// Fails with [Error::NotFound] if nothing is saved under that name.
+    pub fn resume_subscription(&self, name: &str) -> Result<PersistentSubscription> {
+        PersistentSubscription::resume(self, name)
+    }
+
+    /// Stop saving the subscription `name`. Returns false if nothing was
+    /// saved under it. A live [PersistentSubscription] by that name stays
+    /// subscribed until it is dropped.
+    pub fn forget_subscription(&self, name: &str) -> Result<bool> {
+        self.refs.saved_subs.remove(name)
+    }
+
+    pub(crate) fn saved_subscriptions(&self) -> &SavedSubscriptions {
+        &self.refs.saved_subs
+    }
+
+    /// Subscribe to new notes matching `filters`, along with deletions of
+    /// and newer versions of the ones already stored. See [ChangeFeed].
+    pub fn subscribe_changes(&self, filters: &[Filter]) -> Result<ChangeFeed> {
+        ChangeFeed::new(self, filters)
+    }
+
+    /// A merged, deduplicated feed of the notes matching `filters` that
+    /// stays live. See [Timeline].
+    pub fn timeline(&self, filters: &[Filter]) -> Result<Timeline> {
+        Timeline::new(self, filters)
+    }
+
+    /// Watch for newer versions of a pubkey's profile, without having to
+    /// subscribe to every kind 0 note
+    pub fn watch_profile(&self, pubkey: &[u8; 32]) -> Result<ProfileWatch> {
+        ProfileWatch::new(self, pubkey)
+    }
+
+    /// Replace the filters of a live subscription. The subscription keeps
+    /// its id, and notes that were already queued on it are still returned
+    /// by the next poll.
+    pub fn update_filters(&self, sub: Subscription, filters: &[Filter]) -> Result<()> {
+        self.swap_filters(sub, filters.to_vec())
+    }
+
+    /// Add more filters to a live subscription, keeping its existing ones.
+    /// See [Ndb::update_filters].
+    pub fn add_filters(&self, sub: Subscription, filters: &[Filter]) -> Result<()> {
+        let mut combined = self
+            .subs()
+            .get(&sub.id())
+            .ok_or(Error::SubscriptionError)?
+            .filters
+            .clone();
+        combined.extend_from_slice(filters);
+        self.swap_filters(sub, combined)
+    }
+
+    /// The filters a subscription is currently matching against
+    pub fn subscription_filters(&self, sub: Subscription) -> Option<Vec<Filter>> {
+        self.subs()
+            .get(&sub.id())
+            .map(|state| state.filters.clone())
+    }
+
+    fn swap_filters(&self, sub: Subscription, filters: Vec<Filter>) -> Result<()> {
+        let mut subs = self.subs();
+        let state = subs.get_mut(&sub.id()).ok_or(Error::SubscriptionError)?;
+
+        // subscribe before tearing down the old subscription so that nothing
+        // matching the new filters is missed in between
+        let new_id = self.raw_subscribe(&filters)?;
+
+        let old_id = state.current;
+        if !state.drain(self.as_ptr(), None) {
+            self.refs.subs.wake();
+        }
+        state.swapped.extend(state.inbox.iter().copied());
+        state.swapped.extend(state.unchecked.iter().copied());
+        unsafe {
+            bindings::ndb_unsubscribe(self.as_ptr(), old_id);
+        }
+
+        state.current = new_id;
+        state.filters = filters;
+        Ok(())
+    }
+
+    /// Index notes under keys derived by `derive`, for lookups nostrdb has
+    /// no index for, eg. geohash `g` tags. Query it with
+    /// [Ndb::index_range] or [Ndb::index_prefix]. Registering a name again
+    /// replaces the index.
+    ///
+    /// The index is not saved anywhere: it lives in memory while the
+    /// database is open, and is built on the first query after every open
+    /// by reading each stored note, which takes time and memory in
+    /// proportion to the database. After that it only looks at new notes.
+    pub fn register_index<F>(&self, name: &str, derive: F)
+    where
+        F: Fn(&Note) -> Vec<Vec<u8>> + Send + Sync + 'static,
+    {
+        self.refs.indexes.register(name, Box::new(derive));
+    }
+
+    /// What was stored for a media url with [Ndb::set_media_meta]
+    pub fn media_meta(&self, url: &str) -> Option<MediaMeta> {
+        self.refs.media.get(url)
+    }
+
+    /// Remember etags, dimensions and fetch failures of profile pictures
+    /// and other media by url, so clients can keep their media cache
+    /// bookkeeping in the database directory.
+    ///
+    /// This is not stored in nostrdb or written in the same transaction as
+    /// any note: nostrdb can't hold anything but notes, so the entry is
+    /// appended to a separate `media.log` file before this returns. After a
+    /// crash it can be out of step with the notes. Fails with
+    /// [Error::DecodeError] if the url or etag contains a tab or newline.
+    pub fn set_media_meta(&self, url: &str, meta: &MediaMeta) -> Result<()> {
+        self.refs.media.set(url, meta)
+    }
+
+    /// Forget a media url. Returns false if nothing was stored for it.
+    pub fn remove_media_meta(&self, url: &str) -> Result<bool> {
+        self.refs.media.remove(url)
+    }
+
+    /// The custom index of text notes by language, keyed by ISO 639-1
+    /// code, see [Config::set_language_index]
+    #[cfg(feature = "language")]
+    pub const LANGUAGE_INDEX: &'static str = "language";
+
+    /// Up to `limit` notes matching any of the filters whose content is in
+    /// `language`, an ISO 639-1 code like `"en"`, newest first. Languages
+    /// are guessed with [detect_language]. nostrdb filters can't match on
+    /// it, so this goes through [Ndb::LANGUAGE_INDEX] instead. Fails with
+    /// [Error::NotFound] unless the database was opened with
+    /// [Config::set_language_index].
+    ///
+    /// [detect_language]: crate::detect_language
+    #[cfg(feature = "language")]
+    pub fn query_language<'a>(
+        &self,
+        txn: &'a Transaction,
+        filters: &[Filter],
+        language: &str,
+        limit: usize,
+    ) -> Result<Vec<QueryResult<'a>>> {
+        let code = language.as_bytes();
+        let mut results = self.index_range(txn, Self::LANGUAGE_INDEX, code..=code, usize::MAX)?;
+        results.retain(|result| filters.iter().any(|filter| filter.matches(&result.note)));
+        results.sort_by_key(|result| Reverse((result.note.created_at(), result.note_key)));
+        results.truncate(limit);
+        Ok(results)
+    }
+
+    /// The answer to a REQ with these filters, as the EVENT messages for
+    /// up to `max_results` stored notes, newest first, then EOSE. For
+    /// serving notes from the database like a relay would.
+    pub fn req_responses<'a>(
+        &self,
+        txn: &'a Transaction,
+        sub_id: &str,
+        filters: &[Filter],
+        max_results: i32,
+    ) -> Result<ResponseIter<'a>> {
+        let mut results = self.query(txn, filters, max_results)?;
+        let mut seen = HashSet::new();
+        results.retain(|result| seen.insert(result.note_key));
+        results.sort_by_key(|result| Reverse((result.note.created_at(), result.note_key)));
+        Ok(ResponseIter::new(sub_id, results))
+    }
+
+    /// How many stored notes match any of the filters, for answering a
+    /// NIP-45 COUNT with [count_message]. Filter limits are ignored. There
+    /// is no count index, so this pages through every match.
+    ///
+    /// [count_message]: crate::count_message
+    pub fn count(&self, txn: &Transaction, filters: &[Filter]) -> Result<u64> {
+        let mut keys = HashSet::new();
+        for filter in filters {
+            for (note_key, _) in QueryIter::new(self, txn, filter.clone()) {
+                keys.insert(note_key);
+            }
+        }
+        Ok(keys.len() as u64)
+    }
+
+    /// The custom index of NIP-26 delegated notes, keyed by delegator
+    /// pubkey, see [Config::set_delegation_index]
+    pub const DELEGATION_INDEX: &'static str = "delegation";
+
+    /// Up to `limit` notes matching any of the filters whose effective
+    /// author is `author`: notes they signed, and notes signed on their
+    /// behalf through a NIP-26 delegation. Newest first. The filters'
+    /// `authors` fields are ignored. Delegated notes are found through
+    /// [Ndb::DELEGATION_INDEX], which reads every note the first time.
+    /// Fails with [Error::NotFound] unless the database was opened with
+    /// [Config::set_delegation_index].
+    pub fn query_by_effective_author<'a>(
+        &self,
+        txn: &'a Transaction,
+        filters: &[Filter],
+        author: &[u8; 32],
+        limit: usize,
+    ) -> Result<Vec<QueryResult<'a>>> {
+        let any_author: Vec<Filter> = filters
+            .iter()
+            .map(|filter| {
+                let fields = filter
+                    .into_iter()
+                    .filter(|field| !matches!(field, FilterField::Authors(_)));
+                Filter::copy_from(fields).build()
+            })
+            .collect();
+        let signed: Vec<Filter> = any_author
+            .iter()
+            .map(|filter| Filter::copy_from(filter).authors([author]).build())
+            .collect();
+
+        let max_results = limit.min(i32::MAX as usize) as i32;
+        let mut results = self.query(txn, &signed, max_results)?;
+        let mut seen: HashSet<NoteKey> = results.iter().map(|result| result.note_key).collect();
+        let key = &author[..];
+        for result in self.index_range(txn, Self::DELEGATION_INDEX, key..=key, usize::MAX)? {
+            let matches = any_author.iter().any(|filter| filter.matches(&result.note));
+            if matches && seen.insert(result.note_key) {
+                results.push(result);
+            }
+        }
+        results.sort_by_key(|result| Reverse((result.note.created_at(), result.note_key)));
+        results.truncate(limit);
+        Ok(results)
+    }
+
+    /// Drop a custom index. Returns false if there was no index by that
+    /// name.
+    pub fn unregister_index(&self, name: &str) -> bool {
+        self.refs.indexes.unregister(name)
+    }
+
+    /// Up to `limit` notes with a key in `range` in the custom index
+    /// `name`, newest first. Fails with [Error::NotFound] if the index
+    /// isn't registered.
+    pub fn index_range<'a, 'k>(
+        &self,
+        txn: &'a Transaction,
+        name: &str,
+        range: impl RangeBounds<&'k [u8]>,
+        limit: usize,
+    ) -> Result<Vec<QueryResult<'a>>> {
+        let index = self.refs.indexes.get(name).ok_or(Error::NotFound)?;
+        Ok(index.range(self, txn, range, limit))
+    }
+
+    /// Like [Ndb::index_range], for the keys starting with `prefix`
+    pub fn index_prefix<'a>(
+        &self,
+        txn: &'a Transaction,
+        name: &str,
+        prefix: &[u8],
+        limit: usize,
+    ) -> Result<Vec<QueryResult<'a>>> {
+        let index = self.refs.indexes.get(name).ok_or(Error::NotFound)?;
+        Ok(index.prefix(self, txn, prefix, limit))
+    }
+
+    /// The custom index of single letter tags, keyed by the tag name
+    /// followed by its value, see [Config::set_tag_index]
+    pub const TAG_INDEX: &'static str = "tag";
+
+    /// Up to `limit` notes with a `tag` tag whose value starts with
+    /// `prefix`, newest first, eg. every note with an `r` tag on
+    /// `https://example.com`, or the `a` tags of one author's addressable
+    /// notes with `"30023:<pubkey>:"`. nostrdb's tag index only matches
+    /// whole values, and the bindings have no cursor to range over it, so
+    /// this goes through [Ndb::TAG_INDEX], which reads every note the
+    /// first time and is kept in memory after that. Fails with
+    /// [Error::NotFound] unless the database was opened with
+    /// [Config::set_tag_index].
+    pub fn iter_tag<'a>(
+        &self,
+        txn: &'a Transaction,
+        tag: char,
+        prefix: &str,
+        limit: usize,
+    ) -> Result<Vec<QueryResult<'a>>> {
+        let mut key = tag.to_string().into_bytes();
+        key.extend_from_slice(prefix.as_bytes());
+        self.index_prefix(txn, Self::TAG_INDEX, &key, limit)
+    }
+
+    /// The read transactions open on this database right now, oldest
+    /// first. Readers that stay open keep LMDB from reusing the pages they
+    /// can see, so the database grows while they're held. See
+    /// [Config::set_long_reader_warning] to hear about them as they happen.
+    pub fn reader_info(&self) -> Vec<ReaderInfo> {
+        self.refs.readers.info()
+    }
+
+    pub(crate) fn readers(&self) -> &Readers {
+        &self.refs.readers
+    }
+
+    pub(crate) fn subs(&self) -> MutexGuard<'_, HashMap<u64, SubState>> {
+        self.refs.subs.lock()
+    }
+
+    pub(crate) fn sub_registry(&self) -> &SubRegistry {
+        &self.refs.subs
+    }
+
+    fn raw_subscribe(&self, filters: &[Filter]) -> Result<u64> {
         unsafe {
             let mut ndb_filters: Vec<bindings::ndb_filter> =
                 filters.iter().map(|a| a.data).collect();
@@ -137,50 +1368,72 @@ impl Ndb {
             if id == 0 {
                 Err(Error::SubscriptionError)
             } else {
-                Ok(Subscription::new(id))
+                Ok(id)
             }
         }
     }
 
+    /// Take up to `max_notes` queued notes without blocking. Notes the
+    /// subscription hides are checked in a read transaction, so if this
+    /// thread already has one open they stay queued until the drain thread
+    /// has checked them. [Ndb::poll_for_results] checks them with the
+    /// caller's transaction instead.
     pub fn poll_for_notes(&self, sub: Subscription, max_notes: u32) -> Vec<NoteKey> {
-        let mut vec = vec![];
-        vec.reserve_exact(max_notes as usize);
+        self.poll_keys(sub, max_notes, None)
+    }
 
-        unsafe {
-            let res = bindings::ndb_poll_for_notes(
-                self.as_ptr(),
-                sub.id(),
-                vec.as_mut_ptr(),
-                max_notes as c_int,
-            );
-            vec.set_len(res as usize);
+    fn poll_keys(
+        &self,
+        sub: Subscription,
+        max_notes: u32,
+        txn: Option<&Transaction>,
+    ) -> Vec<NoteKey> {
+        let keys = if let Some(state) = self.subs().get_mut(&sub.id()) {
+            // don't wait for the drain thread to get to it
+            if !state.drain(self.as_ptr(), txn) {
+                self.refs.subs.wake();
+            }
+            state.take(max_notes as usize)
+        } else {
+            subscription::poll_raw(self.as_ptr(), sub.id(), max_notes)
         };
 
-        vec.into_iter().map(NoteKey::new).collect()
+        keys.into_iter().map(NoteKey::new).collect()
     }
 
-    pub async fn wait_for_notes(&self, sub_id: Subscription, max_notes: u32) -> Result<Vec<NoteKey>> {
+    /// Like [Ndb::poll_for_notes], but with the notes and their keys
+    pub fn poll_for_results<'a>(
+        &self,
+        txn: &'a Transaction,
+        sub: Subscription,
+        max_notes: u32,
+    ) -> Vec<QueryResult<'a>> {
+        let keys = self.poll_keys(sub, max_notes, Some(txn));
+        self.get_results_by_keys(txn, &keys)
+    }
+
+    /// Look up notes by key, eg. the ones returned by
+    /// [Ndb::wait_for_notes], keeping their keys alongside. Keys that
+    /// don't exist are skipped.
+    pub fn get_results_by_keys<'a>(
+        &self,
+        txn: &'a Transaction,
+        keys: &[NoteKey],
+    ) -> Vec<QueryResult<'a>> {
+        keys.iter()
+            .filter_map(|key| QueryResult::from_key(self, txn, *key))
+            .collect()
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn wait_for_notes(
+        &self,
+        sub_id: Subscription,
+        max_notes: u32,
+    ) -> Result<Vec<NoteKey>> {
         let ndb = self.clone();
-        let handle = task::spawn_blocking(move || {
-            let mut vec: Vec<u64> = vec![];
-            vec.reserve_exact(max_notes as usize);
-            let res = unsafe {
-                bindings::ndb_wait_for_notes(
-                    ndb.as_ptr(),
-                    sub_id.id(),
-                    vec.as_mut_ptr(),
-                    max_notes as c_int,
-                )
-            };
-            if res == 0 {
-                Err(Error::SubscriptionError)
-            } else {
-                unsafe {
-                    vec.set_len(res as usize);
-                };
-                Ok(vec)
-            }
-        });
+        let handle =
+            task::spawn_blocking(move || ndb.sub_registry().wait(sub_id.id(), max_notes as usize));
 
         match handle.await {
             Ok(Ok(res)) => Ok(res.into_iter().map(NoteKey::new).collect()),
@@ -189,6 +1442,95 @@ impl Ndb {
         }
     }
 
+    #[cfg(target_arch = "wasm32")]
+    pub async fn wait_for_notes(
+        &self,
+        sub_id: Subscription,
+        max_notes: u32,
+    ) -> Result<Vec<NoteKey>> {
+        let notes = subscription::WaitForNotes::new(self, vec![sub_id], max_notes as usize).await?;
+        Ok(notes
+            .into_iter()
+            .map(|(_, key)| NoteKey::new(key))
+            .collect())
+    }
+
+    /// Wait up to `timeout` for the note with this id to be stored, eg. to
+    /// fill in a placeholder once a relay delivers it. Resolves right away
+    /// if it already is. Fails with [Error::NotFound] if it hasn't arrived
+    /// in time, and waits for good if `timeout` is too long to count from
+    /// now, eg. [Duration::MAX]. Dropping the future stops waiting. Not on
+    /// wasm, which has no timer to give up with.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn await_note(&self, id: &[u8; 32], timeout: Duration) -> Result<NoteKey> {
+        let filter = Filter::new().ids([id]).build();
+        let id = *id;
+        self.await_match(filter, timeout, move |ndb, txn| {
+            ndb.get_notekey_by_id(txn, &id).ok().map(NoteKey::new)
+        })
+        .await
+    }
+
+    /// Like [Ndb::await_note], for the profile of `pubkey`. This only
+    /// waits for there to be one, see [Ndb::watch_profile] for updates.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn await_profile(&self, pubkey: &[u8; 32], timeout: Duration) -> Result<ProfileKey> {
+        let filter = Filter::new().kinds([0]).authors([pubkey]).build();
+        let pubkey = *pubkey;
+        self.await_match(filter, timeout, move |ndb, txn| {
+            ndb.get_profile_by_pubkey(txn, &pubkey).ok()?.key()
+        })
+        .await
+    }
+
+    /// Wait until `lookup` finds something, looking again whenever a note
+    /// matching `filter` is written
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn await_match<T, F>(&self, filter: Filter, timeout: Duration, lookup: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: Fn(&Ndb, &Transaction) -> Option<T> + Send + 'static,
+    {
+        // subscribe first so that nothing written before the lookup is missed
+        let sub = self.subscribe(&[filter])?;
+        let ndb = self.clone();
+
+        // dropping the future stops the task and unsubscribes
+        let cancel = subscription::CancelOnDrop::new(self);
+        let cancelled = cancel.flag();
+        let handle = task::spawn_blocking(move || {
+            let deadline = Instant::now().checked_add(timeout);
+            let found = loop {
+                let found = Transaction::new(&ndb)
+                    .ok()
+                    .and_then(|txn| lookup(&ndb, &txn));
+                if found.is_some() {
+                    break found;
+                }
+                let remaining = match deadline {
+                    Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+                    None => Duration::MAX,
+                };
+                match ndb
+                    .sub_registry()
+                    .wait_timeout(sub.id(), 1, remaining, &cancelled)
+                {
+                    Ok(keys) if !keys.is_empty() => continue,
+                    _ => break None,
+                }
+            };
+            let _ = ndb.unsubscribe(sub);
+            found.ok_or(Error::NotFound)
+        });
+
+        let res = handle.await;
+        drop(cancel);
+        match res {
+            Ok(res) => res,
+            Err(_) => Err(Error::SubscriptionError),
+        }
+    }
+
     pub fn get_profile_by_key<'a>(
         &self,
         transaction: &'a Transaction,
@@ -245,6 +1587,31 @@ impl Ndb {
         ))
     }
 
+    /// Get the profiles for many pubkeys in one call, for hydrating the
+    /// names and avatars of a whole page of notes. There is one entry in
+    /// the result for each pubkey, in the same order, with None for
+    /// profiles we don't have.
+    ///
+    /// This saves the caller a loop, not lookups. Each pubkey is still
+    /// looked up on its own, in sorted order, just like
+    /// [Ndb::get_profile_by_pubkey].
+    pub fn get_profiles_by_pubkeys<'a>(
+        &self,
+        txn: &'a Transaction,
+        pubkeys: &[[u8; 32]],
+    ) -> Vec<Option<ProfileRecord<'a>>> {
+        let mut order: Vec<usize> = (0..pubkeys.len()).collect();
+        order.sort_unstable_by_key(|&i| &pubkeys[i]);
+
+        let mut profiles: Vec<Option<ProfileRecord<'a>>> =
+            (0..pubkeys.len()).map(|_| None).collect();
+        for i in order {
+            profiles[i] = self.get_profile_by_pubkey(txn, &pubkeys[i]).ok();
+        }
+
+        profiles
+    }
+
     pub fn get_notekey_by_id(&self, txn: &Transaction, id: &[u8; 32]) -> Result<u64> {
         let res = unsafe {
             bindings::ndb_get_notekey_by_id(
@@ -319,18 +1686,449 @@ impl Ndb {
             )
         };
 
-        if note_ptr.is_null() {
-            // Handle null pointer (e.g., note not found or error occurred)
-            return Err(Error::NotFound);
-        }
+        if note_ptr.is_null() {
+            return Err(Error::NotFound);
+        }
+
+        // Convert the raw pointer to a Note instance
+        Ok(Note::new_transactional(
+            note_ptr,
+            len,
+            NoteKey::new(primkey),
+            transaction,
+        ))
+    }
+
+    /// Visit every stored note in insertion order, starting at `start_key`.
+    /// Use `NoteKey::new(1)` to start from the beginning. See [NoteIter]
+    /// for how it finds the end.
+    pub fn iter_notes<'a>(&self, txn: &'a Transaction, start_key: NoteKey) -> NoteIter<'a> {
+        NoteIter::new(self, txn, start_key)
+    }
+
+    /// Walk the notes of a single kind, newest first, optionally bounded by
+    /// created_at. Notes are fetched a page of kind queries at a time, so
+    /// walking a large range, like every zap receipt in a month, never
+    /// holds more than a page of results. See [QueryIter] for how pages
+    /// are joined up; it is not a cursor over the kind index itself.
+    pub fn iter_kind<'a>(
+        &self,
+        txn: &'a Transaction,
+        kind: u32,
+        since: Option<u64>,
+        until: Option<u64>,
+    ) -> QueryIter<'a> {
+        let builder = Filter::new().kinds([kind as u64]);
+        QueryIter::new(self, txn, time_bounded(builder, since, until))
+    }
+
+    /// Walk an author's notes of a single kind, newest first, optionally
+    /// bounded by created_at. The nostrdb this is built against has no
+    /// pubkey+kind index, so this pages through authors+kinds queries like
+    /// [Ndb::iter_kind] and is as quick as nostrdb's plan for them.
+    pub fn iter_author_kind<'a>(
+        &self,
+        txn: &'a Transaction,
+        pubkey: &[u8; 32],
+        kind: u32,
+        since: Option<u64>,
+        until: Option<u64>,
+    ) -> QueryIter<'a> {
+        let builder = Filter::new().authors([pubkey]).kinds([kind as u64]);
+        QueryIter::new(self, txn, time_bounded(builder, since, until))
+    }
+
+    /// Walk every note in a created_at range, newest first, across all
+    /// kinds. Useful for global chronological views and time-bucketed
+    /// stats.
+    pub fn iter_time_range<'a>(
+        &self,
+        txn: &'a Transaction,
+        since: Option<u64>,
+        until: Option<u64>,
+    ) -> QueryIter<'a> {
+        QueryIter::new(self, txn, time_bounded(Filter::new(), since, until))
+    }
+
+    /// A negentropy (NIP-77) sync session over the notes matching `filter`,
+    /// as they are in this transaction. Use this on the responding side, or
+    /// see [Ndb::negentropy_initiate].
+    ///
+    /// Without a limit on the filter, fails with [Error::LimitExceeded] if
+    /// more than [Ndb::MAX_NEGENTROPY_ITEMS] notes match. Syncing part of
+    /// the set would tell the other side we're missing the rest, so split
+    /// the filter up instead, eg. by time.
+    pub fn negentropy(&self, txn: &Transaction, filter: &Filter) -> Result<Negentropy> {
+        self.negentropy_capped(txn, filter, Self::MAX_NEGENTROPY_ITEMS)
+    }
+
+    fn negentropy_capped(
+        &self,
+        txn: &Transaction,
+        filter: &Filter,
+        cap: i32,
+    ) -> Result<Negentropy> {
+        let results = match filter.limit() {
+            Some(limit) => self.query(txn, std::slice::from_ref(filter), limit as i32)?,
+            None => {
+                // one more than the cap, to tell a full set from a cut one
+                let results = self.query(txn, std::slice::from_ref(filter), cap + 1)?;
+                if results.len() > cap as usize {
+                    return Err(Error::LimitExceeded(cap as usize));
+                }
+                results
+            }
+        };
+        Ok(Negentropy::new(
+            results.iter().map(|r| (r.note.created_at(), *r.note.id())),
+        ))
+    }
+
+    /// Start a negentropy sync of the notes matching `filter`. Returns the
+    /// session, and the first message to send to the relay in NEG-OPEN.
+    pub fn negentropy_initiate(
+        &self,
+        txn: &Transaction,
+        filter: &Filter,
+    ) -> Result<(Negentropy, Vec<u8>)> {
+        let mut negentropy = self.negentropy(txn, filter)?;
+        let msg = negentropy.initiate();
+        Ok((negentropy, msg))
+    }
+
+    /// Get many notes by id in one call. There is one entry in the result
+    /// for each id, in the same order, with None for notes we don't have.
+    ///
+    /// This is still one id index lookup per note, as the bindings can't
+    /// walk the index with a cursor. The lookups are made in sorted id
+    /// order, so neighbouring ids hit pages that are already cached, but
+    /// it's no faster in principle than calling [Ndb::get_note_by_id] in
+    /// a loop.
+    pub fn get_notes_by_ids<'a>(
+        &self,
+        txn: &'a Transaction,
+        ids: &[[u8; 32]],
+    ) -> Vec<Option<Note<'a>>> {
+        let mut order: Vec<usize> = (0..ids.len()).collect();
+        order.sort_unstable_by_key(|&i| &ids[i]);
+
+        let mut notes: Vec<Option<Note<'a>>> = (0..ids.len()).map(|_| None).collect();
+        for i in order {
+            notes[i] = self.get_note_by_id(txn, &ids[i]).ok();
+        }
+
+        notes
+    }
+
+    /// Get the latest version of a parameterized replaceable note by its
+    /// kind, author and `d` tag identifier.
+    pub fn get_note_by_address<'a>(
+        &self,
+        txn: &'a Transaction,
+        kind: u32,
+        pubkey: &[u8; 32],
+        identifier: &str,
+    ) -> Result<Note<'a>> {
+        let filter = Filter::new()
+            .kinds([kind as u64])
+            .authors([pubkey])
+            .tags([identifier.to_string()], 'd')
+            .build();
+
+        // there may be a few older versions lying around
+        let results = self.query(txn, &[filter], 16)?;
+        results
+            .into_iter()
+            .map(|r| r.note)
+            .max_by_key(|note| note.created_at())
+            .ok_or(Error::NotFound)
+    }
+
+    /// Look up a long-form article from an naddr mention
+    pub fn get_article_by_naddr<'a>(
+        &self,
+        txn: &'a Transaction,
+        naddr: &bindings::bech32_naddr,
+    ) -> Result<Article<'a>> {
+        let note =
+            self.get_note_by_address(txn, Article::KIND, naddr.pubkey(), naddr.identifier())?;
+        Article::new(note).ok_or(Error::NotFound)
+    }
+
+    /// An `nprofile` share link for `pubkey`, with up to
+    /// [Ndb::MAX_SHARE_RELAYS] relays where recipients can find it: the
+    /// write relays of the author's NIP-65 relay list, then any recorded
+    /// [sources] of their profile.
+    ///
+    /// [sources]: crate::Config::set_record_sources
+    pub fn nprofile(&self, txn: &Transaction, pubkey: &[u8; 32]) -> Result<String> {
+        let mut relays = self.write_relays_of(txn, pubkey)?;
+        let filter = Filter::new()
+            .kinds([Kind::Metadata.into()])
+            .authors([pubkey])
+            .build();
+        let profile = self
+            .query(txn, &[filter], 16)?
+            .into_iter()
+            .map(|r| r.note)
+            .max_by_key(|note| note.created_at());
+        if let Some(profile) = profile {
+            push_relays(&mut relays, self.note_sources(txn, profile.id()));
+        }
+        relays.truncate(Self::MAX_SHARE_RELAYS);
+
+        let relays: Vec<&str> = relays.iter().map(String::as_str).collect();
+        Ok(nip19::encode_nprofile(pubkey, &relays))
+    }
+
+    /// An `nevent` share link for a stored note, with its author, kind and
+    /// up to [Ndb::MAX_SHARE_RELAYS] relays: where the note was seen, if
+    /// [sources] are recorded, then its author's NIP-65 write relays.
+    ///
+    /// [sources]: crate::Config::set_record_sources
+    pub fn nevent(&self, txn: &Transaction, id: &[u8; 32]) -> Result<String> {
+        let note = self.get_note_by_id(txn, id)?;
+        let mut relays = vec![];
+        push_relays(&mut relays, self.note_sources(txn, id));
+        let written = self.write_relays_of(txn, note.pubkey())?;
+        push_relays(&mut relays, written);
+        relays.truncate(Self::MAX_SHARE_RELAYS);
+
+        let relays: Vec<&str> = relays.iter().map(String::as_str).collect();
+        Ok(nip19::encode_nevent(
+            id,
+            &relays,
+            Some(note.pubkey()),
+            Some(note.kind()),
+        ))
+    }
+
+    /// The write relays in an author's latest NIP-65 relay list
+    fn write_relays_of(&self, txn: &Transaction, pubkey: &[u8; 32]) -> Result<Vec<String>> {
+        let filter = Filter::new()
+            .kinds([Kind::RelayList.into()])
+            .authors([pubkey])
+            .build();
+        let results = self.query(txn, &[filter], 16)?;
+        let relays = results
+            .iter()
+            .map(|r| &r.note)
+            .max_by_key(|note| note.created_at())
+            .map(nip65::write_relays)
+            .unwrap_or_default();
+        Ok(relays.into_iter().map(str::to_string).collect())
+    }
+
+    /// Get an author's NIP-51 list of the given kind. `identifier` is the
+    /// `d` tag of a set, and is ignored for the standard lists like
+    /// bookmarks.
+    pub fn get_list<'a>(
+        &self,
+        txn: &'a Transaction,
+        kind: Kind,
+        pubkey: &[u8; 32],
+        identifier: &str,
+    ) -> Result<List<'a>> {
+        let note = if kind.is_addressable() {
+            self.get_note_by_address(txn, kind.as_u32(), pubkey, identifier)?
+        } else {
+            let filter = Filter::new().kinds([kind.into()]).authors([pubkey]).build();
+            let results = self.query(txn, &[filter], 16)?;
+            results
+                .into_iter()
+                .map(|r| r.note)
+                .max_by_key(|note| note.created_at())
+                .ok_or(Error::NotFound)?
+        };
+        List::new(note).ok_or(Error::NotFound)
+    }
+
+    /// The latest version of each of an author's lists of the given kind,
+    /// newest first
+    pub fn lists_of<'a>(
+        &self,
+        txn: &'a Transaction,
+        pubkey: &[u8; 32],
+        kind: Kind,
+    ) -> Result<Vec<List<'a>>> {
+        let filter = Filter::new().kinds([kind.into()]).authors([pubkey]).build();
+        let notes = QueryIter::new(self, txn, filter).try_collect()?;
+        Ok(nip51::latest_versions(notes))
+    }
+
+    /// Every author's latest set of the given kind with this `d` tag,
+    /// newest first, eg. to find curated feeds by name
+    pub fn lists_by_identifier<'a>(
+        &self,
+        txn: &'a Transaction,
+        kind: Kind,
+        identifier: &str,
+    ) -> Result<Vec<List<'a>>> {
+        let filter = Filter::new()
+            .kinds([kind.into()])
+            .tags([identifier.to_string()], 'd')
+            .build();
+        let notes = QueryIter::new(self, txn, filter).try_collect()?;
+        Ok(nip51::latest_versions(notes))
+    }
+
+    /// The NIP-84 highlights taken from a note, article or web page,
+    /// newest first
+    pub fn highlights_for<'a>(
+        &self,
+        txn: &'a Transaction,
+        source: HighlightSource<'_>,
+    ) -> Result<Vec<Highlight<'a>>> {
+        let filter = Filter::new().kinds([Kind::Highlight.into()]);
+        let filter = match source {
+            HighlightSource::Note(id) => filter.event(id),
+            HighlightSource::Address(coord) => filter.tags([coord.to_string()], 'a'),
+            HighlightSource::Url(url) => filter.tags([url.to_string()], 'r'),
+        }
+        .build();
+
+        let notes = QueryIter::new(self, txn, filter).try_collect()?;
+        Ok(notes.into_iter().filter_map(Highlight::new).collect())
+    }
+
+    /// How much a NIP-75 zap goal has raised so far, in millisats: the
+    /// invoice amounts of the stored zap receipts tagging it, up to its
+    /// `closed_at` time
+    pub fn zap_goal_progress(&self, txn: &Transaction, goal_id: &[u8; 32]) -> Result<u64> {
+        let goal = ZapGoal::new(self.get_note_by_id(txn, goal_id)?).ok_or(Error::NotFound)?;
+        let builder = Filter::new()
+            .kinds([Kind::ZapReceipt.into()])
+            .event(goal_id);
+        let mut receipts = QueryIter::new(self, txn, time_bounded(builder, None, goal.closed_at()));
+
+        let total = receipts
+            .by_ref()
+            .filter_map(|(_, note)| ZapReceipt::new(note)?.amount_msats())
+            .sum();
+        receipts.take_error()?;
+        Ok(total)
+    }
+
+    /// Counters for a pubkey: notes stored, latest activity, reactions
+    /// and zaps received. They are kept up to date as notes are written
+    /// instead of being aggregated per call, and start at zero for
+    /// pubkeys nothing is known about.
+    pub fn author_stats(&self, txn: &Transaction, pubkey: &[u8; 32]) -> AuthorStats {
+        self.refs.author_stats.get(self, txn, pubkey)
+    }
+
+    /// Get the NIP-32 labels pointing at a note or pubkey, grouped by
+    /// namespace
+    pub fn labels_for<'a>(
+        &self,
+        txn: &'a Transaction,
+        target: LabelTarget<'_>,
+    ) -> Result<LabelsByNamespace<'a>> {
+        let filter = Filter::new().kinds([nip32::LABEL_KIND as u64]);
+        let filter = match target {
+            LabelTarget::Note(id) => filter.event(id),
+            LabelTarget::Pubkey(pk) => filter.pubkeys([pk]),
+        }
+        .build();
+
+        let notes = QueryIter::new(self, txn, filter).try_collect()?;
+        Ok(nip32::group_labels(
+            notes.iter().flat_map(nip32::note_labels),
+        ))
+    }
+
+    /// Notes p-tagging `pubkey`, newest first: replies, mentions,
+    /// reactions, reposts and zaps. Notes written by `pubkey` itself are
+    /// left out. An empty `kinds` means [Ndb::NOTIFICATION_KINDS]. At most
+    /// `limit` notes are returned.
+    pub fn notifications<'a>(
+        &self,
+        txn: &'a Transaction,
+        pubkey: &[u8; 32],
+        since: Option<u64>,
+        kinds: &[Kind],
+        limit: usize,
+    ) -> Result<Vec<QueryResult<'a>>> {
+        let kinds = if kinds.is_empty() {
+            &Self::NOTIFICATION_KINDS[..]
+        } else {
+            kinds
+        };
+
+        // one filter, so nostrdb walks the p tag index newest first
+        let builder = Filter::new()
+            .pubkeys([pubkey])
+            .kinds(kinds.iter().map(|kind| u64::from(*kind)));
+        let filter = time_bounded(builder, since, None);
+
+        let mut notes = QueryIter::new(self, txn, filter);
+        let found = notes
+            .by_ref()
+            .filter(|(_, note)| note.pubkey() != pubkey)
+            .take(limit)
+            .map(|(note_key, note)| QueryResult {
+                note_size: note.size() as u64,
+                note_key,
+                note,
+            })
+            .collect();
+        notes.take_error()?;
+        Ok(found)
+    }
+
+    /// The pubkeys in the latest stored contact list (kind 3) of `pubkey`,
+    /// sorted. Empty if there isn't one.
+    ///
+    /// The follow graph is built from the stored contact lists the first
+    /// time it's used, and kept up to date as new lists are written after
+    /// that, so lookups don't scan every contact list.
+    pub fn follows_of(&self, txn: &Transaction, pubkey: &[u8; 32]) -> Result<Vec<[u8; 32]>> {
+        self.refs.follows.with(self, txn, |graph| {
+            follows::sorted(graph.follows(pubkey).into_iter().flatten())
+        })
+    }
 
-        // Convert the raw pointer to a Note instance
-        Ok(Note::new_transactional(
-            note_ptr,
-            len,
-            NoteKey::new(primkey),
-            transaction,
-        ))
+    /// The authors whose latest contact list includes `pubkey`, sorted. See
+    /// [Ndb::follows_of].
+    pub fn followers_of(&self, txn: &Transaction, pubkey: &[u8; 32]) -> Result<Vec<[u8; 32]>> {
+        self.refs.follows.with(self, txn, |graph| {
+            follows::sorted(graph.followers(pubkey).into_iter().flatten())
+        })
+    }
+
+    /// The pubkeys followed by both `a` and `b`, sorted. See
+    /// [Ndb::follows_of].
+    pub fn follow_intersection(
+        &self,
+        txn: &Transaction,
+        a: &[u8; 32],
+        b: &[u8; 32],
+    ) -> Result<Vec<[u8; 32]>> {
+        self.refs.follows.with(self, txn, |graph| {
+            match (graph.follows(a), graph.follows(b)) {
+                (Some(a), Some(b)) => follows::sorted(a.intersection(b)),
+                _ => vec![],
+            }
+        })
+    }
+
+    /// Score how much `root` can be expected to trust the pubkeys around
+    /// it, from how far away they are in the follow graph and how many
+    /// paths lead to them. See [Ndb::follows_of] for how the graph is kept.
+    ///
+    /// Scores are cached, and only recomputed once a contact list they
+    /// depend on changes. Use [FilterBuilder::min_trust] to restrict a
+    /// query or subscription to the root's extended network.
+    #[cfg(feature = "wot")]
+    pub fn trust_scores(
+        &self,
+        txn: &Transaction,
+        root: &[u8; 32],
+        options: &crate::TrustOptions,
+    ) -> Result<Arc<crate::TrustScores>> {
+        self.refs
+            .trust
+            .scores(self, &self.refs.follows, txn, root, options)
     }
 
     /// Get the underlying pointer to the context in C
@@ -339,11 +2137,49 @@ impl Ndb {
     }
 }
 
+/// Lets [Ndb::with_fresh_snapshot] hand a lookup that borrows filters to
+/// its thread
+struct AssertSend<F>(F);
+
+// the lookup only runs while the thread that made it is blocked waiting
+// for it, so nothing it borrows is used from two threads at once
+unsafe impl<F> Send for AssertSend<F> {}
+
+impl<F> AssertSend<F> {
+    fn into_inner(self) -> F {
+        self.0
+    }
+}
+
+fn time_bounded(mut builder: FilterBuilder, since: Option<u64>, until: Option<u64>) -> Filter {
+    if let Some(since) = since {
+        builder = builder.since(since);
+    }
+    if let Some(until) = until {
+        builder = builder.until(until);
+    }
+    builder.build()
+}
+
+/// Add relays to a share link's list, skipping ones it already has
+fn push_relays(relays: &mut Vec<String>, more: Vec<String>) {
+    for relay in more {
+        let relay = match relay_hints::normalize(&relay) {
+            Some(relay) => relay.to_string(),
+            None => continue,
+        };
+        if !relays.contains(&relay) {
+            relays.push(relay);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::config::Config;
     use crate::test_util;
+    use crate::{NoteBuilder, Rejection, SubscriptionEvent};
 
     #[test]
     fn ndb_init_works() {
@@ -400,6 +2236,487 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn await_note_works() {
+        let db = "target/testdbs/await_note";
+        test_util::cleanup_db(db);
+        let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+
+        let id = crate::util::decode_hex32(
+            "702555e52e82cc24ad517ba78c21879f6e47a7c0692b9b20df147916ae8731a3",
+        )
+        .expect("id");
+        let pubkey = crate::util::decode_hex32(
+            "32bf915904bfde2d136ba45dde32c88f4aca863783999faea2e847a8fafd2f15",
+        )
+        .expect("pubkey");
+        assert_eq!(
+            ndb.await_profile(&pubkey, Duration::from_millis(50)).await,
+            Err(Error::NotFound)
+        );
+
+        let waiter = ndb.await_note(&id, Duration::from_secs(10));
+        ndb.process_event(&test_util::hello_event())
+            .expect("process ok");
+        let key = waiter.await.expect("arrived");
+
+        // already stored
+        let again = ndb.await_note(&id, Duration::ZERO).await;
+        assert_eq!(again, Ok(key));
+        assert_eq!(ndb.await_note(&id, Duration::MAX).await, Ok(key));
+
+        // dropping a waiter that would wait forever unsubscribes it
+        let waiter = ndb.await_note(&[9; 32], Duration::MAX);
+        tokio::select! {
+            biased;
+            _ = waiter => panic!("never stored"),
+            _ = std::future::ready(()) => {}
+        }
+        let mut tries = 0;
+        while ndb.subscription_count() != 0 {
+            tries += 1;
+            assert!(tries < 500, "still subscribed");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[tokio::test]
+    async fn update_filters_works() {
+        let db = "target/testdbs/update_filters";
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+
+            let kind1 = Filter::new().kinds(vec![1]).build();
+            let kind0 = Filter::new().kinds(vec![0]).build();
+
+            let sub = ndb.subscribe(std::slice::from_ref(&kind1)).expect("sub_id");
+            let other = ndb.subscribe(&[kind1]).expect("sub_id");
+            let waiter = ndb.wait_for_notes(other, 1);
+            ndb.process_event(&test_util::hello_event())
+                .expect("process ok");
+            assert_eq!(waiter.await.expect("await ok"), vec![NoteKey::new(1)]);
+
+            // the note queued before the update is kept
+            ndb.update_filters(sub, &[kind0]).expect("update");
+            assert_eq!(ndb.subscription_count(), 2);
+            assert_eq!(ndb.poll_for_notes(sub, 10), vec![NoteKey::new(1)]);
+            assert!(ndb.poll_for_notes(sub, 10).is_empty());
+
+            ndb.add_filters(sub, &[Filter::new().kinds(vec![1]).build()])
+                .expect("add");
+            let filters = ndb.subscription_filters(sub).expect("filters");
+            assert_eq!(filters.len(), 2);
+
+            ndb.unsubscribe(sub).expect("unsub");
+            assert_eq!(ndb.subscription_count(), 1);
+            assert!(ndb.update_filters(sub, &filters).is_err());
+        }
+    }
+
+    #[test]
+    fn subscribe_with_backfill_works() {
+        let db = "target/testdbs/subscribe_with_backfill";
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            ndb.process_event(&test_util::hello_event())
+                .expect("process ok");
+        }
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let filter = Filter::new().kinds(vec![1]).build();
+            // holding a transaction on this thread doesn't get in the way
+            let txn = Transaction::new(&ndb).expect("txn");
+            let mut sub = ndb.subscribe_with_backfill(&[filter], 10).expect("sub");
+            drop(txn);
+
+            assert!(!sub.is_live());
+            assert_eq!(
+                sub.poll_for_notes(10),
+                vec![
+                    SubscriptionEvent::Note(NoteKey::new(1)),
+                    SubscriptionEvent::EndOfStoredEvents
+                ]
+            );
+            assert!(sub.is_live());
+            assert!(sub.poll_for_notes(10).is_empty());
+
+            drop(sub);
+            assert_eq!(ndb.subscription_count(), 0);
+        }
+    }
+
+    #[test]
+    fn open_twice_shares_db() {
+        let db = "target/testdbs/open_twice";
+        test_util::cleanup_db(db);
+
+        {
+            let a = Ndb::new(db, &Config::new()).expect("ndb");
+            let b = Ndb::new(db, &Config::new()).expect("second open");
+
+            let filter = Filter::new().kinds(vec![1]).build();
+            let sub = a.subscribe(&[filter]).expect("sub");
+            assert_eq!(b.subscription_count(), 1);
+            b.unsubscribe(sub).expect("unsub");
+            assert_eq!(a.subscription_count(), 0);
+        }
+
+        // and it can be opened again once every handle is gone
+        Ndb::new(db, &Config::new()).expect("reopen");
+    }
+
+    #[test]
+    fn concurrent_opens_share_db() {
+        let db = "target/testdbs/concurrent_opens";
+        let other = "target/testdbs/concurrent_opens_other";
+        test_util::cleanup_db(db);
+        test_util::cleanup_db(other);
+
+        // the threads racing on one path all get the same database, while
+        // another path opens and closes alongside them
+        let opened: Vec<Ndb> = thread::scope(|s| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| s.spawn(|| Ndb::new(db, &Config::new()).expect("ndb")))
+                .collect();
+            for _ in 0..4 {
+                Ndb::new(other, &Config::new()).expect("other");
+            }
+            handles
+                .into_iter()
+                .map(|h| h.join().expect("open"))
+                .collect()
+        });
+        assert!(opened
+            .iter()
+            .all(|ndb| Arc::ptr_eq(&ndb.refs, &opened[0].refs)));
+
+        drop(opened);
+        Ndb::new(db, &Config::new()).expect("reopen");
+    }
+
+    #[test]
+    fn close_works() {
+        let db = "target/testdbs/close";
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let other = ndb.clone();
+            assert_eq!(other.close(), Err(Error::DbInUse));
+
+            ndb.process_event(&test_util::hello_event())
+                .expect("process ok");
+            ndb.close().expect("close");
+        }
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let txn = Transaction::new(&ndb).expect("txn");
+            let id: [u8; 32] =
+                hex::decode("702555e52e82cc24ad517ba78c21879f6e47a7c0692b9b20df147916ae8731a3")
+                    .expect("hex")
+                    .try_into()
+                    .expect("id");
+            assert!(ndb.get_note_by_id(&txn, &id).is_ok());
+        }
+    }
+
+    #[test]
+    fn negentropy_initiate_works() {
+        let db = "target/testdbs/negentropy";
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            ndb.process_event(&test_util::hello_event())
+                .expect("process ok");
+        }
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let txn = Transaction::new(&ndb).expect("txn");
+            let filter = Filter::new().kinds(vec![1]).build();
+            let (mut neg, msg) = ndb.negentropy_initiate(&txn, &filter).expect("neg");
+            assert_eq!(neg.len(), 1);
+
+            // a set that doesn't fit is an error, not a partial snapshot
+            assert_eq!(
+                ndb.negentropy_capped(&txn, &filter, 0).err(),
+                Some(Error::LimitExceeded(0))
+            );
+            assert!(ndb.negentropy_capped(&txn, &filter, 1).is_ok());
+
+            // a relay that has nothing
+            let mut relay = Negentropy::new(vec![]);
+            let reply = relay.reconcile(&msg).expect("reply");
+            let res = neg.reconcile(&reply.message.unwrap()).expect("reconcile");
+            let id: [u8; 32] =
+                hex::decode("702555e52e82cc24ad517ba78c21879f6e47a7c0692b9b20df147916ae8731a3")
+                    .expect("hex")
+                    .try_into()
+                    .expect("id");
+            assert_eq!(res.have_ids, vec![id]);
+            assert!(res.need_ids.is_empty());
+            assert!(res.message.is_none());
+        }
+    }
+
+    #[test]
+    fn stat_and_text_search_work() {
+        let db = "target/testdbs/stat_text_search";
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            ndb.process_events(&format!("{}\n", test_util::hello_event()))
+                .expect("process ok");
+        }
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let stat = ndb.stat().expect("stat");
+            assert_eq!(stat.notes(), 1);
+
+            let txn = Transaction::new(&ndb).expect("txn");
+            let keys = ndb.text_search(&txn, "hello", 10).expect("search");
+            assert_eq!(keys.len(), 1);
+            let note = ndb.get_note_by_key(&txn, keys[0]).expect("note");
+            assert_eq!(note.content(), "hello, world");
+            assert!(ndb
+                .text_search(&txn, "goodbye", 10)
+                .expect("search")
+                .is_empty());
+        }
+    }
+
+    #[test]
+    fn verify_pool_works() {
+        let db = "target/testdbs/verify_pool";
+        test_util::cleanup_db(db);
+
+        let event = &test_util::hello_event();
+
+        {
+            let mut config = Config::new();
+            config.set_verify_threads(2).set_verify_batch_size(4);
+            let ndb = Ndb::new(db, &config).expect("ndb");
+            ndb.process_event(event).expect("process ok");
+            ndb.process_event(&event.replace("31a3", "31a4"))
+                .expect("process ok");
+        }
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let txn = Transaction::new(&ndb).expect("txn");
+            let filter = Filter::new().kinds(vec![1]).build();
+            let res = ndb.query(&txn, &[filter], 10).expect("query");
+            assert_eq!(res.len(), 1);
+            assert_eq!(res[0].note.content(), "hello, world");
+        }
+    }
+
+    /// Ingest a valid and a forged event with `ingest`, on a database with
+    /// a verify pool, and check that only the valid one is stored
+    fn check_verify_pool_covers(name: &str, ingest: impl FnOnce(&Ndb, &str, &str)) {
+        let db = format!("target/testdbs/verify_pool_{name}");
+        test_util::cleanup_db(&db);
+
+        let event = |content: &str| {
+            let note = NoteBuilder::new()
+                .kind(1)
+                .content(content)
+                .sign(&[7u8; 32])
+                .build()
+                .expect("note");
+            format!("[\"EVENT\",\"v\",{}]", note.json().expect("json"))
+        };
+        let valid = event(&format!("{name} ok"));
+        let forged = event(&format!("{name} forged")).replace("forged", "Forged");
+
+        {
+            let mut config = Config::new();
+            config.set_verify_threads(1);
+            let ndb = Ndb::new(&db, &config).expect("ndb");
+            ingest(&ndb, &valid, &forged);
+            ndb.close().expect("close");
+        }
+
+        let ndb = Ndb::new(&db, &Config::new()).expect("ndb");
+        let txn = Transaction::new(&ndb).expect("txn");
+        let filter = Filter::new().kinds([1]).build();
+        let res = ndb.query(&txn, &[filter], 10).expect("query");
+        let contents: Vec<&str> = res.iter().map(|r| r.note.content()).collect();
+        assert_eq!(contents, [format!("{name} ok")]);
+    }
+
+    #[test]
+    fn verify_pool_covers_process_event() {
+        check_verify_pool_covers("process_event", |ndb, valid, forged| {
+            ndb.process_event(valid).expect("process ok");
+            ndb.process_event(forged).expect("process ok");
+        });
+    }
+
+    #[test]
+    fn verify_pool_covers_process_event_with() {
+        check_verify_pool_covers("process_event_with", |ndb, valid, forged| {
+            let options = IngestOptions::new();
+            ndb.process_event_with(valid, &options).expect("process ok");
+            ndb.process_event_with(forged, &options)
+                .expect("process ok");
+        });
+    }
+
+    #[test]
+    fn verify_pool_covers_process_event_from() {
+        check_verify_pool_covers("process_event_from", |ndb, valid, forged| {
+            ndb.process_event_from(valid, "wss://a")
+                .expect("process ok");
+            ndb.process_event_from(forged, "wss://a")
+                .expect("process ok");
+        });
+    }
+
+    #[test]
+    fn verify_pool_covers_process_events() {
+        check_verify_pool_covers("process_events", |ndb, valid, forged| {
+            ndb.process_events(&format!("{valid}\n{forged}\n"))
+                .expect("process ok");
+        });
+    }
+
+    #[test]
+    fn verify_pool_covers_apply_changes() {
+        check_verify_pool_covers("apply_changes", |ndb, valid, forged| {
+            ndb.apply_changes(&format!("{valid}\n{forged}\n"))
+                .expect("apply");
+        });
+    }
+
+    #[test]
+    fn verify_pool_covers_import_file() {
+        check_verify_pool_covers("import_file", |ndb, valid, forged| {
+            let path = "target/testdbs/verify_pool_import_file.jsonl";
+            fs::write(path, format!("{valid}\n{forged}\n")).expect("write dump");
+            ndb.import_file(path).expect("import");
+            let _ = fs::remove_file(path);
+        });
+    }
+
+    #[test]
+    fn verify_pool_covers_import_snapshot() {
+        check_verify_pool_covers("import_snapshot", |ndb, valid, forged| {
+            let src = "target/testdbs/verify_pool_snapshot_src";
+            let path = "target/testdbs/verify_pool_snapshot.bin";
+            test_util::cleanup_db(src);
+
+            // store the note before it was tampered with, then tamper with
+            // the snapshot instead
+            let original = forged.replace("Forged", "forged");
+            {
+                let src = Ndb::new(src, &Config::new()).expect("ndb");
+                src.process_events(&format!("{valid}\n{original}\n"))
+                    .expect("process ok");
+                src.close().expect("close");
+            }
+            {
+                let src = Ndb::new(src, &Config::new()).expect("ndb");
+                let txn = Transaction::new(&src).expect("txn");
+                assert_eq!(src.export_snapshot(&txn, path).expect("export"), 2);
+            }
+
+            let mut snapshot = fs::read(path).expect("read snapshot");
+            let at = snapshot
+                .windows(6)
+                .position(|w| w == b"forged")
+                .expect("content");
+            snapshot[at] = b'F';
+            fs::write(path, snapshot).expect("write snapshot");
+
+            assert_eq!(ndb.import_snapshot(path).expect("import"), 2);
+            let _ = fs::remove_file(path);
+        });
+    }
+
+    #[test]
+    fn bounded_verify_queue_works() {
+        let db = "target/testdbs/bounded_verify_queue";
+        test_util::cleanup_db(db);
+
+        let events: Vec<String> = (0..64)
+            .map(|i| {
+                let note = NoteBuilder::new()
+                    .kind(1)
+                    .content(&format!("queued {i}"))
+                    .created_at(1_700_000_000 + i)
+                    .sign(&[5u8; 32])
+                    .build()
+                    .expect("note");
+                note.json().expect("json")
+            })
+            .collect();
+
+        {
+            let mut config = Config::new();
+            config
+                .set_verify_threads(1)
+                .set_verify_batch_size(1)
+                .set_verify_queue_depth(1);
+            assert_eq!(config.verify_queue_depth(), 1);
+
+            // many times what fits in the queue, so pushes have to wait on
+            // the verify thread instead of dropping or deadlocking
+            let ndb = Ndb::new(db, &config).expect("ndb");
+            ndb.process_events(&events.join("\n")).expect("process ok");
+        }
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let txn = Transaction::new(&ndb).expect("txn");
+            let filter = Filter::new().kinds(vec![1]).build();
+            let res = ndb.query(&txn, &[filter], 100).expect("query");
+            assert_eq!(res.len(), events.len());
+        }
+    }
+
+    #[test]
+    fn max_event_size_works() {
+        let db = "target/testdbs/max_event_size";
+        test_util::cleanup_db(db);
+
+        let event = |content: &str| {
+            let note = NoteBuilder::new()
+                .kind(1)
+                .content(content)
+                .sign(&[6u8; 32])
+                .build()
+                .expect("note");
+            note.json().expect("json")
+        };
+        let small = event("short");
+        let big = event(&"long ".repeat(1000));
+
+        let mut config = Config::new();
+        config.set_max_event_size(small.len());
+        assert_eq!(config.max_event_size(), small.len());
+        let ndb = Ndb::new(db, &config).expect("ndb");
+
+        assert_eq!(
+            ndb.process_event(&big),
+            Err(Error::Rejected(Rejection::TooLarge))
+        );
+        assert_eq!(ndb.process_event(&small), Ok(()));
+        assert_eq!(
+            ndb.admission().retain(&[big, small.clone()].join("\n")),
+            small + "\n"
+        );
+    }
+
     #[test]
     fn poll_note_works() {
         let db = "target/testdbs/poll";
@@ -423,6 +2740,29 @@ mod tests {
         }
     }
 
+    #[test]
+    fn poll_for_results_works() {
+        let db = "target/testdbs/poll_results";
+        test_util::cleanup_db(db);
+
+        let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+        let filter = Filter::new().kinds(vec![1]).build();
+        let sub = ndb.subscribe(&[filter]).expect("sub_id");
+        ndb.process_event(&test_util::hello_event())
+            .expect("process ok");
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        let txn = Transaction::new(&ndb).expect("txn");
+        let res = ndb.poll_for_results(&txn, sub, 10);
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].note_key, NoteKey::new(1));
+        assert_eq!(res[0].created_at(), 1702675561);
+        assert_eq!(res[0].note.content(), "hello, world");
+
+        let missing = ndb.get_results_by_keys(&txn, &[NoteKey::new(1), NoteKey::new(100)]);
+        assert_eq!(missing.len(), 1);
+    }
+
     #[test]
     fn process_event_works() {
         let db = "target/testdbs/event_works";
@@ -444,4 +2784,111 @@ mod tests {
             assert_eq!(note.kind(), 1);
         }
     }
+
+    #[test]
+    fn iter_notes_works() {
+        let db = "target/testdbs/iter_notes";
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            ndb.process_event(&test_util::hello_event())
+                .expect("process ok");
+        }
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let txn = Transaction::new(&ndb).expect("txn");
+            let notes: Vec<(NoteKey, Note)> = ndb.iter_notes(&txn, NoteKey::new(1)).collect();
+            assert_eq!(notes.len(), 1);
+            assert_eq!(notes[0].0, NoteKey::new(1));
+            assert_eq!(notes[0].1.content(), "hello, world");
+            assert_eq!(ndb.iter_notes(&txn, NoteKey::new(2)).count(), 0);
+        }
+    }
+
+    #[test]
+    fn notifications_works() {
+        let db = "target/testdbs/notifications";
+        test_util::cleanup_db(db);
+
+        let me = [1u8; 32];
+        let them = [2u8; 32];
+        let note = |seckey: &[u8; 32], kind: u32, created_at: u64, p: Option<&[u8; 32]>| {
+            let builder = NoteBuilder::new()
+                .kind(kind)
+                .content("+")
+                .created_at(created_at);
+            let builder = match p {
+                Some(p) => builder.start_tag().tag_str("p").tag_str(&hex::encode(p)),
+                None => builder,
+            };
+            builder.sign(seckey).build().expect("note")
+        };
+
+        let my_pubkey = *note(&me, 1, 1, None).pubkey();
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            for note in [
+                note(&them, 1, 10, Some(&my_pubkey)),
+                note(&them, 7, 20, Some(&my_pubkey)),
+                note(&them, 1, 30, None),
+                note(&me, 1, 40, Some(&my_pubkey)),
+                note(&them, 1, 5, Some(&my_pubkey)),
+            ] {
+                let json = note.json().expect("json");
+                ndb.process_event(&format!("[\"EVENT\",\"n\",{json}]"))
+                    .expect("process ok");
+            }
+        }
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let txn = Transaction::new(&ndb).expect("txn");
+            let notes = ndb
+                .notifications(&txn, &my_pubkey, Some(6), &[], 10)
+                .expect("notifications");
+            let created: Vec<u64> = notes.iter().map(|r| r.note.created_at()).collect();
+            assert_eq!(created, vec![20, 10]);
+
+            let newest = ndb
+                .notifications(&txn, &my_pubkey, Some(6), &[], 1)
+                .expect("notifications");
+            assert_eq!(newest.len(), 1);
+            assert_eq!(newest[0].note.created_at(), 20);
+
+            let notes = ndb
+                .notifications(&txn, &my_pubkey, None, &[Kind::Reaction], 10)
+                .expect("notifications");
+            assert_eq!(notes.len(), 1);
+            assert_eq!(notes[0].note.kind(), 7);
+        }
+    }
+
+    #[test]
+    fn get_notes_by_ids_works() {
+        let db = "target/testdbs/notes_by_ids";
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            ndb.process_event(&test_util::hello_event())
+                .expect("process ok");
+        }
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let id: [u8; 32] =
+                hex::decode("702555e52e82cc24ad517ba78c21879f6e47a7c0692b9b20df147916ae8731a3")
+                    .expect("hex id")
+                    .try_into()
+                    .expect("id bytes");
+            let txn = Transaction::new(&ndb).expect("txn");
+            let notes = ndb.get_notes_by_ids(&txn, &[[0xff; 32], id, [0; 32]]);
+            assert_eq!(notes.len(), 3);
+            assert!(notes[0].is_none());
+            assert_eq!(notes[1].as_ref().expect("note").id(), &id);
+            assert!(notes[2].is_none());
+        }
+    }
 }
@@ -0,0 +1,473 @@
+//! NIP-77 negentropy range-based set reconciliation, see
+//! <https://github.com/hoytech/negentropy/blob/master/docs/negentropy-protocol-v1.md>
+
+use crate::util::sha256;
+use crate::{Error, Result};
+use std::collections::HashSet;
+
+const PROTOCOL_VERSION: u8 = 0x61;
+const ID_SIZE: usize = 32;
+const FINGERPRINT_SIZE: usize = 16;
+
+/// How many fingerprinted ranges a mismatching range is split into
+const BUCKETS: usize = 16;
+
+const MODE_SKIP: u64 = 0;
+const MODE_FINGERPRINT: u64 = 1;
+const MODE_ID_LIST: u64 = 2;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+struct Item {
+    timestamp: u64,
+    id: [u8; 32],
+}
+
+/// A range boundary. Only the first `prefix_len` bytes of the id were
+/// sent, the rest are zero.
+#[derive(Debug, Clone, Copy)]
+struct Bound {
+    item: Item,
+    prefix_len: usize,
+}
+
+impl Bound {
+    fn new(timestamp: u64) -> Self {
+        Bound {
+            item: Item {
+                timestamp,
+                id: [0; 32],
+            },
+            prefix_len: 0,
+        }
+    }
+
+    /// The shortest bound that sorts after `prev` and not after `curr`
+    fn between(prev: &Item, curr: &Item) -> Self {
+        if prev.timestamp != curr.timestamp {
+            return Bound::new(curr.timestamp);
+        }
+
+        let shared = prev
+            .id
+            .iter()
+            .zip(curr.id.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let prefix_len = (shared + 1).min(ID_SIZE);
+
+        let mut id = [0; 32];
+        id[..prefix_len].copy_from_slice(&curr.id[..prefix_len]);
+        Bound {
+            item: Item {
+                timestamp: curr.timestamp,
+                id,
+            },
+            prefix_len,
+        }
+    }
+}
+
+fn encode_varint(out: &mut Vec<u8>, mut n: u64) {
+    let mut bytes = vec![(n & 0x7f) as u8];
+    n >>= 7;
+    while n > 0 {
+        bytes.push((n & 0x7f) as u8 | 0x80);
+        n >>= 7;
+    }
+    out.extend(bytes.iter().rev());
+}
+
+fn fingerprint(items: &[Item]) -> [u8; FINGERPRINT_SIZE] {
+    // sum of the ids as little-endian 256 bit integers, mod 2^256
+    let mut sum = [0u8; 32];
+    for item in items {
+        let mut carry = 0u16;
+        for (s, b) in sum.iter_mut().zip(item.id.iter()) {
+            let v = *s as u16 + *b as u16 + carry;
+            *s = v as u8;
+            carry = v >> 8;
+        }
+    }
+
+    let mut input = sum.to_vec();
+    encode_varint(&mut input, items.len() as u64);
+
+    let hash = sha256(&input);
+
+    let mut fp = [0; FINGERPRINT_SIZE];
+    fp.copy_from_slice(&hash[..FINGERPRINT_SIZE]);
+    fp
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.buf.len() < n {
+            return Err(Error::DecodeError);
+        }
+        let (bytes, rest) = self.buf.split_at(n);
+        self.buf = rest;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut n: u64 = 0;
+        // a u64 never needs more than 10 bytes
+        for _ in 0..10 {
+            let b = self.byte()?;
+            n = (n << 7) | (b & 0x7f) as u64;
+            if b & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(Error::DecodeError)
+    }
+
+    fn id(&mut self) -> Result<[u8; 32]> {
+        let mut id = [0; 32];
+        id.copy_from_slice(self.bytes(ID_SIZE)?);
+        Ok(id)
+    }
+}
+
+/// The result of processing a negentropy message
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct Reconciliation {
+    /// The next message to send. When this is None on the initiating side,
+    /// reconciliation is complete.
+    pub message: Option<Vec<u8>>,
+
+    /// Ids we have that the other side doesn't. Only filled in on the
+    /// initiating side.
+    pub have_ids: Vec<[u8; 32]>,
+
+    /// Ids the other side has that we don't. Only filled in on the
+    /// initiating side.
+    pub need_ids: Vec<[u8; 32]>,
+}
+
+/// One side of a negentropy sync over a fixed snapshot of (created_at, id)
+/// pairs. Construct one over the notes matching a filter with
+/// [crate::Ndb::negentropy] or [crate::Ndb::negentropy_initiate].
+///
+/// Messages are raw bytes. NIP-77 relay messages carry them hex encoded.
+#[derive(Debug, Clone)]
+pub struct Negentropy {
+    items: Vec<Item>,
+    initiator: bool,
+    last_timestamp_in: u64,
+    last_timestamp_out: u64,
+}
+
+impl Negentropy {
+    pub fn new(items: impl IntoIterator<Item = (u64, [u8; 32])>) -> Self {
+        let mut items: Vec<Item> = items
+            .into_iter()
+            .map(|(timestamp, id)| Item { timestamp, id })
+            .collect();
+        items.sort();
+        items.dedup();
+
+        Negentropy {
+            items,
+            initiator: false,
+            last_timestamp_in: 0,
+            last_timestamp_out: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Start reconciliation. This makes us the initiating side, and returns
+    /// the first message to send.
+    pub fn initiate(&mut self) -> Vec<u8> {
+        self.initiator = true;
+        self.last_timestamp_out = 0;
+
+        let mut out = vec![PROTOCOL_VERSION];
+        self.split_range(0, self.items.len(), Bound::new(u64::MAX), &mut out);
+        out
+    }
+
+    /// Process a message from the other side
+    pub fn reconcile(&mut self, msg: &[u8]) -> Result<Reconciliation> {
+        self.last_timestamp_in = 0;
+        self.last_timestamp_out = 0;
+
+        let mut res = Reconciliation::default();
+        let mut out = vec![PROTOCOL_VERSION];
+        let mut reader = Reader { buf: msg };
+
+        let version = reader.byte()?;
+        if !(0x60..=0x6f).contains(&version) {
+            return Err(Error::DecodeError);
+        }
+        if version != PROTOCOL_VERSION {
+            if self.initiator {
+                return Err(Error::DecodeError);
+            }
+            // tell the other side which version we speak
+            res.message = Some(out);
+            return Ok(res);
+        }
+
+        let mut prev_bound = Bound::new(0);
+        let mut prev_index = 0;
+        let mut skip = false;
+
+        while !reader.is_empty() {
+            let curr_bound = self.decode_bound(&mut reader)?;
+            let mode = reader.varint()?;
+
+            let lower = prev_index;
+            let upper = lower + self.items[lower..].partition_point(|item| *item < curr_bound.item);
+
+            match mode {
+                MODE_SKIP => skip = true,
+
+                MODE_FINGERPRINT => {
+                    let theirs = reader.bytes(FINGERPRINT_SIZE)?;
+                    if theirs != fingerprint(&self.items[lower..upper]) {
+                        self.flush_skip(&mut skip, prev_bound, &mut out);
+                        self.split_range(lower, upper, curr_bound, &mut out);
+                    } else {
+                        skip = true;
+                    }
+                }
+
+                MODE_ID_LIST => {
+                    let count = reader.varint()?;
+                    let mut theirs = HashSet::new();
+                    for _ in 0..count {
+                        theirs.insert(reader.id()?);
+                    }
+
+                    for item in &self.items[lower..upper] {
+                        if !theirs.remove(&item.id) && self.initiator {
+                            res.have_ids.push(item.id);
+                        }
+                    }
+
+                    if self.initiator {
+                        skip = true;
+                        res.need_ids.extend(theirs);
+                    } else {
+                        // send back everything we have in this range
+                        self.flush_skip(&mut skip, prev_bound, &mut out);
+                        self.encode_bound(curr_bound, &mut out);
+                        encode_varint(&mut out, MODE_ID_LIST);
+                        encode_varint(&mut out, (upper - lower) as u64);
+                        for item in &self.items[lower..upper] {
+                            out.extend_from_slice(&item.id);
+                        }
+                    }
+                }
+
+                _ => return Err(Error::DecodeError),
+            }
+
+            prev_index = upper;
+            prev_bound = curr_bound;
+        }
+
+        res.need_ids.sort();
+        if !self.initiator || out.len() > 1 {
+            res.message = Some(out);
+        }
+        Ok(res)
+    }
+
+    fn flush_skip(&mut self, skip: &mut bool, bound: Bound, out: &mut Vec<u8>) {
+        if *skip {
+            *skip = false;
+            self.encode_bound(bound, out);
+            encode_varint(out, MODE_SKIP);
+        }
+    }
+
+    fn split_range(&mut self, lower: usize, upper: usize, upper_bound: Bound, out: &mut Vec<u8>) {
+        let count = upper - lower;
+
+        // small ranges are cheaper to just send in full
+        if count < BUCKETS * 2 {
+            self.encode_bound(upper_bound, out);
+            encode_varint(out, MODE_ID_LIST);
+            encode_varint(out, count as u64);
+            for item in &self.items[lower..upper] {
+                out.extend_from_slice(&item.id);
+            }
+            return;
+        }
+
+        let per_bucket = count / BUCKETS;
+        let with_extra = count % BUCKETS;
+        let mut curr = lower;
+
+        for i in 0..BUCKETS {
+            let size = per_bucket + usize::from(i < with_extra);
+            let fp = fingerprint(&self.items[curr..curr + size]);
+            curr += size;
+
+            let bound = if curr == upper {
+                upper_bound
+            } else {
+                Bound::between(&self.items[curr - 1], &self.items[curr])
+            };
+
+            self.encode_bound(bound, out);
+            encode_varint(out, MODE_FINGERPRINT);
+            out.extend_from_slice(&fp);
+        }
+    }
+
+    // timestamps are sent as deltas from the previous bound in the same
+    // message, plus one. zero is infinity
+
+    fn encode_bound(&mut self, bound: Bound, out: &mut Vec<u8>) {
+        let timestamp = bound.item.timestamp;
+        if timestamp == u64::MAX {
+            self.last_timestamp_out = u64::MAX;
+            encode_varint(out, 0);
+        } else {
+            let delta = timestamp.saturating_sub(self.last_timestamp_out);
+            self.last_timestamp_out = timestamp;
+            encode_varint(out, delta + 1);
+        }
+
+        encode_varint(out, bound.prefix_len as u64);
+        out.extend_from_slice(&bound.item.id[..bound.prefix_len]);
+    }
+
+    fn decode_bound(&mut self, reader: &mut Reader) -> Result<Bound> {
+        let encoded = reader.varint()?;
+        let timestamp = if encoded == 0 || self.last_timestamp_in == u64::MAX {
+            u64::MAX
+        } else {
+            (encoded - 1).saturating_add(self.last_timestamp_in)
+        };
+        self.last_timestamp_in = timestamp;
+
+        let prefix_len = reader.varint()? as usize;
+        if prefix_len > ID_SIZE {
+            return Err(Error::DecodeError);
+        }
+
+        let mut id = [0; 32];
+        id[..prefix_len].copy_from_slice(reader.bytes(prefix_len)?);
+        Ok(Bound {
+            item: Item { timestamp, id },
+            prefix_len,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(i: u64) -> (u64, [u8; 32]) {
+        let mut id = [0; 32];
+        id[..8].copy_from_slice(&i.wrapping_mul(0x9e3779b97f4a7c15).to_be_bytes());
+        id[31] = i as u8;
+        // lots of shared timestamps so id prefixes end up in bounds
+        (1_700_000_000 + i / 4, id)
+    }
+
+    fn sync(client: &mut Negentropy, server: &mut Negentropy) -> (Vec<[u8; 32]>, Vec<[u8; 32]>) {
+        let mut have = vec![];
+        let mut need = vec![];
+
+        let mut msg = client.initiate();
+        for _ in 0..32 {
+            let reply = server.reconcile(&msg).expect("server");
+            let res = client
+                .reconcile(&reply.message.expect("reply"))
+                .expect("client");
+            have.extend(res.have_ids);
+            need.extend(res.need_ids);
+            match res.message {
+                Some(next) => msg = next,
+                None => {
+                    have.sort();
+                    need.sort();
+                    return (have, need);
+                }
+            }
+        }
+
+        panic!("reconciliation didn't finish");
+    }
+
+    #[test]
+    fn varint_roundtrip() {
+        for n in [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+            let mut buf = vec![];
+            encode_varint(&mut buf, n);
+            assert_eq!(Reader { buf: &buf }.varint(), Ok(n));
+        }
+    }
+
+    #[test]
+    fn negentropy_sync_works() {
+        let client_ids: Vec<u64> = (0..2000).filter(|i| i % 7 != 0).collect();
+        let server_ids: Vec<u64> = (500..2500).collect();
+
+        let mut client = Negentropy::new(client_ids.iter().map(|i| item(*i)));
+        let mut server = Negentropy::new(server_ids.iter().map(|i| item(*i)));
+        let (have, need) = sync(&mut client, &mut server);
+
+        let mut expected_have: Vec<[u8; 32]> = client_ids
+            .iter()
+            .filter(|i| !server_ids.contains(i))
+            .map(|i| item(*i).1)
+            .collect();
+        let mut expected_need: Vec<[u8; 32]> = server_ids
+            .iter()
+            .filter(|i| !client_ids.contains(i))
+            .map(|i| item(*i).1)
+            .collect();
+        expected_have.sort();
+        expected_need.sort();
+
+        assert_eq!(have, expected_have);
+        assert_eq!(need, expected_need);
+    }
+
+    #[test]
+    fn negentropy_identical_sets_finish_immediately() {
+        let mut client = Negentropy::new((0..100).map(item));
+        let mut server = Negentropy::new((0..100).map(item));
+        let (have, need) = sync(&mut client, &mut server);
+        assert!(have.is_empty());
+        assert!(need.is_empty());
+    }
+
+    #[test]
+    fn negentropy_rejects_garbage() {
+        let mut neg = Negentropy::new((0..10).map(item));
+        assert!(neg.reconcile(&[]).is_err());
+        assert!(neg.reconcile(&[0x01]).is_err());
+        assert!(neg.reconcile(&[PROTOCOL_VERSION, 0x00, 0x01]).is_err());
+
+        // newer protocol versions get our version back
+        let res = neg.reconcile(&[0x62]).expect("version reply");
+        assert_eq!(res.message, Some(vec![PROTOCOL_VERSION]));
+    }
+}
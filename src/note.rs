@@ -1,9 +1,14 @@
+use crate::json;
 use crate::tags::Tags;
 use crate::transaction::Transaction;
-use crate::{bindings, Error};
+use crate::verify;
+use crate::{bindings, Error, Kind, NoteError};
 use ::std::os::raw::c_uchar;
 use std::hash::Hash;
 
+/// The largest buffer tried for [Note::commitment]
+const MAX_COMMITMENT_SIZE: usize = 16 * 1024 * 1024;
+
 #[derive(Debug, Clone, Copy, Eq, Ord, PartialEq, PartialOrd, Hash)]
 pub struct NoteKey(u64);
 
@@ -80,6 +85,76 @@ impl<'a> Note<'a> {
         Note::Owned { ptr, size }
     }
 
+    /// Parse a single nostr event object into an owned note. The id and
+    /// signature are not checked.
+    #[allow(dead_code)]
+    pub(crate) fn owned_from_json(json: &str) -> Result<Note<'static>, Error> {
+        // notes are always smaller than their json
+        let bufsize = json.len() + 1024;
+        let buf = unsafe { libc::malloc(bufsize as libc::size_t) as *mut c_uchar };
+        if buf.is_null() {
+            return Err(Error::BufferOverflow);
+        }
+
+        let mut note_ptr: *mut bindings::ndb_note = std::ptr::null_mut();
+        let size = unsafe {
+            bindings::ndb_note_from_json(
+                json.as_ptr() as *const ::std::os::raw::c_char,
+                json.len() as ::std::os::raw::c_int,
+                &mut note_ptr as *mut *mut bindings::ndb_note,
+                buf,
+                bufsize as ::std::os::raw::c_int,
+            ) as usize
+        };
+
+        if size == 0 {
+            unsafe { libc::free(buf as *mut libc::c_void) };
+            return Err(Error::DecodeError);
+        }
+
+        // the note is built at the start of the buffer
+        let note_ptr = unsafe {
+            libc::realloc(note_ptr as *mut libc::c_void, size) as *mut bindings::ndb_note
+        };
+        if note_ptr.is_null() {
+            unsafe { libc::free(buf as *mut libc::c_void) };
+            return Err(Error::BufferOverflow);
+        }
+
+        Ok(Note::new_owned(note_ptr, size))
+    }
+
+    /// Parse a nostr event object without storing it, checking its id and
+    /// signature. Fails with a [NoteError] saying what is wrong, eg. for
+    /// validating user input or inspecting an event before accepting it.
+    pub fn from_json(json: &str) -> Result<Note<'static>, Error> {
+        let note = Note::from_json_unverified(json)?;
+        let checked = verify::with_verifier(|verifier| {
+            if !verifier.check_id(&note, json.len()) {
+                Err(NoteError::IdMismatch)
+            } else if !verifier.check_sig(&note) {
+                Err(NoteError::InvalidSignature)
+            } else {
+                Ok(())
+            }
+        });
+
+        match checked {
+            Some(Ok(())) => Ok(note),
+            Some(Err(err)) => Err(Error::Note(err)),
+            // no secp256k1 context to check the signature with
+            None => Err(Error::NoteProcessFailed),
+        }
+    }
+
+    /// Like [Note::from_json], without checking the id or signature
+    pub fn from_json_unverified(json: &str) -> Result<Note<'static>, Error> {
+        Note::owned_from_json(json).map_err(|err| match err {
+            Error::DecodeError => Error::Note(diagnose_json(json)),
+            err => err,
+        })
+    }
+
     /// Constructs a `Note` in a transactional context.
     /// Use [Note::new_transactional] to create a new transactional note.
     /// You normally wouldn't use this method directly, it is used by
@@ -152,6 +227,63 @@ impl<'a> Note<'a> {
         self.json_with_bufsize(1024usize * 1024usize)
     }
 
+    /// The canonical `[0,pubkey,created_at,kind,tags,content]`
+    /// serialization that the id is the sha256 of, made the same way
+    /// nostrdb makes it when checking ids. For external signers, and for
+    /// auditing ids byte for byte.
+    pub fn commitment(&self) -> Result<Vec<u8>, Error> {
+        self.calculate(|_, commitment| commitment.to_vec())
+    }
+
+    /// Recompute the id from the note's contents. It's the same as
+    /// [Note::id] unless the note was tampered with.
+    pub fn calculate_id(&self) -> Result<[u8; 32], Error> {
+        self.calculate(|id, _| *id)
+    }
+
+    /// A copy of the note that outlives its transaction
+    pub(crate) fn copy_owned(&self) -> Result<Note<'static>, Error> {
+        let size = self.size();
+        let ptr = unsafe { libc::malloc(size as libc::size_t) as *mut bindings::ndb_note };
+        if ptr.is_null() {
+            return Err(Error::BufferOverflow);
+        }
+        unsafe { std::ptr::copy_nonoverlapping(self.as_ptr() as *const u8, ptr as *mut u8, size) };
+        Ok(Note::new_owned(ptr, size))
+    }
+
+    /// nostrdb writes the computed id into the note, so this works on a
+    /// copy. Stored notes are in a read-only memory map.
+    fn calculate<T>(&self, f: impl FnOnce(&[u8; 32], &[u8]) -> T) -> Result<T, Error> {
+        let size = self.size();
+        let copy = self.copy_owned()?;
+
+        // escaping can make the commitment bigger than the note itself
+        let mut bufsize = size * 2 + 1024;
+        loop {
+            let mut buf = vec![0u8; bufsize];
+            let ok = unsafe {
+                bindings::ndb_calculate_id(
+                    copy.as_ptr(),
+                    buf.as_mut_ptr(),
+                    bufsize as ::std::os::raw::c_int,
+                ) != 0
+            };
+
+            if ok {
+                // nostrdb doesn't say how long it is, but the json can't
+                // contain nul bytes, they're escaped
+                let len = buf.iter().position(|b| *b == 0).unwrap_or(bufsize);
+                return Ok(f(copy.id(), &buf[..len]));
+            }
+
+            if bufsize >= MAX_COMMITMENT_SIZE {
+                return Err(Error::BufferOverflow);
+            }
+            bufsize *= 2;
+        }
+    }
+
     fn content_size(&self) -> usize {
         unsafe { bindings::ndb_note_content_length(self.as_ptr()) as usize }
     }
@@ -192,6 +324,11 @@ impl<'a> Note<'a> {
         unsafe { bindings::ndb_note_kind(self.as_ptr()) }
     }
 
+    /// The kind as a [Kind], for matching on
+    pub fn kind_enum(&self) -> Kind {
+        Kind::from_u32(self.kind())
+    }
+
     pub fn tags(&self) -> Tags<'a> {
         let tags = unsafe { bindings::ndb_note_tags(self.as_ptr()) };
         Tags::new(tags, self.clone())
@@ -203,6 +340,54 @@ impl<'a> Note<'a> {
             &*(ptr as *const [u8; 64])
         }
     }
+
+    /// The reason given in a NIP-36 `content-warning` tag, or an empty
+    /// string if the tag has none. None if there's no such tag.
+    pub fn content_warning(&self) -> Option<&'a str> {
+        for tag in self.tags() {
+            if tag.get_unchecked(0).variant().str() != Some("content-warning") {
+                continue;
+            }
+            let reason = tag.get(1).and_then(|reason| reason.variant().str());
+            return Some(reason.unwrap_or(""));
+        }
+        None
+    }
+
+    /// Whether the note has a NIP-70 `["-"]` tag, asking relays to only
+    /// accept it from its author
+    pub fn is_protected(&self) -> bool {
+        self.tags()
+            .iter()
+            .any(|tag| tag.count() == 1 && tag.get_unchecked(0).variant().str() == Some("-"))
+    }
+
+    /// Who the note was published on behalf of, from a NIP-26 delegation
+    /// tag. Delegations are checked at ingest unless validation is skipped,
+    /// see [Delegation::is_valid_for] for notes from elsewhere.
+    ///
+    /// [Delegation::is_valid_for]: crate::Delegation::is_valid_for
+    pub fn delegator(&self) -> Option<[u8; 32]> {
+        crate::util::nip26::note_delegation(self).map(|delegation| delegation.delegator)
+    }
+
+    /// The relays or other sources the note was seen at, oldest first, if
+    /// the database was opened with [Config::set_record_sources]. Works
+    /// for owned notes too, by id.
+    ///
+    /// [Config::set_record_sources]: crate::Config::set_record_sources
+    pub fn sources(&self, txn: &Transaction) -> Vec<String> {
+        txn.ndb().note_sources(txn, self.id())
+    }
+
+    /// When the note was first received, in unix seconds, if the database
+    /// was opened with [Config::set_record_first_seen]. Unlike created_at
+    /// this can't be set by the author.
+    ///
+    /// [Config::set_record_first_seen]: crate::Config::set_record_first_seen
+    pub fn first_seen(&self, txn: &Transaction) -> Option<u64> {
+        txn.ndb().note_first_seen(txn, self.id())
+    }
 }
 
 impl<'a> Drop for Note<'a> {
@@ -220,7 +405,7 @@ impl bindings::ndb_builder {
 }
 
 impl bindings::ndb_keypair {
-    fn as_mut_ptr(&mut self) -> *mut bindings::ndb_keypair {
+    pub(crate) fn as_mut_ptr(&mut self) -> *mut bindings::ndb_keypair {
         self as *mut bindings::ndb_keypair
     }
 }
@@ -442,9 +627,104 @@ impl<'a> NoteBuilder<'a> {
     }
 }
 
+/// Why nostrdb couldn't parse an event
+fn diagnose_json(json: &str) -> NoteError {
+    let members = if let Some(members) = json::object_members(json) {
+        members
+    } else {
+        return NoteError::NotAnObject;
+    };
+    let field = |name: &str| members.iter().find(|(key, _)| *key == name).map(|m| m.1);
+
+    for (name, hex_len) in [("id", 64), ("pubkey", 64), ("sig", 128)] {
+        let value = if let Some(value) = field(name) {
+            value
+        } else {
+            return NoteError::MissingField(name);
+        };
+
+        let hex = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .unwrap_or("");
+        if hex.len() != hex_len || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return NoteError::InvalidHex(name);
+        }
+    }
+
+    for name in ["created_at", "kind", "tags", "content"] {
+        let value = if let Some(value) = field(name) {
+            value
+        } else {
+            return NoteError::MissingField(name);
+        };
+
+        let ok = match name {
+            "tags" => value.starts_with('['),
+            "content" => value.starts_with('"'),
+            _ => value.parse::<u64>().is_ok(),
+        };
+        if !ok {
+            return NoteError::InvalidField(name);
+        }
+    }
+
+    NoteError::Malformed
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util;
+
+    #[test]
+    fn note_from_json_works() {
+        let json = test_util::HELLO_NOTE;
+        let note = Note::from_json(json).expect("valid note");
+        assert_eq!(note.content(), "hello, world");
+        assert_eq!(note.kind(), 1);
+
+        let tampered = json.replace("hello, world", "hello, moon");
+        assert_eq!(
+            Note::from_json(&tampered).err(),
+            Some(Error::Note(NoteError::IdMismatch))
+        );
+        assert!(Note::from_json_unverified(&tampered).is_ok());
+
+        let bad_sig = json.replace("2275c5f5", "2275c5f6");
+        assert_eq!(
+            Note::from_json(&bad_sig).err(),
+            Some(Error::Note(NoteError::InvalidSignature))
+        );
+
+        let no_pubkey = json.replace(r#""pubkey""#, r#""author""#);
+        assert_eq!(
+            Note::from_json(&no_pubkey).err(),
+            Some(Error::Note(NoteError::MissingField("pubkey")))
+        );
+
+        let bad_hex = json.replace("702555e5", "zz2555e5");
+        assert_eq!(
+            Note::from_json(&bad_hex).err(),
+            Some(Error::Note(NoteError::InvalidHex("id")))
+        );
+        assert_eq!(
+            Note::from_json("[]").err(),
+            Some(Error::Note(NoteError::NotAnObject))
+        );
+    }
+
+    #[test]
+    fn note_commitment_works() {
+        let note = Note::from_json(test_util::HELLO_NOTE).expect("note");
+
+        let commitment = note.commitment().expect("commitment");
+        assert_eq!(
+            std::str::from_utf8(&commitment).expect("utf8"),
+            r#"[0,"32bf915904bfde2d136ba45dde32c88f4aca863783999faea2e847a8fafd2f15",1702675561,1,[],"hello, world"]"#
+        );
+        assert_eq!(&note.calculate_id().expect("id"), note.id());
+    }
 
     #[test]
     fn note_query_works() {
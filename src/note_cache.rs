@@ -0,0 +1,212 @@
+use crate::{Error, Ndb, Note, NoteKey, Result, Transaction};
+use std::cell::{Cell, Ref, RefCell};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// Notes for UI code to hold on to across frames, without keeping one read
+/// transaction open forever. The cache holds a single transaction and
+/// swaps it for a fresh one once it is older than `max_age`, so newly
+/// written notes show up and LMDB can reuse the pages of old ones.
+/// [CachedNote]s outlive the swap and look their note up again the first
+/// time they are used after it.
+///
+/// Transactions are tied to their thread, and only one can be open per
+/// thread, so the cache isn't [Send]. Use [NoteCache::with_txn] for other
+/// lookups on the same thread.
+#[derive(Debug, Clone)]
+pub struct NoteCache {
+    inner: Rc<CacheInner>,
+}
+
+#[derive(Debug)]
+struct CacheInner {
+    ndb: Ndb,
+    max_age: Duration,
+    txn: RefCell<Option<Transaction>>,
+    opened: Cell<Instant>,
+
+    /// Bumped whenever the transaction is swapped
+    generation: Cell<u64>,
+}
+
+/// A note handed out by a [NoteCache]. Cheap to clone and keep around, it
+/// only holds the note key, and where the note was in the current
+/// transaction.
+#[derive(Debug, Clone)]
+pub struct CachedNote {
+    cache: Rc<CacheInner>,
+    key: NoteKey,
+
+    /// The note's location in the transaction of this generation
+    found: Cell<Option<(u64, *mut crate::bindings::ndb_note, usize)>>,
+}
+
+impl NoteCache {
+    pub fn new(ndb: &Ndb, max_age: Duration) -> Self {
+        NoteCache {
+            inner: Rc::new(CacheInner {
+                ndb: ndb.clone(),
+                max_age,
+                txn: RefCell::new(None),
+                opened: Cell::new(Instant::now()),
+                generation: Cell::new(0),
+            }),
+        }
+    }
+
+    /// A handle to a stored note, None if there is no such note
+    pub fn get(&self, key: NoteKey) -> Option<CachedNote> {
+        let note = CachedNote {
+            cache: self.inner.clone(),
+            key,
+            found: Cell::new(None),
+        };
+        note.with(|_| ()).ok()?;
+        Some(note)
+    }
+
+    pub fn get_by_id(&self, id: &[u8; 32]) -> Option<CachedNote> {
+        let key = self
+            .with_txn(|ndb, txn| ndb.get_notekey_by_id(txn, id))
+            .ok()?
+            .ok()?;
+        self.get(NoteKey::new(key))
+    }
+
+    /// Run `f` with the cache's transaction, eg. for queries
+    pub fn with_txn<R>(&self, f: impl FnOnce(&Ndb, &Transaction) -> R) -> Result<R> {
+        let txn = self.inner.txn()?;
+        Ok(f(&self.inner.ndb, &txn))
+    }
+
+    /// Swap in a new transaction now instead of waiting for it to get old,
+    /// eg. after a subscription delivered notes. Fails while the current
+    /// one is in use.
+    pub fn refresh(&self) -> Result<()> {
+        self.inner.rotate()
+    }
+
+    /// How many times the transaction was swapped
+    pub fn generation(&self) -> u64 {
+        self.inner.generation.get()
+    }
+}
+
+impl CacheInner {
+    /// The current transaction, swapping it first if it's too old and
+    /// nothing is using it
+    fn txn(&self) -> Result<Ref<'_, Transaction>> {
+        let stale = self.opened.get().elapsed() >= self.max_age;
+        let open = self.txn.try_borrow().is_ok_and(|txn| txn.is_some());
+        if !open || stale {
+            if let Err(err) = self.rotate() {
+                if !open {
+                    return Err(err);
+                }
+            }
+        }
+        Ref::filter_map(self.txn.borrow(), Option::as_ref).map_err(|_| Error::TransactionFailed)
+    }
+
+    fn rotate(&self) -> Result<()> {
+        let mut txn = self
+            .txn
+            .try_borrow_mut()
+            .map_err(|_| Error::TransactionFailed)?;
+        // only one transaction per thread, the old one has to end first
+        txn.take();
+        *txn = Some(Transaction::new(&self.ndb)?);
+        self.opened.set(Instant::now());
+        self.generation.set(self.generation.get() + 1);
+        Ok(())
+    }
+}
+
+impl CachedNote {
+    pub fn key(&self) -> NoteKey {
+        self.key
+    }
+
+    /// Run `f` on the note, looking it up again if the transaction was
+    /// swapped since it was last used
+    pub fn with<R>(&self, f: impl FnOnce(&Note) -> R) -> Result<R> {
+        let txn = self.cache.txn()?;
+        let generation = self.cache.generation.get();
+
+        let note = match self.found.get() {
+            Some((found_in, ptr, size)) if found_in == generation => {
+                Note::new_transactional(ptr, size, self.key, &txn)
+            }
+            _ => {
+                let note = self.cache.ndb.get_note_by_key(&txn, self.key)?;
+                self.found
+                    .set(Some((generation, note.as_ptr(), note.size())));
+                note
+            }
+        };
+        Ok(f(&note))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util, Config, NoteBuilder};
+
+    #[test]
+    fn note_cache_works() {
+        let db = "target/testdbs/note_cache";
+        test_util::cleanup_db(db);
+
+        let write = |ndb: &Ndb, content: &str| {
+            let note = NoteBuilder::new()
+                .kind(1)
+                .content(content)
+                .sign(&[1u8; 32])
+                .build()
+                .expect("note");
+            let json = note.json().expect("json");
+            ndb.process_event(&format!("[\"EVENT\",\"cache\",{json}]"))
+                .expect("process ok");
+            note
+        };
+        let first = {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            write(&ndb, "first")
+        };
+
+        let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+        let cache = NoteCache::new(&ndb, Duration::from_secs(3600));
+        let cached = cache.get_by_id(first.id()).expect("cached");
+        assert_eq!(
+            cached.with(|note| note.content().to_string()),
+            Ok("first".to_string())
+        );
+        assert_eq!(cache.generation(), 1);
+
+        let second = write(&ndb, "second");
+        let mut tries = 0;
+        let found = loop {
+            cache.refresh().expect("refresh");
+            if let Some(found) = cache.get_by_id(second.id()) {
+                break found;
+            }
+            tries += 1;
+            assert!(tries < 500, "note never written");
+            std::thread::sleep(Duration::from_millis(10));
+        };
+
+        // handles from before the refresh still work
+        assert!(cache.generation() > 1);
+        assert_eq!(
+            cached.with(|note| note.content().to_string()),
+            Ok("first".to_string())
+        );
+        assert_eq!(
+            found.with(|note| note.created_at()),
+            Ok(second.created_at())
+        );
+        assert!(cached.with(|_| cache.refresh()).expect("with").is_err());
+        assert!(cache.get(NoteKey::new(u64::MAX)).is_none());
+    }
+}
@@ -0,0 +1,123 @@
+use crate::{Deletion, Kind, Ndb, Note, NoteKey, Result, Transaction};
+
+/// The subscription id of an exported entry is this followed by its sequence
+/// number, so a replica can tell how far it got
+const SEQ_PREFIX: &str = "[\"EVENT\",\"oplog:";
+
+/// One write to the database, see [Ndb::changes_since].
+///
+/// Sequence numbers are the note keys, which the writer hands out in
+/// increasing order, so the note table doubles as the log and nothing extra
+/// has to be stored. nostrdb never removes notes, so deletions are logged
+/// as the NIP-09 deletion request that was written, see
+/// [OplogEntry::deleted_ids].
+#[derive(Debug, Clone)]
+pub struct OplogEntry<'a> {
+    seq: u64,
+    note: Note<'a>,
+}
+
+impl<'a> OplogEntry<'a> {
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    pub fn note(&self) -> &Note<'a> {
+        &self.note
+    }
+
+    pub fn is_deletion(&self) -> bool {
+        self.note.kind_enum() == Kind::Deletion
+    }
+
+    /// The ids of the notes this entry asks to delete. Empty unless it is a
+    /// deletion request.
+    pub fn deleted_ids(&self) -> Vec<&'a [u8; 32]> {
+        Deletion::new(self.note.clone()).map_or(vec![], |deletion| deletion.ids())
+    }
+
+    /// This entry as an `["EVENT","oplog:<seq>",...]` line, for sending to a
+    /// replica which applies it with [Ndb::apply_changes]
+    pub fn to_json(&self) -> Result<String> {
+        Ok(format!("{SEQ_PREFIX}{}\",{}]", self.seq, self.note.json()?))
+    }
+}
+
+/// Up to `limit` log entries after `seq`, oldest first. 0 is before the
+/// first entry.
+pub(crate) fn changes_since<'a>(
+    ndb: &Ndb,
+    txn: &'a Transaction,
+    seq: u64,
+    limit: usize,
+) -> Vec<OplogEntry<'a>> {
+    ndb.iter_notes(txn, NoteKey::new(seq + 1))
+        .take(limit)
+        .map(|(key, note)| OplogEntry {
+            seq: key.as_u64(),
+            note,
+        })
+        .collect()
+}
+
+/// Write exported entries into a replica. Returns the sequence number of
+/// the last entry, to pass to [Ndb::changes_since] on the next sync.
+pub(crate) fn apply(ndb: &Ndb, ldjson: &str) -> Result<Option<u64>> {
+    let last = ldjson.lines().filter_map(entry_seq).max();
+    ndb.process_events(ldjson)?;
+    Ok(last)
+}
+
+fn entry_seq(line: &str) -> Option<u64> {
+    let rest = line.trim_start().strip_prefix(SEQ_PREFIX)?;
+    let end = rest.find('"')?;
+    rest[..end].parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util, Config};
+
+    #[test]
+    fn changes_since_works() {
+        let db = "target/testdbs/oplog_master";
+        let replica = "target/testdbs/oplog_replica";
+        test_util::cleanup_db(db);
+        test_util::cleanup_db(replica);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            ndb.process_event(&test_util::hello_event())
+                .expect("process ok");
+        }
+
+        let export = {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let txn = Transaction::new(&ndb).expect("txn");
+            let entries = ndb.changes_since(&txn, 0, 100);
+            assert_eq!(entries.len(), 1);
+            assert_eq!(entries[0].seq(), 1);
+            assert!(!entries[0].is_deletion());
+            assert!(entries[0].deleted_ids().is_empty());
+            assert!(ndb.changes_since(&txn, 1, 100).is_empty());
+
+            let line = entries[0].to_json().expect("json");
+            assert_eq!(entry_seq(&line), Some(1));
+            line
+        };
+
+        {
+            let ndb = Ndb::new(replica, &Config::new()).expect("ndb");
+            assert_eq!(ndb.apply_changes(&export), Ok(Some(1)));
+        }
+
+        {
+            let ndb = Ndb::new(replica, &Config::new()).expect("ndb");
+            let txn = Transaction::new(&ndb).expect("txn");
+            let entries = ndb.changes_since(&txn, 0, 100);
+            assert_eq!(entries.len(), 1);
+            assert_eq!(entries[0].note().content(), "hello, world");
+        }
+    }
+}
@@ -0,0 +1,475 @@
+use crate::util::lock;
+use crate::{
+    BackfillSubscription, Error, Filter, Ndb, NoteKey, Result, Subscription, SubscriptionEvent,
+    Transaction,
+};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Where named subscriptions and their watermarks are saved, one line
+/// per change, in the database directory
+pub(crate) const SUBSCRIPTIONS_FILE: &str = "subscriptions.log";
+
+/// In place of the watermark on a line that forgets a subscription
+const REMOVED: &str = "-";
+
+/// Rewrite the log once it has this many more lines than subscriptions
+const COMPACT_SLACK: usize = 4096;
+
+/// The last note a [PersistentSubscription] delivered
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Watermark {
+    pub note_key: NoteKey,
+    pub created_at: u64,
+}
+
+#[derive(Debug, Clone)]
+struct Saved {
+    /// One filter json per entry
+    filters: Vec<String>,
+    watermark: Watermark,
+}
+
+impl Saved {
+    fn to_line(&self, name: &str) -> String {
+        format!(
+            "{name}\t{}\t{}\t{}\n",
+            self.watermark.note_key.as_u64(),
+            self.watermark.created_at,
+            self.filters.join("\t")
+        )
+    }
+
+    /// A line from the log. A name followed by [REMOVED] means it was
+    /// forgotten.
+    fn from_line(line: &str) -> Option<(&str, Option<Saved>)> {
+        let mut fields = line.split('\t');
+        let name = fields.next()?;
+        let note_key = fields.next()?;
+        if note_key == REMOVED {
+            return Some((name, None));
+        }
+        let watermark = Watermark {
+            note_key: NoteKey::new(note_key.parse().ok()?),
+            created_at: fields.next()?.parse().ok()?,
+        };
+        let filters: Vec<String> = fields.map(str::to_string).collect();
+        if filters.is_empty() {
+            return None;
+        }
+        Some((name, Some(Saved { filters, watermark })))
+    }
+
+    fn parse_filters(&self) -> Result<Vec<Filter>> {
+        self.filters.iter().map(|f| Filter::from_json(f)).collect()
+    }
+}
+
+/// The named subscriptions and how far each got, see
+/// [Ndb::resume_subscription]. Like [MediaStore], a log of
+/// `<name>\t<note key>\t<created_at>\t<filters>` lines in the database
+/// directory, the latest line for a name winning.
+///
+/// [Ndb::resume_subscription]: crate::Ndb::resume_subscription
+/// [MediaStore]: crate::media::MediaStore
+#[derive(Debug)]
+pub(crate) struct SavedSubscriptions {
+    path: PathBuf,
+    state: Mutex<Option<SavedState>>,
+}
+
+#[derive(Debug)]
+struct SavedState {
+    by_name: HashMap<String, Saved>,
+    lines: usize,
+    file: File,
+}
+
+impl SavedSubscriptions {
+    pub(crate) fn new(db_dir: &Path) -> Self {
+        SavedSubscriptions {
+            path: db_dir.join(SUBSCRIPTIONS_FILE),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn get(&self, name: &str) -> Result<Option<Saved>> {
+        let mut state = lock(&self.state);
+        let state = self.load(&mut state).map_err(|_| Error::IoError)?;
+        Ok(state.by_name.get(name).cloned())
+    }
+
+    fn set(&self, name: &str, saved: Saved) -> Result<()> {
+        let mut state = lock(&self.state);
+        let state = self.load(&mut state).map_err(|_| Error::IoError)?;
+        state.append(&saved.to_line(name))?;
+        state.by_name.insert(name.to_string(), saved);
+        self.maybe_compact(state)
+    }
+
+    /// Move a subscription's watermark forward. Never goes back, and does
+    /// nothing if the subscription was forgotten in the meantime.
+    fn advance(&self, name: &str, watermark: Watermark) -> Result<()> {
+        let mut state = lock(&self.state);
+        let state = self.load(&mut state).map_err(|_| Error::IoError)?;
+        let mut saved = match state.by_name.get(name) {
+            Some(saved) if saved.watermark.note_key < watermark.note_key => saved.clone(),
+            _ => return Ok(()),
+        };
+        saved.watermark = watermark;
+        state.append(&saved.to_line(name))?;
+        state.by_name.insert(name.to_string(), saved);
+        self.maybe_compact(state)
+    }
+
+    pub(crate) fn remove(&self, name: &str) -> Result<bool> {
+        let mut state = lock(&self.state);
+        let state = self.load(&mut state).map_err(|_| Error::IoError)?;
+        if !state.by_name.contains_key(name) {
+            return Ok(false);
+        }
+        state.append(&format!("{name}\t{REMOVED}\n"))?;
+        state.by_name.remove(name);
+        self.maybe_compact(state)?;
+        Ok(true)
+    }
+
+    fn load<'a>(&self, state: &'a mut Option<SavedState>) -> std::io::Result<&'a mut SavedState> {
+        if let Some(state) = state {
+            return Ok(state);
+        }
+
+        let mut by_name = HashMap::new();
+        let mut lines = 0;
+        if let Ok(file) = File::open(&self.path) {
+            for line in BufReader::new(file).lines() {
+                let line = line?;
+                // a line cut short by a crash is skipped
+                let (name, saved) = match Saved::from_line(&line) {
+                    Some(parsed) => parsed,
+                    None => continue,
+                };
+                lines += 1;
+                match saved {
+                    Some(saved) => by_name.insert(name.to_string(), saved),
+                    None => by_name.remove(name),
+                };
+            }
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        Ok(state.insert(SavedState {
+            by_name,
+            lines,
+            file,
+        }))
+    }
+
+    /// Write out just the current subscriptions and swap them in for the
+    /// log
+    fn maybe_compact(&self, state: &mut SavedState) -> Result<()> {
+        if state.lines < state.by_name.len() + COMPACT_SLACK {
+            return Ok(());
+        }
+
+        let tmp = self.path.with_extension("log.tmp");
+        let mut lines = String::new();
+        for (name, saved) in &state.by_name {
+            lines.push_str(&saved.to_line(name));
+        }
+        fs::write(&tmp, lines)
+            .and_then(|_| fs::rename(&tmp, &self.path))
+            .map_err(|_| Error::IoError)?;
+
+        state.file = OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .map_err(|_| Error::IoError)?;
+        state.lines = state.by_name.len();
+        Ok(())
+    }
+}
+
+impl SavedState {
+    fn append(&mut self, line: &str) -> Result<()> {
+        self.file
+            .write_all(line.as_bytes())
+            .map_err(|_| Error::IoError)?;
+        self.lines += 1;
+        Ok(())
+    }
+}
+
+/// The newest note key, 0 if there are no notes. Keys are handed out in
+/// order, so this searches for the first one that's missing.
+fn last_note_key(ndb: &Ndb, txn: &Transaction) -> u64 {
+    let exists = |key: u64| ndb.get_note_by_key(txn, NoteKey::new(key)).is_ok();
+    let mut hi = 1;
+    while exists(hi) {
+        hi *= 2;
+    }
+    let mut lo = hi / 2;
+    // lo exists (or is 0), hi doesn't
+    while hi - lo > 1 {
+        let mid = lo + (hi - lo) / 2;
+        if exists(mid) {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    lo
+}
+
+/// A subscription that remembers, under a stable name, the last note it
+/// delivered, so that after a restart [Ndb::resume_subscription] can
+/// replay what matched in the meantime before going live. Construct one
+/// with [Ndb::register_subscription].
+///
+/// The watermark moves when notes are returned by a poll, not when the
+/// app is done with them, so a crash in between skips them on the next
+/// resume. Replayed notes come in the order they were written, oldest
+/// first. The subscription is unsubscribed when this is dropped, but
+/// stays saved until [Ndb::forget_subscription].
+///
+/// [Ndb::resume_subscription]: crate::Ndb::resume_subscription
+/// [Ndb::register_subscription]: crate::Ndb::register_subscription
+/// [Ndb::forget_subscription]: crate::Ndb::forget_subscription
+#[derive(Debug)]
+pub struct PersistentSubscription {
+    ndb: Ndb,
+    name: String,
+    inner: BackfillSubscription,
+    watermark: Watermark,
+
+    /// Events polled but not returned yet, because the watermark couldn't
+    /// be saved
+    pending: Vec<SubscriptionEvent>,
+}
+
+impl PersistentSubscription {
+    /// Save `filters` under `name`, replacing what was saved before, and
+    /// start from the newest stored note
+    pub(crate) fn register(ndb: &Ndb, name: &str, filters: &[Filter]) -> Result<Self> {
+        if name.is_empty() || name.contains(['\t', '\n', '\r']) {
+            return Err(Error::DecodeError);
+        }
+        let filter_json = filters
+            .iter()
+            .map(Filter::json)
+            .collect::<Result<Vec<String>>>()?;
+
+        // the newest note in the snapshot begun after subscribing, so
+        // nothing written in between is missed
+        let mut watermark = None;
+        let inner = ndb.subscribe_with_stored(filters, |ndb, txn| {
+            let note_key = NoteKey::new(last_note_key(ndb, txn));
+            let created_at = ndb
+                .get_note_by_key(txn, note_key)
+                .map_or(0, |note| note.created_at());
+            watermark = Some(Watermark {
+                note_key,
+                created_at,
+            });
+            Ok(vec![])
+        })?;
+        let watermark = watermark.ok_or(Error::TransactionFailed)?;
+        let saved = Saved {
+            filters: filter_json,
+            watermark,
+        };
+        ndb.saved_subscriptions().set(name, saved)?;
+
+        Ok(PersistentSubscription {
+            ndb: ndb.clone(),
+            name: name.to_string(),
+            inner,
+            watermark,
+            pending: vec![],
+        })
+    }
+
+    /// Pick up the subscription saved under `name`, with the notes that
+    /// matched after its watermark delivered first
+    pub(crate) fn resume(ndb: &Ndb, name: &str) -> Result<Self> {
+        let saved = ndb
+            .saved_subscriptions()
+            .get(name)?
+            .ok_or(Error::NotFound)?;
+        let filters = saved.parse_filters()?;
+        let after = saved.watermark.note_key.as_u64() + 1;
+
+        let inner = ndb.subscribe_with_stored(&filters, |ndb, txn| {
+            Ok(ndb
+                .iter_notes(txn, NoteKey::new(after))
+                .filter(|(_, note)| filters.iter().any(|filter| filter.matches(note)))
+                .map(|(note_key, _)| note_key)
+                .collect())
+        })?;
+
+        Ok(PersistentSubscription {
+            ndb: ndb.clone(),
+            name: name.to_string(),
+            inner,
+            watermark: saved.watermark,
+            pending: vec![],
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn subscription(&self) -> Subscription {
+        self.inner.subscription()
+    }
+
+    /// Whether everything that matched while the app wasn't running has
+    /// been delivered
+    pub fn is_live(&self) -> bool {
+        self.inner.is_live()
+    }
+
+    pub fn watermark(&self) -> Watermark {
+        self.watermark
+    }
+
+    /// The events since the last poll, moving the watermark past them.
+    /// `txn` is used to read the created_at of the newest note.
+    pub fn poll_for_notes(
+        &mut self,
+        txn: &Transaction,
+        max_notes: u32,
+    ) -> Result<Vec<SubscriptionEvent>> {
+        let events = self.inner.poll_for_notes(max_notes);
+        self.pending.extend(events);
+        self.deliver(txn)
+    }
+
+    /// Wait for events. The newest note is read with a transaction of its
+    /// own, so no other transaction can be open on this thread; if one is,
+    /// this fails and the events are returned by the next poll.
+    pub async fn wait_for_notes(&mut self, max_notes: u32) -> Result<Vec<SubscriptionEvent>> {
+        if self.pending.is_empty() {
+            let events = self.inner.wait_for_notes(max_notes).await?;
+            self.pending.extend(events);
+        }
+        let txn = Transaction::new(&self.ndb)?;
+        self.deliver(&txn)
+    }
+
+    /// Save the watermark and hand over the pending events. If it can't be
+    /// saved they stay pending, so nothing is skipped.
+    fn deliver(&mut self, txn: &Transaction) -> Result<Vec<SubscriptionEvent>> {
+        let mut keys: Vec<NoteKey> = self
+            .pending
+            .iter()
+            .filter_map(|event| match event {
+                SubscriptionEvent::Note(note_key) => Some(*note_key),
+                SubscriptionEvent::EndOfStoredEvents => None,
+            })
+            .filter(|note_key| *note_key > self.watermark.note_key)
+            .collect();
+        keys.sort_unstable_by(|a, b| b.cmp(a));
+
+        // notes written after `txn` began can't be read yet. stopping
+        // short of them only means they're replayed after a restart
+        let newest = keys.into_iter().find_map(|note_key| {
+            let note = self.ndb.get_note_by_key(txn, note_key).ok()?;
+            Some(Watermark {
+                note_key,
+                created_at: note.created_at(),
+            })
+        });
+        if let Some(watermark) = newest {
+            self.ndb
+                .saved_subscriptions()
+                .advance(&self.name, watermark)?;
+            self.watermark = watermark;
+        }
+        Ok(std::mem::take(&mut self.pending))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util, Config, NoteBuilder};
+    use std::time::Duration;
+
+    fn process(ndb: &Ndb, kind: u32, created_at: u64) {
+        let note = NoteBuilder::new()
+            .kind(kind)
+            .content("inbox")
+            .created_at(created_at)
+            .sign(&[1u8; 32])
+            .build()
+            .expect("note");
+        let json = note.json().expect("json");
+        ndb.process_event(&format!("[\"EVENT\",\"p\",{json}]"))
+            .expect("process ok");
+    }
+
+    #[test]
+    fn persistent_subscription_works() {
+        let db = "target/testdbs/persistent_subscription";
+        test_util::cleanup_db(db);
+        let filters = [Filter::new().kinds([1]).build()];
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            process(&ndb, 1, 1);
+            let mut tries = 0;
+            loop {
+                let txn = Transaction::new(&ndb).expect("txn");
+                if last_note_key(&ndb, &txn) == 1 {
+                    break;
+                }
+                tries += 1;
+                assert!(tries < 500, "note never written");
+                std::thread::sleep(Duration::from_millis(10));
+            }
+
+            let mut sub = ndb.register_subscription("inbox", &filters).expect("sub");
+            let poll = |sub: &mut PersistentSubscription| {
+                let txn = Transaction::new(&ndb).expect("txn");
+                sub.poll_for_notes(&txn, 10).expect("poll")
+            };
+            assert_eq!(poll(&mut sub), vec![SubscriptionEvent::EndOfStoredEvents]);
+            process(&ndb, 1, 2);
+            let mut tries = 0;
+            while poll(&mut sub).is_empty() {
+                tries += 1;
+                assert!(tries < 500, "note never delivered");
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            assert_eq!(sub.watermark().created_at, 2);
+
+            // written while the app isn't listening
+            drop(sub);
+            process(&ndb, 7, 3);
+            process(&ndb, 1, 4);
+        }
+
+        let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+        let mut sub = ndb.resume_subscription("inbox").expect("resume");
+        let txn = Transaction::new(&ndb).expect("txn");
+        let events = sub.poll_for_notes(&txn, 10).expect("poll");
+        drop(txn);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1], SubscriptionEvent::EndOfStoredEvents);
+        assert_eq!(sub.watermark().created_at, 4);
+
+        assert!(ndb.forget_subscription("inbox").expect("forget"));
+        assert_eq!(
+            ndb.resume_subscription("inbox").map(|_| ()),
+            Err(Error::NotFound)
+        );
+    }
+}
@@ -1,7 +1,7 @@
 use crate::ndb_profile::{
     root_as_ndb_profile_record, root_as_ndb_profile_record_unchecked, NdbProfileRecord,
 };
-use crate::{Error, Result, Transaction};
+use crate::{Error, Filter, Ndb, NoteKey, Result, Subscription, Transaction};
 
 pub struct TransactionalProfileRecord<'a> {
     pub record: NdbProfileRecord<'a>,
@@ -65,6 +65,124 @@ impl<'a> ProfileRecord<'a> {
     }
 }
 
+/// How many profile notes to look at per poll of a [ProfileWatch]
+const PROFILE_WATCH_BATCH: u32 = 16;
+
+/// Fires whenever a profile (kind 0) newer than any seen before is written
+/// for a pubkey. Construct one with [Ndb::watch_profile]. The underlying
+/// subscription is unsubscribed when this is dropped.
+#[derive(Debug)]
+pub struct ProfileWatch {
+    ndb: Ndb,
+    sub: Subscription,
+    pubkey: [u8; 32],
+
+    /// created_at of the newest profile we know about
+    newest: Option<u64>,
+
+    /// Profile notes that were polled but not read yet
+    pending: Vec<NoteKey>,
+}
+
+impl ProfileWatch {
+    pub(crate) fn new(ndb: &Ndb, pubkey: &[u8; 32]) -> Result<Self> {
+        let filter = Filter::new().kinds([0]).authors([pubkey]).build();
+
+        // subscribe before looking up the current profile so that an update
+        // in between isn't missed
+        let sub = ndb.subscribe(std::slice::from_ref(&filter))?;
+        let mut watch = ProfileWatch {
+            ndb: ndb.clone(),
+            sub,
+            pubkey: *pubkey,
+            newest: None,
+            pending: vec![],
+        };
+
+        // without a snapshot of our own, eg. while this thread has one
+        // open, the first profile written is reported even if it's the
+        // one already stored
+        if let Ok(txn) = Transaction::new(ndb) {
+            watch.newest = ndb
+                .query(&txn, &[filter], 1)?
+                .iter()
+                .map(|r| r.note.created_at())
+                .max();
+        }
+
+        Ok(watch)
+    }
+
+    pub fn pubkey(&self) -> &[u8; 32] {
+        &self.pubkey
+    }
+
+    pub fn subscription(&self) -> Subscription {
+        self.sub
+    }
+
+    /// The key of the profile note, if a newer profile was written since
+    /// the last poll. Profiles written after `txn` began are kept for a
+    /// later poll.
+    pub fn poll(&mut self, txn: &Transaction) -> Option<NoteKey> {
+        let keys = self.ndb.poll_for_notes(self.sub, PROFILE_WATCH_BATCH);
+        self.pending.extend(keys);
+        self.newer(txn)
+    }
+
+    /// Wait until a newer profile is written, returning its note key. The
+    /// profiles are read with a transaction of its own, so no other
+    /// transaction can be open on this thread; if one is, this fails and
+    /// they stay queued for the next poll.
+    pub async fn wait(&mut self) -> Result<NoteKey> {
+        loop {
+            if !self.pending.is_empty() {
+                let txn = Transaction::new(&self.ndb)?;
+                if let Some(key) = self.newer(&txn) {
+                    return Ok(key);
+                }
+            }
+            let keys = self
+                .ndb
+                .wait_for_notes(self.sub, PROFILE_WATCH_BATCH)
+                .await?;
+            self.pending.extend(keys);
+        }
+    }
+
+    fn newer(&mut self, txn: &Transaction) -> Option<NoteKey> {
+        let mut found = None;
+        let mut later = vec![];
+        for key in std::mem::take(&mut self.pending) {
+            let created_at = match self.ndb.get_note_by_key(txn, key) {
+                Ok(note) => note.created_at(),
+                Err(_) => {
+                    later.push(key);
+                    continue;
+                }
+            };
+
+            // older profiles can still show up, eg. from a relay that is
+            // behind. they don't change anything
+            if self.newest.is_some_and(|newest| created_at <= newest) {
+                continue;
+            }
+
+            self.newest = Some(created_at);
+            found = Some(key);
+        }
+        self.pending = later;
+
+        found
+    }
+}
+
+impl Drop for ProfileWatch {
+    fn drop(&mut self) {
+        let _ = self.ndb.unsubscribe(self.sub);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -102,4 +220,74 @@ mod tests {
 
         test_util::cleanup_db(db);
     }
+
+    #[test]
+    fn profiles_by_pubkeys_works() {
+        use crate::config::Config;
+        use crate::ndb::Ndb;
+        use crate::test_util;
+
+        let db = "target/testdbs/profiles_by_pubkeys";
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).unwrap();
+            let _ = ndb.process_event(r#"["EVENT","nostril-query",{"content":"{\"nip05\":\"_@jb55.com\",\"website\":\"https://damus.io\",\"name\":\"jb55\",\"about\":\"I made damus, npubs and zaps. banned by apple & the ccp. my notes are not for sale.\",\"lud16\":\"jb55@sendsats.lol\",\"banner\":\"https://nostr.build/i/3d6f22d45d95ecc2c19b1acdec57aa15f2dba9c423b536e26fc62707c125f557.jpg\",\"display_name\":\"Will\",\"picture\":\"https://cdn.jb55.com/img/red-me.jpg\"}","created_at":1700855305,"id":"cad04d11f7fa9c36d57400baca198582dfeb94fa138366c4469e58da9ed60051","kind":0,"pubkey":"32e1827635450ebb3c5a7d12c1f8e7b2b514439ac10a67eef3d9fd9c5c68e245","sig":"7a15e379ff27318460172b4a1d55a13e064c5007d05d5a188e7f60e244a9ed08996cb7676058b88c7a91ae9488f8edc719bc966cb5bf1eb99be44cdb745f915f","tags":[]}]"#);
+        }
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("db open");
+            let txn = Transaction::new(&ndb).expect("new txn");
+
+            let pk: [u8; 32] =
+                hex::decode("32e1827635450ebb3c5a7d12c1f8e7b2b514439ac10a67eef3d9fd9c5c68e245")
+                    .expect("hex decode")
+                    .try_into()
+                    .expect("bytes");
+            let profiles = ndb.get_profiles_by_pubkeys(&txn, &[[0; 32], pk]);
+            assert_eq!(profiles.len(), 2);
+            assert!(profiles[0].is_none());
+            let profile = profiles[1].as_ref().expect("profile").record().profile();
+            assert_eq!(Some("jb55"), profile.unwrap().name());
+        }
+
+        test_util::cleanup_db(db);
+    }
+
+    #[tokio::test]
+    async fn watch_profile_works() {
+        use crate::config::Config;
+        use crate::ndb::Ndb;
+        use crate::test_util;
+
+        let db = "target/testdbs/watch_profile";
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("db open");
+            let pk: [u8; 32] =
+                hex::decode("32e1827635450ebb3c5a7d12c1f8e7b2b514439ac10a67eef3d9fd9c5c68e245")
+                    .expect("hex decode")
+                    .try_into()
+                    .expect("bytes");
+
+            let mut watch = ndb.watch_profile(&pk).expect("watch");
+            {
+                let txn = Transaction::new(&ndb).expect("txn");
+                assert!(watch.poll(&txn).is_none());
+            }
+
+            let _ = ndb.process_event(r#"["EVENT","nostril-query",{"content":"{\"nip05\":\"_@jb55.com\",\"website\":\"https://damus.io\",\"name\":\"jb55\",\"about\":\"I made damus, npubs and zaps. banned by apple & the ccp. my notes are not for sale.\",\"lud16\":\"jb55@sendsats.lol\",\"banner\":\"https://nostr.build/i/3d6f22d45d95ecc2c19b1acdec57aa15f2dba9c423b536e26fc62707c125f557.jpg\",\"display_name\":\"Will\",\"picture\":\"https://cdn.jb55.com/img/red-me.jpg\"}","created_at":1700855305,"id":"cad04d11f7fa9c36d57400baca198582dfeb94fa138366c4469e58da9ed60051","kind":0,"pubkey":"32e1827635450ebb3c5a7d12c1f8e7b2b514439ac10a67eef3d9fd9c5c68e245","sig":"7a15e379ff27318460172b4a1d55a13e064c5007d05d5a188e7f60e244a9ed08996cb7676058b88c7a91ae9488f8edc719bc966cb5bf1eb99be44cdb745f915f","tags":[]}]"#);
+
+            let key = watch.wait().await.expect("profile update");
+            let txn = Transaction::new(&ndb).expect("txn");
+            let note = ndb.get_note_by_key(&txn, key).expect("note");
+            assert_eq!(note.pubkey(), &pk);
+
+            drop(watch);
+            assert_eq!(ndb.subscription_count(), 0);
+        }
+
+        test_util::cleanup_db(db);
+    }
 }
@@ -1,4 +1,10 @@
-use crate::{bindings, Note, NoteKey, Transaction};
+use crate::{
+    bindings, ContentWarnings, Filter, MutedWords, Ndb, Note, NoteKey, QueryIter, Transaction,
+};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 #[derive(Debug)]
 pub struct QueryResult<'a> {
@@ -20,4 +26,330 @@ impl<'a> QueryResult<'a> {
             note_key: NoteKey::new(result.note_id),
         }
     }
+
+    /// Look up a stored note as a query result, eg. for a key from a
+    /// subscription
+    pub(crate) fn from_key(ndb: &Ndb, txn: &'a Transaction, key: NoteKey) -> Option<Self> {
+        let note = ndb.get_note_by_key(txn, key).ok()?;
+        Some(QueryResult {
+            note_size: note.size() as u64,
+            note_key: key,
+            note,
+        })
+    }
+
+    pub fn created_at(&self) -> u64 {
+        self.note.created_at()
+    }
+}
+
+/// Stops a query started with [Ndb::query_with_options] from another
+/// thread. Clones share the same flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Limits on how long a query may run, see [Ndb::query_with_options]
+#[derive(Debug, Clone, Default)]
+pub struct QueryOptions {
+    deadline: Option<Instant>,
+    cancel: Option<CancelToken>,
+    content_warnings: ContentWarnings,
+    muted_words: MutedWords,
+}
+
+impl QueryOptions {
+    pub fn new() -> Self {
+        QueryOptions::default()
+    }
+
+    /// Stop the query once it has been running for `timeout`. One too
+    /// long to count from now, eg. [Duration::MAX], means no deadline.
+    pub fn set_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.deadline = Instant::now().checked_add(timeout);
+        self
+    }
+
+    pub fn set_deadline(&mut self, deadline: Instant) -> &mut Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    pub fn set_cancel_token(&mut self, token: CancelToken) -> &mut Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// Leave out notes with a content warning. They don't count towards
+    /// the limit.
+    pub fn set_content_warnings(&mut self, content_warnings: ContentWarnings) -> &mut Self {
+        self.content_warnings = content_warnings;
+        self
+    }
+
+    /// Leave out notes that mention any of these words or phrases. Like
+    /// content warnings, they don't count towards the limit.
+    pub fn set_muted_words(&mut self, muted_words: MutedWords) -> &mut Self {
+        self.muted_words = muted_words;
+        self
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    pub fn content_warnings(&self) -> ContentWarnings {
+        self.content_warnings
+    }
+
+    pub fn muted_words(&self) -> &MutedWords {
+        &self.muted_words
+    }
+
+    /// Whether the query should stop where it is
+    pub fn interrupted(&self) -> bool {
+        self.cancel.as_ref().is_some_and(|c| c.is_cancelled())
+            || self.deadline.is_some_and(|d| Instant::now() >= d)
+    }
+}
+
+/// How a query's results changed since an earlier run of it, see
+/// [Ndb::diff_query]. Removing the `removed` entries from the earlier
+/// results, last index first, then inserting the `inserted` ones, first
+/// index first, gives the new results.
+#[derive(Debug)]
+pub struct QueryDiff<'a> {
+    /// The new results, newest first
+    pub results: Vec<QueryResult<'a>>,
+
+    /// Notes that weren't in the earlier results, with their index in
+    /// `results`, in increasing order
+    pub inserted: Vec<(usize, NoteKey)>,
+
+    /// Notes that are gone, with their index in the earlier results, in
+    /// increasing order
+    pub removed: Vec<(usize, NoteKey)>,
+}
+
+impl<'a> QueryDiff<'a> {
+    /// Nothing changed
+    pub fn is_empty(&self) -> bool {
+        self.inserted.is_empty() && self.removed.is_empty()
+    }
+
+    /// The keys of the new results, to diff against next time
+    pub fn keys(&self) -> Vec<NoteKey> {
+        self.results.iter().map(|r| r.note_key).collect()
+    }
+}
+
+/// What a query found before it finished or was stopped
+#[derive(Debug)]
+pub struct PartialResults<T> {
+    pub results: Vec<T>,
+
+    /// The query was cancelled or ran out of time, so there may be more
+    /// matches than `results`
+    pub cancelled: bool,
+}
+
+impl<T> PartialResults<T> {
+    pub fn is_complete(&self) -> bool {
+        !self.cancelled
+    }
+}
+
+/// Run a query a page at a time, checking `options` in between. A single
+/// nostrdb query can't be interrupted, but the pages are small enough that
+/// stopping between them is quick.
+pub(crate) fn query_with_options<'a>(
+    ndb: &Ndb,
+    txn: &'a Transaction,
+    filters: &[Filter],
+    max_results: i32,
+    options: &QueryOptions,
+) -> PartialResults<QueryResult<'a>> {
+    let max = max_results.max(0) as usize;
+    let mut seen = HashSet::new();
+    let mut results = vec![];
+    let mut cancelled = false;
+
+    'filters: for filter in filters {
+        let limit = filter.limit().map_or(max, |limit| max.min(limit as usize));
+        let mut notes = QueryIter::new(ndb, txn, filter.clone());
+
+        let mut found = 0;
+        while found < limit {
+            if options.interrupted() {
+                cancelled = true;
+                break 'filters;
+            }
+
+            let (note_key, note) = if let Some(next) = notes.next() {
+                next
+            } else {
+                break;
+            };
+
+            if options.content_warnings.excludes(note.content_warning())
+                || options.muted_words.mutes(&note)
+            {
+                continue;
+            }
+            found += 1;
+
+            if seen.insert(note_key) {
+                results.push(QueryResult {
+                    note_size: note.size() as u64,
+                    note_key,
+                    note,
+                });
+            }
+        }
+    }
+
+    sort_newest_first(&mut results);
+    results.truncate(max);
+    PartialResults { results, cancelled }
+}
+
+/// Newest first, and the latest written first within the same second
+pub(crate) fn sort_newest_first(results: &mut [QueryResult]) {
+    results.sort_by(|a, b| {
+        b.created_at()
+            .cmp(&a.created_at())
+            .then(b.note_key.cmp(&a.note_key))
+    });
+}
+
+pub(crate) fn diff<'a>(prev: &[NoteKey], mut results: Vec<QueryResult<'a>>) -> QueryDiff<'a> {
+    let mut seen = HashSet::new();
+    results.retain(|r| seen.insert(r.note_key));
+    sort_newest_first(&mut results);
+
+    let prev_keys: HashSet<NoteKey> = prev.iter().copied().collect();
+    let inserted = results
+        .iter()
+        .enumerate()
+        .filter(|(_, r)| !prev_keys.contains(&r.note_key))
+        .map(|(i, r)| (i, r.note_key))
+        .collect();
+    let removed = prev
+        .iter()
+        .enumerate()
+        .filter(|(_, key)| !seen.contains(*key))
+        .map(|(i, key)| (i, *key))
+        .collect();
+
+    QueryDiff {
+        results,
+        inserted,
+        removed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util, Config};
+
+    #[test]
+    fn query_cancellation_works() {
+        let db = "target/testdbs/query_cancellation";
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            ndb.process_event(&test_util::hello_event())
+                .expect("process ok");
+        }
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let txn = Transaction::new(&ndb).expect("txn");
+            let filters = [Filter::new().kinds([1]).build()];
+
+            let mut options = QueryOptions::new();
+            options.set_timeout(Duration::MAX);
+            assert_eq!(options.deadline(), None);
+            options.set_timeout(Duration::from_secs(60));
+            let res = ndb.query_with_options(&txn, &filters, 10, &options);
+            assert!(res.is_complete());
+            assert_eq!(res.results.len(), 1);
+            assert_eq!(res.results[0].note.content(), "hello, world");
+
+            let token = CancelToken::new();
+            options.set_cancel_token(token.clone());
+            token.cancel();
+            let res = ndb.query_with_options(&txn, &filters, 10, &options);
+            assert!(res.cancelled);
+            assert!(res.results.is_empty());
+
+            let mut options = QueryOptions::new();
+            options.set_deadline(Instant::now());
+            let res = ndb.query_with_options(&txn, &filters, 10, &options);
+            assert!(res.cancelled);
+        }
+    }
+
+    #[test]
+    fn diff_query_works() {
+        let db = "target/testdbs/diff_query";
+        test_util::cleanup_db(db);
+
+        let write = |ndb: &Ndb, content: &str, created_at: u64| {
+            let note = crate::NoteBuilder::new()
+                .kind(1)
+                .content(content)
+                .created_at(created_at)
+                .sign(&[1u8; 32])
+                .build()
+                .expect("note");
+            let json = note.json().expect("json");
+            ndb.process_event(&format!("[\"EVENT\",\"diff\",{json}]"))
+                .expect("process ok");
+        };
+        let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+        write(&ndb, "one", 1);
+        write(&ndb, "two", 2);
+        ndb.close().expect("close");
+
+        let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+        let filters = [Filter::new().kinds([1]).build()];
+        let first = {
+            let txn = Transaction::new(&ndb).expect("txn");
+            let diff = ndb.diff_query(&txn, &[], &filters, 2).expect("diff");
+            assert_eq!(diff.inserted.len(), 2);
+            assert!(diff.removed.is_empty());
+            diff.keys()
+        };
+        {
+            let txn = Transaction::new(&ndb).expect("txn");
+            let diff = ndb.diff_query(&txn, &first, &filters, 2).expect("diff");
+            assert!(diff.is_empty());
+        }
+
+        write(&ndb, "three", 3);
+        ndb.close().expect("close");
+        let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+        let txn = Transaction::new(&ndb).expect("txn");
+        let diff = ndb.diff_query(&txn, &first, &filters, 2).expect("diff");
+        let contents: Vec<&str> = diff.results.iter().map(|r| r.note.content()).collect();
+        assert_eq!(contents, vec!["three", "two"]);
+        assert_eq!(diff.inserted, vec![(0, diff.results[0].note_key)]);
+        assert_eq!(diff.removed, vec![(1, first[1])]);
+    }
 }
@@ -0,0 +1,199 @@
+//! nostrdb's C API, for reaching what the safe API doesn't cover yet.
+//!
+//! This re-exports the generated bindings, so code using them doesn't
+//! depend on where they live inside the crate. The bindings follow the
+//! nostrdb version the crate is built against and change when it is
+//! updated. The guards below are part of the crate's API and keep their
+//! names and signatures across minor releases.
+//!
+//! Pointers to pass to the C functions come from [Ndb::as_ptr],
+//! [Transaction::as_mut_ptr], [Note::as_ptr] and friends. Nothing here
+//! checks that they outlive their use, which is why the guards are
+//! `unsafe` to construct.
+//!
+//! [Ndb::as_ptr]: crate::Ndb::as_ptr
+//! [Transaction::as_mut_ptr]: crate::Transaction::as_mut_ptr
+//! [Note::as_ptr]: crate::Note::as_ptr
+
+pub use crate::bindings::*;
+
+use std::ffi::CStr;
+use std::mem;
+
+/// Owns a `ndb_blocks`, eg. from `ndb_parse_content`, and frees it with
+/// `ndb_blocks_free` when dropped
+#[derive(Debug)]
+pub struct BlocksGuard(*mut ndb_blocks);
+
+impl BlocksGuard {
+    /// None if `ptr` is null
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to blocks from nostrdb that nothing else frees
+    pub unsafe fn from_raw(ptr: *mut ndb_blocks) -> Option<Self> {
+        (!ptr.is_null()).then_some(BlocksGuard(ptr))
+    }
+
+    pub fn as_ptr(&self) -> *mut ndb_blocks {
+        self.0
+    }
+
+    /// Give up ownership without freeing the blocks
+    pub fn into_raw(self) -> *mut ndb_blocks {
+        let ptr = self.0;
+        mem::forget(self);
+        ptr
+    }
+}
+
+impl Drop for BlocksGuard {
+    fn drop(&mut self) {
+        unsafe { ndb_blocks_free(self.0) };
+    }
+}
+
+/// An initialized `ndb_filter`, destroyed with `ndb_filter_destroy` when
+/// dropped. For filters the [FilterBuilder] can't build.
+///
+/// [FilterBuilder]: crate::FilterBuilder
+#[derive(Debug)]
+pub struct FilterGuard(ndb_filter);
+
+impl Default for FilterGuard {
+    fn default() -> Self {
+        FilterGuard::new()
+    }
+}
+
+impl FilterGuard {
+    pub fn new() -> Self {
+        FilterGuard(ndb_filter::default())
+    }
+
+    pub fn as_ptr(&self) -> *const ndb_filter {
+        &self.0
+    }
+
+    pub fn as_mut_ptr(&mut self) -> *mut ndb_filter {
+        &mut self.0
+    }
+}
+
+impl Drop for FilterGuard {
+    fn drop(&mut self) {
+        unsafe { ndb_filter_destroy(&mut self.0) };
+    }
+}
+
+/// A read transaction started with `ndb_begin_query` and ended with
+/// `ndb_end_query` when dropped. Unlike [Transaction] it isn't tied to a
+/// borrow of the [Ndb].
+///
+/// [Transaction]: crate::Transaction
+/// [Ndb]: crate::Ndb
+#[derive(Debug)]
+pub struct TxnGuard(ndb_txn);
+
+impl TxnGuard {
+    /// None if nostrdb couldn't start the transaction
+    ///
+    /// # Safety
+    ///
+    /// `ndb` must stay open until the guard is dropped
+    pub unsafe fn begin(ndb: *mut ndb) -> Option<Self> {
+        let mut txn = ndb_txn::new();
+        (ndb_begin_query(ndb, &mut txn) != 0).then_some(TxnGuard(txn))
+    }
+
+    pub fn as_mut_ptr(&mut self) -> *mut ndb_txn {
+        &mut self.0
+    }
+}
+
+impl Drop for TxnGuard {
+    fn drop(&mut self) {
+        unsafe { ndb_end_query(&mut self.0) };
+    }
+}
+
+/// A profile search cursor from `ndb_search_profile`, ended with
+/// `ndb_search_profile_end` when dropped
+#[derive(Debug)]
+pub struct ProfileSearchGuard(ndb_search);
+
+impl ProfileSearchGuard {
+    /// Start searching profiles for `query`. None if nothing matches.
+    ///
+    /// # Safety
+    ///
+    /// `txn` must be an open transaction that outlives the guard
+    pub unsafe fn start(txn: *mut ndb_txn, query: &CStr) -> Option<Self> {
+        let mut search: ndb_search = mem::zeroed();
+        (ndb_search_profile(txn, &mut search, query.as_ptr()) != 0)
+            .then_some(ProfileSearchGuard(search))
+    }
+
+    /// The profile key the cursor is on
+    pub fn profile_key(&self) -> u64 {
+        self.0.profile_key
+    }
+
+    /// Move to the next match. False once there are no more.
+    pub fn advance(&mut self) -> bool {
+        unsafe { ndb_search_profile_next(&mut self.0) != 0 }
+    }
+
+    pub fn as_mut_ptr(&mut self) -> *mut ndb_search {
+        &mut self.0
+    }
+}
+
+impl Drop for ProfileSearchGuard {
+    fn drop(&mut self) {
+        unsafe { ndb_search_profile_end(&mut self.0) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util, Config, Ndb};
+
+    #[test]
+    fn raw_guards_work() {
+        let db = "target/testdbs/raw_guards";
+        test_util::cleanup_db(db);
+        let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+
+        let mut filter = FilterGuard::new();
+        unsafe {
+            assert_eq!(
+                ndb_filter_start_field(filter.as_mut_ptr(), ndb_filter_fieldtype_NDB_FILTER_KINDS),
+                1
+            );
+            assert_eq!(ndb_filter_add_int_element(filter.as_mut_ptr(), 1), 1);
+            ndb_filter_end_field(filter.as_mut_ptr());
+            assert_eq!(ndb_filter_end(filter.as_mut_ptr()), 1);
+        }
+
+        let mut txn = unsafe { TxnGuard::begin(ndb.as_ptr()) }.expect("txn");
+        let mut results: Vec<ndb_query_result> = Vec::with_capacity(1);
+        let mut count = 0;
+        let ok = unsafe {
+            ndb_query(
+                txn.as_mut_ptr(),
+                filter.as_mut_ptr(),
+                1,
+                results.as_mut_ptr(),
+                1,
+                &mut count,
+            )
+        };
+        assert_eq!(ok, 1);
+        assert_eq!(count, 0);
+
+        let query = c"nobody";
+        assert!(unsafe { ProfileSearchGuard::start(txn.as_mut_ptr(), query) }.is_none());
+    }
+}
@@ -0,0 +1,170 @@
+use crate::util::lock;
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
+
+/// Called once for each read transaction held longer than the threshold
+/// given to [Config::set_long_reader_warning]
+///
+/// [Config::set_long_reader_warning]: crate::Config::set_long_reader_warning
+pub(crate) type ReaderWarning = Arc<dyn Fn(&ReaderInfo) + Send + Sync>;
+
+/// The longest the watcher sleeps between looking at the open readers
+const MAX_WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The shortest, so a tiny threshold doesn't keep a core busy
+const MIN_WATCH_INTERVAL: Duration = Duration::from_millis(10);
+
+/// An open read transaction, see [Ndb::reader_info]
+///
+/// [Ndb::reader_info]: crate::Ndb::reader_info
+#[derive(Debug, Clone)]
+pub struct ReaderInfo {
+    /// Unique for the lifetime of the database handle
+    pub id: u64,
+    pub opened: Instant,
+
+    /// The thread holding the transaction. LMDB read transactions can't
+    /// move between threads.
+    pub thread: ThreadId,
+    pub thread_name: Option<String>,
+}
+
+impl ReaderInfo {
+    /// How long the transaction has been open
+    pub fn age(&self) -> Duration {
+        self.opened.elapsed()
+    }
+}
+
+#[derive(Debug)]
+struct Reader {
+    info: ReaderInfo,
+    warned: bool,
+}
+
+/// The read transactions open on a database. Every open reader pins the
+/// pages it can see, so LMDB can't reuse them until it ends.
+#[derive(Debug, Default)]
+pub(crate) struct Readers {
+    next_id: AtomicU64,
+    open: Mutex<HashMap<u64, Reader>>,
+}
+
+impl Readers {
+    /// Record a transaction opened on this thread, returning its id
+    pub(crate) fn register(&self) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let thread = thread::current();
+        let info = ReaderInfo {
+            id,
+            opened: Instant::now(),
+            thread: thread.id(),
+            thread_name: thread.name().map(str::to_string),
+        };
+        let reader = Reader {
+            info,
+            warned: false,
+        };
+        lock(&self.open).insert(id, reader);
+        id
+    }
+
+    pub(crate) fn unregister(&self, id: u64) {
+        lock(&self.open).remove(&id);
+    }
+
+    /// The open readers, oldest first
+    pub(crate) fn info(&self) -> Vec<ReaderInfo> {
+        let mut readers: Vec<ReaderInfo> = lock(&self.open)
+            .values()
+            .map(|reader| reader.info.clone())
+            .collect();
+        readers.sort_by_key(|reader| reader.opened);
+        readers
+    }
+
+    /// Readers open for longer than `threshold` that haven't been warned
+    /// about yet
+    fn overdue(&self, threshold: Duration) -> Vec<ReaderInfo> {
+        lock(&self.open)
+            .values_mut()
+            .filter(|reader| !reader.warned && reader.info.age() >= threshold)
+            .map(|reader| {
+                reader.warned = true;
+                reader.info.clone()
+            })
+            .collect()
+    }
+}
+
+/// Start the thread that calls `warning` for readers held longer than
+/// `threshold`. It only holds a weak reference, and exits once the database
+/// is closed.
+pub(crate) fn spawn_watcher(
+    readers: &Arc<Readers>,
+    threshold: Duration,
+    warning: ReaderWarning,
+) -> io::Result<()> {
+    let readers: Weak<Readers> = Arc::downgrade(readers);
+    let interval = threshold.clamp(MIN_WATCH_INTERVAL, MAX_WATCH_INTERVAL);
+
+    thread::Builder::new()
+        .name("ndb-readers".to_string())
+        .spawn(move || loop {
+            thread::sleep(interval);
+            let overdue = if let Some(readers) = readers.upgrade() {
+                readers.overdue(threshold)
+            } else {
+                return;
+            };
+
+            // called without the lock, so the callback can look at the
+            // readers itself
+            for reader in &overdue {
+                warning(reader);
+            }
+        })
+        .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{test_util, Config, Ndb, Transaction};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn long_reader_warning_works() {
+        let db = "target/testdbs/long_readers";
+        test_util::cleanup_db(db);
+
+        let (tx, rx) = mpsc::channel();
+        let tx = std::sync::Mutex::new(tx);
+        let mut config = Config::new();
+        config.set_long_reader_warning(Duration::from_millis(10), move |reader| {
+            let _ = tx.lock().unwrap().send(reader.id);
+        });
+
+        let ndb = Ndb::new(db, &config).expect("ndb");
+        assert!(ndb.reader_info().is_empty());
+
+        let txn = Transaction::new(&ndb).expect("txn");
+        let readers = ndb.reader_info();
+        assert_eq!(readers.len(), 1);
+        assert_eq!(readers[0].thread, thread::current().id());
+
+        let warned = rx.recv_timeout(Duration::from_secs(5)).expect("warning");
+        assert_eq!(warned, readers[0].id);
+
+        drop(txn);
+        assert!(ndb.reader_info().is_empty());
+
+        // each reader is only warned about once
+        assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+    }
+}
@@ -0,0 +1,298 @@
+use crate::util::lock;
+use crate::{Error, Filter, Ndb, Result};
+use futures_util::{SinkExt, StreamExt};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, Notify};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message;
+
+/// How long to wait before the first reconnect attempt
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The longest we'll wait between reconnect attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// How many messages from [RelayPool::send] each relay holds while it is
+/// reconnecting or can't keep up
+const OUTBOX_SIZE: usize = 1024;
+
+/// REQ messages for the pool's active subscriptions, keyed by
+/// subscription id. These are resent whenever a relay reconnects.
+type Reqs = Arc<Mutex<BTreeMap<String, String>>>;
+
+struct Relay {
+    /// raw messages to send to the relay
+    outbox: mpsc::Sender<String>,
+
+    /// Woken when [Reqs] changes, so the connection catches up with it
+    reqs_changed: Arc<Notify>,
+    connected: Arc<AtomicBool>,
+    task: JoinHandle<()>,
+}
+
+/// A set of relay connections that feed every EVENT they receive straight
+/// into [Ndb::process_event]. Subscriptions are sent to all connected
+/// relays in the pool, and to the others once they connect, so a relay
+/// never gets a REQ for a subscription that was closed while it was away.
+/// Dropped connections are retried with exponential backoff.
+///
+/// Relays are driven by tokio tasks, so [RelayPool::add_relay] must be
+/// called from within a tokio runtime.
+pub struct RelayPool {
+    ndb: Ndb,
+    relays: HashMap<String, Relay>,
+    reqs: Reqs,
+}
+
+impl RelayPool {
+    pub fn new(ndb: &Ndb) -> Self {
+        RelayPool {
+            ndb: ndb.clone(),
+            relays: HashMap::new(),
+            reqs: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    /// Start connecting to a relay. Adding a relay that is already in the
+    /// pool does nothing.
+    pub fn add_relay(&mut self, url: &str) {
+        if self.relays.contains_key(url) {
+            return;
+        }
+
+        let (outbox, rx) = mpsc::channel(OUTBOX_SIZE);
+        let reqs_changed = Arc::new(Notify::new());
+        let connected = Arc::new(AtomicBool::new(false));
+        let task = tokio::spawn(run_relay(
+            url.to_string(),
+            self.ndb.clone(),
+            self.reqs.clone(),
+            reqs_changed.clone(),
+            rx,
+            connected.clone(),
+        ));
+
+        self.relays.insert(
+            url.to_string(),
+            Relay {
+                outbox,
+                reqs_changed,
+                connected,
+                task,
+            },
+        );
+    }
+
+    /// Disconnect from a relay and remove it from the pool. Returns false
+    /// if the relay wasn't in the pool.
+    pub fn remove_relay(&mut self, url: &str) -> bool {
+        if let Some(relay) = self.relays.remove(url) {
+            relay.task.abort();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// The urls of every relay in the pool
+    pub fn relays(&self) -> impl Iterator<Item = &str> {
+        self.relays.keys().map(String::as_str)
+    }
+
+    /// Whether we currently have an open connection to this relay
+    pub fn is_connected(&self, url: &str) -> bool {
+        self.relays
+            .get(url)
+            .is_some_and(|relay| relay.connected.load(Ordering::Relaxed))
+    }
+
+    /// Send a REQ for these filters to every relay in the pool. Subscribing
+    /// again with the same id replaces the previous filters.
+    pub fn subscribe(&mut self, sub_id: &str, filters: &[Filter]) -> Result<()> {
+        let req = Filter::req(sub_id, filters)?;
+        lock(&self.reqs).insert(sub_id.to_string(), req);
+        self.reqs_changed();
+        Ok(())
+    }
+
+    /// CLOSE a subscription on every relay in the pool
+    pub fn unsubscribe(&mut self, sub_id: &str) {
+        if lock(&self.reqs).remove(sub_id).is_some() {
+            self.reqs_changed();
+        }
+    }
+
+    fn reqs_changed(&self) {
+        for relay in self.relays.values() {
+            relay.reqs_changed.notify_one();
+        }
+    }
+
+    /// Send a raw message, eg. an EVENT to publish, to every relay in the
+    /// pool. Messages for relays that are reconnecting are sent once they
+    /// are back.
+    ///
+    /// Fails with [Error::BufferOverflow] if some relay already has too
+    /// many messages waiting. Those relays don't get this one, the others
+    /// still do.
+    pub fn send(&self, msg: &str) -> Result<()> {
+        let mut full = false;
+        for relay in self.relays.values() {
+            if let Err(mpsc::error::TrySendError::Full(_)) = relay.outbox.try_send(msg.to_string())
+            {
+                full = true;
+            }
+        }
+
+        if full {
+            return Err(Error::BufferOverflow);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for RelayPool {
+    fn drop(&mut self) {
+        for relay in self.relays.values() {
+            relay.task.abort();
+        }
+    }
+}
+
+/// The REQs and CLOSEs that bring a connection that was sent `sent` up
+/// to date with `reqs`. `sent` is updated to match.
+fn req_changes(reqs: &Reqs, sent: &mut BTreeMap<String, String>) -> Vec<String> {
+    let reqs = lock(reqs);
+    let mut msgs = vec![];
+    sent.retain(|sub_id, _| {
+        let open = reqs.contains_key(sub_id);
+        if !open {
+            msgs.push(Filter::close(sub_id));
+        }
+        open
+    });
+    for (sub_id, req) in reqs.iter() {
+        if sent.get(sub_id) != Some(req) {
+            sent.insert(sub_id.clone(), req.clone());
+            msgs.push(req.clone());
+        }
+    }
+    msgs
+}
+
+async fn run_relay(
+    url: String,
+    ndb: Ndb,
+    reqs: Reqs,
+    reqs_changed: Arc<Notify>,
+    mut outbox: mpsc::Receiver<String>,
+    connected: Arc<AtomicBool>,
+) {
+    let mut backoff = MIN_BACKOFF;
+
+    loop {
+        if let Ok((ws, _)) = tokio_tungstenite::connect_async(url.as_str()).await {
+            backoff = MIN_BACKOFF;
+            connected.store(true, Ordering::Relaxed);
+            let (mut write, mut read) = ws.split();
+
+            // a new connection has no subscriptions
+            let mut sent = BTreeMap::new();
+            let mut changes = req_changes(&reqs, &mut sent);
+            let mut ok = true;
+
+            while ok {
+                for msg in changes.drain(..) {
+                    if write.send(Message::Text(msg)).await.is_err() {
+                        ok = false;
+                        break;
+                    }
+                }
+                if !ok {
+                    break;
+                }
+
+                tokio::select! {
+                    _ = reqs_changed.notified() => changes = req_changes(&reqs, &mut sent),
+                    cmd = outbox.recv() => match cmd {
+                        Some(msg) => ok = write.send(Message::Text(msg)).await.is_ok(),
+                        // the pool is gone
+                        None => return,
+                    },
+                    msg = read.next() => match msg {
+                        Some(Ok(Message::Text(text))) => handle_message(&ndb, &url, &text),
+                        Some(Ok(Message::Close(_))) | Some(Err(_)) | None => ok = false,
+                        Some(Ok(_)) => {}
+                    },
+                }
+            }
+
+            connected.store(false, Ordering::Relaxed);
+        }
+
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// Only EVENTs go into the database, tagged with the relay they came from.
+/// EOSE, NOTICE, OK and friends are ignored.
+fn handle_message(ndb: &Ndb, url: &str, msg: &str) {
+    if message_type(msg) == Some("EVENT") {
+        let _ = ndb.process_event_from(msg, url);
+    }
+}
+
+/// The first element of a relay message, eg. `EVENT` or `EOSE`
+fn message_type(msg: &str) -> Option<&str> {
+    let rest = msg.trim_start().strip_prefix('[')?.trim_start();
+    let rest = rest.strip_prefix('"')?;
+    let end = rest.find('"')?;
+    Some(&rest[..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn req_changes_work() {
+        let reqs: Reqs = Arc::default();
+        let mut sent = BTreeMap::new();
+        let req = |sub_id, kind| Filter::req(sub_id, &[Filter::new().kinds([kind]).build()]);
+
+        lock(&reqs).insert("a".to_string(), req("a", 1).expect("req"));
+        lock(&reqs).insert("b".to_string(), req("b", 1).expect("req"));
+        let msgs = req_changes(&reqs, &mut sent);
+        assert_eq!(msgs.len(), 2);
+        assert!(req_changes(&reqs, &mut sent).is_empty());
+
+        // changed filters are sent again, closed ones are closed
+        lock(&reqs).insert("a".to_string(), req("a", 7).expect("req"));
+        lock(&reqs).remove("b");
+        assert_eq!(
+            req_changes(&reqs, &mut sent),
+            [Filter::close("b"), req("a", 7).expect("req")]
+        );
+
+        // a subscription opened and closed while disconnected never goes
+        // out on the next connection
+        lock(&reqs).insert("c".to_string(), req("c", 1).expect("req"));
+        lock(&reqs).remove("c");
+        let mut reconnected = BTreeMap::new();
+        assert_eq!(
+            req_changes(&reqs, &mut reconnected),
+            [req("a", 7).expect("req")]
+        );
+    }
+
+    #[test]
+    fn relay_messages_work() {
+        assert_eq!(message_type(r#"[ "EVENT","s",{}]"#), Some("EVENT"));
+        assert_eq!(message_type(r#"["EOSE","s"]"#), Some("EOSE"));
+        assert_eq!(message_type("{}"), None);
+    }
+}
@@ -0,0 +1,115 @@
+use crate::filter::json_string;
+use crate::{Note, QueryResult, Result};
+use std::vec;
+
+/// An `["EVENT",<sub_id>,<note>]` message, the way a relay answers a REQ
+pub fn event_message(sub_id: &str, note: &Note) -> Result<String> {
+    Ok(format!(
+        "[\"EVENT\",{},{}]",
+        json_string(sub_id),
+        note.json()?
+    ))
+}
+
+/// The `["EOSE",<sub_id>]` message that ends the stored notes of a REQ
+pub fn eose_message(sub_id: &str) -> String {
+    format!("[\"EOSE\",{}]", json_string(sub_id))
+}
+
+/// A NIP-45 `["COUNT",<sub_id>,{"count":<n>}]` message, see [Ndb::count]
+///
+/// [Ndb::count]: crate::Ndb::count
+pub fn count_message(sub_id: &str, count: u64) -> String {
+    format!("[\"COUNT\",{},{{\"count\":{count}}}]", json_string(sub_id))
+}
+
+/// The messages a relay sends in answer to a REQ: an EVENT for each
+/// stored note, newest first, then EOSE. Notes are serialized one at a
+/// time as it is iterated, so they can be written straight to a socket.
+/// Construct one with [Ndb::req_responses].
+///
+/// [Ndb::req_responses]: crate::Ndb::req_responses
+pub struct ResponseIter<'a> {
+    sub_id: String,
+    results: vec::IntoIter<QueryResult<'a>>,
+    done: bool,
+}
+
+impl<'a> ResponseIter<'a> {
+    pub(crate) fn new(sub_id: &str, results: Vec<QueryResult<'a>>) -> Self {
+        ResponseIter {
+            sub_id: sub_id.to_string(),
+            results: results.into_iter(),
+            done: false,
+        }
+    }
+
+    /// EVENT messages still to come, not counting EOSE
+    pub fn remaining(&self) -> usize {
+        self.results.len()
+    }
+}
+
+impl Iterator for ResponseIter<'_> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        // a note too big to serialize is left out rather than ending the
+        // stream early
+        for result in self.results.by_ref() {
+            if let Ok(msg) = event_message(&self.sub_id, &result.note) {
+                return Some(msg);
+            }
+        }
+
+        if self.done {
+            return None;
+        }
+        self.done = true;
+        Some(eose_message(&self.sub_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util, Config, Filter, Ndb, NoteBuilder, Transaction};
+
+    #[test]
+    fn relay_responses_work() {
+        assert_eq!(eose_message("s\"1"), r#"["EOSE","s\"1"]"#);
+        assert_eq!(count_message("c", 42), r#"["COUNT","c",{"count":42}]"#);
+
+        let db = "target/testdbs/relay_responses";
+        test_util::cleanup_db(db);
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            for created_at in [1, 2, 3] {
+                let note = NoteBuilder::new()
+                    .kind(1)
+                    .content("stored")
+                    .created_at(created_at)
+                    .sign(&[1u8; 32])
+                    .build()
+                    .expect("note");
+                let json = note.json().expect("json");
+                ndb.process_event(&format!("[\"EVENT\",\"r\",{json}]"))
+                    .expect("process ok");
+            }
+        }
+
+        let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+        let txn = Transaction::new(&ndb).expect("txn");
+        let filters = [Filter::new().kinds([1]).limit(2).build()];
+        let responses: Vec<String> = ndb
+            .req_responses(&txn, "sub", &filters, 10)
+            .expect("query")
+            .collect();
+        assert_eq!(responses.len(), 3);
+        assert!(responses[0].starts_with("[\"EVENT\",\"sub\",{"));
+        assert!(responses[0].contains("\"created_at\":3"));
+        assert_eq!(responses[2], eose_message("sub"));
+
+        assert_eq!(ndb.count(&txn, &filters).expect("count"), 3);
+    }
+}
@@ -0,0 +1,96 @@
+//! Conversions between nostrdb types and their [rust-nostr] counterparts,
+//! for projects that already use rust-nostr for networking. Everything
+//! goes through the wire json, so these are as lossless as the json is.
+//!
+//! [rust-nostr]: https://github.com/rust-nostr/nostr
+
+use crate::{Error, Filter, Note};
+use nostr::JsonUtil;
+
+impl TryFrom<&Note<'_>> for nostr::Event {
+    type Error = Error;
+
+    fn try_from(note: &Note<'_>) -> Result<Self, Error> {
+        nostr::Event::from_json(note.json()?).map_err(|_| Error::DecodeError)
+    }
+}
+
+impl TryFrom<Note<'_>> for nostr::Event {
+    type Error = Error;
+
+    fn try_from(note: Note<'_>) -> Result<Self, Error> {
+        nostr::Event::try_from(&note)
+    }
+}
+
+impl TryFrom<&nostr::Event> for Note<'static> {
+    type Error = Error;
+
+    fn try_from(event: &nostr::Event) -> Result<Self, Error> {
+        Note::owned_from_json(&event.as_json())
+    }
+}
+
+impl TryFrom<nostr::Event> for Note<'static> {
+    type Error = Error;
+
+    fn try_from(event: nostr::Event) -> Result<Self, Error> {
+        Note::try_from(&event)
+    }
+}
+
+impl TryFrom<&Filter> for nostr::Filter {
+    type Error = Error;
+
+    fn try_from(filter: &Filter) -> Result<Self, Error> {
+        nostr::Filter::from_json(filter.json()?).map_err(|_| Error::DecodeError)
+    }
+}
+
+impl TryFrom<&nostr::Filter> for Filter {
+    type Error = Error;
+
+    fn try_from(filter: &nostr::Filter) -> Result<Self, Error> {
+        Filter::from_json(&filter.as_json())
+    }
+}
+
+impl TryFrom<nostr::Filter> for Filter {
+    type Error = Error;
+
+    fn try_from(filter: nostr::Filter) -> Result<Self, Error> {
+        Filter::try_from(&filter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NoteBuilder;
+
+    #[test]
+    fn rust_nostr_conversions_work() {
+        let seckey: [u8; 32] = [1; 32];
+        let note = NoteBuilder::new()
+            .kind(1)
+            .content("hello, world")
+            .start_tag()
+            .tag_str("t")
+            .tag_str("nostrdb")
+            .sign(&seckey)
+            .build()
+            .expect("note");
+
+        let event = nostr::Event::try_from(&note).expect("event");
+        let back = Note::try_from(&event).expect("note");
+        assert_eq!(back.id(), note.id());
+        assert_eq!(back.content(), "hello, world");
+        assert_eq!(back.tags().count(), 1);
+
+        let filter = Filter::new().kinds([1]).limit(10).build();
+        let nfilter = nostr::Filter::try_from(&filter).expect("nostr filter");
+        let back = Filter::try_from(&nfilter).expect("filter");
+        assert_eq!(back.limit(), Some(10));
+        assert!(back.matches(&note));
+    }
+}
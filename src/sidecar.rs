@@ -0,0 +1,289 @@
+use crate::util::{decode_hex32, decode_hex64, encode_hex, lock};
+use crate::{Ndb, Transaction};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// How many more dead lines than live ones a log can have before it is
+/// rewritten during a session
+const COMPACT_SLACK: usize = 4096;
+
+/// What a [Sidecar] knows about stored notes, rebuilt from its log
+pub(crate) trait SidecarIndex: Default + std::fmt::Debug {
+    /// Written after the id on each line, so it can't contain a newline
+    type Value: Clone + std::fmt::Debug;
+
+    fn parse(value: &str) -> Option<Self::Value>;
+    fn format(value: &Self::Value) -> String;
+
+    /// Add a value for a stored note. False if it adds nothing.
+    fn insert(&mut self, id: [u8; 32], value: Self::Value) -> bool;
+
+    /// Whether inserting `value` would add nothing
+    fn has(&self, id: &[u8; 32], value: &Self::Value) -> bool;
+
+    /// Whether `new` adds nothing to `old` when both arrived with the same
+    /// copy of a note
+    fn redundant(old: &Self::Value, new: &Self::Value) -> bool;
+
+    /// Every value, in the order they should be written back
+    fn for_each(&self, f: impl FnMut(&[u8; 32], &Self::Value));
+}
+
+/// An event that arrived, but wasn't seen stored yet
+#[derive(Debug)]
+struct Arrival<V> {
+    sig: [u8; 64],
+    value: V,
+
+    /// Logged in an earlier session. nostrdb writes or drops everything
+    /// queued before it closes, so if the note isn't stored now it never
+    /// will be.
+    stale: bool,
+}
+
+/// An append-only log next to the database for facts about notes that
+/// nostrdb has no table for, like where they came from.
+///
+/// Facts are noted when an event arrives, before nostrdb has checked it,
+/// as `? <id> <sig> <value>` lines. They're only taken as true once the
+/// note is stored with that signature, which is checked against the
+/// caller's transaction the next time the log is read, and then a plain
+/// `<id> <value>` line is added. So an event nostrdb rejects, including a
+/// forged copy of a real note, never counts. The log is rewritten without
+/// the lines that no longer mean anything once a session has checked the
+/// ones left over from the last.
+#[derive(Debug)]
+pub(crate) struct Sidecar<I: SidecarIndex> {
+    path: PathBuf,
+    state: Mutex<Option<SidecarState<I>>>,
+}
+
+#[derive(Debug)]
+struct SidecarState<I: SidecarIndex> {
+    index: I,
+
+    /// By note id
+    pending: HashMap<[u8; 32], Vec<Arrival<I::Value>>>,
+    file: File,
+
+    /// Plain lines in the log that are in the index
+    live: usize,
+
+    /// Lines in the log that add nothing any more
+    dead: usize,
+
+    /// The log as an earlier session left it hasn't been checked and
+    /// compacted yet
+    unchecked: bool,
+}
+
+fn open_log(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn line<I: SidecarIndex>(id: &[u8; 32], value: &I::Value) -> String {
+    format!("{} {}\n", encode_hex(id), I::format(value))
+}
+
+fn pending_line<I: SidecarIndex>(id: &[u8; 32], sig: &[u8; 64], value: &I::Value) -> String {
+    format!(
+        "? {} {} {}\n",
+        encode_hex(id),
+        encode_hex(sig),
+        I::format(value)
+    )
+}
+
+fn parse_line<I: SidecarIndex>(line: &str) -> Option<([u8; 32], I::Value)> {
+    let (id, value) = line.split_once(' ')?;
+    Some((decode_hex32(id)?, I::parse(value)?))
+}
+
+fn parse_pending<I: SidecarIndex>(line: &str) -> Option<([u8; 32], [u8; 64], I::Value)> {
+    let (id, rest) = line.split_once(' ')?;
+    let (sig, value) = rest.split_once(' ')?;
+    Some((decode_hex32(id)?, decode_hex64(sig)?, I::parse(value)?))
+}
+
+impl<I: SidecarIndex> Sidecar<I> {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Sidecar {
+            path,
+            state: Mutex::new(None),
+        }
+    }
+
+    /// Note values for events that were just handed to nostrdb, by id and
+    /// signature
+    pub(crate) fn record(
+        &self,
+        arrivals: impl IntoIterator<Item = ([u8; 32], [u8; 64], I::Value)>,
+    ) -> io::Result<()> {
+        let mut state = lock(&self.state);
+        let state = self.load(&mut state)?;
+
+        let mut lines = String::new();
+        for (id, sig, value) in arrivals {
+            if state.index.has(&id, &value) {
+                continue;
+            }
+            let waiting = state.pending.entry(id).or_default();
+            if waiting
+                .iter()
+                .any(|arrival| arrival.sig == sig && I::redundant(&arrival.value, &value))
+            {
+                continue;
+            }
+            lines.push_str(&pending_line::<I>(&id, &sig, &value));
+            waiting.push(Arrival {
+                sig,
+                value,
+                stale: false,
+            });
+        }
+        if lines.is_empty() {
+            return Ok(());
+        }
+        state.file.write_all(lines.as_bytes())
+    }
+
+    /// Run `f` on the index, once everything stored as of `txn` is in it.
+    /// None if the log can't be read.
+    pub(crate) fn with<R>(
+        &self,
+        ndb: &Ndb,
+        txn: &Transaction,
+        f: impl FnOnce(&I) -> R,
+    ) -> Option<R> {
+        let mut state = lock(&self.state);
+        let state = self.load(&mut state).ok()?;
+        if !state.pending.is_empty() || state.unchecked {
+            // what couldn't be written is checked again next time
+            let _ = self.settle(state, ndb, txn);
+        }
+        Some(f(&state.index))
+    }
+
+    /// Move the arrivals whose notes are stored into the index
+    fn settle(&self, state: &mut SidecarState<I>, ndb: &Ndb, txn: &Transaction) -> io::Result<()> {
+        let mut lines = String::new();
+        let (mut live, mut dead) = (0, 0);
+        let index = &mut state.index;
+
+        state.pending.retain(|id, arrivals| {
+            let note = match ndb.get_note_by_id(txn, id) {
+                Ok(note) => note,
+                Err(_) => {
+                    // not written yet, or dropped
+                    let before = arrivals.len();
+                    arrivals.retain(|arrival| !arrival.stale);
+                    dead += before - arrivals.len();
+                    return !arrivals.is_empty();
+                }
+            };
+
+            for arrival in arrivals.drain(..) {
+                if arrival.sig == *note.sig() && index.insert(*id, arrival.value.clone()) {
+                    lines.push_str(&line::<I>(id, &arrival.value));
+                    live += 1;
+                }
+                dead += 1;
+            }
+            false
+        });
+
+        state.live += live;
+        state.dead += dead;
+        let unchecked = std::mem::take(&mut state.unchecked);
+        if !lines.is_empty() {
+            state.file.write_all(lines.as_bytes())?;
+        }
+        if state.dead > 0 && (unchecked || state.dead > state.live + COMPACT_SLACK) {
+            self.compact(state)?;
+        }
+        Ok(())
+    }
+
+    /// Rewrite the log with only what's in the index and still pending
+    fn compact(&self, state: &mut SidecarState<I>) -> io::Result<()> {
+        let mut out = String::new();
+        let mut live = 0;
+        state.index.for_each(|id, value| {
+            out.push_str(&line::<I>(id, value));
+            live += 1;
+        });
+        for (id, arrivals) in &state.pending {
+            for arrival in arrivals {
+                out.push_str(&pending_line::<I>(id, &arrival.sig, &arrival.value));
+            }
+        }
+
+        // a crash part way leaves the old log in place
+        let tmp = self.path.with_extension("log.tmp");
+        fs::write(&tmp, out)?;
+        fs::rename(&tmp, &self.path)?;
+        state.file = open_log(&self.path)?;
+        state.live = live;
+        state.dead = 0;
+        Ok(())
+    }
+
+    fn load<'a>(
+        &self,
+        state: &'a mut Option<SidecarState<I>>,
+    ) -> io::Result<&'a mut SidecarState<I>> {
+        if let Some(state) = state {
+            return Ok(state);
+        }
+
+        let mut index = I::default();
+        let mut pending: HashMap<[u8; 32], Vec<Arrival<I::Value>>> = HashMap::new();
+        let (mut live, mut dead) = (0, 0);
+        if let Ok(file) = File::open(&self.path) {
+            for line in BufReader::new(file).lines() {
+                let line = line?;
+
+                // a line cut short by a crash is skipped
+                if let Some(line) = line.strip_prefix("? ") {
+                    match parse_pending::<I>(line) {
+                        Some((id, sig, value)) => pending.entry(id).or_default().push(Arrival {
+                            sig,
+                            value,
+                            stale: true,
+                        }),
+                        None => dead += 1,
+                    }
+                } else {
+                    let inserted =
+                        parse_line::<I>(&line).is_some_and(|(id, value)| index.insert(id, value));
+                    if inserted {
+                        live += 1;
+                    } else {
+                        dead += 1;
+                    }
+                }
+            }
+        }
+
+        // most were confirmed further down the log
+        pending.retain(|id, arrivals| {
+            let before = arrivals.len();
+            arrivals.retain(|arrival| !index.has(id, &arrival.value));
+            dead += before - arrivals.len();
+            !arrivals.is_empty()
+        });
+
+        let file = open_log(&self.path)?;
+        Ok(state.insert(SidecarState {
+            index,
+            unchecked: dead > 0 || !pending.is_empty(),
+            pending,
+            file,
+            live,
+            dead,
+        }))
+    }
+}
@@ -0,0 +1,144 @@
+use crate::{bindings, Error, Ndb, Note, NoteKey, Result, Transaction};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+
+/// The first bytes of a snapshot, with the format version
+const MAGIC: &[u8; 8] = b"NDBSNAP1";
+
+/// Bigger records than this are taken to be corruption rather than notes
+const MAX_NOTE_SIZE: usize = 64 * 1024 * 1024;
+
+/// Notes handed to nostrdb at a time on import
+const IMPORT_BATCH: usize = 1024;
+
+/// Write every note as nostrdb packed it, after [MAGIC]: a little endian
+/// u64 note key, a u32 size, then the note itself
+pub(crate) fn export(ndb: &Ndb, txn: &Transaction, path: &Path) -> Result<usize> {
+    let file = File::create(path).map_err(|_| Error::IoError)?;
+    let mut out = BufWriter::new(file);
+    let mut written = 0;
+
+    let mut write = |bytes: &[u8]| out.write_all(bytes).map_err(|_| Error::IoError);
+    write(MAGIC)?;
+    for (note_key, note) in ndb.iter_notes(txn, NoteKey::new(1)) {
+        let size = u32::try_from(note.size()).map_err(|_| Error::BufferOverflow)?;
+        let bytes = unsafe { std::slice::from_raw_parts(note.as_ptr() as *const u8, note.size()) };
+        write(&note_key.as_u64().to_le_bytes())?;
+        write(&size.to_le_bytes())?;
+        write(bytes)?;
+        written += 1;
+    }
+
+    out.flush().map_err(|_| Error::IoError)?;
+    Ok(written)
+}
+
+/// Queue the notes of a snapshot made by [export]. nostrdb only takes
+/// events as json, so each note is written back out as an EVENT message
+/// for its ingester, which parses it again. This is the slow half, and
+/// it can't get faster until nostrdb accepts packed notes.
+pub(crate) fn import(ndb: &Ndb, path: &Path) -> Result<usize> {
+    let file = File::open(path).map_err(|_| Error::IoError)?;
+    let mut input = BufReader::new(file);
+
+    let mut magic = [0u8; 8];
+    input
+        .read_exact(&mut magic)
+        .map_err(|_| Error::DecodeError)?;
+    if &magic != MAGIC {
+        return Err(Error::DecodeError);
+    }
+
+    let mut batch = String::new();
+    let mut queued = 0;
+    while let Some(note) = read_note(&mut input)? {
+        batch.push_str(r#"["EVENT","snapshot","#);
+        batch.push_str(&note.json()?);
+        batch.push_str("]\n");
+        queued += 1;
+        if queued % IMPORT_BATCH == 0 {
+            ndb.process_events(&batch)?;
+            batch.clear();
+        }
+    }
+    if !batch.is_empty() {
+        ndb.process_events(&batch)?;
+    }
+    Ok(queued)
+}
+
+/// The next note in a snapshot, None at the end
+fn read_note(input: &mut impl Read) -> Result<Option<Note<'static>>> {
+    let mut header = [0u8; 12];
+    match input.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(_) => return Err(Error::IoError),
+    }
+
+    // the source's note key is of no use here, the writer hands out new
+    // ones
+    let size = u32::from_le_bytes([header[8], header[9], header[10], header[11]]) as usize;
+    if size < std::mem::size_of::<bindings::ndb_note>() || size > MAX_NOTE_SIZE {
+        return Err(Error::DecodeError);
+    }
+
+    let ptr = unsafe { libc::malloc(size as libc::size_t) as *mut u8 };
+    if ptr.is_null() {
+        return Err(Error::BufferOverflow);
+    }
+    unsafe { std::ptr::write_bytes(ptr, 0, size) };
+    // freed with the note from here on
+    let note = Note::new_owned(ptr as *mut bindings::ndb_note, size);
+    let buf = unsafe { std::slice::from_raw_parts_mut(ptr, size) };
+    input.read_exact(buf).map_err(|_| Error::DecodeError)?;
+    Ok(Some(note))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{test_util, Config, Error, Filter, Ndb, NoteBuilder, Transaction};
+
+    #[test]
+    fn snapshot_works() {
+        let src = "target/testdbs/snapshot_src";
+        let dst = "target/testdbs/snapshot_dst";
+        test_util::cleanup_db(src);
+        test_util::cleanup_db(dst);
+        let path = "target/testdbs/snapshot_src/notes.snapshot";
+
+        {
+            let ndb = Ndb::new(src, &Config::new()).expect("ndb");
+            for created_at in 1..=3 {
+                let note = NoteBuilder::new()
+                    .kind(1)
+                    .content("moving devices")
+                    .created_at(created_at)
+                    .sign(&[1u8; 32])
+                    .build()
+                    .expect("note");
+                let json = note.json().expect("json");
+                ndb.process_event(&format!("[\"EVENT\",\"snap\",{json}]"))
+                    .expect("process ok");
+            }
+        }
+
+        let ndb = Ndb::new(src, &Config::new()).expect("ndb");
+        let txn = Transaction::new(&ndb).expect("txn");
+        assert_eq!(ndb.export_snapshot(&txn, path).expect("export"), 3);
+
+        {
+            let dst = Ndb::new(dst, &Config::new()).expect("ndb");
+            assert_eq!(dst.import_snapshot(path).expect("import"), 3);
+            assert_eq!(dst.import_snapshot("Cargo.toml"), Err(Error::DecodeError));
+        }
+
+        let dst = Ndb::new(dst, &Config::new()).expect("ndb");
+        let txn = Transaction::new(&dst).expect("txn");
+        let results = dst
+            .query(&txn, &[Filter::new().kinds([1]).build()], 10)
+            .expect("query");
+        assert_eq!(results.len(), 3);
+    }
+}
@@ -0,0 +1,141 @@
+use crate::sidecar::{Sidecar, SidecarIndex};
+use crate::{Ndb, Transaction};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// The log of relay urls notes arrived from, in the database directory
+pub(crate) const SOURCES_FILE: &str = "sources.log";
+
+/// Where each stored note came from, see [Config::set_record_sources]. A
+/// note can arrive from several relays before nostrdb has written it, and
+/// a relay can send a copy with a bad signature, so a source only counts
+/// once the note it brought is stored; see [Sidecar].
+///
+/// [Config::set_record_sources]: crate::Config::set_record_sources
+#[derive(Debug)]
+pub(crate) struct Sources {
+    log: Sidecar<SourceIndex>,
+}
+
+/// Every source of each note, in the order they were recorded
+#[derive(Debug, Default)]
+pub(crate) struct SourceIndex {
+    by_id: HashMap<[u8; 32], Vec<String>>,
+}
+
+impl SidecarIndex for SourceIndex {
+    type Value = String;
+
+    fn parse(value: &str) -> Option<String> {
+        Some(value.to_string())
+    }
+
+    fn format(value: &String) -> String {
+        value.clone()
+    }
+
+    fn insert(&mut self, id: [u8; 32], source: String) -> bool {
+        if self.has(&id, &source) {
+            return false;
+        }
+        self.by_id.entry(id).or_default().push(source);
+        true
+    }
+
+    fn has(&self, id: &[u8; 32], source: &String) -> bool {
+        self.by_id
+            .get(id)
+            .is_some_and(|sources| sources.contains(source))
+    }
+
+    fn redundant(old: &String, new: &String) -> bool {
+        old == new
+    }
+
+    fn for_each(&self, mut f: impl FnMut(&[u8; 32], &String)) {
+        for (id, sources) in &self.by_id {
+            for source in sources {
+                f(id, source);
+            }
+        }
+    }
+}
+
+impl Sources {
+    pub(crate) fn new(db_dir: &Path) -> Self {
+        Sources {
+            log: Sidecar::new(db_dir.join(SOURCES_FILE)),
+        }
+    }
+
+    /// Remember that the event with this id and signature was just handed
+    /// to nostrdb from `source`. Sources can't contain newlines, they are
+    /// cut off at the first one.
+    pub(crate) fn record(&self, id: [u8; 32], sig: [u8; 64], source: &str) -> std::io::Result<()> {
+        let source = source.lines().next().unwrap_or("");
+        self.log.record([(id, sig, source.to_string())])
+    }
+
+    /// Every source the note was seen at, in the order they were recorded
+    pub(crate) fn get(&self, ndb: &Ndb, txn: &Transaction, id: &[u8; 32]) -> Vec<String> {
+        self.log
+            .with(ndb, txn, |index| index.by_id.get(id).cloned())
+            .flatten()
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{test_util, Config, Ndb, Transaction};
+
+    #[test]
+    fn note_sources_work() {
+        let event = test_util::hello_event();
+        let db = "target/testdbs/note_sources";
+        test_util::cleanup_db(db);
+
+        let mut config = Config::new();
+        config.set_record_sources(true);
+        {
+            let ndb = Ndb::new(db, &config).expect("ndb");
+            ndb.process_event_from(&event, "wss://relay.damus.io")
+                .expect("process ok");
+            ndb.process_event_from(&event, "wss://nos.lol")
+                .expect("process ok");
+            ndb.process_event_from(&event, "wss://nos.lol")
+                .expect("process ok");
+
+            // a copy of the note with a bad signature, and a note that
+            // never verifies at all
+            let forged = event.replace("25675\"", "25676\"");
+            ndb.process_event_from(&forged, "wss://forged")
+                .expect("process ok");
+            let bogus = forged.replace("31a3", "31a4");
+            ndb.process_event_from(&bogus, "wss://bogus")
+                .expect("process ok");
+        }
+
+        let id = crate::util::decode_hex32(
+            "702555e52e82cc24ad517ba78c21879f6e47a7c0692b9b20df147916ae8731a3",
+        )
+        .expect("id");
+        for _ in 0..2 {
+            let ndb = Ndb::new(db, &config).expect("ndb");
+            let txn = Transaction::new(&ndb).expect("txn");
+            let note = ndb.get_note_by_id(&txn, &id).expect("note");
+            assert_eq!(
+                note.sources(&txn),
+                vec![
+                    "wss://relay.damus.io".to_string(),
+                    "wss://nos.lol".to_string()
+                ]
+            );
+        }
+
+        // the lines for the forged and bogus events were compacted away
+        let log = std::fs::read_to_string(format!("{db}/{}", super::SOURCES_FILE)).expect("log");
+        assert_eq!(log.lines().count(), 2, "{log}");
+        assert!(log.lines().all(|line| !line.starts_with('?')), "{log}");
+    }
+}
@@ -0,0 +1,88 @@
+use crate::bindings;
+use std::ffi::CStr;
+
+/// Entry counts and sizes for a database or a kind of note
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct StatCounts {
+    pub count: usize,
+    pub key_size: usize,
+    pub value_size: usize,
+}
+
+impl StatCounts {
+    fn new(counts: &bindings::ndb_stat_counts) -> Self {
+        StatCounts {
+            count: counts.count,
+            key_size: counts.key_size,
+            value_size: counts.value_size,
+        }
+    }
+
+    /// Total bytes used by keys and values
+    pub fn total_size(&self) -> usize {
+        self.key_size + self.value_size
+    }
+}
+
+/// A snapshot of how much is stored in nostrdb. Get one with [Ndb::stat].
+///
+/// [Ndb::stat]: crate::Ndb::stat
+#[derive(Debug, Clone, Default)]
+pub struct Stat {
+    /// Counts for each of nostrdb's internal databases, by name
+    pub dbs: Vec<(&'static str, StatCounts)>,
+
+    /// Counts for the note kinds nostrdb keeps track of, by name
+    pub kinds: Vec<(&'static str, StatCounts)>,
+
+    /// Counts for all other note kinds
+    pub other_kinds: StatCounts,
+}
+
+fn static_name(name: *const ::std::os::raw::c_char) -> &'static str {
+    if name.is_null() {
+        return "unknown";
+    }
+
+    // these are string literals in nostrdb
+    unsafe { CStr::from_ptr(name) }
+        .to_str()
+        .unwrap_or("unknown")
+}
+
+impl Stat {
+    pub(crate) fn new(stat: &bindings::ndb_stat) -> Self {
+        let dbs = stat
+            .dbs
+            .iter()
+            .enumerate()
+            .map(|(i, counts)| {
+                let name = unsafe { bindings::ndb_db_name(i as bindings::ndb_dbs) };
+                (static_name(name), StatCounts::new(counts))
+            })
+            .collect();
+
+        let kinds = stat
+            .common_kinds
+            .iter()
+            .enumerate()
+            .map(|(i, counts)| {
+                let name = unsafe { bindings::ndb_kind_name(i as bindings::ndb_common_kind) };
+                (static_name(name), StatCounts::new(counts))
+            })
+            .collect();
+
+        Stat {
+            dbs,
+            kinds,
+            other_kinds: StatCounts::new(&stat.other_kinds),
+        }
+    }
+
+    /// The number of notes in the database
+    pub fn notes(&self) -> usize {
+        self.dbs
+            .get(bindings::ndb_dbs_NDB_DB_NOTE as usize)
+            .map_or(0, |(_, counts)| counts.count)
+    }
+}
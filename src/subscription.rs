@@ -1,3 +1,27 @@
+use crate::util::lock;
+use crate::{
+    bindings, ContentWarnings, Error, Filter, MutedWords, Ndb, Note, NoteKey, Result, Transaction,
+};
+use std::collections::{HashMap, HashSet, VecDeque};
+#[cfg(target_arch = "wasm32")]
+use std::future::Future;
+use std::io;
+use std::mem::ManuallyDrop;
+use std::os::raw::{c_int, c_void};
+#[cfg(target_arch = "wasm32")]
+use std::pin::Pin;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+#[cfg(target_arch = "wasm32")]
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::task;
+
+/// How many notes to move at a time out of a nostrdb subscription queue
+const DRAIN_BATCH: u32 = 256;
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub struct Subscription(u64);
@@ -9,4 +33,1002 @@ impl Subscription {
     pub fn id(self) -> u64 {
         self.0
     }
+
+    /// Whether notes were dropped because this subscription's inbox was
+    /// full. This stays set until [Subscription::clear_overflowed].
+    pub fn overflowed(self, ndb: &Ndb) -> bool {
+        ndb.subs()
+            .get(&self.0)
+            .is_some_and(|state| state.overflowed)
+    }
+
+    pub fn clear_overflowed(self, ndb: &Ndb) {
+        if let Some(state) = ndb.subs().get_mut(&self.0) {
+            state.overflowed = false;
+        }
+    }
+
+    /// Counters for diagnosing subscriptions that seem to have stopped
+    /// updating. None if this isn't a live subscription.
+    pub fn stats(self, ndb: &Ndb) -> Option<SubscriptionStats> {
+        ndb.subs().get(&self.0).map(|state| SubscriptionStats {
+            queued: state.inbox.len(),
+            ..state.stats
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct SubscriptionStats {
+    /// Notes written to the database that matched the subscription
+    pub matched: u64,
+
+    /// Notes returned by polls and waits
+    pub delivered: u64,
+
+    /// Notes discarded because the inbox was full
+    pub dropped: u64,
+
+    /// Notes waiting to be delivered
+    pub queued: usize,
+
+    pub last_match: Option<Instant>,
+}
+
+impl SubscriptionStats {
+    pub fn since_last_match(&self) -> Option<Duration> {
+        self.last_match.map(|last| last.elapsed())
+    }
+}
+
+/// What happens to new notes when a subscription's inbox is full
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum OverflowPolicy {
+    /// Discard the oldest queued note to make room
+    #[default]
+    DropOldest,
+
+    /// Discard the incoming note
+    DropNewest,
+
+    /// Discard the incoming note, and fail [Ndb::wait_for_notes] with
+    /// [Error::SubscriptionOverflow] until the overflow is cleared so the
+    /// consumer knows it has to resync
+    Error,
+}
+
+/// Per-subscription options, see [Ndb::subscribe_with_config]
+#[derive(Debug, Clone)]
+pub struct SubscriptionConfig {
+    capacity: usize,
+    overflow: OverflowPolicy,
+    content_warnings: ContentWarnings,
+    muted_words: MutedWords,
+}
+
+impl Default for SubscriptionConfig {
+    fn default() -> Self {
+        SubscriptionConfig::new()
+    }
+}
+
+impl SubscriptionConfig {
+    pub const DEFAULT_CAPACITY: usize = 4096;
+
+    pub fn new() -> Self {
+        SubscriptionConfig {
+            capacity: Self::DEFAULT_CAPACITY,
+            overflow: OverflowPolicy::default(),
+            content_warnings: ContentWarnings::default(),
+            muted_words: MutedWords::new(),
+        }
+    }
+
+    /// The maximum number of notes queued for this subscription before the
+    /// overflow policy kicks in
+    pub fn set_capacity(&mut self, capacity: usize) -> &mut Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    pub fn set_overflow_policy(&mut self, policy: OverflowPolicy) -> &mut Self {
+        self.overflow = policy;
+        self
+    }
+
+    /// Leave out notes with a content warning. They are dropped before
+    /// they are queued, so they don't take up capacity.
+    pub fn set_content_warnings(&mut self, content_warnings: ContentWarnings) -> &mut Self {
+        self.content_warnings = content_warnings;
+        self
+    }
+
+    /// Leave out notes that mention any of these words or phrases, before
+    /// they are queued
+    pub fn set_muted_words(&mut self, muted_words: MutedWords) -> &mut Self {
+        self.muted_words = muted_words;
+        self
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        self.overflow
+    }
+
+    pub fn content_warnings(&self) -> ContentWarnings {
+        self.content_warnings
+    }
+
+    pub fn muted_words(&self) -> &MutedWords {
+        &self.muted_words
+    }
+}
+
+/// Bookkeeping for a [Subscription]. Notes are moved out of nostrdb's
+/// subscription queue into `inbox` as soon as they are written, so that the
+/// capacity and overflow policy are under our control.
+///
+/// nostrdb can't change the filters of a live subscription either, so
+/// updating them swaps in a new nostrdb subscription underneath the same id.
+#[derive(Debug)]
+pub(crate) struct SubState {
+    /// The nostrdb subscription currently backing this one
+    pub(crate) current: u64,
+    pub(crate) filters: Vec<Filter>,
+    pub(crate) config: SubscriptionConfig,
+    pub(crate) inbox: VecDeque<u64>,
+    pub(crate) overflowed: bool,
+    pub(crate) stats: SubscriptionStats,
+
+    /// Notes moved out of a replaced nostrdb subscription. A note written
+    /// while both the old and new nostrdb subscriptions were live can show
+    /// up again on the new one.
+    pub(crate) swapped: HashSet<u64>,
+
+    /// Notes that still have to be checked against the hidden filters. A
+    /// thread that already has a read transaction open can't begin
+    /// another, so when one drains without passing it in, the notes wait
+    /// here for the drain thread.
+    pub(crate) unchecked: Vec<u64>,
+}
+
+impl SubState {
+    pub(crate) fn new(current: u64, filters: Vec<Filter>, config: SubscriptionConfig) -> Self {
+        SubState {
+            current,
+            filters,
+            config,
+            inbox: VecDeque::new(),
+            overflowed: false,
+            stats: SubscriptionStats::default(),
+            swapped: HashSet::new(),
+            unchecked: Vec::new(),
+        }
+    }
+
+    pub(crate) fn take(&mut self, max_notes: usize) -> Vec<u64> {
+        let n = max_notes.min(self.inbox.len());
+        self.stats.delivered += n as u64;
+        self.inbox.drain(..n).collect()
+    }
+
+    fn pop(&mut self) -> Option<u64> {
+        let key = self.inbox.pop_front()?;
+        self.stats.delivered += 1;
+        Some(key)
+    }
+
+    /// Drop notes that were already moved over from a replaced nostrdb
+    /// subscription
+    pub(crate) fn dedup(&mut self, keys: &mut Vec<u64>) {
+        let newest_swapped = if let Some(newest) = self.swapped.iter().max() {
+            *newest
+        } else {
+            return;
+        };
+
+        keys.retain(|k| !self.swapped.contains(k));
+
+        // note keys are assigned in order, so once we see a note newer than
+        // anything swapped there is nothing left to dedup
+        if keys.iter().any(|k| *k > newest_swapped) {
+            self.swapped.clear();
+        }
+    }
+
+    /// Drop notes that the subscriber already got from a query. Like
+    /// swapped notes, they may still be on their way from nostrdb.
+    pub(crate) fn exclude(&mut self, keys: &HashSet<u64>) {
+        self.inbox.retain(|k| !keys.contains(k));
+        self.unchecked.retain(|k| !keys.contains(k));
+        self.swapped.extend(keys);
+    }
+
+    /// Queue notes, applying the overflow policy
+    pub(crate) fn push(&mut self, keys: Vec<u64>) {
+        if keys.is_empty() {
+            return;
+        }
+        self.stats.matched += keys.len() as u64;
+        self.stats.last_match = Some(Instant::now());
+
+        for key in keys {
+            if self.inbox.len() >= self.config.capacity {
+                self.overflowed = true;
+                self.stats.dropped += 1;
+                match self.config.overflow {
+                    OverflowPolicy::DropOldest => {
+                        self.inbox.pop_front();
+                    }
+                    OverflowPolicy::DropNewest | OverflowPolicy::Error => continue,
+                }
+            }
+            self.inbox.push_back(key);
+        }
+    }
+
+    /// Move everything in the nostrdb subscription queue into our inbox.
+    /// Hidden notes are dropped on the way, reading them with `txn` if
+    /// the caller has one open. Returns false if some couldn't be read
+    /// and were left for the drain thread.
+    pub(crate) fn drain(&mut self, ndb: *mut bindings::ndb, txn: Option<&Transaction>) -> bool {
+        let hides = self.config.content_warnings == ContentWarnings::Exclude
+            || !self.config.muted_words.is_empty();
+        let mut checked = true;
+        loop {
+            let mut keys = poll_raw(ndb, self.current, DRAIN_BATCH);
+            let done = keys.len() < DRAIN_BATCH as usize;
+            self.dedup(&mut keys);
+            if hides {
+                // older notes waiting to be checked go first
+                let mut pending = std::mem::take(&mut self.unchecked);
+                pending.append(&mut keys);
+                self.unchecked = drop_hidden(ndb, txn, &self.config, &mut pending);
+                checked &= self.unchecked.is_empty();
+                keys = pending;
+            }
+            self.push(keys);
+            if done {
+                break;
+            }
+        }
+        checked
+    }
+
+    fn failed(&self) -> bool {
+        self.overflowed && self.config.overflow == OverflowPolicy::Error
+    }
+}
+
+#[derive(Debug, Default)]
+struct Wakeup {
+    dirty: bool,
+    shutdown: bool,
+}
+
+/// Subscription state shared between an [Ndb], its waiters and the thread
+/// that moves notes out of nostrdb's subscription queues
+#[derive(Debug, Default)]
+pub(crate) struct SubRegistry {
+    subs: Mutex<HashMap<u64, SubState>>,
+
+    /// Signalled when notes are queued or a subscription goes away
+    changed: Condvar,
+
+    wakeup: Mutex<Wakeup>,
+    wakeup_cond: Condvar,
+
+    /// Futures waiting on [SubRegistry::changed] without a thread, see
+    /// [WaitForNotes]
+    #[cfg(target_arch = "wasm32")]
+    wakers: Mutex<Vec<Waker>>,
+
+    /// The subscription callback the config came with, called after ours
+    chained: Option<ChainedCallback>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ChainedCallback {
+    cb: unsafe extern "C" fn(*mut c_void, u64),
+    ctx: *mut c_void,
+}
+
+/// The filters in here are only ever touched with the lock held, and the
+/// chained callback's context is the caller's to make thread safe, as it
+/// would be if nostrdb called it directly
+unsafe impl Send for SubRegistry {}
+unsafe impl Sync for SubRegistry {}
+
+impl SubRegistry {
+    /// A registry that passes nostrdb's subscription callbacks on to `cb`
+    /// too, if there is one
+    pub(crate) fn chained(cb: bindings::ndb_sub_fn, ctx: *mut c_void) -> Self {
+        SubRegistry {
+            chained: cb.map(|cb| ChainedCallback { cb, ctx }),
+            ..SubRegistry::default()
+        }
+    }
+
+    pub(crate) fn lock(&self) -> MutexGuard<'_, HashMap<u64, SubState>> {
+        lock(&self.subs)
+    }
+
+    /// Wake up anyone waiting on a subscription
+    pub(crate) fn notify(&self) {
+        self.changed.notify_all();
+
+        #[cfg(target_arch = "wasm32")]
+        for waker in std::mem::take(&mut *lock(&self.wakers)) {
+            waker.wake();
+        }
+    }
+
+    pub(crate) fn wake(&self) {
+        lock(&self.wakeup).dirty = true;
+        self.wakeup_cond.notify_one();
+    }
+
+    pub(crate) fn shutdown(&self) {
+        lock(&self.wakeup).shutdown = true;
+        self.wakeup_cond.notify_one();
+    }
+
+    /// Block until the subscription has notes queued
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn wait(&self, id: u64, max_notes: usize) -> Result<Vec<u64>> {
+        let notes = self.wait_any(&[Subscription::new(id)], max_notes)?;
+        Ok(notes.into_iter().map(|(_, key)| key).collect())
+    }
+
+    /// Like [SubRegistry::wait], giving up with no notes after `timeout`,
+    /// or once `cancelled` is set. A timeout too big to add to the time now
+    /// waits forever. `cancelled` has to be set with the lock held, and
+    /// followed by [SubRegistry::notify].
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn wait_timeout(
+        &self,
+        id: u64,
+        max_notes: usize,
+        timeout: Duration,
+        cancelled: &AtomicBool,
+    ) -> Result<Vec<u64>> {
+        let deadline = Instant::now().checked_add(timeout);
+        let ids = [Subscription::new(id)];
+        let mut subs = self.lock();
+        loop {
+            if cancelled.load(Ordering::SeqCst) {
+                return Ok(vec![]);
+            }
+            if let Some(notes) = try_take(&mut subs, &ids, max_notes)? {
+                return Ok(notes.into_iter().map(|(_, key)| key).collect());
+            }
+            let deadline = if let Some(deadline) = deadline {
+                deadline
+            } else {
+                subs = self.changed.wait(subs).unwrap_or_else(|e| e.into_inner());
+                continue;
+            };
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(vec![]);
+            }
+            subs = self
+                .changed
+                .wait_timeout(subs, remaining)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+    }
+
+    /// Block until any of the subscriptions have notes queued. Fails once
+    /// none of them exist anymore.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn wait_any(
+        &self,
+        ids: &[Subscription],
+        max_notes: usize,
+    ) -> Result<Vec<(Subscription, u64)>> {
+        let mut subs = self.lock();
+        loop {
+            if let Some(notes) = try_take(&mut subs, ids, max_notes)? {
+                return Ok(notes);
+            }
+            subs = self.changed.wait(subs).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    fn drain_loop(&self, ndb: NdbPtr) {
+        loop {
+            {
+                let mut wakeup = lock(&self.wakeup);
+                while !wakeup.dirty && !wakeup.shutdown {
+                    wakeup = self
+                        .wakeup_cond
+                        .wait(wakeup)
+                        .unwrap_or_else(|e| e.into_inner());
+                }
+                if wakeup.shutdown {
+                    return;
+                }
+                wakeup.dirty = false;
+            }
+
+            for state in self.lock().values_mut() {
+                state.drain(ndb.0, None);
+            }
+            self.notify();
+        }
+    }
+}
+
+/// Stops a [SubRegistry::wait_timeout] on another thread when dropped, eg.
+/// along with a future that was waiting on it
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) struct CancelOnDrop {
+    ndb: Ndb,
+    cancelled: Arc<AtomicBool>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl CancelOnDrop {
+    pub(crate) fn new(ndb: &Ndb) -> Self {
+        CancelOnDrop {
+            ndb: ndb.clone(),
+            cancelled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub(crate) fn flag(&self) -> Arc<AtomicBool> {
+        self.cancelled.clone()
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        // set with the lock held so a waiter can't miss it between looking
+        // and going to sleep
+        let subs = self.ndb.subs();
+        self.cancelled.store(true, Ordering::SeqCst);
+        drop(subs);
+        self.ndb.sub_registry().notify();
+    }
+}
+
+/// Take whatever is queued for any of the subscriptions, or None if nothing
+/// is yet. Fails once none of them exist anymore.
+fn try_take(
+    subs: &mut HashMap<u64, SubState>,
+    ids: &[Subscription],
+    max_notes: usize,
+) -> Result<Option<Vec<(Subscription, u64)>>> {
+    let mut live = false;
+    for sub in ids {
+        if let Some(state) = subs.get(&sub.id()) {
+            if state.failed() {
+                return Err(Error::SubscriptionOverflow);
+            }
+            live = true;
+        }
+    }
+    if !live {
+        return Err(Error::SubscriptionError);
+    }
+
+    let notes = take_merged(subs, ids, max_notes);
+    Ok((!notes.is_empty()).then_some(notes))
+}
+
+/// Resolves once any of the subscriptions have notes queued. There is no
+/// blocking pool to park a waiter on in wasm, so the futures there register
+/// a waker with the [SubRegistry] instead.
+#[cfg(target_arch = "wasm32")]
+pub(crate) struct WaitForNotes {
+    ndb: Ndb,
+    ids: Vec<Subscription>,
+    max_notes: usize,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl WaitForNotes {
+    pub(crate) fn new(ndb: &Ndb, ids: Vec<Subscription>, max_notes: usize) -> Self {
+        WaitForNotes {
+            ndb: ndb.clone(),
+            ids,
+            max_notes,
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Future for WaitForNotes {
+    type Output = Result<Vec<(Subscription, u64)>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let registry = self.ndb.sub_registry();
+        let mut subs = registry.lock();
+        match try_take(&mut subs, &self.ids, self.max_notes) {
+            Ok(Some(notes)) => Poll::Ready(Ok(notes)),
+            Ok(None) => {
+                // registered with the inboxes still locked, so a note queued
+                // after we looked always wakes us
+                lock(&registry.wakers).push(cx.waker().clone());
+                Poll::Pending
+            }
+            Err(err) => Poll::Ready(Err(err)),
+        }
+    }
+}
+
+/// Take up to `max_notes` from several inboxes, oldest note key first
+fn take_merged(
+    subs: &mut HashMap<u64, SubState>,
+    ids: &[Subscription],
+    max_notes: usize,
+) -> Vec<(Subscription, u64)> {
+    let mut notes = vec![];
+
+    while notes.len() < max_notes {
+        let oldest = ids
+            .iter()
+            .filter_map(|sub| {
+                let key = subs.get(&sub.id())?.inbox.front()?;
+                Some((*sub, *key))
+            })
+            .min_by_key(|(_, key)| *key);
+
+        let (sub, key) = if let Some(oldest) = oldest {
+            oldest
+        } else {
+            break;
+        };
+
+        if let Some(state) = subs.get_mut(&sub.id()) {
+            state.pop();
+        }
+        notes.push((sub, key));
+    }
+
+    notes
+}
+
+/// A group of subscriptions that are polled together, so that a UI with
+/// many views (timeline, notifications, DMs...) only needs one task
+/// waiting on the database. Notes are returned with the subscription they
+/// matched, oldest first. Everything in the set is unsubscribed when it is
+/// dropped.
+#[derive(Debug)]
+pub struct SubscriptionSet {
+    ndb: Ndb,
+    subs: Vec<Subscription>,
+}
+
+impl SubscriptionSet {
+    pub fn new(ndb: &Ndb) -> Self {
+        SubscriptionSet {
+            ndb: ndb.clone(),
+            subs: vec![],
+        }
+    }
+
+    /// Create a new subscription owned by this set
+    pub fn subscribe(&mut self, filters: &[Filter]) -> Result<Subscription> {
+        let sub = self.ndb.subscribe(filters)?;
+        self.subs.push(sub);
+        Ok(sub)
+    }
+
+    /// Take ownership of an existing subscription
+    pub fn insert(&mut self, sub: Subscription) {
+        if !self.subs.contains(&sub) {
+            self.subs.push(sub);
+        }
+    }
+
+    /// Remove a subscription from the set and unsubscribe it
+    pub fn unsubscribe(&mut self, sub: Subscription) -> Result<()> {
+        self.subs.retain(|s| *s != sub);
+        self.ndb.unsubscribe(sub)
+    }
+
+    pub fn subscriptions(&self) -> &[Subscription] {
+        &self.subs
+    }
+
+    pub fn len(&self) -> usize {
+        self.subs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subs.is_empty()
+    }
+
+    /// Get up to `max_notes` queued notes across every subscription in the
+    /// set without blocking
+    pub fn poll_for_notes(&self, max_notes: u32) -> Vec<(Subscription, NoteKey)> {
+        let mut subs = self.ndb.subs();
+        for sub in &self.subs {
+            if let Some(state) = subs.get_mut(&sub.id()) {
+                if !state.drain(self.ndb.as_ptr(), None) {
+                    self.ndb.sub_registry().wake();
+                }
+            }
+        }
+
+        take_merged(&mut subs, &self.subs, max_notes as usize)
+            .into_iter()
+            .map(|(sub, key)| (sub, NoteKey::new(key)))
+            .collect()
+    }
+
+    /// Wait until any subscription in the set has notes
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn wait_for_notes(&self, max_notes: u32) -> Result<Vec<(Subscription, NoteKey)>> {
+        let ndb = self.ndb.clone();
+        let subs = self.subs.clone();
+        let handle =
+            task::spawn_blocking(move || ndb.sub_registry().wait_any(&subs, max_notes as usize));
+
+        match handle.await {
+            Ok(Ok(notes)) => Ok(notes
+                .into_iter()
+                .map(|(sub, key)| (sub, NoteKey::new(key)))
+                .collect()),
+            Ok(Err(err)) => Err(err),
+            Err(_) => Err(Error::SubscriptionError),
+        }
+    }
+
+    /// Wait until any subscription in the set has notes
+    #[cfg(target_arch = "wasm32")]
+    pub async fn wait_for_notes(&self, max_notes: u32) -> Result<Vec<(Subscription, NoteKey)>> {
+        let notes = WaitForNotes::new(&self.ndb, self.subs.clone(), max_notes as usize).await?;
+        Ok(notes
+            .into_iter()
+            .map(|(sub, key)| (sub, NoteKey::new(key)))
+            .collect())
+    }
+}
+
+impl Drop for SubscriptionSet {
+    fn drop(&mut self) {
+        for sub in self.subs.drain(..) {
+            let _ = self.ndb.unsubscribe(sub);
+        }
+    }
+}
+
+/// Something delivered by a [BackfillSubscription]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SubscriptionEvent {
+    Note(NoteKey),
+
+    /// All of the stored notes have been delivered. Everything after this
+    /// is a live match.
+    EndOfStoredEvents,
+}
+
+/// A subscription that first delivers matching stored notes, then live
+/// ones. Construct one with [Ndb::subscribe_with_backfill]. The
+/// subscription is unsubscribed when this is dropped.
+#[derive(Debug)]
+pub struct BackfillSubscription {
+    ndb: Ndb,
+    sub: Subscription,
+    stored: VecDeque<NoteKey>,
+    eose_pending: bool,
+}
+
+impl BackfillSubscription {
+    pub(crate) fn new(ndb: &Ndb, sub: Subscription, stored: Vec<NoteKey>) -> Self {
+        BackfillSubscription {
+            ndb: ndb.clone(),
+            sub,
+            stored: stored.into(),
+            eose_pending: true,
+        }
+    }
+
+    pub fn subscription(&self) -> Subscription {
+        self.sub
+    }
+
+    /// Whether all the stored notes and the end of stored events marker
+    /// have been delivered
+    pub fn is_live(&self) -> bool {
+        !self.eose_pending
+    }
+
+    fn take_stored(&mut self, max_notes: usize) -> Vec<SubscriptionEvent> {
+        let mut events = vec![];
+        while events.len() < max_notes && self.eose_pending {
+            if let Some(key) = self.stored.pop_front() {
+                events.push(SubscriptionEvent::Note(key));
+            } else {
+                self.eose_pending = false;
+                events.push(SubscriptionEvent::EndOfStoredEvents);
+            }
+        }
+        events
+    }
+
+    pub fn poll_for_notes(&mut self, max_notes: u32) -> Vec<SubscriptionEvent> {
+        let mut events = self.take_stored(max_notes as usize);
+        let remaining = max_notes as usize - events.len();
+        if remaining > 0 && self.is_live() {
+            let live = self.ndb.poll_for_notes(self.sub, remaining as u32);
+            events.extend(live.into_iter().map(SubscriptionEvent::Note));
+        }
+        events
+    }
+
+    pub async fn wait_for_notes(&mut self, max_notes: u32) -> Result<Vec<SubscriptionEvent>> {
+        let events = self.take_stored(max_notes as usize);
+        if !events.is_empty() {
+            return Ok(events);
+        }
+
+        let live = self.ndb.wait_for_notes(self.sub, max_notes).await?;
+        Ok(live.into_iter().map(SubscriptionEvent::Note).collect())
+    }
+}
+
+impl Drop for BackfillSubscription {
+    fn drop(&mut self) {
+        let _ = self.ndb.unsubscribe(self.sub);
+    }
+}
+
+struct NdbPtr(*mut bindings::ndb);
+
+/// The drain thread is stopped before the database is destroyed
+unsafe impl Send for NdbPtr {}
+
+/// Start the thread that moves notes into subscription inboxes. It runs
+/// until [SubRegistry::shutdown].
+pub(crate) fn spawn_drainer(
+    registry: Arc<SubRegistry>,
+    ndb: *mut bindings::ndb,
+) -> io::Result<thread::JoinHandle<()>> {
+    let ndb = NdbPtr(ndb);
+    thread::Builder::new()
+        .name("ndb-subscriptions".to_string())
+        .spawn(move || registry.drain_loop(ndb))
+}
+
+/// Called by the nostrdb writer whenever a subscription has new notes. This
+/// happens with nostrdb's subscription lock held, so we can't call back into
+/// nostrdb here; the drain thread does the actual polling.
+pub(crate) unsafe extern "C" fn sub_callback(ctx: *mut c_void, subid: u64) {
+    let registry = &*(ctx as *const SubRegistry);
+    registry.wake();
+    if let Some(chained) = registry.chained {
+        (chained.cb)(chained.ctx, subid);
+    }
+}
+
+/// Drop the notes the config hides: ones with a content warning or muted
+/// words. They are read with `txn`, or a transaction of our own, as the
+/// drain thread has no [Ndb] to make a [Transaction] with. Notes that
+/// can't be read, because there is no transaction or the caller's is
+/// older than them, are taken out and returned to be checked later.
+fn drop_hidden(
+    ndb: *mut bindings::ndb,
+    txn: Option<&Transaction>,
+    config: &SubscriptionConfig,
+    keys: &mut Vec<u64>,
+) -> Vec<u64> {
+    if keys.is_empty() {
+        return vec![];
+    }
+
+    let mut own = bindings::ndb_txn::new();
+    let ptr = match txn {
+        Some(txn) => txn.as_mut_ptr(),
+        None => {
+            if unsafe { bindings::ndb_begin_query(ndb, &mut own) } == 0 {
+                return std::mem::take(keys);
+            }
+            &mut own as *mut bindings::ndb_txn
+        }
+    };
+
+    let mut unread = vec![];
+    keys.retain(|key| {
+        let mut len = 0;
+        let ptr = unsafe { bindings::ndb_get_note_by_key(ptr, *key, &mut len) };
+        if ptr.is_null() {
+            // a transaction of our own sees every note that was queued
+            if txn.is_some() {
+                unread.push(*key);
+                return false;
+            }
+            return true;
+        }
+        // the note lives in the database, it must not be freed
+        let note = ManuallyDrop::new(Note::new_owned(ptr, len));
+        !config.content_warnings.excludes(note.content_warning())
+            && !config.muted_words.mutes(&note)
+    });
+
+    if txn.is_none() {
+        unsafe { bindings::ndb_end_query(&mut own) };
+    }
+    unread
+}
+
+pub(crate) fn poll_raw(ndb: *mut bindings::ndb, subid: u64, max_notes: u32) -> Vec<u64> {
+    let mut vec = vec![];
+    vec.reserve_exact(max_notes as usize);
+
+    unsafe {
+        let res = bindings::ndb_poll_for_notes(ndb, subid, vec.as_mut_ptr(), max_notes as c_int);
+        vec.set_len(res as usize);
+    };
+
+    vec
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util, Config};
+
+    unsafe extern "C" fn count_calls(ctx: *mut c_void, _subid: u64) {
+        let calls = &*(ctx as *const std::sync::atomic::AtomicUsize);
+        calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    #[test]
+    fn config_sub_callback_is_chained() {
+        let db = "target/testdbs/chained_sub_callback";
+        test_util::cleanup_db(db);
+
+        static CALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        let mut config = Config::new();
+        config.config.sub_cb = Some(count_calls);
+        config.config.sub_cb_ctx = &CALLS as *const _ as *mut c_void;
+
+        let ndb = Ndb::new(db, &config).expect("ndb");
+        let sub = ndb
+            .subscribe(&[Filter::new().kinds([1]).build()])
+            .expect("sub");
+        ndb.process_event(&test_util::hello_event())
+            .expect("process ok");
+
+        // our own delivery still works, and the config's callback heard
+        // about the same write
+        let notes = ndb
+            .sub_registry()
+            .wait_timeout(sub.id(), 1, Duration::from_secs(5), &AtomicBool::new(false))
+            .expect("wait");
+        assert_eq!(notes.len(), 1);
+        assert!(CALLS.load(std::sync::atomic::Ordering::SeqCst) >= 1);
+    }
+
+    #[tokio::test]
+    async fn subscription_set_works() {
+        let db = "target/testdbs/subscription_set";
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let mut set = SubscriptionSet::new(&ndb);
+
+            let notes = set
+                .subscribe(&[Filter::new().kinds(vec![1]).build()])
+                .expect("sub");
+            let profiles = set
+                .subscribe(&[Filter::new().kinds(vec![0]).build()])
+                .expect("sub");
+            assert_eq!(set.len(), 2);
+            assert_eq!(ndb.subscription_count(), 2);
+
+            let waiter = set.wait_for_notes(10);
+            ndb.process_event(&test_util::hello_event())
+                .expect("process ok");
+            let res = waiter.await.expect("await ok");
+            assert_eq!(res, vec![(notes, NoteKey::new(1))]);
+            assert!(set.poll_for_notes(10).is_empty());
+
+            let stats = notes.stats(&ndb).expect("stats");
+            assert_eq!(stats.matched, 1);
+            assert_eq!(stats.delivered, 1);
+            assert_eq!(stats.queued, 0);
+            assert!(profiles.stats(&ndb).expect("stats").last_match.is_none());
+
+            set.unsubscribe(profiles).expect("unsub");
+            assert_eq!(set.subscriptions(), &[notes]);
+
+            drop(set);
+            assert_eq!(ndb.subscription_count(), 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn poll_with_txn_open_works() {
+        let db = "target/testdbs/poll_with_txn_open";
+        test_util::cleanup_db(db);
+
+        let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+        let mut muted = MutedWords::new();
+        muted.add("bitcoin");
+        let mut config = SubscriptionConfig::new();
+        config.set_muted_words(muted);
+        let sub = ndb
+            .subscribe_with_config(&[Filter::new().kinds([1]).build()], &config)
+            .expect("sub");
+
+        let write = |content: &str| {
+            let note = crate::NoteBuilder::new()
+                .kind(1)
+                .content(content)
+                .sign(&[1u8; 32])
+                .build()
+                .expect("note");
+            let json = note.json().expect("json");
+            ndb.process_event(&format!("[\"EVENT\",\"mute\",{json}]"))
+                .expect("process ok");
+        };
+
+        // polling on a thread with a transaction open can't check the notes
+        // itself, and must not let them through unchecked
+        let mut keys = vec![];
+        {
+            let txn = Transaction::new(&ndb).expect("txn");
+            write("buy bitcoin");
+            write("gm");
+            for _ in 0..500 {
+                keys.extend(
+                    ndb.poll_for_results(&txn, sub, 10)
+                        .iter()
+                        .map(|r| r.note_key),
+                );
+                keys.extend(ndb.poll_for_notes(sub, 10));
+                if !keys.is_empty() {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        }
+        if keys.is_empty() {
+            keys = ndb.wait_for_notes(sub, 10).await.expect("wait");
+        }
+
+        let txn = Transaction::new(&ndb).expect("txn");
+        let results = ndb.get_results_by_keys(&txn, &keys);
+        let contents: Vec<&str> = results.iter().map(|r| r.note.content()).collect();
+        assert_eq!(contents, vec!["gm"]);
+    }
+
+    #[test]
+    fn overflow_policies_work() {
+        let mut config = SubscriptionConfig::new();
+        config.set_capacity(2);
+
+        let mut state = SubState::new(1, vec![], config.clone());
+        state.push(vec![1, 2, 3]);
+        assert!(state.overflowed);
+        assert_eq!(state.take(10), vec![2, 3]);
+        assert_eq!(state.stats.matched, 3);
+        assert_eq!(state.stats.delivered, 2);
+        assert_eq!(state.stats.dropped, 1);
+        assert!(state.stats.last_match.is_some());
+
+        config.set_overflow_policy(OverflowPolicy::DropNewest);
+        let mut state = SubState::new(1, vec![], config.clone());
+        state.push(vec![1, 2, 3]);
+        assert_eq!(state.take(10), vec![1, 2]);
+        assert!(!state.failed());
+
+        config.set_overflow_policy(OverflowPolicy::Error);
+        let mut state = SubState::new(1, vec![], config);
+        state.push(vec![1, 2]);
+        assert!(!state.overflowed);
+        state.push(vec![3]);
+        assert!(state.failed());
+    }
 }
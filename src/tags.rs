@@ -1,4 +1,5 @@
-use crate::{bindings, NdbStr, Note};
+use crate::util::encode_hex;
+use crate::{bindings, NdbStr, NdbStrVariant, Note};
 
 #[derive(Debug, Clone)]
 pub struct Tag<'n> {
@@ -174,11 +175,36 @@ impl<'a> Iterator for TagsIter<'a> {
     }
 }
 
+/// The keys a note is filed under in [Ndb::TAG_INDEX]: the name of each
+/// single letter tag followed by its value. Ids nostrdb stored packed are
+/// put back in hex, the way they appear in the event.
+///
+/// [Ndb::TAG_INDEX]: crate::Ndb::TAG_INDEX
+pub(crate) fn index_keys(note: &Note) -> Vec<Vec<u8>> {
+    let mut keys = Vec::new();
+    for tag in note.tags() {
+        if tag.count() < 2 {
+            continue;
+        }
+        let name = match tag.get_unchecked(0).variant().str() {
+            Some(name) if name.chars().count() == 1 => name,
+            _ => continue,
+        };
+        let mut key = name.as_bytes().to_vec();
+        match tag.get_unchecked(1).variant() {
+            NdbStrVariant::Id(id) => key.extend_from_slice(encode_hex(id).as_bytes()),
+            NdbStrVariant::Str(value) => key.extend_from_slice(value.as_bytes()),
+        }
+        keys.push(key);
+    }
+    keys
+}
+
 #[cfg(test)]
 mod tests {
     use crate::config::Config;
     use crate::test_util;
-    use crate::{Filter, Ndb, NdbStrVariant, Transaction};
+    use crate::{Error, Filter, Ndb, NdbStrVariant, NoteBuilder, Transaction};
 
     #[tokio::test]
     async fn tag_iter_works() {
@@ -246,4 +272,55 @@ mod tests {
             assert_eq!(tags_iter.next().is_none(), true);
         }
     }
+
+    #[test]
+    fn tag_prefix_scan_works() {
+        let db = "target/testdbs/tag_prefix_scan";
+        test_util::cleanup_db(db);
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let txn = Transaction::new(&ndb).expect("txn");
+            assert_eq!(
+                ndb.iter_tag(&txn, 'r', "", 10).map(|r| r.len()),
+                Err(Error::NotFound)
+            );
+            drop(txn);
+            let urls = [
+                "https://example.com/a",
+                "https://example.org/b",
+                "https://example.com/c",
+            ];
+            for (created_at, url) in urls.into_iter().enumerate() {
+                let note = NoteBuilder::new()
+                    .kind(1)
+                    .content("link")
+                    .created_at(created_at as u64 + 1)
+                    .start_tag()
+                    .tag_str("r")
+                    .tag_str(url)
+                    .start_tag()
+                    .tag_str("p")
+                    .tag_str(&"07".repeat(32))
+                    .sign(&[1u8; 32])
+                    .build()
+                    .expect("note");
+                let json = note.json().expect("json");
+                ndb.process_event(&format!("[\"EVENT\",\"t\",{json}]"))
+                    .expect("process ok");
+            }
+        }
+
+        let ndb = Ndb::new(db, Config::new().set_tag_index(true)).expect("ndb");
+        let txn = Transaction::new(&ndb).expect("txn");
+        let results = ndb
+            .iter_tag(&txn, 'r', "https://example.com", 10)
+            .expect("scan");
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].note.created_at(), 3);
+        assert_eq!(results[1].note.created_at(), 1);
+
+        let results = ndb.iter_tag(&txn, 'p', "0707", 10).expect("scan");
+        assert_eq!(results.len(), 3);
+        assert!(ndb.iter_tag(&txn, 'e', "", 10).expect("scan").is_empty());
+    }
 }
@@ -1,9 +1,42 @@
+use crate::author_stats::STATS_FILE;
+use crate::first_seen::FIRST_SEEN_FILE;
+use crate::media::MEDIA_FILE;
+use crate::migrate::APP_VERSION_FILE;
+use crate::persistent::SUBSCRIPTIONS_FILE;
+use crate::sources::SOURCES_FILE;
 use std::fs;
 use std::path::Path;
 
+/// Files the bindings keep next to nostrdb's, which a fresh database
+/// shouldn't inherit
+const SIDECAR_FILES: &[&str] = &[
+    SOURCES_FILE,
+    FIRST_SEEN_FILE,
+    MEDIA_FILE,
+    STATS_FILE,
+    SUBSCRIPTIONS_FILE,
+    APP_VERSION_FILE,
+];
+
+/// A signed kind 1 note from 2023 saying "hello, world"
+#[allow(dead_code)]
+pub const HELLO_NOTE: &str = r#"{"id": "702555e52e82cc24ad517ba78c21879f6e47a7c0692b9b20df147916ae8731a3","pubkey": "32bf915904bfde2d136ba45dde32c88f4aca863783999faea2e847a8fafd2f15","created_at": 1702675561,"kind": 1,"tags": [],"content": "hello, world","sig": "2275c5f5417abfd644b7bc74f0388d70feb5d08b6f90fa18655dda5c95d013bfbc5258ea77c05b7e40e0ee51d8a2efa931dc7a0ec1db4c0a94519762c6625675"}"#;
+
+/// [HELLO_NOTE] in a relay `EVENT` message
+#[allow(dead_code)]
+pub fn hello_event() -> String {
+    format!(r#"["EVENT","s",{}]"#, HELLO_NOTE)
+}
+
 #[allow(dead_code)]
 pub fn cleanup_db(path: &str) {
     let p = Path::new(path);
     let _ = fs::remove_file(p.join("data.mdb"));
     let _ = fs::remove_file(p.join("lock.mdb"));
+    for file in SIDECAR_FILES {
+        let file = p.join(file);
+        let _ = fs::remove_file(file.with_extension("log.tmp"));
+        let _ = fs::remove_file(file.with_extension("tmp"));
+        let _ = fs::remove_file(file);
+    }
 }
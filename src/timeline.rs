@@ -0,0 +1,333 @@
+use crate::iter::QueryCursor;
+use crate::{Filter, Ndb, NoteKey, QueryIter, Result, SubscriptionSet, Transaction};
+use std::cmp::Reverse;
+use std::collections::HashSet;
+
+/// A note in a [Timeline]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct TimelineEntry {
+    pub created_at: u64,
+    pub key: NoteKey,
+}
+
+impl TimelineEntry {
+    /// Newest first, and the latest written first within the same second
+    fn order(&self) -> Reverse<(u64, NoteKey)> {
+        Reverse((self.created_at, self.key))
+    }
+}
+
+/// The notes matching several filters, eg. follows, hashtags and lists
+/// merged into one feed, newest first and without duplicates. Construct
+/// one with [Ndb::timeline], load stored notes with [Timeline::load_older]
+/// and pick up new ones with [Timeline::poll] or [Timeline::wait].
+///
+/// The timeline also remembers how far the user has read, see
+/// [Timeline::mark_seen]. Its subscription is unsubscribed when it is
+/// dropped.
+#[derive(Debug)]
+pub struct Timeline {
+    ndb: Ndb,
+    filters: Vec<Filter>,
+    set: SubscriptionSet,
+    entries: Vec<TimelineEntry>,
+    keys: HashSet<NoteKey>,
+    seen_until: Option<u64>,
+
+    /// How far back [Timeline::load_older] has got
+    loaded: QueryCursor,
+
+    /// Written notes that were polled but not read yet
+    pending: Vec<NoteKey>,
+}
+
+impl Timeline {
+    pub(crate) fn new(ndb: &Ndb, filters: &[Filter]) -> Result<Self> {
+        // subscribe before anything is loaded, so notes written in between
+        // aren't missed
+        let mut set = SubscriptionSet::new(ndb);
+        set.subscribe(filters)?;
+
+        Ok(Timeline {
+            ndb: ndb.clone(),
+            filters: filters.to_vec(),
+            set,
+            entries: vec![],
+            keys: HashSet::new(),
+            seen_until: None,
+            loaded: QueryCursor::default(),
+            pending: vec![],
+        })
+    }
+
+    pub fn filters(&self) -> &[Filter] {
+        &self.filters
+    }
+
+    /// Everything loaded so far, newest first
+    pub fn entries(&self) -> &[TimelineEntry] {
+        &self.entries
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = NoteKey> + '_ {
+        self.entries.iter().map(|entry| entry.key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Load up to `limit` stored notes from each filter that are older
+    /// than the ones loaded before, for the initial load and infinite
+    /// scrolling. Returns how many notes were added.
+    pub fn load_older(&mut self, txn: &Transaction, limit: i32) -> Result<usize> {
+        let limit = limit.max(0) as usize;
+        if limit == 0 {
+            return Ok(0);
+        }
+
+        loop {
+            let mut found = vec![];
+
+            // a filter that filled its page may have more notes older than
+            // its oldest one, so nothing older than that is complete yet
+            let mut cutoff = None;
+            for filter in &self.filters {
+                let notes = QueryIter::resume(&self.ndb, txn, filter.clone(), self.loaded.clone());
+                let before = found.len();
+                found.extend(notes.take(limit).map(|(key, note)| TimelineEntry {
+                    created_at: note.created_at(),
+                    key,
+                }));
+                if found.len() - before == limit {
+                    cutoff = cutoff.max(found.last().map(|entry| entry.created_at));
+                }
+            }
+
+            found.retain(|entry| Some(entry.created_at) >= cutoff);
+            found.sort_by_key(TimelineEntry::order);
+            for entry in &found {
+                self.loaded.pass(entry.created_at, entry.key);
+            }
+            let added = found
+                .into_iter()
+                .filter(|entry| self.insert(*entry))
+                .count();
+
+            // everything on the page may have been written live already,
+            // there can still be more past it
+            if added > 0 || cutoff.is_none() {
+                return Ok(added);
+            }
+        }
+    }
+
+    /// Add up to `max_notes` newly written notes without blocking, returning
+    /// the ones that weren't already in the timeline. Notes written after
+    /// `txn` began are kept for a later poll.
+    pub fn poll(&mut self, txn: &Transaction, max_notes: u32) -> Result<Vec<TimelineEntry>> {
+        let notes = self.set.poll_for_notes(max_notes);
+        self.pending.extend(notes.into_iter().map(|(_, key)| key));
+        Ok(self.add_written(txn))
+    }
+
+    /// Wait until new notes are written. They are read with a transaction
+    /// of its own, so no other transaction can be open on this thread; if
+    /// one is, this fails and the notes stay queued for the next poll.
+    pub async fn wait(&mut self, max_notes: u32) -> Result<Vec<TimelineEntry>> {
+        loop {
+            if !self.pending.is_empty() {
+                let added = {
+                    let txn = Transaction::new(&self.ndb)?;
+                    self.add_written(&txn)
+                };
+                if !added.is_empty() {
+                    return Ok(added);
+                }
+            }
+            let notes = self.set.wait_for_notes(max_notes).await?;
+            self.pending.extend(notes.into_iter().map(|(_, key)| key));
+        }
+    }
+
+    fn add_written(&mut self, txn: &Transaction) -> Vec<TimelineEntry> {
+        let mut added = vec![];
+        let mut later = vec![];
+        for key in std::mem::take(&mut self.pending) {
+            if self.keys.contains(&key) {
+                continue;
+            }
+            let created_at = match self.ndb.get_note_by_key(txn, key) {
+                Ok(note) => note.created_at(),
+                Err(_) => {
+                    later.push(key);
+                    continue;
+                }
+            };
+            let entry = TimelineEntry { created_at, key };
+            if self.insert(entry) {
+                added.push(entry);
+            }
+        }
+        self.pending = later;
+        added.sort_by_key(TimelineEntry::order);
+        added
+    }
+
+    fn insert(&mut self, entry: TimelineEntry) -> bool {
+        if !self.keys.insert(entry.key) {
+            return false;
+        }
+        let at = self
+            .entries
+            .partition_point(|other| other.order() < entry.order());
+        self.entries.insert(at, entry);
+        true
+    }
+
+    /// The created_at of the newest note the user has seen
+    pub fn seen_until(&self) -> Option<u64> {
+        self.seen_until
+    }
+
+    /// Mark everything created up to `created_at` as seen. The watermark
+    /// never moves backwards.
+    pub fn mark_seen(&mut self, created_at: u64) {
+        self.seen_until = Some(self.seen_until.map_or(created_at, |s| s.max(created_at)));
+    }
+
+    /// Mark everything in the timeline as seen
+    pub fn mark_all_seen(&mut self) {
+        if let Some(newest) = self.entries.first() {
+            self.mark_seen(newest.created_at);
+        }
+    }
+
+    /// The notes newer than the watermark, newest first. Everything is
+    /// unseen until something is marked seen.
+    pub fn unseen(&self) -> &[TimelineEntry] {
+        let seen_until = if let Some(seen_until) = self.seen_until {
+            seen_until
+        } else {
+            return &self.entries;
+        };
+        let end = self
+            .entries
+            .partition_point(|entry| entry.created_at > seen_until);
+        &self.entries[..end]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{test_util, Config, Filter, Ndb, Note, NoteBuilder, Transaction};
+
+    fn note(seckey: &[u8; 32], created_at: u64, hashtag: Option<&str>) -> Note<'static> {
+        let builder = NoteBuilder::new()
+            .kind(1)
+            .content("gm")
+            .created_at(created_at);
+        let builder = match hashtag {
+            Some(t) => builder.start_tag().tag_str("t").tag_str(t),
+            None => builder,
+        };
+        builder.sign(seckey).build().expect("note")
+    }
+
+    fn write(ndb: &Ndb, note: Note) {
+        let json = note.json().expect("json");
+        ndb.process_event(&format!("[\"EVENT\",\"timeline\",{json}]"))
+            .expect("process ok");
+    }
+
+    #[tokio::test]
+    async fn timeline_works() {
+        let db = "target/testdbs/timeline";
+        test_util::cleanup_db(db);
+
+        let (friend, stranger) = ([1u8; 32], [2u8; 32]);
+        let friend_pk = *note(&friend, 0, None).pubkey();
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            write(&ndb, note(&friend, 10, None));
+            // matches both filters
+            write(&ndb, note(&friend, 20, Some("nostr")));
+            write(&ndb, note(&stranger, 30, Some("nostr")));
+            write(&ndb, note(&stranger, 40, None));
+        }
+
+        let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+        let follows = Filter::new().kinds([1]).authors([&friend_pk]).build();
+        let hashtag = Filter::new()
+            .kinds([1])
+            .tags(["nostr".to_string()], 't')
+            .build();
+        let mut timeline = ndb.timeline(&[follows, hashtag]).expect("timeline");
+
+        {
+            let txn = Transaction::new(&ndb).expect("txn");
+            assert_eq!(timeline.load_older(&txn, 10).expect("load"), 3);
+            assert_eq!(timeline.load_older(&txn, 10).expect("load"), 0);
+        }
+        let created: Vec<u64> = timeline.entries().iter().map(|e| e.created_at).collect();
+        assert_eq!(created, vec![30, 20, 10]);
+        assert_eq!(timeline.unseen().len(), 3);
+
+        timeline.mark_all_seen();
+        assert_eq!(timeline.seen_until(), Some(30));
+        assert!(timeline.unseen().is_empty());
+
+        write(&ndb, note(&friend, 50, None));
+        let added = timeline.wait(10).await.expect("wait");
+        assert_eq!(added.len(), 1);
+        assert_eq!(added[0].created_at, 50);
+        assert_eq!(timeline.entries()[0], added[0]);
+        assert_eq!(timeline.unseen(), &added[..]);
+
+        let txn = Transaction::new(&ndb).expect("txn");
+        assert!(timeline.poll(&txn, 10).expect("poll").is_empty());
+    }
+
+    #[test]
+    fn load_older_walks_a_crowded_second() {
+        let db = "target/testdbs/timeline_crowded";
+        test_util::cleanup_db(db);
+
+        // more notes in one second than fit on a page of load_older
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let mut events = String::new();
+            for i in 0..120u64 {
+                let created_at = if i < 100 { 500 } else { i };
+                let note = NoteBuilder::new()
+                    .kind(1)
+                    .content(&format!("gm {i}"))
+                    .created_at(created_at)
+                    .sign(&[1u8; 32])
+                    .build()
+                    .expect("note");
+                let json = note.json().expect("json");
+                events.push_str(&format!("[\"EVENT\",\"timeline\",{json}]\n"));
+            }
+            ndb.process_events(&events).expect("process ok");
+        }
+
+        let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+        let mut timeline = ndb
+            .timeline(&[Filter::new().kinds([1]).build()])
+            .expect("timeline");
+        let txn = Transaction::new(&ndb).expect("txn");
+        let mut pages = 0;
+        while timeline.load_older(&txn, 30).expect("load") > 0 {
+            pages += 1;
+        }
+        assert_eq!(timeline.len(), 120);
+        assert!(pages >= 4);
+        assert_eq!(timeline.entries()[0].created_at, 500);
+        assert_eq!(timeline.entries()[119].created_at, 100);
+    }
+}
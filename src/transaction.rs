@@ -2,11 +2,21 @@ use crate::bindings;
 use crate::error::Error;
 use crate::ndb::Ndb;
 use crate::result::Result;
+use std::sync::mpsc;
+use std::thread;
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::task;
 
 /// A `nostrdb` transaction. Only one is allowed to be active per thread.
+/// The database stays open for as long as a transaction on it exists, so
+/// its LMDB reader slot is always released before the environment closes.
 #[derive(Debug)]
 pub struct Transaction {
     txn: bindings::ndb_txn,
+    ndb: Ndb,
+
+    /// Our entry in [Ndb::reader_info]
+    reader: u64,
 }
 
 impl Transaction {
@@ -20,7 +30,15 @@ impl Transaction {
             return Err(Error::TransactionFailed);
         }
 
-        Ok(Transaction { txn })
+        Ok(Transaction {
+            txn,
+            ndb: ndb.clone(),
+            reader: ndb.readers().register(),
+        })
+    }
+
+    pub(crate) fn ndb(&self) -> &Ndb {
+        &self.ndb
     }
 
     pub fn as_ptr(&self) -> *const bindings::ndb_txn {
@@ -39,11 +57,12 @@ impl Drop for Transaction {
             // Replace with your actual function
             bindings::ndb_end_query(&mut self.txn);
         }
+        self.ndb.readers().unregister(self.reader);
     }
 }
 
 impl bindings::ndb_txn {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         // just create something uninitialized. ndb_begin_query will initialize it for us
         let lmdb: *mut bindings::ndb_lmdb = std::ptr::null_mut();
         let mdb_txn: *mut ::std::os::raw::c_void = std::ptr::null_mut();
@@ -51,6 +70,121 @@ impl bindings::ndb_txn {
     }
 }
 
+type TxnJob = Box<dyn FnOnce(&Ndb, &Transaction) + Send>;
+
+/// A read snapshot that isn't tied to a borrow or a thread, so it can be
+/// held across `.await` points. LMDB read transactions have to stay on the
+/// thread that started them, so the snapshot lives on its own thread and
+/// closures are run against it there. Anything borrowed from the
+/// transaction has to be copied out before the closure returns.
+#[derive(Debug)]
+pub struct OwnedTransaction {
+    jobs: Option<mpsc::Sender<TxnJob>>,
+    worker: Option<thread::JoinHandle<()>>,
+}
+
+impl OwnedTransaction {
+    pub fn new(ndb: &Ndb) -> Result<Self> {
+        let (jobs, job_rx) = mpsc::channel::<TxnJob>();
+        let (ready, ready_rx) = mpsc::channel();
+        let ndb = ndb.clone();
+
+        let worker = thread::Builder::new()
+            .name("ndb-txn".to_string())
+            .spawn(move || {
+                let txn = match Transaction::new(&ndb) {
+                    Ok(txn) => txn,
+                    Err(err) => {
+                        let _ = ready.send(Err(err));
+                        return;
+                    }
+                };
+                let _ = ready.send(Ok(()));
+
+                for job in job_rx {
+                    job(&ndb, &txn);
+                }
+            })
+            .map_err(|_| Error::TransactionFailed)?;
+
+        ready_rx.recv().map_err(|_| Error::TransactionFailed)??;
+
+        Ok(OwnedTransaction {
+            jobs: Some(jobs),
+            worker: Some(worker),
+        })
+    }
+
+    fn submit<F, R>(&self, f: F) -> Result<mpsc::Receiver<R>>
+    where
+        F: FnOnce(&Ndb, &Transaction) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel();
+        self.send(Box::new(move |ndb, txn| {
+            let _ = tx.send(f(ndb, txn));
+        }))?;
+        Ok(rx)
+    }
+
+    fn send(&self, job: TxnJob) -> Result<()> {
+        self.jobs
+            .as_ref()
+            .ok_or(Error::TransactionFailed)?
+            .send(job)
+            .map_err(|_| Error::TransactionFailed)
+    }
+
+    /// Run `f` against the snapshot, blocking until it's done
+    pub fn with<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&Ndb, &Transaction) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        self.submit(f)?.recv().map_err(|_| Error::TransactionFailed)
+    }
+
+    /// Run `f` against the snapshot without blocking the async runtime
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn run<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&Ndb, &Transaction) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let rx = self.submit(f)?;
+        match task::spawn_blocking(move || rx.recv()).await {
+            Ok(Ok(res)) => Ok(res),
+            _ => Err(Error::TransactionFailed),
+        }
+    }
+
+    /// Run `f` against the snapshot without blocking the async runtime
+    #[cfg(target_arch = "wasm32")]
+    pub async fn run<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&Ndb, &Transaction) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        // no blocking pool to wait on the result in, so have the snapshot
+        // thread wake us instead
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.send(Box::new(move |ndb, txn| {
+            let _ = tx.send(f(ndb, txn));
+        }))?;
+        rx.await.map_err(|_| Error::TransactionFailed)
+    }
+}
+
+impl Drop for OwnedTransaction {
+    fn drop(&mut self) {
+        // closing the job queue ends the transaction on the worker thread
+        self.jobs.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -79,4 +213,71 @@ mod tests {
 
         test_util::cleanup_db(db);
     }
+
+    #[tokio::test]
+    async fn owned_transaction_works() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<OwnedTransaction>();
+
+        let db = "target/testdbs/owned_transaction";
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            ndb.process_event(&test_util::hello_event())
+                .expect("process ok");
+        }
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let owned = OwnedTransaction::new(&ndb).expect("owned txn");
+
+            // the snapshot doesn't occupy this thread's transaction
+            drop(Transaction::new(&ndb).expect("txn"));
+
+            let content = owned
+                .run(|ndb, txn| {
+                    let id = hex::decode(
+                        "702555e52e82cc24ad517ba78c21879f6e47a7c0692b9b20df147916ae8731a3",
+                    )
+                    .expect("hex")
+                    .try_into()
+                    .expect("id");
+                    ndb.get_note_by_id(txn, &id)
+                        .map(|note| note.content().to_string())
+                })
+                .await
+                .expect("run");
+            assert_eq!(content, Ok("hello, world".to_string()));
+
+            let missing = owned
+                .with(|ndb, txn| ndb.get_note_by_id(txn, &[0; 32]).is_err())
+                .expect("with");
+            assert!(missing);
+        }
+
+        test_util::cleanup_db(db);
+    }
+
+    #[test]
+    fn reopen_after_close_works() {
+        let db = "target/testdbs/reopen_after_close";
+        test_util::cleanup_db(db);
+
+        for _ in 0..3 {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+
+            let txn = Transaction::new(&ndb).expect("txn");
+            assert_eq!(ndb.close(), Err(Error::DbInUse));
+
+            // the transaction was the last thing keeping it open
+            drop(txn);
+            Ndb::new(db, &Config::new())
+                .expect("reopen")
+                .close()
+                .expect("close");
+        }
+
+        test_util::cleanup_db(db);
+    }
 }
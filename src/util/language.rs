@@ -0,0 +1,273 @@
+use crate::Note;
+
+/// The fewest letters worth guessing a language from
+const MIN_LETTERS: usize = 8;
+
+/// Common short words of the languages written in latin script that can be
+/// told apart, by ISO 639-1 code
+const STOPWORDS: [(&str, &[&str]); 8] = [
+    (
+        "en",
+        &[
+            "the", "and", "is", "are", "to", "of", "in", "that", "it", "you", "this", "for",
+            "with", "have", "was", "not", "what", "just", "be", "my",
+        ],
+    ),
+    (
+        "es",
+        &[
+            "el", "la", "los", "las", "que", "y", "es", "en", "un", "una", "por", "para", "con",
+            "de", "del", "pero", "muy", "está", "como", "más", "yo",
+        ],
+    ),
+    (
+        "pt",
+        &[
+            "o", "os", "as", "que", "e", "é", "um", "uma", "não", "em", "de", "do", "da", "para",
+            "com", "mas", "muito", "está", "isso", "você", "eu",
+        ],
+    ),
+    (
+        "fr",
+        &[
+            "le", "la", "les", "et", "est", "un", "une", "de", "des", "que", "pas", "je", "vous",
+            "pour", "dans", "avec", "ce", "c'est", "sur", "mais", "très",
+        ],
+    ),
+    (
+        "de",
+        &[
+            "der", "die", "das", "und", "ist", "nicht", "ich", "ein", "eine", "zu", "mit", "es",
+            "auf", "für", "sie", "aber", "auch", "wie", "sind", "den",
+        ],
+    ),
+    (
+        "it",
+        &[
+            "il", "lo", "la", "gli", "che", "e", "è", "di", "un", "una", "non", "per", "con",
+            "sono", "ma", "molto", "questo", "anche", "io", "mi",
+        ],
+    ),
+    (
+        "nl",
+        &[
+            "de", "het", "een", "en", "is", "van", "ik", "niet", "dat", "op", "te", "je", "met",
+            "voor", "maar", "ook", "zijn", "wat", "er", "dit",
+        ],
+    ),
+    (
+        "id",
+        &[
+            "yang", "dan", "di", "ini", "itu", "dengan", "untuk", "tidak", "ada", "saya", "aku",
+            "kamu", "dari", "akan", "juga", "sudah", "bisa", "apa", "ke", "karena",
+        ],
+    ),
+];
+
+#[derive(Default)]
+struct Scripts {
+    latin: usize,
+    cyrillic: usize,
+    ukrainian: usize,
+    greek: usize,
+    arabic: usize,
+    hebrew: usize,
+    devanagari: usize,
+    thai: usize,
+    hangul: usize,
+    kana: usize,
+    han: usize,
+}
+
+impl Scripts {
+    fn count(&mut self, c: char) {
+        match c {
+            'a'..='z' | 'A'..='Z' | '\u{c0}'..='\u{24f}' => self.latin += 1,
+            'і' | 'ї' | 'є' | 'ґ' | 'І' | 'Ї' | 'Є' | 'Ґ' => {
+                self.cyrillic += 1;
+                self.ukrainian += 1;
+            }
+            '\u{400}'..='\u{4ff}' => self.cyrillic += 1,
+            '\u{370}'..='\u{3ff}' => self.greek += 1,
+            '\u{600}'..='\u{6ff}' => self.arabic += 1,
+            '\u{590}'..='\u{5ff}' => self.hebrew += 1,
+            '\u{900}'..='\u{97f}' => self.devanagari += 1,
+            '\u{e00}'..='\u{e7f}' => self.thai += 1,
+            '\u{ac00}'..='\u{d7af}' | '\u{1100}'..='\u{11ff}' => self.hangul += 1,
+            '\u{3040}'..='\u{30ff}' => self.kana += 1,
+            '\u{4e00}'..='\u{9fff}' | '\u{3400}'..='\u{4dbf}' => self.han += 1,
+            _ => {}
+        }
+    }
+}
+
+/// Guess the dominant language of `text`, as an ISO 639-1 code like
+/// `"en"`. Scripts used by a single language are told apart by their
+/// characters, latin script languages by their most common words. Links,
+/// mentions and hashtags are ignored. None if the text is too short, or
+/// doesn't look like any language it knows.
+///
+/// This is a heuristic meant for sorting feeds, not a general purpose
+/// classifier.
+pub fn detect_language(text: &str) -> Option<&'static str> {
+    let words: Vec<&str> = text
+        .split_whitespace()
+        .filter(|word| !is_markup(word))
+        .collect();
+
+    let mut scripts = Scripts::default();
+    for c in words.iter().flat_map(|word| word.chars()) {
+        scripts.count(c);
+    }
+
+    let cjk = scripts.hangul + scripts.kana + scripts.han;
+    let candidates = [
+        (scripts.latin, None),
+        (scripts.cyrillic, Some("ru")),
+        (scripts.greek, Some("el")),
+        (scripts.arabic, Some("ar")),
+        (scripts.hebrew, Some("he")),
+        (scripts.devanagari, Some("hi")),
+        (scripts.thai, Some("th")),
+        // a single CJK character says about as much as a latin word
+        (cjk * 3, Some("zh")),
+    ];
+    let (letters, language) = candidates.into_iter().max_by_key(|(n, _)| *n)?;
+    if letters < MIN_LETTERS {
+        return None;
+    }
+
+    match language {
+        Some("ru") if scripts.ukrainian > 0 => Some("uk"),
+        Some("zh") if scripts.hangul > scripts.kana + scripts.han => Some("ko"),
+        Some("zh") if scripts.kana > 0 => Some("ja"),
+        Some(language) => Some(language),
+        None => latin_language(&words),
+    }
+}
+
+/// The latin script language with the most stopwords in `words`
+fn latin_language(words: &[&str]) -> Option<&'static str> {
+    let words: Vec<String> = words
+        .iter()
+        .map(|word| {
+            word.trim_matches(|c: char| !c.is_alphanumeric() && c != '\'')
+                .to_lowercase()
+        })
+        .collect();
+
+    let mut best = None;
+    let mut best_hits = 0;
+    for (language, stopwords) in STOPWORDS {
+        let hits = words
+            .iter()
+            .filter(|word| stopwords.contains(&word.as_str()))
+            .count();
+        if hits > best_hits {
+            best = Some(language);
+            best_hits = hits;
+        }
+    }
+    best
+}
+
+fn is_markup(word: &str) -> bool {
+    word.starts_with("http://")
+        || word.starts_with("https://")
+        || word.starts_with("nostr:")
+        || word.starts_with('#')
+        || word.starts_with('@')
+}
+
+/// The key a note is filed under in [Ndb::LANGUAGE_INDEX]. Only kind 1 text
+/// notes are indexed.
+///
+/// [Ndb::LANGUAGE_INDEX]: crate::Ndb::LANGUAGE_INDEX
+pub(crate) fn index_keys(note: &Note) -> Vec<Vec<u8>> {
+    if note.kind() != 1 {
+        return vec![];
+    }
+    detect_language(note.content())
+        .map(|language| vec![language.as_bytes().to_vec()])
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util, Config, Filter, Ndb, NoteBuilder, Transaction};
+
+    #[test]
+    fn detect_language_works() {
+        assert_eq!(
+            detect_language("just setting up my nostr, this is the future"),
+            Some("en")
+        );
+        assert_eq!(
+            detect_language("hola, esta es una prueba de que el relay funciona"),
+            Some("es")
+        );
+        assert_eq!(
+            detect_language("ich glaube, das ist nicht die Lösung https://example.com"),
+            Some("de")
+        );
+        assert_eq!(
+            detect_language("Привет, как у тебя дела сегодня?"),
+            Some("ru")
+        );
+        assert_eq!(
+            detect_language("Привіт, як твої справи сьогодні?"),
+            Some("uk")
+        );
+        assert_eq!(detect_language("今日はいい天気ですね"), Some("ja"));
+        assert_eq!(detect_language("今天天气很好"), Some("zh"));
+        assert_eq!(detect_language("오늘 날씨가 좋네요"), Some("ko"));
+        assert_eq!(detect_language("gm"), None);
+        assert_eq!(detect_language("#bitcoin #nostr https://example.com"), None);
+    }
+
+    #[test]
+    fn language_index_works() {
+        let db = "target/testdbs/language_index";
+        test_util::cleanup_db(db);
+
+        let mut config = Config::new();
+        config.set_language_index(true);
+        {
+            let ndb = Ndb::new(db, &config).expect("ndb");
+            for (content, created_at) in [
+                ("this is what i was talking about", 1),
+                ("eso es lo que te dije ayer por la tarde", 2),
+                ("and the rest of it is just noise", 3),
+            ] {
+                let note = NoteBuilder::new()
+                    .kind(1)
+                    .content(content)
+                    .created_at(created_at)
+                    .sign(&[1u8; 32])
+                    .build()
+                    .expect("note");
+                let json = note.json().expect("json");
+                ndb.process_event(&format!("[\"EVENT\",\"lang\",{json}]"))
+                    .expect("process ok");
+            }
+        }
+
+        let ndb = Ndb::new(db, &config).expect("ndb");
+        let txn = Transaction::new(&ndb).expect("txn");
+        let filters = [Filter::new().kinds([1]).build()];
+        let english = ndb.query_language(&txn, &filters, "en", 10).expect("query");
+        let created: Vec<u64> = english.iter().map(|r| r.note.created_at()).collect();
+        assert_eq!(created, vec![3, 1]);
+        assert_eq!(
+            ndb.query_language(&txn, &filters, "es", 10)
+                .expect("query")
+                .len(),
+            1
+        );
+        assert!(ndb
+            .query_language(&txn, &filters, "fr", 10)
+            .expect("query")
+            .is_empty());
+    }
+}
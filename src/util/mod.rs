@@ -1 +1,94 @@
+use crate::Note;
+use std::os::raw::c_void;
+use std::sync::{Mutex, MutexGuard};
+
+#[cfg(feature = "language")]
+pub mod language;
+pub mod nip09;
 pub mod nip10;
+pub mod nip18;
+pub mod nip19;
+pub mod nip23;
+pub mod nip25;
+pub mod nip26;
+pub mod nip30;
+pub mod nip32;
+pub mod nip36;
+pub mod nip51;
+pub mod nip57;
+pub mod nip65;
+pub mod nip75;
+pub mod nip84;
+pub mod nip92;
+pub mod relay_hints;
+pub mod url;
+
+/// The string value of the first `[name, value, ...]` tag in a note
+pub(crate) fn first_tag_str<'a>(note: &Note<'a>, name: &str) -> Option<&'a str> {
+    for tag in note.tags() {
+        if tag.count() < 2 || tag.get_unchecked(0).variant().str() != Some(name) {
+            continue;
+        }
+
+        if let Some(value) = tag.get_unchecked(1).variant().str() {
+            return Some(value);
+        }
+    }
+
+    None
+}
+
+/// Lock a mutex even if a thread panicked while holding it. Nothing the
+/// bindings guard is left half updated while a lock is held, so the state
+/// is still usable.
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[repr(C, align(4))]
+struct Sha256([u8; 32]);
+
+extern "C" {
+    // from ccan/crypto/sha256, which is built along with nostrdb
+    #[link_name = "sha256"]
+    fn ccan_sha256(sha: *mut Sha256, p: *const c_void, size: usize);
+}
+
+pub(crate) fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut hash = Sha256([0; 32]);
+    unsafe { ccan_sha256(&mut hash, data.as_ptr() as *const c_void, data.len()) };
+    hash.0
+}
+
+/// Decode a 64 character hex string, eg. the pubkey in an `a` tag
+pub(crate) fn decode_hex32(s: &str) -> Option<[u8; 32]> {
+    decode_hex(s)
+}
+
+/// Decode a 128 character hex string, eg. a signature
+pub(crate) fn decode_hex64(s: &str) -> Option<[u8; 64]> {
+    decode_hex(s)
+}
+
+fn decode_hex<const N: usize>(s: &str) -> Option<[u8; N]> {
+    if s.len() != N * 2 || !s.is_ascii() {
+        return None;
+    }
+
+    let mut out = [0u8; N];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(out)
+}
+
+/// Lowercase hex, eg. for ids in text files
+pub(crate) fn encode_hex(bytes: &[u8]) -> String {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        out.push(DIGITS[(byte >> 4) as usize] as char);
+        out.push(DIGITS[(byte & 0xf) as usize] as char);
+    }
+    out
+}
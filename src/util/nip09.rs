@@ -0,0 +1,97 @@
+use crate::{Kind, NdbStrVariant, Note};
+
+/// NIP-09 deletion request (kind 5). Only the author of a note can delete
+/// it, which is up to the consumer to check against [Deletion::author].
+#[derive(Debug)]
+pub struct Deletion<'a> {
+    note: Note<'a>,
+}
+
+impl<'a> Deletion<'a> {
+    /// Returns None if the note isn't a deletion request
+    pub fn new(note: Note<'a>) -> Option<Self> {
+        if note.kind_enum() != Kind::Deletion {
+            return None;
+        }
+        Some(Deletion { note })
+    }
+
+    pub fn note(&self) -> &Note<'a> {
+        &self.note
+    }
+
+    pub fn author(&self) -> &'a [u8; 32] {
+        self.note.pubkey()
+    }
+
+    /// The ids of the notes to delete, from `e` tags
+    pub fn ids(&self) -> Vec<&'a [u8; 32]> {
+        let mut ids = vec![];
+        for tag in self.note.tags() {
+            if tag.count() < 2 || tag.get_unchecked(0).variant().str() != Some("e") {
+                continue;
+            }
+            if let NdbStrVariant::Id(id) = tag.get_unchecked(1).variant() {
+                ids.push(id);
+            }
+        }
+        ids
+    }
+
+    /// The `kind:pubkey:identifier` coordinates of the replaceable notes to
+    /// delete, from `a` tags. Versions newer than the request aren't
+    /// deleted.
+    pub fn addresses(&self) -> Vec<&'a str> {
+        let mut addresses = vec![];
+        for tag in self.note.tags() {
+            if tag.count() < 2 || tag.get_unchecked(0).variant().str() != Some("a") {
+                continue;
+            }
+            if let Some(coord) = tag.get_unchecked(1).variant().str() {
+                addresses.push(coord);
+            }
+        }
+        addresses
+    }
+
+    /// Why the notes are being deleted, if the author said
+    pub fn reason(&self) -> Option<&'a str> {
+        let reason = self.note.content();
+        (!reason.is_empty()).then_some(reason)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NoteBuilder;
+
+    #[test]
+    fn deletion_works() {
+        let note = NoteBuilder::new()
+            .kind(5)
+            .content("oops")
+            .start_tag()
+            .tag_str("e")
+            .tag_str("702555e52e82cc24ad517ba78c21879f6e47a7c0692b9b20df147916ae8731a3")
+            .start_tag()
+            .tag_str("a")
+            .tag_str("30023:32bf915904bfde2d136ba45dde32c88f4aca863783999faea2e847a8fafd2f15:post")
+            .build()
+            .expect("note");
+
+        let deletion = Deletion::new(note).expect("deletion");
+        assert_eq!(deletion.ids().len(), 1);
+        assert_eq!(deletion.ids()[0][0], 0x70);
+        assert_eq!(deletion.addresses().len(), 1);
+        assert!(deletion.addresses()[0].ends_with(":post"));
+        assert_eq!(deletion.reason(), Some("oops"));
+
+        let note = NoteBuilder::new()
+            .kind(1)
+            .content("")
+            .build()
+            .expect("note");
+        assert!(Deletion::new(note).is_none());
+    }
+}
@@ -0,0 +1,50 @@
+use crate::{Kind, NdbStrVariant, Note};
+
+/// NIP-18 repost, of a text note (kind 6) or of any other kind (kind 16)
+#[derive(Debug)]
+pub struct Repost<'a> {
+    note: Note<'a>,
+}
+
+impl<'a> Repost<'a> {
+    /// Returns None if the note isn't a repost
+    pub fn new(note: Note<'a>) -> Option<Self> {
+        match note.kind_enum() {
+            Kind::Repost | Kind::GenericRepost => Some(Repost { note }),
+            _ => None,
+        }
+    }
+
+    pub fn note(&self) -> &Note<'a> {
+        &self.note
+    }
+
+    /// The id of the reposted note
+    pub fn reposted_id(&self) -> Option<&'a [u8; 32]> {
+        first_tag_id(&self.note, "e")
+    }
+
+    /// The author of the reposted note
+    pub fn reposted_author(&self) -> Option<&'a [u8; 32]> {
+        first_tag_id(&self.note, "p")
+    }
+
+    /// The json of the reposted note, which clients can include so the
+    /// repost shows without fetching it
+    pub fn reposted_json(&self) -> Option<&'a str> {
+        let json = self.note.content();
+        json.trim_start().starts_with('{').then_some(json)
+    }
+}
+
+fn first_tag_id<'a>(note: &Note<'a>, name: &str) -> Option<&'a [u8; 32]> {
+    for tag in note.tags() {
+        if tag.count() < 2 || tag.get_unchecked(0).variant().str() != Some(name) {
+            continue;
+        }
+        if let NdbStrVariant::Id(id) = tag.get_unchecked(1).variant() {
+            return Some(id);
+        }
+    }
+    None
+}
@@ -0,0 +1,173 @@
+//! NIP-19 `nprofile` and `nevent` encoding, for share links. See
+//! [Ndb::nprofile] and [Ndb::nevent] to fill in the relay hints from the
+//! database.
+//!
+//! [Ndb::nprofile]: crate::Ndb::nprofile
+//! [Ndb::nevent]: crate::Ndb::nevent
+
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+const TLV_SPECIAL: u8 = 0;
+const TLV_RELAY: u8 = 1;
+const TLV_AUTHOR: u8 = 2;
+const TLV_KIND: u8 = 3;
+
+/// An `nprofile` for `pubkey`, with relays where it can be found
+pub fn encode_nprofile(pubkey: &[u8; 32], relays: &[&str]) -> String {
+    let mut tlv = vec![];
+    push_tlv(&mut tlv, TLV_SPECIAL, pubkey);
+    push_relays(&mut tlv, relays);
+    bech32("nprofile", &tlv)
+}
+
+/// An `nevent` for the note `id`, with relays where it can be found and
+/// optionally its author and kind
+pub fn encode_nevent(
+    id: &[u8; 32],
+    relays: &[&str],
+    author: Option<&[u8; 32]>,
+    kind: Option<u32>,
+) -> String {
+    let mut tlv = vec![];
+    push_tlv(&mut tlv, TLV_SPECIAL, id);
+    push_relays(&mut tlv, relays);
+    if let Some(author) = author {
+        push_tlv(&mut tlv, TLV_AUTHOR, author);
+    }
+    if let Some(kind) = kind {
+        push_tlv(&mut tlv, TLV_KIND, &kind.to_be_bytes());
+    }
+    bech32("nevent", &tlv)
+}
+
+fn push_tlv(tlv: &mut Vec<u8>, kind: u8, value: &[u8]) {
+    tlv.push(kind);
+    tlv.push(value.len() as u8);
+    tlv.extend_from_slice(value);
+}
+
+/// Relays too long for a TLV entry are left out
+fn push_relays(tlv: &mut Vec<u8>, relays: &[&str]) {
+    for relay in relays {
+        if relay.len() <= u8::MAX as usize {
+            push_tlv(tlv, TLV_RELAY, relay.as_bytes());
+        }
+    }
+}
+
+fn polymod(values: impl IntoIterator<Item = u8>) -> u32 {
+    const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+    let mut chk: u32 = 1;
+    for value in values {
+        let top = chk >> 25;
+        chk = ((chk & 0x1ffffff) << 5) ^ value as u32;
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= generator;
+            }
+        }
+    }
+    chk
+}
+
+/// Bech32 without the 90 character limit, which NIP-19 entities with
+/// relays go over
+fn bech32(hrp: &str, data: &[u8]) -> String {
+    let mut words = vec![];
+    let (mut acc, mut bits) = (0u32, 0);
+    for byte in data {
+        acc = (acc << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            words.push(((acc >> bits) & 31) as u8);
+        }
+    }
+    if bits > 0 {
+        words.push(((acc << (5 - bits)) & 31) as u8);
+    }
+
+    let expanded = hrp
+        .bytes()
+        .map(|b| b >> 5)
+        .chain([0])
+        .chain(hrp.bytes().map(|b| b & 31));
+    let checksum = polymod(expanded.chain(words.iter().copied()).chain([0; 6])) ^ 1;
+    words.extend((0..6).map(|i| ((checksum >> (5 * (5 - i))) & 31) as u8));
+
+    let mut out = String::with_capacity(hrp.len() + 1 + words.len());
+    out.push_str(hrp);
+    out.push('1');
+    out.extend(words.iter().map(|w| CHARSET[*w as usize] as char));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util, Config, Ndb, NoteBuilder, Transaction};
+
+    #[test]
+    fn nip19_encoding_works() {
+        // from NIP-19
+        let pubkey = crate::util::decode_hex32(
+            "3bf0c63fcb93463407af97a5e5ee64fa883d107ef9e558472c4eb9aaaefa459d",
+        )
+        .expect("pubkey");
+        assert_eq!(
+            encode_nprofile(&pubkey, &["wss://r.x.com", "wss://djbas.sadkb.com"]),
+            "nprofile1qqsrhuxx8l9ex335q7he0f09aej04zpazpl0ne2cgukyawd24mayt8gpp4mhxue69uhhytnc9e3k7mgpz4mhxue69uhkg6nzv9ejuumpv34kytnrdaksjlyr9p"
+        );
+
+        let db = "target/testdbs/nip19_links";
+        test_util::cleanup_db(db);
+        let mut config = Config::new();
+        config.set_record_sources(true);
+
+        let seckey = [1u8; 32];
+        let note = NoteBuilder::new()
+            .kind(1)
+            .content("share me")
+            .sign(&seckey)
+            .build()
+            .expect("note");
+        let relays = NoteBuilder::new()
+            .kind(10002)
+            .content("")
+            .start_tag()
+            .tag_str("r")
+            .tag_str("wss://outbox.example.com/")
+            .start_tag()
+            .tag_str("r")
+            .tag_str("wss://inbox.example.com")
+            .tag_str("read")
+            .sign(&seckey)
+            .build()
+            .expect("note");
+        {
+            let ndb = Ndb::new(db, &config).expect("ndb");
+            let msg = |note: &crate::Note| {
+                format!("[\"EVENT\",\"nip19\",{}]", note.json().expect("json"))
+            };
+            ndb.process_event_from(&msg(&note), "wss://seen.example.com")
+                .expect("process ok");
+            ndb.process_event(&msg(&relays)).expect("process ok");
+        }
+
+        let ndb = Ndb::new(db, &config).expect("ndb");
+        let txn = Transaction::new(&ndb).expect("txn");
+        assert_eq!(
+            ndb.nprofile(&txn, note.pubkey()).expect("nprofile"),
+            encode_nprofile(note.pubkey(), &["wss://outbox.example.com"])
+        );
+        assert_eq!(
+            ndb.nevent(&txn, note.id()).expect("nevent"),
+            encode_nevent(
+                note.id(),
+                &["wss://seen.example.com", "wss://outbox.example.com"],
+                Some(note.pubkey()),
+                Some(1)
+            )
+        );
+    }
+}
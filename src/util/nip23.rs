@@ -0,0 +1,350 @@
+use crate::block::BlockIter;
+use crate::util::first_tag_str;
+use crate::{bindings, Note};
+use std::os::raw::{c_char, c_int};
+
+/// A piece of an article's content, see [Article::code_segments]. Code is
+/// kept separate so that nostr entities, urls and hashtags are only looked
+/// for in prose.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CodeSegment<'a> {
+    /// Everything that isn't code, still as markdown
+    Prose(&'a str),
+    InlineCode(&'a str),
+    CodeBlock {
+        lang: Option<&'a str>,
+        code: &'a str,
+    },
+}
+
+/// The nostr blocks of one prose segment of an article: mentions,
+/// hashtags, urls and the text between them. See [Article::prose_blocks].
+pub struct ProseBlocks<'a> {
+    prose: &'a str,
+    blocks: *mut bindings::ndb_blocks,
+
+    /// nostrdb parses the blocks into here. u64s keep them aligned.
+    #[allow(dead_code)]
+    buf: Vec<u64>,
+}
+
+impl<'a> ProseBlocks<'a> {
+    /// None if nostrdb can't parse the text
+    fn parse(prose: &'a str) -> Option<Self> {
+        let len = c_int::try_from(prose.len()).ok()?;
+
+        // far more than blocks ever take; grown in case that's wrong
+        let mut size = (prose.len() * 4).max(4096);
+        for _ in 0..4 {
+            let mut buf = vec![0u64; size / 8];
+            let mut blocks = std::ptr::null_mut();
+            let ok = unsafe {
+                bindings::ndb_parse_content(
+                    buf.as_mut_ptr() as *mut u8,
+                    c_int::try_from(buf.len() * 8).ok()?,
+                    prose.as_ptr() as *const c_char,
+                    len,
+                    &mut blocks,
+                )
+            };
+            if ok != 0 && !blocks.is_null() {
+                return Some(ProseBlocks { prose, blocks, buf });
+            }
+            size *= 4;
+        }
+        None
+    }
+
+    /// The markdown the blocks were parsed from
+    pub fn prose(&self) -> &'a str {
+        self.prose
+    }
+
+    pub fn iter(&self) -> BlockIter<'_> {
+        BlockIter::new_owned(self.prose.as_ptr() as *const c_char, self.blocks)
+    }
+}
+
+impl std::fmt::Debug for ProseBlocks<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProseBlocks")
+            .field("prose", &self.prose)
+            .finish()
+    }
+}
+
+/// NIP-23 long-form article (kind 30023)
+#[derive(Debug)]
+pub struct Article<'a> {
+    note: Note<'a>,
+}
+
+impl<'a> Article<'a> {
+    pub const KIND: u32 = 30023;
+
+    /// Returns None if the note isn't a long-form article
+    pub fn new(note: Note<'a>) -> Option<Self> {
+        if note.kind() != Self::KIND {
+            return None;
+        }
+        Some(Article { note })
+    }
+
+    pub fn note(&self) -> &Note<'a> {
+        &self.note
+    }
+
+    /// The `d` tag identifier used in naddrs
+    pub fn identifier(&self) -> Option<&'a str> {
+        first_tag_str(&self.note, "d")
+    }
+
+    pub fn title(&self) -> Option<&'a str> {
+        first_tag_str(&self.note, "title")
+    }
+
+    pub fn summary(&self) -> Option<&'a str> {
+        first_tag_str(&self.note, "summary")
+    }
+
+    pub fn image(&self) -> Option<&'a str> {
+        first_tag_str(&self.note, "image")
+    }
+
+    /// When the article was first published. Unlike created_at, this
+    /// doesn't change when the article is edited.
+    pub fn published_at(&self) -> Option<u64> {
+        first_tag_str(&self.note, "published_at").and_then(|s| s.parse().ok())
+    }
+
+    pub fn hashtags(&self) -> Vec<&'a str> {
+        let mut hashtags = vec![];
+        for tag in self.note.tags() {
+            if tag.count() < 2 || tag.get_unchecked(0).variant().str() != Some("t") {
+                continue;
+            }
+            if let Some(t) = tag.get_unchecked(1).variant().str() {
+                hashtags.push(t);
+            }
+        }
+        hashtags
+    }
+
+    /// The raw markdown content
+    pub fn content(&self) -> &'a str {
+        self.note.content()
+    }
+
+    /// The content split into prose, inline code and fenced code blocks.
+    /// This is all of the markdown that is parsed: headings, links,
+    /// emphasis and the rest are left in the prose as written.
+    pub fn code_segments(&self) -> Vec<CodeSegment<'a>> {
+        code_segments(self.content())
+    }
+
+    /// The nostr blocks of each prose segment of [Article::code_segments].
+    /// Code isn't parsed, so a `#tag` or `nostr:` link inside inline code
+    /// or a code block isn't reported.
+    pub fn prose_blocks(&self) -> Vec<ProseBlocks<'a>> {
+        prose_blocks(self.content())
+    }
+}
+
+fn line_end(content: &str, pos: usize) -> usize {
+    content[pos..]
+        .find('\n')
+        .map_or(content.len(), |i| pos + i + 1)
+}
+
+fn code_fence(line: &str) -> Option<&'static str> {
+    let trimmed = line.trim_start();
+    if trimmed.starts_with("```") {
+        Some("```")
+    } else if trimmed.starts_with("~~~") {
+        Some("~~~")
+    } else {
+        None
+    }
+}
+
+fn backtick_run(text: &str, pos: usize) -> usize {
+    text.as_bytes()[pos..]
+        .iter()
+        .take_while(|b| **b == b'`')
+        .count()
+}
+
+fn push_inline<'a>(segments: &mut Vec<CodeSegment<'a>>, text: &'a str) {
+    let mut text_start = 0;
+    let mut pos = 0;
+
+    while let Some(off) = text[pos..].find('`') {
+        let open = pos + off;
+        let run = backtick_run(text, open);
+
+        // inline code is closed by a backtick run of the same length
+        let mut close = None;
+        let mut p = open + run;
+        while let Some(off) = text[p..].find('`') {
+            let c = p + off;
+            let crun = backtick_run(text, c);
+            if crun == run {
+                close = Some(c);
+                break;
+            }
+            p = c + crun;
+        }
+
+        if let Some(close) = close {
+            if open > text_start {
+                segments.push(CodeSegment::Prose(&text[text_start..open]));
+            }
+            segments.push(CodeSegment::InlineCode(&text[open + run..close]));
+            pos = close + run;
+            text_start = pos;
+        } else {
+            pos = open + run;
+        }
+    }
+
+    if text_start < text.len() {
+        segments.push(CodeSegment::Prose(&text[text_start..]));
+    }
+}
+
+/// Split markdown into prose, inline code and fenced code blocks. An
+/// unterminated code fence runs to the end of the content.
+pub fn code_segments(content: &str) -> Vec<CodeSegment<'_>> {
+    let mut segments = vec![];
+    let mut text_start = 0;
+    let mut pos = 0;
+
+    while pos < content.len() {
+        let end = line_end(content, pos);
+        let line = &content[pos..end];
+
+        let fence = if let Some(fence) = code_fence(line) {
+            fence
+        } else {
+            pos = end;
+            continue;
+        };
+
+        let lang = line.trim_start()[fence.len()..].trim();
+        let code_start = end;
+        let mut code_end = content.len();
+        let mut after = content.len();
+
+        let mut p = code_start;
+        while p < content.len() {
+            let e = line_end(content, p);
+            if code_fence(&content[p..e]) == Some(fence) {
+                code_end = p;
+                after = e;
+                break;
+            }
+            p = e;
+        }
+
+        push_inline(&mut segments, &content[text_start..pos]);
+        segments.push(CodeSegment::CodeBlock {
+            lang: if lang.is_empty() { None } else { Some(lang) },
+            code: &content[code_start..code_end],
+        });
+
+        pos = after;
+        text_start = after;
+    }
+
+    push_inline(&mut segments, &content[text_start..]);
+    segments
+}
+
+/// The nostr blocks of the prose in markdown, see [Article::prose_blocks]
+pub fn prose_blocks(content: &str) -> Vec<ProseBlocks<'_>> {
+    code_segments(content)
+        .into_iter()
+        .filter_map(|segment| match segment {
+            CodeSegment::Prose(prose) => ProseBlocks::parse(prose),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BlockType, NoteBuilder};
+
+    #[test]
+    fn code_segments_work() {
+        let md = "# hi #nostr\nuse `ndb_query` here\n```rust\nlet x = \"#notatag\";\n```\ndone";
+        let segments = code_segments(md);
+        assert_eq!(
+            segments,
+            vec![
+                CodeSegment::Prose("# hi #nostr\nuse "),
+                CodeSegment::InlineCode("ndb_query"),
+                CodeSegment::Prose(" here\n"),
+                CodeSegment::CodeBlock {
+                    lang: Some("rust"),
+                    code: "let x = \"#notatag\";\n"
+                },
+                CodeSegment::Prose("done"),
+            ]
+        );
+    }
+
+    #[test]
+    fn prose_blocks_work() {
+        let npub = "npub1xtscya34g58tk0z605fvr788k263gsu6cy9x0mhnm87echrgufzsevkk5s";
+        let md = format!(
+            "#nostr by nostr:{npub}\nsee `#inline nostr:{npub}`\n```\n#notatag nostr:{npub}\n```\nhttps://damus.io"
+        );
+        let blocks = prose_blocks(&md);
+        assert_eq!(blocks.len(), 3);
+
+        let found: Vec<(BlockType, String)> = blocks
+            .iter()
+            .flat_map(|prose| prose.iter())
+            .filter(|block| block.blocktype() != BlockType::Text)
+            .map(|block| (block.blocktype(), block.as_str().to_string()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (BlockType::Hashtag, "nostr".to_string()),
+                (BlockType::MentionBech32, npub.to_string()),
+                (BlockType::Url, "https://damus.io".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn article_accessors_work() {
+        let note = NoteBuilder::new()
+            .kind(30023)
+            .content("Lorem [ipsum][nostr:nevent1qqst8cujky046negxgwwm5ynqwn53t8aqjr6afd8g59nfqwxpdhylpcpzamhxue69uhhyetvv9ujuetcv9khqmr99e3k7mg8arnc9] dolor sit amet")
+            .start_tag()
+            .tag_str("d")
+            .tag_str("lorem-ipsum")
+            .start_tag()
+            .tag_str("title")
+            .tag_str("Lorem Ipsum")
+            .start_tag()
+            .tag_str("published_at")
+            .tag_str("1296962229")
+            .start_tag()
+            .tag_str("t")
+            .tag_str("placeholder")
+            .build()
+            .expect("note");
+
+        let article = Article::new(note).expect("article");
+        assert_eq!(article.identifier(), Some("lorem-ipsum"));
+        assert_eq!(article.title(), Some("Lorem Ipsum"));
+        assert_eq!(article.summary(), None);
+        assert_eq!(article.published_at(), Some(1296962229));
+        assert_eq!(article.hashtags(), vec!["placeholder"]);
+    }
+}
@@ -0,0 +1,86 @@
+use crate::{Kind, NdbStrVariant, Note};
+
+/// NIP-25 reaction (kind 7)
+#[derive(Debug)]
+pub struct Reaction<'a> {
+    note: Note<'a>,
+}
+
+impl<'a> Reaction<'a> {
+    /// Returns None if the note isn't a reaction
+    pub fn new(note: Note<'a>) -> Option<Self> {
+        if note.kind_enum() != Kind::Reaction {
+            return None;
+        }
+        Some(Reaction { note })
+    }
+
+    pub fn note(&self) -> &Note<'a> {
+        &self.note
+    }
+
+    /// The reaction itself: `+`, `-`, an emoji or a `:shortcode:`
+    pub fn content(&self) -> &'a str {
+        self.note.content()
+    }
+
+    /// An empty reaction counts as a like
+    pub fn is_like(&self) -> bool {
+        matches!(self.content(), "+" | "")
+    }
+
+    pub fn is_dislike(&self) -> bool {
+        self.content() == "-"
+    }
+
+    /// The id of the note reacted to. Clients may tag the whole thread, so
+    /// this is the last `e` tag.
+    pub fn target_id(&self) -> Option<&'a [u8; 32]> {
+        last_tag_id(&self.note, "e")
+    }
+
+    /// The author of the note reacted to, the last `p` tag
+    pub fn target_author(&self) -> Option<&'a [u8; 32]> {
+        last_tag_id(&self.note, "p")
+    }
+}
+
+fn last_tag_id<'a>(note: &Note<'a>, name: &str) -> Option<&'a [u8; 32]> {
+    let mut last = None;
+    for tag in note.tags() {
+        if tag.count() < 2 || tag.get_unchecked(0).variant().str() != Some(name) {
+            continue;
+        }
+        if let NdbStrVariant::Id(id) = tag.get_unchecked(1).variant() {
+            last = Some(id);
+        }
+    }
+    last
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NoteBuilder;
+
+    #[test]
+    fn reaction_works() {
+        let note = NoteBuilder::new()
+            .kind(7)
+            .content("-")
+            .start_tag()
+            .tag_str("e")
+            .tag_str("0000000000000000000000000000000000000000000000000000000000000001")
+            .start_tag()
+            .tag_str("e")
+            .tag_str("702555e52e82cc24ad517ba78c21879f6e47a7c0692b9b20df147916ae8731a3")
+            .build()
+            .expect("note");
+
+        let reaction = Reaction::new(note).expect("reaction");
+        assert!(reaction.is_dislike());
+        assert!(!reaction.is_like());
+        assert_eq!(reaction.target_id().map(|id| id[0]), Some(0x70));
+        assert_eq!(reaction.target_author(), None);
+    }
+}
@@ -0,0 +1,214 @@
+use crate::util::{decode_hex32, encode_hex, sha256};
+use crate::verify::with_verifier;
+use crate::{bindings, NdbStrVariant, Note};
+
+/// A NIP-26 `["delegation", <delegator>, <conditions>, <token>]` tag: the
+/// delegator let the note's signer publish on their behalf, within the
+/// conditions
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Delegation<'a> {
+    pub delegator: [u8; 32],
+
+    /// eg. `kind=1&created_at>1674834236&created_at<1677426236`
+    pub conditions: &'a str,
+
+    /// The delegator's signature over the delegatee and conditions, in hex
+    pub token: &'a str,
+}
+
+/// The kinds and time window a [Delegation] allows
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct DelegationConditions {
+    /// Empty means any kind
+    pub kinds: Vec<u32>,
+
+    /// Notes must be created after this
+    pub since: Option<u64>,
+
+    /// Notes must be created before this
+    pub until: Option<u64>,
+}
+
+impl DelegationConditions {
+    /// None if there's a condition NIP-26 doesn't define
+    pub fn parse(conditions: &str) -> Option<Self> {
+        let mut parsed = DelegationConditions::default();
+        for condition in conditions.split('&').filter(|c| !c.is_empty()) {
+            if let Some(kind) = condition.strip_prefix("kind=") {
+                parsed.kinds.push(kind.parse().ok()?);
+            } else if let Some(since) = condition.strip_prefix("created_at>") {
+                let since = since.parse().ok()?;
+                parsed.since = Some(parsed.since.map_or(since, |s: u64| s.max(since)));
+            } else if let Some(until) = condition.strip_prefix("created_at<") {
+                let until = until.parse().ok()?;
+                parsed.until = Some(parsed.until.map_or(until, |u: u64| u.min(until)));
+            } else {
+                return None;
+            }
+        }
+        Some(parsed)
+    }
+
+    pub fn allows(&self, kind: u32, created_at: u64) -> bool {
+        (self.kinds.is_empty() || self.kinds.contains(&kind))
+            && self.since.is_none_or(|since| created_at > since)
+            && self.until.is_none_or(|until| created_at < until)
+    }
+}
+
+impl Delegation<'_> {
+    /// Whether the token is the delegator's signature for `note`'s
+    /// signer, and `note` is within the conditions
+    pub fn is_valid_for(&self, note: &Note) -> bool {
+        let allowed = DelegationConditions::parse(self.conditions)
+            .is_some_and(|conditions| conditions.allows(note.kind(), note.created_at()));
+        let sig = match decode_sig(self.token) {
+            Some(sig) => sig,
+            None => return false,
+        };
+        let hash = token_hash(note.pubkey(), self.conditions);
+        allowed && with_verifier(|v| v.check_schnorr(&self.delegator, &hash, &sig)).unwrap_or(false)
+    }
+}
+
+/// The delegation tag of a note, if it has one
+pub fn note_delegation<'a>(note: &Note<'a>) -> Option<Delegation<'a>> {
+    let tag = note.tags().iter().find(|tag| {
+        tag.count() >= 4 && tag.get_unchecked(0).variant().str() == Some("delegation")
+    })?;
+    let delegator = match tag.get_unchecked(1).variant() {
+        NdbStrVariant::Id(delegator) => *delegator,
+        NdbStrVariant::Str(hex) => decode_hex32(hex)?,
+    };
+    Some(Delegation {
+        delegator,
+        conditions: tag.get_unchecked(2).variant().str()?,
+        token: tag.get_unchecked(3).variant().str()?,
+    })
+}
+
+/// Sign a delegation to `delegatee` with the delegator's secret key.
+/// Returns the delegator's pubkey and the token, for the delegatee's
+/// `delegation` tags.
+pub fn sign_delegation(
+    seckey: &[u8; 32],
+    delegatee: &[u8; 32],
+    conditions: &str,
+) -> Option<([u8; 32], String)> {
+    let mut keypair = bindings::ndb_keypair::default();
+    keypair.secret.copy_from_slice(seckey);
+    if unsafe { bindings::ndb_create_keypair(keypair.as_mut_ptr()) } == 0 {
+        return None;
+    }
+
+    let mut hash = token_hash(delegatee, conditions);
+    let mut sig = [0u8; 64];
+    let ok = unsafe {
+        bindings::ndb_sign_id(keypair.as_mut_ptr(), hash.as_mut_ptr(), sig.as_mut_ptr()) != 0
+    };
+    ok.then(|| (keypair.pubkey, encode_hex(&sig)))
+}
+
+/// Whether a note's delegation, if it has one, holds up. Checked at ingest.
+pub(crate) fn check(note: &Note) -> bool {
+    let delegated = note
+        .tags()
+        .iter()
+        .any(|tag| tag.count() > 0 && tag.get_unchecked(0).variant().str() == Some("delegation"));
+    !delegated || note_delegation(note).is_some_and(|delegation| delegation.is_valid_for(note))
+}
+
+/// The key a note is filed under in [Ndb::DELEGATION_INDEX]
+///
+/// [Ndb::DELEGATION_INDEX]: crate::Ndb::DELEGATION_INDEX
+pub(crate) fn index_keys(note: &Note) -> Vec<Vec<u8>> {
+    note_delegation(note)
+        .map(|delegation| vec![delegation.delegator.to_vec()])
+        .unwrap_or_default()
+}
+
+fn token_hash(delegatee: &[u8; 32], conditions: &str) -> [u8; 32] {
+    let token = format!("nostr:delegation:{}:{conditions}", encode_hex(delegatee));
+    sha256(token.as_bytes())
+}
+
+fn decode_sig(hex: &str) -> Option<[u8; 64]> {
+    if hex.len() != 128 || !hex.is_ascii() {
+        return None;
+    }
+    let mut sig = [0u8; 64];
+    sig[..32].copy_from_slice(&decode_hex32(&hex[..64])?);
+    sig[32..].copy_from_slice(&decode_hex32(&hex[64..])?);
+    Some(sig)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util, Config, Error, Filter, Ndb, NoteBuilder, Rejection, Transaction};
+
+    #[test]
+    fn delegation_works() {
+        let conditions = DelegationConditions::parse("kind=1&created_at>1000&created_at<3000")
+            .expect("conditions");
+        assert!(conditions.allows(1, 2000));
+        assert!(!conditions.allows(7, 2000));
+        assert!(!conditions.allows(1, 3000));
+        assert_eq!(DelegationConditions::parse("tags=p"), None);
+
+        let db = "target/testdbs/delegation";
+        test_util::cleanup_db(db);
+        let ndb = Ndb::new(db, Config::new().set_delegation_index(true)).expect("ndb");
+
+        let delegatee_key = [2u8; 32];
+        let delegatee = *NoteBuilder::new()
+            .sign(&delegatee_key)
+            .build()
+            .expect("note")
+            .pubkey();
+        let cond = "kind=1&created_at>1000&created_at<3000";
+        let (delegator, token) = sign_delegation(&[1u8; 32], &delegatee, cond).expect("sign");
+
+        let msg = |content: &str, created_at: u64, token: &str| {
+            let note = NoteBuilder::new()
+                .kind(1)
+                .content(content)
+                .created_at(created_at)
+                .start_tag()
+                .tag_str("delegation")
+                .tag_str(&encode_hex(&delegator))
+                .tag_str(cond)
+                .tag_str(token)
+                .sign(&delegatee_key)
+                .build()
+                .expect("note");
+            assert_eq!(note.delegator(), Some(delegator));
+            format!("[\"EVENT\",\"d\",{}]", note.json().expect("json"))
+        };
+
+        let rejected = Err(Error::Rejected(Rejection::Delegation));
+        assert_eq!(ndb.process_event(&msg("late", 4000, &token)), rejected);
+        let mut forged = token.clone();
+        forged.replace_range(..2, if token.starts_with("00") { "11" } else { "00" });
+        assert_eq!(ndb.process_event(&msg("forged", 2000, &forged)), rejected);
+        ndb.process_event(&msg("on behalf", 2000, &token))
+            .expect("process ok");
+
+        let filter = Filter::new().kinds([1]).build();
+        let mut tries = 0;
+        loop {
+            let txn = Transaction::new(&ndb).expect("txn");
+            let results = ndb
+                .query_by_effective_author(&txn, std::slice::from_ref(&filter), &delegator, 10)
+                .expect("query");
+            if let Some(result) = results.first() {
+                assert_eq!(result.note.content(), "on behalf");
+                assert_eq!(result.note.delegator(), Some(delegator));
+                break;
+            }
+            tries += 1;
+            assert!(tries < 500, "note never written");
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use crate::block::BlockIter;
+use crate::{Block, BlockType, Note};
+use std::collections::VecDeque;
+
+/// A NIP-30 custom emoji, parsed from an `emoji` tag
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Emoji<'a> {
+    pub shortcode: &'a str,
+    pub url: &'a str,
+}
+
+/// A content block with `:shortcode:` custom emoji split out of text blocks
+#[derive(Debug)]
+pub enum EmojiBlock<'a> {
+    /// A regular nostrdb block. Text blocks without known emoji are passed
+    /// through as-is.
+    Block(Block<'a>),
+
+    /// Text surrounding an emoji
+    Text(&'a str),
+
+    /// A `:shortcode:` resolved against the note's `emoji` tags
+    Emoji(Emoji<'a>),
+}
+
+fn is_shortcode(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Collect all the custom emoji defined in a note's `emoji` tags
+pub fn note_emojis<'a>(note: &Note<'a>) -> Vec<Emoji<'a>> {
+    let mut emojis = vec![];
+
+    for tag in note.tags() {
+        if tag.count() < 3 || tag.get_unchecked(0).variant().str() != Some("emoji") {
+            continue;
+        }
+
+        let shortcode = tag.get_unchecked(1).variant().str();
+        let url = tag.get_unchecked(2).variant().str();
+
+        if let (Some(shortcode), Some(url)) = (shortcode, url) {
+            if is_shortcode(shortcode) {
+                emojis.push(Emoji { shortcode, url });
+            }
+        }
+    }
+
+    emojis
+}
+
+/// Split text into [EmojiBlock::Text] and [EmojiBlock::Emoji] pieces.
+/// Shortcodes that aren't in `emojis` are left as text.
+pub fn split_emojis<'a>(text: &'a str, emojis: &[Emoji<'a>]) -> Vec<EmojiBlock<'a>> {
+    let mut blocks = vec![];
+    let mut text_start = 0;
+    let mut pos = 0;
+
+    while let Some(off) = text[pos..].find(':') {
+        let open = pos + off;
+        let close = if let Some(off) = text[open + 1..].find(':') {
+            open + 1 + off
+        } else {
+            break;
+        };
+
+        let shortcode = &text[open + 1..close];
+        let emoji = if is_shortcode(shortcode) {
+            emojis.iter().find(|e| e.shortcode == shortcode)
+        } else {
+            None
+        };
+
+        if let Some(emoji) = emoji {
+            if open > text_start {
+                blocks.push(EmojiBlock::Text(&text[text_start..open]));
+            }
+            blocks.push(EmojiBlock::Emoji(*emoji));
+            text_start = close + 1;
+            pos = close + 1;
+        } else {
+            // the closing colon might open the next shortcode
+            pos = close;
+        }
+    }
+
+    if text_start < text.len() {
+        blocks.push(EmojiBlock::Text(&text[text_start..]));
+    }
+
+    blocks
+}
+
+/// Iterates over a note's blocks, splitting custom emoji out of text blocks.
+/// Construct one with [crate::Blocks::iter_emojis]
+pub struct EmojiBlockIter<'a> {
+    blocks: BlockIter<'a>,
+    emojis: Vec<Emoji<'a>>,
+    pending: VecDeque<EmojiBlock<'a>>,
+}
+
+impl<'a> EmojiBlockIter<'a> {
+    pub(crate) fn new(blocks: BlockIter<'a>, emojis: Vec<Emoji<'a>>) -> Self {
+        EmojiBlockIter {
+            blocks,
+            emojis,
+            pending: VecDeque::new(),
+        }
+    }
+
+    pub fn emojis(&self) -> &[Emoji<'a>] {
+        &self.emojis
+    }
+}
+
+impl<'a> Iterator for EmojiBlockIter<'a> {
+    type Item = EmojiBlock<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(block) = self.pending.pop_front() {
+            return Some(block);
+        }
+
+        let block = self.blocks.next()?;
+        if self.emojis.is_empty() || block.blocktype() != BlockType::Text {
+            return Some(EmojiBlock::Block(block));
+        }
+
+        let split = split_emojis(block.as_str(), &self.emojis);
+        if !split.iter().any(|b| matches!(b, EmojiBlock::Emoji(_))) {
+            return Some(EmojiBlock::Block(block));
+        }
+
+        self.pending.extend(split);
+        self.pending.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NoteBuilder;
+
+    #[test]
+    fn split_emojis_works() {
+        let emojis = [
+            Emoji {
+                shortcode: "soapbox",
+                url: "https://gleasonator.com/emoji/Gleasonator/soapbox.png",
+            },
+            Emoji {
+                shortcode: "ablobcatrainbow",
+                url: "https://gleasonator.com/emoji/blobcat/ablobcatrainbow.png",
+            },
+        ];
+
+        let blocks = split_emojis("hi :soapbox: time: :unknown::ablobcatrainbow:!", &emojis);
+        assert_eq!(blocks.len(), 5);
+        assert!(matches!(blocks[0], EmojiBlock::Text("hi ")));
+        assert!(matches!(blocks[1], EmojiBlock::Emoji(e) if e == emojis[0]));
+        assert!(matches!(blocks[2], EmojiBlock::Text(" time: :unknown:")));
+        assert!(matches!(blocks[3], EmojiBlock::Emoji(e) if e == emojis[1]));
+        assert!(matches!(blocks[4], EmojiBlock::Text("!")));
+
+        assert!(split_emojis("no emoji here", &emojis).len() == 1);
+    }
+
+    #[test]
+    fn note_emojis_works() {
+        let note = NoteBuilder::new()
+            .kind(1)
+            .content("hello :soapbox:")
+            .start_tag()
+            .tag_str("emoji")
+            .tag_str("soapbox")
+            .tag_str("https://gleasonator.com/emoji/Gleasonator/soapbox.png")
+            .start_tag()
+            .tag_str("emoji")
+            .tag_str("bad-shortcode")
+            .tag_str("https://example.com/bad.png")
+            .build()
+            .expect("note");
+
+        let emojis = note_emojis(&note);
+        assert_eq!(emojis.len(), 1);
+        assert_eq!(emojis[0].shortcode, "soapbox");
+        assert_eq!(
+            emojis[0].url,
+            "https://gleasonator.com/emoji/Gleasonator/soapbox.png"
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use crate::{Note, NoteKey};
+use std::collections::BTreeMap;
+
+/// The namespace used by labels that don't specify one
+pub const UGC_NAMESPACE: &str = "ugc";
+
+/// Something a NIP-32 label event (kind 1985) can point at
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LabelTarget<'a> {
+    Note(&'a [u8; 32]),
+    Pubkey(&'a [u8; 32]),
+}
+
+/// A single `l` tag from a label event
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Label<'a> {
+    pub namespace: &'a str,
+    pub value: &'a str,
+
+    /// The pubkey of the label event's author
+    pub labeler: &'a [u8; 32],
+
+    /// The label event this label came from
+    pub note_key: Option<NoteKey>,
+}
+
+pub const LABEL_KIND: u32 = 1985;
+
+/// Labels grouped by namespace
+pub type LabelsByNamespace<'a> = BTreeMap<&'a str, Vec<Label<'a>>>;
+
+/// Parse the `l` tags of a label event
+pub fn note_labels<'a>(note: &Note<'a>) -> Vec<Label<'a>> {
+    let mut labels = vec![];
+    if note.kind() != LABEL_KIND {
+        return labels;
+    }
+
+    for tag in note.tags() {
+        if tag.count() < 2 || tag.get_unchecked(0).variant().str() != Some("l") {
+            continue;
+        }
+
+        let value = if let Some(value) = tag.get_unchecked(1).variant().str() {
+            value
+        } else {
+            continue;
+        };
+
+        let namespace = tag
+            .get(2)
+            .and_then(|t| t.variant().str())
+            .filter(|ns| !ns.is_empty())
+            .unwrap_or(UGC_NAMESPACE);
+
+        labels.push(Label {
+            namespace,
+            value,
+            labeler: note.pubkey(),
+            note_key: note.key(),
+        });
+    }
+
+    labels
+}
+
+pub(crate) fn group_labels<'a>(
+    labels: impl IntoIterator<Item = Label<'a>>,
+) -> LabelsByNamespace<'a> {
+    let mut grouped: LabelsByNamespace<'a> = BTreeMap::new();
+    for label in labels {
+        grouped.entry(label.namespace).or_default().push(label);
+    }
+    grouped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NoteBuilder;
+
+    #[test]
+    fn note_labels_work() {
+        let note = NoteBuilder::new()
+            .kind(LABEL_KIND)
+            .content("")
+            .start_tag()
+            .tag_str("L")
+            .tag_str("ISO-639-1")
+            .start_tag()
+            .tag_str("l")
+            .tag_str("en")
+            .tag_str("ISO-639-1")
+            .start_tag()
+            .tag_str("l")
+            .tag_str("funny")
+            .build()
+            .expect("note");
+
+        let grouped = group_labels(note_labels(&note));
+        assert_eq!(grouped.len(), 2);
+        assert_eq!(grouped["ISO-639-1"][0].value, "en");
+        assert_eq!(grouped[UGC_NAMESPACE][0].value, "funny");
+    }
+}
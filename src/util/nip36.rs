@@ -0,0 +1,89 @@
+/// What to do with notes that have a NIP-36 `content-warning` tag, see
+/// [QueryOptions::set_content_warnings] and
+/// [SubscriptionConfig::set_content_warnings]. Included notes can be
+/// flagged for the user with [Note::content_warning].
+///
+/// [QueryOptions::set_content_warnings]: crate::QueryOptions::set_content_warnings
+/// [SubscriptionConfig::set_content_warnings]: crate::SubscriptionConfig::set_content_warnings
+/// [Note::content_warning]: crate::Note::content_warning
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum ContentWarnings {
+    #[default]
+    Include,
+    Exclude,
+}
+
+impl ContentWarnings {
+    pub(crate) fn excludes(&self, reason: Option<&str>) -> bool {
+        *self == ContentWarnings::Exclude && reason.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_util, Config, Filter, Ndb, Note, NoteBuilder, QueryOptions, SubscriptionConfig,
+        Transaction,
+    };
+
+    fn note(content: &str, warning: Option<&str>) -> Note<'static> {
+        let builder = NoteBuilder::new().kind(1).content(content);
+        let builder = match warning {
+            Some("") => builder.start_tag().tag_str("content-warning"),
+            Some(reason) => builder
+                .start_tag()
+                .tag_str("content-warning")
+                .tag_str(reason),
+            None => builder,
+        };
+        builder.sign(&[1u8; 32]).build().expect("note")
+    }
+
+    fn write(ndb: &Ndb, note: Note) {
+        let json = note.json().expect("json");
+        ndb.process_event(&format!("[\"EVENT\",\"cw\",{json}]"))
+            .expect("process ok");
+    }
+
+    #[tokio::test]
+    async fn content_warnings_work() {
+        let db = "target/testdbs/content_warnings";
+        test_util::cleanup_db(db);
+
+        assert_eq!(note("a", Some("nudity")).content_warning(), Some("nudity"));
+        assert_eq!(note("a", Some("")).content_warning(), Some(""));
+        assert_eq!(note("a", None).content_warning(), None);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            write(&ndb, note("spoiler", Some("")));
+            write(&ndb, note("gm", None));
+        }
+
+        let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+        let filters = [Filter::new().kinds([1]).build()];
+        {
+            let txn = Transaction::new(&ndb).expect("txn");
+            let mut options = QueryOptions::new();
+            let all = ndb.query_with_options(&txn, &filters, 10, &options);
+            assert_eq!(all.results.len(), 2);
+
+            options.set_content_warnings(ContentWarnings::Exclude);
+            let safe = ndb.query_with_options(&txn, &filters, 10, &options);
+            assert_eq!(safe.results.len(), 1);
+            assert_eq!(safe.results[0].note.content(), "gm");
+        }
+
+        let mut config = SubscriptionConfig::new();
+        config.set_content_warnings(ContentWarnings::Exclude);
+        let sub = ndb.subscribe_with_config(&filters, &config).expect("sub");
+        write(&ndb, note("nsfw", Some("nsfw")));
+        write(&ndb, note("gn", None));
+        let keys = ndb.wait_for_notes(sub, 10).await.expect("wait");
+        let txn = Transaction::new(&ndb).expect("txn");
+        let results = ndb.get_results_by_keys(&txn, &keys);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].note.content(), "gn");
+    }
+}
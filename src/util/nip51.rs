@@ -0,0 +1,191 @@
+use crate::util::first_tag_str;
+use crate::{Kind, NdbStrVariant, Note};
+
+/// The kinds that are NIP-51 lists: one per author for the standard lists,
+/// and any number of sets told apart by their `d` tag
+pub const LIST_KINDS: [Kind; 6] = [
+    Kind::MuteList,
+    Kind::PinList,
+    Kind::BookmarkList,
+    Kind::FollowSet,
+    Kind::RelaySet,
+    Kind::BookmarkSet,
+];
+
+/// A NIP-51 list, eg. a mute list, bookmarks or a named set of people.
+/// Only the public items in the tags are read. Private items are encrypted
+/// to the author in the content, see [List::has_private_items].
+#[derive(Debug)]
+pub struct List<'a> {
+    note: Note<'a>,
+}
+
+impl<'a> List<'a> {
+    /// Returns None if the note isn't one of the [LIST_KINDS]
+    pub fn new(note: Note<'a>) -> Option<Self> {
+        if !LIST_KINDS.contains(&note.kind_enum()) {
+            return None;
+        }
+        Some(List { note })
+    }
+
+    pub fn note(&self) -> &Note<'a> {
+        &self.note
+    }
+
+    pub fn kind(&self) -> Kind {
+        self.note.kind_enum()
+    }
+
+    /// The `d` tag of a set. None for the standard lists.
+    pub fn identifier(&self) -> Option<&'a str> {
+        if !self.kind().is_addressable() {
+            return None;
+        }
+        first_tag_str(&self.note, "d")
+    }
+
+    pub fn title(&self) -> Option<&'a str> {
+        first_tag_str(&self.note, "title")
+    }
+
+    pub fn description(&self) -> Option<&'a str> {
+        first_tag_str(&self.note, "description")
+    }
+
+    pub fn image(&self) -> Option<&'a str> {
+        first_tag_str(&self.note, "image")
+    }
+
+    /// People on the list, from `p` tags
+    pub fn pubkeys(&self) -> Vec<&'a [u8; 32]> {
+        tag_ids(&self.note, "p")
+    }
+
+    /// Notes on the list, from `e` tags
+    pub fn note_ids(&self) -> Vec<&'a [u8; 32]> {
+        tag_ids(&self.note, "e")
+    }
+
+    /// `kind:pubkey:identifier` coordinates of replaceable notes on the
+    /// list, eg. bookmarked articles, from `a` tags
+    pub fn addresses(&self) -> Vec<&'a str> {
+        tag_strs(&self.note, "a")
+    }
+
+    pub fn hashtags(&self) -> Vec<&'a str> {
+        tag_strs(&self.note, "t")
+    }
+
+    /// Bookmarked urls, from `r` tags
+    pub fn urls(&self) -> Vec<&'a str> {
+        tag_strs(&self.note, "r")
+    }
+
+    /// The relays in a relay set, from `relay` tags
+    pub fn relays(&self) -> Vec<&'a str> {
+        tag_strs(&self.note, "relay")
+    }
+
+    /// Muted words, from `word` tags
+    pub fn words(&self) -> Vec<&'a str> {
+        tag_strs(&self.note, "word")
+    }
+
+    pub fn has_private_items(&self) -> bool {
+        !self.note.content().is_empty()
+    }
+}
+
+/// The latest version of each list among `notes`, by author and `d` tag,
+/// newest first
+pub(crate) fn latest_versions<'a>(notes: impl IntoIterator<Item = Note<'a>>) -> Vec<List<'a>> {
+    let mut latest: Vec<List<'a>> = vec![];
+    for list in notes.into_iter().filter_map(List::new) {
+        let same = latest.iter().position(|other| {
+            other.note.pubkey() == list.note.pubkey()
+                && other.kind() == list.kind()
+                && other.identifier() == list.identifier()
+        });
+        match same {
+            Some(i) if latest[i].note.created_at() >= list.note.created_at() => {}
+            Some(i) => latest[i] = list,
+            None => latest.push(list),
+        }
+    }
+    latest.sort_by_key(|list| std::cmp::Reverse(list.note.created_at()));
+    latest
+}
+
+fn tag_ids<'a>(note: &Note<'a>, name: &str) -> Vec<&'a [u8; 32]> {
+    let mut ids = vec![];
+    for tag in note.tags() {
+        if tag.count() < 2 || tag.get_unchecked(0).variant().str() != Some(name) {
+            continue;
+        }
+        if let NdbStrVariant::Id(id) = tag.get_unchecked(1).variant() {
+            ids.push(id);
+        }
+    }
+    ids
+}
+
+fn tag_strs<'a>(note: &Note<'a>, name: &str) -> Vec<&'a str> {
+    let mut values = vec![];
+    for tag in note.tags() {
+        if tag.count() < 2 || tag.get_unchecked(0).variant().str() != Some(name) {
+            continue;
+        }
+        if let Some(value) = tag.get_unchecked(1).variant().str() {
+            values.push(value);
+        }
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NoteBuilder;
+
+    #[test]
+    fn list_works() {
+        let note = NoteBuilder::new()
+            .kind(30003)
+            .content("")
+            .start_tag()
+            .tag_str("d")
+            .tag_str("reading")
+            .start_tag()
+            .tag_str("title")
+            .tag_str("To read")
+            .start_tag()
+            .tag_str("e")
+            .tag_str("702555e52e82cc24ad517ba78c21879f6e47a7c0692b9b20df147916ae8731a3")
+            .start_tag()
+            .tag_str("a")
+            .tag_str("30023:32bf915904bfde2d136ba45dde32c88f4aca863783999faea2e847a8fafd2f15:post")
+            .start_tag()
+            .tag_str("r")
+            .tag_str("https://example.com")
+            .build()
+            .expect("note");
+
+        let list = List::new(note).expect("list");
+        assert_eq!(list.kind(), Kind::BookmarkSet);
+        assert_eq!(list.identifier(), Some("reading"));
+        assert_eq!(list.title(), Some("To read"));
+        assert_eq!(list.note_ids().len(), 1);
+        assert_eq!(list.addresses().len(), 1);
+        assert_eq!(list.urls(), vec!["https://example.com"]);
+        assert!(list.pubkeys().is_empty());
+        assert!(!list.has_private_items());
+
+        let note = NoteBuilder::new()
+            .kind(1)
+            .content("")
+            .build()
+            .expect("note");
+        assert!(List::new(note).is_none());
+    }
+}
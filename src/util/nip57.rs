@@ -0,0 +1,200 @@
+use crate::util::first_tag_str;
+use crate::{Kind, NdbStrVariant, Note};
+
+/// A recipient of a zap split, from a `["zap", <pubkey>, <relay>, <weight>]`
+/// tag (NIP-57 appendix G)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ZapSplit<'a> {
+    pub pubkey: &'a [u8; 32],
+
+    /// Where to find the recipient's profile
+    pub relay: Option<&'a str>,
+
+    /// Relative share, None if the tag has none
+    pub weight: Option<u64>,
+}
+
+/// Every zap split tag in a note, in tag order
+pub fn note_zap_splits<'a>(note: &Note<'a>) -> Vec<ZapSplit<'a>> {
+    let mut splits = vec![];
+    for tag in note.tags() {
+        if tag.count() < 2 || tag.get_unchecked(0).variant().str() != Some("zap") {
+            continue;
+        }
+        let pubkey = match tag.get_unchecked(1).variant() {
+            NdbStrVariant::Id(pubkey) => pubkey,
+            NdbStrVariant::Str(_) => continue,
+        };
+        let relay = tag
+            .get(2)
+            .and_then(|relay| relay.variant().str())
+            .filter(|relay| !relay.is_empty());
+        let weight = tag
+            .get(3)
+            .and_then(|weight| weight.variant().str())
+            .and_then(|weight| weight.parse().ok());
+        splits.push(ZapSplit {
+            pubkey,
+            relay,
+            weight,
+        });
+    }
+    splits
+}
+
+/// Divide `msats` between `splits` by weight, in the same order. If none of
+/// them has a weight they get equal shares, otherwise the ones without a
+/// weight get nothing. What can't be divided evenly goes to the first
+/// recipient with a share.
+pub fn split_amount(splits: &[ZapSplit], msats: u64) -> Vec<u64> {
+    let weighted = splits.iter().any(|split| split.weight.is_some());
+    let weights: Vec<u64> = splits
+        .iter()
+        .map(|split| match split.weight {
+            Some(weight) => weight,
+            None if weighted => 0,
+            None => 1,
+        })
+        .collect();
+    let total: u128 = weights.iter().map(|w| *w as u128).sum();
+    if total == 0 {
+        return vec![0; splits.len()];
+    }
+
+    let mut amounts: Vec<u64> = weights
+        .iter()
+        .map(|w| (msats as u128 * *w as u128 / total) as u64)
+        .collect();
+    let rest = msats - amounts.iter().sum::<u64>();
+    if let Some(first) = weights.iter().position(|w| *w > 0) {
+        amounts[first] += rest;
+    }
+    amounts
+}
+
+/// The amount of a bolt11 invoice in millisats, from its human readable
+/// part. None for invoices without an amount.
+pub fn bolt11_msats(invoice: &str) -> Option<u64> {
+    let invoice = invoice.trim().to_ascii_lowercase();
+    let invoice = invoice.strip_prefix("lightning:").unwrap_or(&invoice);
+    let (hrp, _) = invoice.rsplit_once('1')?;
+    let amount = hrp
+        .strip_prefix("ln")?
+        .trim_start_matches(|c: char| c.is_ascii_alphabetic());
+
+    let (digits, multiplier) = match amount.char_indices().last()? {
+        (i, c) if c.is_ascii_alphabetic() => (&amount[..i], Some(c)),
+        _ => (amount, None),
+    };
+    let n: u64 = digits.parse().ok()?;
+    match multiplier {
+        None => n.checked_mul(100_000_000_000),
+        Some('m') => n.checked_mul(100_000_000),
+        Some('u') => n.checked_mul(100_000),
+        Some('n') => n.checked_mul(100),
+        Some('p') if n.is_multiple_of(10) => Some(n / 10),
+        _ => None,
+    }
+}
+
+/// NIP-57 zap receipt (kind 9735), published by the recipient's lightning
+/// service once an invoice is paid
+#[derive(Debug)]
+pub struct ZapReceipt<'a> {
+    note: Note<'a>,
+}
+
+impl<'a> ZapReceipt<'a> {
+    /// Returns None if the note isn't a zap receipt
+    pub fn new(note: Note<'a>) -> Option<Self> {
+        if note.kind_enum() != Kind::ZapReceipt {
+            return None;
+        }
+        Some(ZapReceipt { note })
+    }
+
+    pub fn note(&self) -> &Note<'a> {
+        &self.note
+    }
+
+    pub fn bolt11(&self) -> Option<&'a str> {
+        first_tag_str(&self.note, "bolt11")
+    }
+
+    /// What was paid, going by the invoice rather than the zap request
+    pub fn amount_msats(&self) -> Option<u64> {
+        self.bolt11().and_then(bolt11_msats)
+    }
+
+    /// Who was zapped
+    pub fn recipient(&self) -> Option<&'a [u8; 32]> {
+        first_tag_id(&self.note, "p")
+    }
+
+    /// The note that was zapped, if it wasn't just the profile
+    pub fn zapped_note(&self) -> Option<&'a [u8; 32]> {
+        first_tag_id(&self.note, "e")
+    }
+}
+
+fn first_tag_id<'a>(note: &Note<'a>, name: &str) -> Option<&'a [u8; 32]> {
+    for tag in note.tags() {
+        if tag.count() < 2 || tag.get_unchecked(0).variant().str() != Some(name) {
+            continue;
+        }
+        if let NdbStrVariant::Id(id) = tag.get_unchecked(1).variant() {
+            return Some(id);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NoteBuilder;
+
+    #[test]
+    fn zap_splits_work() {
+        assert_eq!(bolt11_msats("lnbc10u1pjq8xyz"), Some(1_000_000));
+        assert_eq!(bolt11_msats("LIGHTNING:LNBC2500N1PJQ"), Some(250_000));
+        assert_eq!(bolt11_msats("lnbcrt1m1pjq"), Some(100_000_000));
+        assert_eq!(bolt11_msats("lnbc15p1pjq"), None);
+        assert_eq!(bolt11_msats("lnbc1pjq8xyz"), None);
+
+        let alice = "82341f882b6eabcd2ba7f1ef90aad961cf074af15b9ef44a09f9d2a8fbfbe6a2";
+        let bob = "32e1827635450ebb3c5a7d12c1f8e7b2b514439ac10a67eef3d9fd9c5c68e245";
+        let note = NoteBuilder::new()
+            .kind(1)
+            .content("thanks to both of them")
+            .start_tag()
+            .tag_str("zap")
+            .tag_str(alice)
+            .tag_str("wss://nos.lol")
+            .tag_str("2")
+            .start_tag()
+            .tag_str("zap")
+            .tag_str(bob)
+            .tag_str("")
+            .tag_str("1")
+            .sign(&[1u8; 32])
+            .build()
+            .expect("note");
+
+        let splits = note_zap_splits(&note);
+        assert_eq!(splits.len(), 2);
+        assert_eq!(splits[0].relay, Some("wss://nos.lol"));
+        assert_eq!(splits[1].relay, None);
+        assert_eq!(splits[1].weight, Some(1));
+        assert_eq!(split_amount(&splits, 10_000), vec![6_667, 3_333]);
+
+        let unweighted: Vec<ZapSplit> = splits
+            .iter()
+            .map(|split| ZapSplit {
+                weight: None,
+                ..*split
+            })
+            .collect();
+        assert_eq!(split_amount(&unweighted, 5), vec![3, 2]);
+    }
+}
@@ -0,0 +1,24 @@
+use crate::util::relay_hints::normalize;
+use crate::Note;
+
+/// The relays a NIP-65 relay list (kind 10002) says its author publishes
+/// to: `r` tags marked `write`, or without a marker
+pub(crate) fn write_relays<'a>(note: &Note<'a>) -> Vec<&'a str> {
+    let mut relays = vec![];
+    for tag in note.tags() {
+        if tag.count() < 2 || tag.get_unchecked(0).variant().str() != Some("r") {
+            continue;
+        }
+        let marker = tag.get(2).and_then(|marker| marker.variant().str());
+        if marker.is_some_and(|marker| marker != "write") {
+            continue;
+        }
+        let relay = tag.get_unchecked(1).variant().str().and_then(normalize);
+        if let Some(relay) = relay {
+            if !relays.contains(&relay) {
+                relays.push(relay);
+            }
+        }
+    }
+    relays
+}
@@ -0,0 +1,145 @@
+use crate::util::first_tag_str;
+use crate::util::nip57::{note_zap_splits, ZapSplit};
+use crate::{Kind, Note};
+
+/// NIP-75 zap goal (kind 9041): a fundraising target that zaps tagging the
+/// goal count towards. See [Ndb::zap_goal_progress] for how far along it
+/// is.
+///
+/// [Ndb::zap_goal_progress]: crate::Ndb::zap_goal_progress
+#[derive(Debug)]
+pub struct ZapGoal<'a> {
+    note: Note<'a>,
+}
+
+impl<'a> ZapGoal<'a> {
+    /// Returns None if the note isn't a zap goal
+    pub fn new(note: Note<'a>) -> Option<Self> {
+        if note.kind_enum() != Kind::ZapGoal {
+            return None;
+        }
+        Some(ZapGoal { note })
+    }
+
+    pub fn note(&self) -> &Note<'a> {
+        &self.note
+    }
+
+    /// What the goal is for
+    pub fn description(&self) -> &'a str {
+        self.note.content()
+    }
+
+    /// The target, in millisats
+    pub fn amount_msats(&self) -> Option<u64> {
+        first_tag_str(&self.note, "amount").and_then(|amount| amount.parse().ok())
+    }
+
+    /// Where zap receipts for the goal are sent, and should be counted from
+    pub fn relays(&self) -> Vec<&'a str> {
+        let tag =
+            self.note.tags().into_iter().find(|tag| {
+                tag.count() > 0 && tag.get_unchecked(0).variant().str() == Some("relays")
+            });
+        match tag {
+            Some(tag) => (1..tag.count())
+                .filter_map(|i| tag.get_unchecked(i).variant().str())
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    /// Zaps after this unix time don't count
+    pub fn closed_at(&self) -> Option<u64> {
+        first_tag_str(&self.note, "closed_at").and_then(|at| at.parse().ok())
+    }
+
+    pub fn image(&self) -> Option<&'a str> {
+        first_tag_str(&self.note, "image")
+    }
+
+    pub fn summary(&self) -> Option<&'a str> {
+        first_tag_str(&self.note, "summary")
+    }
+
+    /// Who the zaps are split between, see [split_amount]
+    ///
+    /// [split_amount]: crate::split_amount
+    pub fn beneficiaries(&self) -> Vec<ZapSplit<'a>> {
+        note_zap_splits(&self.note)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util, Config, Ndb, NoteBuilder, Transaction};
+
+    #[test]
+    fn zap_goal_progress_works() {
+        let db = "target/testdbs/zap_goal";
+        test_util::cleanup_db(db);
+
+        let goal = NoteBuilder::new()
+            .kind(9041)
+            .content("new relay hardware")
+            .start_tag()
+            .tag_str("amount")
+            .tag_str("21000000")
+            .start_tag()
+            .tag_str("relays")
+            .tag_str("wss://nos.lol")
+            .tag_str("wss://relay.damus.io")
+            .start_tag()
+            .tag_str("closed_at")
+            .tag_str("2000")
+            .sign(&[1u8; 32])
+            .build()
+            .expect("note");
+        let goal_id = crate::util::encode_hex(goal.id());
+        let receipt = |bolt11: &str, created_at: u64| {
+            NoteBuilder::new()
+                .kind(9735)
+                .content("")
+                .created_at(created_at)
+                .start_tag()
+                .tag_str("e")
+                .tag_str(&goal_id)
+                .start_tag()
+                .tag_str("bolt11")
+                .tag_str(bolt11)
+                .sign(&[2u8; 32])
+                .build()
+                .expect("note")
+        };
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            for note in [
+                &goal,
+                &receipt("lnbc10u1pjqaaa", 1000),
+                &receipt("lnbc2500n1pjqbbb", 1500),
+                // too late
+                &receipt("lnbc1m1pjqccc", 3000),
+            ] {
+                let json = note.json().expect("json");
+                ndb.process_event(&format!("[\"EVENT\",\"goal\",{json}]"))
+                    .expect("process ok");
+            }
+        }
+
+        let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+        let txn = Transaction::new(&ndb).expect("txn");
+        let stored = ndb.get_note_by_id(&txn, goal.id()).expect("goal");
+        let stored = ZapGoal::new(stored).expect("zap goal");
+        assert_eq!(stored.amount_msats(), Some(21_000_000));
+        assert_eq!(
+            stored.relays(),
+            vec!["wss://nos.lol", "wss://relay.damus.io"]
+        );
+        assert_eq!(stored.closed_at(), Some(2000));
+        assert_eq!(
+            ndb.zap_goal_progress(&txn, goal.id()).expect("progress"),
+            1_250_000
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use crate::util::first_tag_str;
+use crate::{Kind, NdbStrVariant, Note};
+
+/// Where a highlight was taken from. Also what [Ndb::highlights_for] looks
+/// up.
+///
+/// [Ndb::highlights_for]: crate::Ndb::highlights_for
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HighlightSource<'a> {
+    /// A note, from an `e` tag
+    Note(&'a [u8; 32]),
+
+    /// A `kind:pubkey:identifier` coordinate, eg. an article, from an `a`
+    /// tag
+    Address(&'a str),
+
+    /// A web page, from an `r` tag
+    Url(&'a str),
+}
+
+/// NIP-84 highlight (kind 9802)
+#[derive(Debug)]
+pub struct Highlight<'a> {
+    note: Note<'a>,
+}
+
+impl<'a> Highlight<'a> {
+    /// Returns None if the note isn't a highlight
+    pub fn new(note: Note<'a>) -> Option<Self> {
+        if note.kind_enum() != Kind::Highlight {
+            return None;
+        }
+        Some(Highlight { note })
+    }
+
+    pub fn note(&self) -> &Note<'a> {
+        &self.note
+    }
+
+    /// The highlighted text
+    pub fn text(&self) -> &'a str {
+        self.note.content()
+    }
+
+    /// The surrounding text, when the highlight is only part of it
+    pub fn context(&self) -> Option<&'a str> {
+        first_tag_str(&self.note, "context")
+    }
+
+    /// What the highlighter said about it, for quote highlights
+    pub fn comment(&self) -> Option<&'a str> {
+        first_tag_str(&self.note, "comment")
+    }
+
+    /// Every source tag, in tag order
+    pub fn sources(&self) -> Vec<HighlightSource<'a>> {
+        let mut sources = vec![];
+        for tag in self.note.tags() {
+            if tag.count() < 2 {
+                continue;
+            }
+            let value = tag.get_unchecked(1).variant();
+            let source = match (tag.get_unchecked(0).variant().str(), value) {
+                (Some("e"), NdbStrVariant::Id(id)) => HighlightSource::Note(id),
+                (Some("a"), NdbStrVariant::Str(coord)) => HighlightSource::Address(coord),
+                // `r` tags marked as mentions are urls in the comment
+                (Some("r"), NdbStrVariant::Str(url)) => {
+                    let marker = tag.get(2).and_then(|t| t.variant().str());
+                    if matches!(marker, Some(m) if m != "source") {
+                        continue;
+                    }
+                    HighlightSource::Url(url)
+                }
+                _ => continue,
+            };
+            sources.push(source);
+        }
+        sources
+    }
+
+    /// Where the highlight was taken from. An address is preferred over a
+    /// note id, since it stays the same across edits, and either over a
+    /// url.
+    pub fn source(&self) -> Option<HighlightSource<'a>> {
+        let sources = self.sources();
+        let rank = |source: &HighlightSource| match source {
+            HighlightSource::Address(_) => 0,
+            HighlightSource::Note(_) => 1,
+            HighlightSource::Url(_) => 2,
+        };
+        sources.into_iter().min_by_key(rank)
+    }
+
+    /// The authors of the highlighted content, from `p` tags
+    pub fn authors(&self) -> Vec<&'a [u8; 32]> {
+        let mut authors = vec![];
+        for tag in self.note.tags() {
+            if tag.count() < 2 || tag.get_unchecked(0).variant().str() != Some("p") {
+                continue;
+            }
+            if let NdbStrVariant::Id(pubkey) = tag.get_unchecked(1).variant() {
+                authors.push(pubkey);
+            }
+        }
+        authors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NoteBuilder;
+
+    #[test]
+    fn highlight_works() {
+        let note = NoteBuilder::new()
+            .kind(9802)
+            .content("an unfairly fast database")
+            .start_tag()
+            .tag_str("r")
+            .tag_str("https://example.com/post")
+            .start_tag()
+            .tag_str("a")
+            .tag_str("30023:32bf915904bfde2d136ba45dde32c88f4aca863783999faea2e847a8fafd2f15:post")
+            .start_tag()
+            .tag_str("r")
+            .tag_str("https://example.com/other")
+            .tag_str("mention")
+            .start_tag()
+            .tag_str("context")
+            .tag_str("nostrdb is an unfairly fast database")
+            .build()
+            .expect("note");
+
+        let highlight = Highlight::new(note).expect("highlight");
+        assert_eq!(highlight.text(), "an unfairly fast database");
+        assert_eq!(
+            highlight.context(),
+            Some("nostrdb is an unfairly fast database")
+        );
+        assert_eq!(highlight.comment(), None);
+        assert_eq!(highlight.sources().len(), 2);
+        assert_eq!(
+            highlight.source(),
+            Some(HighlightSource::Address(
+                "30023:32bf915904bfde2d136ba45dde32c88f4aca863783999faea2e847a8fafd2f15:post"
+            ))
+        );
+    }
+}
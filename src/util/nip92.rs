@@ -0,0 +1,122 @@
+use crate::{Note, Tag};
+
+/// Media metadata from a NIP-92 `imeta` tag
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Imeta<'a> {
+    pub url: &'a str,
+
+    /// mime type, from `m`
+    pub mime_type: Option<&'a str>,
+
+    /// (width, height) in pixels, from `dim`
+    pub dimensions: Option<(u32, u32)>,
+
+    pub blurhash: Option<&'a str>,
+
+    /// hex encoded sha256 of the file, from `x`
+    pub sha256: Option<&'a str>,
+
+    pub alt: Option<&'a str>,
+
+    /// alternative urls for the same file
+    pub fallbacks: Vec<&'a str>,
+}
+
+fn parse_dimensions(dim: &str) -> Option<(u32, u32)> {
+    let (w, h) = dim.split_once('x')?;
+    Some((w.parse().ok()?, h.parse().ok()?))
+}
+
+impl<'a> Imeta<'a> {
+    /// Parse an `imeta` tag. Returns None if this isn't an imeta tag or it
+    /// is missing its url.
+    pub fn from_tag(tag: &Tag<'a>) -> Option<Imeta<'a>> {
+        if tag.count() < 2 || tag.get_unchecked(0).variant().str() != Some("imeta") {
+            return None;
+        }
+
+        let mut url: Option<&'a str> = None;
+        let mut imeta = Imeta::default();
+
+        for ind in 1..tag.count() {
+            let entry = if let Some(s) = tag.get_unchecked(ind).variant().str() {
+                s
+            } else {
+                continue;
+            };
+
+            let (key, value) = if let Some(kv) = entry.split_once(' ') {
+                kv
+            } else {
+                continue;
+            };
+
+            match key {
+                "url" => url = Some(value),
+                "m" => imeta.mime_type = Some(value),
+                "dim" => imeta.dimensions = parse_dimensions(value),
+                "blurhash" => imeta.blurhash = Some(value),
+                "x" => imeta.sha256 = Some(value),
+                "alt" => imeta.alt = Some(value),
+                "fallback" => imeta.fallbacks.push(value),
+                _ => {}
+            }
+        }
+
+        imeta.url = url?;
+        Some(imeta)
+    }
+}
+
+/// All the `imeta` tags in a note
+pub fn note_imetas<'a>(note: &Note<'a>) -> Vec<Imeta<'a>> {
+    note.tags()
+        .iter()
+        .filter_map(|tag| Imeta::from_tag(&tag))
+        .collect()
+}
+
+/// The `imeta` metadata for a specific url in a note, if any
+pub fn note_imeta<'a>(note: &Note<'a>, url: &str) -> Option<Imeta<'a>> {
+    note.tags()
+        .iter()
+        .filter_map(|tag| Imeta::from_tag(&tag))
+        .find(|imeta| imeta.url == url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NoteBuilder;
+
+    #[test]
+    fn imeta_parsing_works() {
+        let url = "https://nostr.build/i/my-image.jpg";
+        let note = NoteBuilder::new()
+            .kind(1)
+            .content(&format!("look at this {url}"))
+            .start_tag()
+            .tag_str("imeta")
+            .tag_str(&format!("url {url}"))
+            .tag_str("m image/jpeg")
+            .tag_str("blurhash eVF$^OI:${M{o#*0-nNFxakD-?xVM}WEWB%iNKxvR-oetmo#R-aen$")
+            .tag_str("dim 3024x4032")
+            .tag_str("alt A scenic photo overlooking the coast of Costa Rica")
+            .tag_str("fallback https://nostrcheck.me/alt1.jpg")
+            .tag_str("fallback https://void.cat/alt1.jpg")
+            .build()
+            .expect("note");
+
+        let imeta = note_imeta(&note, url).expect("imeta");
+        assert_eq!(imeta.url, url);
+        assert_eq!(imeta.mime_type, Some("image/jpeg"));
+        assert_eq!(imeta.dimensions, Some((3024, 4032)));
+        assert_eq!(
+            imeta.alt,
+            Some("A scenic photo overlooking the coast of Costa Rica")
+        );
+        assert_eq!(imeta.fallbacks.len(), 2);
+        assert_eq!(note_imetas(&note).len(), 1);
+        assert!(note_imeta(&note, "https://example.com").is_none());
+    }
+}
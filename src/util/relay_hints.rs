@@ -0,0 +1,157 @@
+use crate::{Blocks, Mention, NdbStrVariant, Note};
+
+/// What a relay hint says can be fetched from that relay
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum HintTarget<'a> {
+    Event(&'a [u8; 32]),
+    Pubkey(&'a [u8; 32]),
+
+    /// An `a` tag coordinate, `kind:pubkey:identifier`. `naddr` mentions
+    /// don't carry their kind, so those are hinted as their author instead.
+    Address(&'a str),
+}
+
+/// A relay where something a note references can be found
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct RelayHint<'a> {
+    pub relay: &'a str,
+    pub target: HintTarget<'a>,
+}
+
+/// Trailing slashes don't make a different relay
+pub(crate) fn normalize(relay: &str) -> Option<&str> {
+    let relay = relay.trim().trim_end_matches('/');
+    (!relay.is_empty()).then_some(relay)
+}
+
+fn push_hint<'a>(hints: &mut Vec<RelayHint<'a>>, relay: &'a str, target: HintTarget<'a>) {
+    if let Some(relay) = normalize(relay) {
+        let hint = RelayHint { relay, target };
+        if !hints.contains(&hint) {
+            hints.push(hint);
+        }
+    }
+}
+
+/// Relay hints in the third element of `e`, `q`, `p` and `a` tags
+fn tag_hints<'a>(note: &Note<'a>, hints: &mut Vec<RelayHint<'a>>) {
+    for tag in note.tags() {
+        if tag.count() < 3 {
+            continue;
+        }
+
+        let relay = if let Some(relay) = tag.get_unchecked(2).variant().str() {
+            relay
+        } else {
+            continue;
+        };
+
+        let target = match (
+            tag.get_unchecked(0).variant().str(),
+            tag.get_unchecked(1).variant(),
+        ) {
+            (Some("e") | Some("q"), NdbStrVariant::Id(id)) => HintTarget::Event(id),
+            (Some("p"), NdbStrVariant::Id(pk)) => HintTarget::Pubkey(pk),
+            (Some("a"), NdbStrVariant::Str(coord)) => HintTarget::Address(coord),
+            _ => continue,
+        };
+        push_hint(hints, relay, target);
+    }
+}
+
+fn mention_hints<'a>(note: &Note<'a>, blocks: &Blocks<'a>, hints: &mut Vec<RelayHint<'a>>) {
+    for block in blocks.iter(note) {
+        let (relays, target) = match block.as_mention() {
+            Some(Mention::Event(ev)) => (&ev.relays, HintTarget::Event(ev.id())),
+            Some(Mention::Profile(p)) => (&p.relays, HintTarget::Pubkey(p.pubkey())),
+            Some(Mention::Addr(a)) => (&a.relays, HintTarget::Pubkey(a.pubkey())),
+            _ => continue,
+        };
+
+        for relay in relays.iter() {
+            push_hint(hints, relay, target);
+        }
+    }
+}
+
+/// Every relay hint in a note, from `nevent`, `nprofile` and `naddr`
+/// mentions in its content blocks and from the relay element of its
+/// tags, without duplicates. Pass the note's [Blocks] to include mentions.
+pub fn note_relay_hints<'a>(note: &Note<'a>, blocks: Option<&Blocks<'a>>) -> Vec<RelayHint<'a>> {
+    let mut hints = vec![];
+    if let Some(blocks) = blocks {
+        mention_hints(note, blocks, &mut hints);
+    }
+    tag_hints(note, &mut hints);
+    hints
+}
+
+/// The distinct relays hinted at anywhere in a note, see
+/// [note_relay_hints]
+pub fn note_hinted_relays<'a>(note: &Note<'a>, blocks: Option<&Blocks<'a>>) -> Vec<&'a str> {
+    let mut relays: Vec<&str> = vec![];
+    for hint in note_relay_hints(note, blocks) {
+        if !relays.contains(&hint.relay) {
+            relays.push(hint.relay);
+        }
+    }
+    relays
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util, Config, Ndb, Transaction};
+
+    #[test]
+    fn relay_hints_work() {
+        let db = "target/testdbs/relay_hints";
+        test_util::cleanup_db(db);
+
+        let mut config = Config::new();
+        config.skip_validation(true);
+
+        {
+            let ndb = Ndb::new(db, &config).expect("ndb");
+            ndb.process_event("[\"EVENT\",\"s\",{\"id\":\"d28ac02e277c3cf2744b562a414fd92d5fea554a737901364735bfe74577f304\",\"pubkey\":\"b5b1b5d2914daa2eda99af22ae828effe98730bf69dcca000fa37bfb9e395e32\",\"created_at\": 1703989205,\"kind\": 1,\"tags\": [[\"p\",\"32e1827635450ebb3c5a7d12c1f8e7b2b514439ac10a67eef3d9fd9c5c68e245\",\"wss://relay.damus.io/\"],[\"e\",\"702555e52e82cc24ad517ba78c21879f6e47a7c0692b9b20df147916ae8731a3\",\"wss://nos.lol\"],[\"e\",\"702555e52e82cc24ad517ba78c21879f6e47a7c0692b9b20df147916ae8731a3\",\"\"]],\"content\": \"#hashtags, are neat nostr:nprofile1qqsr9cvzwc652r4m83d86ykplrnm9dg5gwdvzzn8ameanlvut35wy3gpz3mhxue69uhhyetvv9ujuerpd46hxtnfduyu75sw https://github.com/damus-io\",\"sig\": \"07af3062616a17ef392769cadb170ac855c817c103e007c72374499bbadb2fe8917a0cc5b3fdc5aa5d56de086e128b3aeaa8868f6fe42a409767241b6a29cc94\"}]").expect("process ok");
+        }
+
+        {
+            let ndb = Ndb::new(db, &config).expect("ndb");
+            let txn = Transaction::new(&ndb).expect("txn");
+            let id: [u8; 32] =
+                hex::decode("d28ac02e277c3cf2744b562a414fd92d5fea554a737901364735bfe74577f304")
+                    .expect("hex id")
+                    .try_into()
+                    .expect("id bytes");
+            let jb55: [u8; 32] =
+                hex::decode("32e1827635450ebb3c5a7d12c1f8e7b2b514439ac10a67eef3d9fd9c5c68e245")
+                    .expect("hex pubkey")
+                    .try_into()
+                    .expect("pubkey bytes");
+
+            let note = ndb.get_note_by_id(&txn, &id).expect("note");
+            let blocks = ndb
+                .get_blocks_by_key(&txn, note.key().unwrap())
+                .expect("blocks");
+
+            let hints = note_relay_hints(&note, Some(&blocks));
+            assert_eq!(hints.len(), 2);
+            assert_eq!(
+                hints[0],
+                RelayHint {
+                    relay: "wss://relay.damus.io",
+                    target: HintTarget::Pubkey(&jb55),
+                }
+            );
+            assert_eq!(hints[1].relay, "wss://nos.lol");
+            assert!(matches!(hints[1].target, HintTarget::Event(_)));
+
+            assert_eq!(note_relay_hints(&note, None).len(), 2);
+            assert_eq!(
+                note_hinted_relays(&note, Some(&blocks)),
+                vec!["wss://relay.damus.io", "wss://nos.lol"]
+            );
+        }
+    }
+}
@@ -0,0 +1,115 @@
+/// What a url block most likely points to. Useful for deciding between
+/// embedding media inline or showing a link preview.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UrlKind {
+    Image,
+    Video,
+    Audio,
+    Webpage,
+}
+
+const IMAGE_EXTS: &[&str] = &[
+    "jpg", "jpeg", "png", "gif", "webp", "bmp", "svg", "avif", "heic", "tiff",
+];
+const VIDEO_EXTS: &[&str] = &["mp4", "mov", "webm", "mkv", "avi", "m4v", "ogv", "m3u8"];
+const AUDIO_EXTS: &[&str] = &["mp3", "wav", "ogg", "oga", "flac", "m4a", "aac", "opus"];
+
+fn split_scheme(url: &str) -> Option<(&str, &str)> {
+    let (scheme, rest) = url.split_once("://")?;
+    if scheme.is_empty()
+        || !scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '+')
+    {
+        return None;
+    }
+    Some((scheme, rest))
+}
+
+/// The host portion of a url, without userinfo or port
+pub fn url_host(url: &str) -> Option<&str> {
+    let (_, rest) = split_scheme(url)?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority.rsplit('@').next()?;
+
+    let host = if let Some(ipv6) = host.strip_prefix('[') {
+        ipv6.split(']').next()?
+    } else {
+        host.split(':').next()?
+    };
+
+    if host.is_empty() {
+        None
+    } else {
+        Some(host)
+    }
+}
+
+fn url_extension(url: &str) -> Option<&str> {
+    let (_, rest) = split_scheme(url)?;
+    let path = rest.split(['?', '#']).next()?;
+    let (_, path) = path.split_once('/')?;
+    let last = path.rsplit('/').next()?;
+    let (_, ext) = last.rsplit_once('.')?;
+    Some(ext)
+}
+
+/// Classify a url by its scheme and file extension
+pub fn url_kind(url: &str) -> UrlKind {
+    let scheme = if let Some((scheme, _)) = split_scheme(url) {
+        scheme
+    } else {
+        return UrlKind::Webpage;
+    };
+
+    if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+        return UrlKind::Webpage;
+    }
+
+    let ext = if let Some(ext) = url_extension(url) {
+        ext.to_ascii_lowercase()
+    } else {
+        return UrlKind::Webpage;
+    };
+
+    if IMAGE_EXTS.contains(&ext.as_str()) {
+        UrlKind::Image
+    } else if VIDEO_EXTS.contains(&ext.as_str()) {
+        UrlKind::Video
+    } else if AUDIO_EXTS.contains(&ext.as_str()) {
+        UrlKind::Audio
+    } else {
+        UrlKind::Webpage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn url_kind_works() {
+        assert_eq!(
+            url_kind("https://cdn.jb55.com/img/red-me.jpg"),
+            UrlKind::Image
+        );
+        assert_eq!(
+            url_kind("https://example.com/a.PNG?w=100#x"),
+            UrlKind::Image
+        );
+        assert_eq!(url_kind("https://example.com/clip.mp4"), UrlKind::Video);
+        assert_eq!(url_kind("http://example.com/song.mp3"), UrlKind::Audio);
+        assert_eq!(url_kind("https://github.com/damus-io"), UrlKind::Webpage);
+        assert_eq!(url_kind("https://example.com.jpg"), UrlKind::Webpage);
+        assert_eq!(url_kind("ftp://example.com/a.jpg"), UrlKind::Webpage);
+    }
+
+    #[test]
+    fn url_host_works() {
+        assert_eq!(url_host("https://github.com/damus-io"), Some("github.com"));
+        assert_eq!(url_host("https://user@damus.io:443/x"), Some("damus.io"));
+        assert_eq!(url_host("http://[::1]:8080/"), Some("::1"));
+        assert_eq!(url_host("https://damus.io?a=b"), Some("damus.io"));
+        assert_eq!(url_host("damus.io"), None);
+    }
+}
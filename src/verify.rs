@@ -0,0 +1,466 @@
+use crate::util::lock;
+use crate::{bindings, Note};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io;
+use std::os::raw::{c_char, c_int, c_uint, c_void};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+
+extern "C" {
+    // from the secp256k1 library bundled with nostrdb
+    fn secp256k1_context_create(flags: c_uint) -> *mut c_void;
+    fn secp256k1_context_destroy(ctx: *mut c_void);
+}
+
+/// SECP256K1_CONTEXT_VERIFY. Newer versions of secp256k1 can verify with
+/// any context, but accept this for compatibility.
+const SECP256K1_CONTEXT_VERIFY: c_uint = (1 << 0) | (1 << 8);
+
+/// How many queued events a verify thread takes at a time when the config
+/// doesn't say
+pub(crate) const DEFAULT_VERIFY_BATCH_SIZE: usize = 32;
+
+/// Checks note ids and signatures. Each one owns a secp256k1 context and a
+/// scratch buffer, so keep it around instead of making one per note.
+pub(crate) struct Verifier {
+    ctx: *mut c_void,
+    buf: Vec<u8>,
+}
+
+impl Verifier {
+    pub(crate) fn new() -> Option<Self> {
+        let ctx = unsafe { secp256k1_context_create(SECP256K1_CONTEXT_VERIFY) };
+        if ctx.is_null() {
+            return None;
+        }
+        Some(Verifier { ctx, buf: vec![] })
+    }
+
+    /// Whether a relay message (`["EVENT","subid",{...}]`), client message
+    /// (`["EVENT",{...}]`) or bare event object has a valid id and
+    /// signature
+    pub(crate) fn verify_message(&mut self, msg: &str) -> bool {
+        let event = if let Some(event) = event_object(msg) {
+            event
+        } else {
+            return false;
+        };
+
+        let note = if let Ok(note) = Note::owned_from_json(event) {
+            note
+        } else {
+            return false;
+        };
+
+        self.verify_owned(&note, event.len())
+    }
+
+    /// Recomputes the id of a note we own and checks it and the signature.
+    /// The note's id is overwritten with the computed one.
+    fn verify_owned(&mut self, note: &Note<'static>, json_len: usize) -> bool {
+        self.check_id(note, json_len) && self.check_sig(note)
+    }
+
+    /// Whether the id of a note we own is the hash of its contents. The
+    /// note's id is overwritten with the computed one.
+    pub(crate) fn check_id(&mut self, note: &Note<'static>, json_len: usize) -> bool {
+        let claimed = *note.id();
+
+        // the id commitment is never bigger than the json it came from
+        self.buf.resize(json_len + 1024, 0);
+        let ok = unsafe {
+            bindings::ndb_calculate_id(
+                note.as_ptr(),
+                self.buf.as_mut_ptr(),
+                self.buf.len() as c_int,
+            ) != 0
+        };
+        ok && *note.id() == claimed
+    }
+
+    pub(crate) fn check_sig(&self, note: &Note) -> bool {
+        self.check_schnorr(note.pubkey(), note.id(), note.sig())
+    }
+
+    /// Whether `sig` is `pubkey`'s signature of the hash `msg`
+    pub(crate) fn check_schnorr(&self, pubkey: &[u8; 32], msg: &[u8; 32], sig: &[u8; 64]) -> bool {
+        unsafe {
+            bindings::ndb_note_verify(
+                self.ctx,
+                pubkey.as_ptr() as *mut u8,
+                msg.as_ptr() as *mut u8,
+                sig.as_ptr() as *mut u8,
+            ) != 0
+        }
+    }
+}
+
+impl Drop for Verifier {
+    fn drop(&mut self) {
+        unsafe { secp256k1_context_destroy(self.ctx) };
+    }
+}
+
+thread_local! {
+    static VERIFIER: RefCell<Option<Verifier>> = const { RefCell::new(None) };
+}
+
+/// Run `f` with this thread's verifier, creating it on first use. Returns
+/// None if a secp256k1 context couldn't be created.
+pub(crate) fn with_verifier<R>(f: impl FnOnce(&mut Verifier) -> R) -> Option<R> {
+    VERIFIER.with(|cell| {
+        let mut verifier = cell.borrow_mut();
+        if verifier.is_none() {
+            *verifier = Verifier::new();
+        }
+        verifier.as_mut().map(f)
+    })
+}
+
+/// [verify_events] doesn't spread batches smaller than this across threads
+const PARALLEL_VERIFY_MIN: usize = 64;
+
+fn verify_batch<S: AsRef<str>>(events: &[S]) -> Vec<bool> {
+    with_verifier(|verifier| {
+        events
+            .iter()
+            .map(|event| verifier.verify_message(event.as_ref()))
+            .collect()
+    })
+    .unwrap_or_else(|| vec![false; events.len()])
+}
+
+/// Check the ids and signatures of many events at once, without storing
+/// them. Each entry can be a bare event object or an `EVENT` message.
+/// Returns whether each event is valid, in order.
+///
+/// Large batches are split across threads, and each thread reuses its
+/// secp256k1 context between calls.
+pub fn verify_events<S: AsRef<str> + Sync>(events: &[S]) -> Vec<bool> {
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    if threads == 1 || events.len() < PARALLEL_VERIFY_MIN {
+        return verify_batch(events);
+    }
+
+    let per_thread = events.len().div_ceil(threads);
+    thread::scope(|s| {
+        let handles: Vec<_> = events
+            .chunks(per_thread)
+            .map(|chunk| s.spawn(move || verify_batch(chunk)))
+            .collect();
+
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+            .collect()
+    })
+}
+
+/// Skip whitespace and a json string starting at `s`, returning the rest
+fn skip_string(s: &str) -> Option<&str> {
+    let s = s.trim_start().strip_prefix('"')?;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => return Some(&s[i + 1..]),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The event object in an EVENT message, or the message itself if it is
+/// already a bare event
+pub(crate) fn event_object(msg: &str) -> Option<&str> {
+    let msg = msg.trim();
+    if msg.starts_with('{') {
+        return Some(msg);
+    }
+
+    let rest = msg.strip_prefix('[')?;
+    let rest = skip_string(rest)?
+        .trim_start()
+        .strip_prefix(',')?
+        .trim_start();
+
+    // relays include a subscription id, clients don't
+    let rest = if rest.starts_with('"') {
+        skip_string(rest)?
+            .trim_start()
+            .strip_prefix(',')?
+            .trim_start()
+    } else {
+        rest
+    };
+
+    let event = rest.strip_suffix(']')?.trim_end();
+    event.starts_with('{').then_some(event)
+}
+
+struct NdbPtr(*mut bindings::ndb);
+
+/// The verify threads are stopped before the database is destroyed
+unsafe impl Send for NdbPtr {}
+
+impl NdbPtr {
+    /// False if nostrdb didn't take the event
+    fn process(&self, msg: &str) -> bool {
+        unsafe {
+            bindings::ndb_process_event(self.0, msg.as_ptr() as *const c_char, msg.len() as c_int)
+                != 0
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Jobs {
+    queue: VecDeque<String>,
+    shutdown: bool,
+}
+
+/// Threads that check ids and signatures of incoming events before handing
+/// them to nostrdb, which is opened with verification turned off. Used when
+/// [Config::set_verify_threads] is set, so verification can be scaled
+/// separately from nostrdb's ingester threads.
+///
+/// [Config::set_verify_threads]: crate::Config::set_verify_threads
+#[derive(Debug)]
+pub(crate) struct VerifyPool {
+    jobs: Mutex<Jobs>,
+    cond: Condvar,
+    batch_size: usize,
+
+    /// The most events queued at once, 0 for no limit
+    depth: usize,
+
+    /// Signalled when the workers take events off a full queue
+    space: Condvar,
+    workers: Mutex<Vec<thread::JoinHandle<()>>>,
+
+    /// Valid events that never reached nostrdb, because it turned them
+    /// away or a worker panicked with them
+    lost: AtomicUsize,
+}
+
+impl VerifyPool {
+    pub(crate) fn spawn(
+        ndb: *mut bindings::ndb,
+        threads: usize,
+        batch_size: usize,
+        depth: usize,
+    ) -> io::Result<Arc<Self>> {
+        let pool = Arc::new(VerifyPool {
+            jobs: Mutex::new(Jobs::default()),
+            cond: Condvar::new(),
+            batch_size: batch_size.max(1),
+            depth,
+            space: Condvar::new(),
+            workers: Mutex::new(vec![]),
+            lost: AtomicUsize::new(0),
+        });
+
+        for i in 0..threads.max(1) {
+            let worker_pool = pool.clone();
+            let ndb = NdbPtr(ndb);
+            let spawned = thread::Builder::new()
+                .name(format!("ndb-verify-{i}"))
+                .spawn(move || worker_pool.work(ndb));
+
+            match spawned {
+                Ok(handle) => lock(&pool.workers).push(handle),
+                Err(err) => {
+                    pool.shutdown();
+                    return Err(err);
+                }
+            }
+        }
+
+        Ok(pool)
+    }
+
+    /// Block while the queue is full
+    fn wait_for_space<'a>(&self, mut jobs: MutexGuard<'a, Jobs>) -> MutexGuard<'a, Jobs> {
+        while self.depth != 0 && jobs.queue.len() >= self.depth && !jobs.shutdown {
+            jobs = self.space.wait(jobs).unwrap_or_else(|e| e.into_inner());
+        }
+        jobs
+    }
+
+    /// Queue an event, blocking while the queue is full. False once the
+    /// pool is shutting down, as no worker would take it.
+    pub(crate) fn push(&self, msg: String) -> bool {
+        let mut jobs = self.wait_for_space(lock(&self.jobs));
+        if jobs.shutdown {
+            return false;
+        }
+        jobs.queue.push_back(msg);
+        drop(jobs);
+        self.cond.notify_one();
+        true
+    }
+
+    /// Like [VerifyPool::push], false if the pool shut down before all of
+    /// them were queued
+    pub(crate) fn push_all(&self, msgs: impl IntoIterator<Item = String>) -> bool {
+        if self.depth != 0 {
+            return msgs.into_iter().all(|msg| self.push(msg));
+        }
+
+        let mut jobs = lock(&self.jobs);
+        if jobs.shutdown {
+            return false;
+        }
+        jobs.queue.extend(msgs);
+        drop(jobs);
+        self.cond.notify_all();
+        true
+    }
+
+    /// Verify and hand off everything already queued, then stop the
+    /// threads. Returns how many valid events were lost along the way, at
+    /// least one for each worker that panicked.
+    pub(crate) fn shutdown(&self) -> usize {
+        lock(&self.jobs).shutdown = true;
+        self.cond.notify_all();
+        self.space.notify_all();
+
+        let workers = std::mem::take(&mut *lock(&self.workers));
+        for worker in workers {
+            if worker.join().is_err() {
+                self.lost.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.lost.load(Ordering::Relaxed)
+    }
+
+    fn work(&self, ndb: NdbPtr) {
+        let mut batch = Vec::with_capacity(self.batch_size);
+
+        loop {
+            {
+                let mut jobs = lock(&self.jobs);
+                while jobs.queue.is_empty() && !jobs.shutdown {
+                    jobs = self.cond.wait(jobs).unwrap_or_else(|e| e.into_inner());
+                }
+                if jobs.queue.is_empty() {
+                    return;
+                }
+
+                let n = jobs.queue.len().min(self.batch_size);
+                batch.extend(jobs.queue.drain(..n));
+            }
+            self.space.notify_all();
+
+            let valid = verify_batch(&batch);
+            for (msg, valid) in batch.drain(..).zip(valid) {
+                if valid && !ndb.process(&msg) {
+                    self.lost.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    #[test]
+    fn event_object_works() {
+        assert_eq!(
+            event_object(r#"["EVENT","s",{"id":"a"}]"#),
+            Some(r#"{"id":"a"}"#)
+        );
+        assert_eq!(
+            event_object(r#"[ "EVENT" , {"id":"a"} ]"#),
+            Some(r#"{"id":"a"}"#)
+        );
+        assert_eq!(event_object(r#"["EVENT","a\"],{",{}]"#), Some("{}"));
+        assert_eq!(event_object(r#" {"id":"a"} "#), Some(r#"{"id":"a"}"#));
+        assert_eq!(event_object(r#"["EOSE","s"]"#), None);
+    }
+
+    #[test]
+    fn verifier_works() {
+        let mut verifier = Verifier::new().expect("verifier");
+        let event = test_util::HELLO_NOTE;
+        assert!(verifier.verify_message(event));
+        assert!(verifier.verify_message(&format!(r#"["EVENT","s",{event}]"#)));
+
+        let tampered = event.replace("hello, world", "hello, world!");
+        assert!(!verifier.verify_message(&tampered));
+    }
+
+    #[test]
+    fn verify_events_works() {
+        let event = &test_util::hello_event();
+        let tampered = event.replace("hello, world", "hello, world!");
+
+        assert_eq!(verify_events(&[event, &tampered]), vec![true, false]);
+
+        // big enough to be split across threads
+        let mut events = vec![event.to_string(); 100];
+        events[42] = tampered;
+        let valid = verify_events(&events);
+        assert_eq!(valid.len(), 100);
+        assert_eq!(valid.iter().filter(|v| !**v).count(), 1);
+        assert!(!valid[42]);
+    }
+
+    #[test]
+    fn push_waits_for_space() {
+        // no workers, so nothing drains the queue unless we do
+        let pool = Arc::new(VerifyPool {
+            jobs: Mutex::new(Jobs::default()),
+            cond: Condvar::new(),
+            batch_size: 1,
+            depth: 2,
+            space: Condvar::new(),
+            workers: Mutex::new(vec![]),
+            lost: AtomicUsize::new(0),
+        });
+        assert!(pool.push("a".to_string()));
+        assert!(pool.push("b".to_string()));
+
+        let pusher = pool.clone();
+        let third = thread::spawn(move || pusher.push_all(["c".to_string(), "d".to_string()]));
+        thread::sleep(std::time::Duration::from_millis(100));
+        assert!(!third.is_finished());
+        assert_eq!(lock(&pool.jobs).queue.len(), 2);
+
+        // taking one off lets exactly one more in
+        lock(&pool.jobs).queue.pop_front();
+        pool.space.notify_all();
+        thread::sleep(std::time::Duration::from_millis(100));
+        assert!(!third.is_finished());
+        assert_eq!(lock(&pool.jobs).queue, ["b", "c"]);
+
+        // shutting down releases anyone still waiting, without queueing
+        // what no worker would take
+        assert_eq!(pool.shutdown(), 0);
+        assert!(!third.join().expect("pusher"));
+        assert_eq!(lock(&pool.jobs).queue, ["b", "c"]);
+        assert!(!pool.push("e".to_string()));
+        assert!(!pool.push_all(["f".to_string()]));
+    }
+
+    #[test]
+    fn shutdown_counts_lost_events() {
+        let pool = VerifyPool {
+            jobs: Mutex::new(Jobs::default()),
+            cond: Condvar::new(),
+            batch_size: 1,
+            depth: 0,
+            space: Condvar::new(),
+            workers: Mutex::new(vec![]),
+            lost: AtomicUsize::new(2),
+        };
+        lock(&pool.workers).push(thread::spawn(|| panic!("worker died")));
+        assert_eq!(pool.shutdown(), 3);
+    }
+}
@@ -0,0 +1,336 @@
+use crate::follows::{FollowGraph, GraphState};
+use crate::util::lock;
+use crate::{Ndb, Result, Transaction};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// How many roots' scores are kept around at once
+const CACHED_ROOTS: usize = 8;
+
+/// How far [Ndb::trust_scores] looks from the root, and how quickly trust
+/// fades with distance
+///
+/// [Ndb::trust_scores]: crate::Ndb::trust_scores
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrustOptions {
+    max_hops: u32,
+    attenuation: f64,
+}
+
+impl Default for TrustOptions {
+    fn default() -> Self {
+        TrustOptions::new()
+    }
+}
+
+impl TrustOptions {
+    pub const DEFAULT_MAX_HOPS: u32 = 3;
+    pub const DEFAULT_ATTENUATION: f64 = 0.5;
+
+    pub fn new() -> Self {
+        TrustOptions {
+            max_hops: Self::DEFAULT_MAX_HOPS,
+            attenuation: Self::DEFAULT_ATTENUATION,
+        }
+    }
+
+    /// Only score pubkeys at most this many follows away from the root.
+    /// 1 is the root's own follows.
+    pub fn set_max_hops(&mut self, hops: u32) -> &mut Self {
+        self.max_hops = hops;
+        self
+    }
+
+    /// How much of a follower's score is passed on to the pubkeys they
+    /// follow, past the root's own follows. Between 0 and 1.
+    pub fn set_attenuation(&mut self, attenuation: f64) -> &mut Self {
+        self.attenuation = attenuation.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn max_hops(&self) -> u32 {
+        self.max_hops
+    }
+
+    pub fn attenuation(&self) -> f64 {
+        self.attenuation
+    }
+}
+
+/// How a pubkey is connected to the root of a [TrustScores]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Trust {
+    /// The fewest follows between the root and this pubkey. 0 is the root.
+    pub hops: u32,
+
+    /// Between 0 and 1. The root and the pubkeys it follows have 1. Further
+    /// out, a pubkey gets the sum of its followers' scores one hop closer
+    /// in, times the attenuation, up to 1. So with the default attenuation
+    /// being followed by two of the root's follows counts as much as being
+    /// followed by the root.
+    pub score: f64,
+}
+
+/// Trust scores of the pubkeys around a root in the follow graph, see
+/// [Ndb::trust_scores]. Pubkeys too far away, or not connected at all,
+/// have no score.
+///
+/// [Ndb::trust_scores]: crate::Ndb::trust_scores
+#[derive(Debug, Clone)]
+pub struct TrustScores {
+    root: [u8; 32],
+    options: TrustOptions,
+    scores: HashMap<[u8; 32], Trust>,
+}
+
+impl TrustScores {
+    pub fn root(&self) -> &[u8; 32] {
+        &self.root
+    }
+
+    pub fn options(&self) -> &TrustOptions {
+        &self.options
+    }
+
+    pub fn get(&self, pubkey: &[u8; 32]) -> Option<Trust> {
+        self.scores.get(pubkey).copied()
+    }
+
+    /// 0 for pubkeys without a score
+    pub fn score(&self, pubkey: &[u8; 32]) -> f64 {
+        self.get(pubkey).map_or(0.0, |trust| trust.score)
+    }
+
+    /// The pubkeys scoring at least `min`, sorted. See also
+    /// [FilterBuilder::min_trust].
+    ///
+    /// [FilterBuilder::min_trust]: crate::FilterBuilder::min_trust
+    pub fn at_least(&self, min: f64) -> Vec<[u8; 32]> {
+        let mut pubkeys: Vec<[u8; 32]> = self
+            .scores
+            .iter()
+            .filter(|(_, trust)| trust.score >= min)
+            .map(|(pubkey, _)| *pubkey)
+            .collect();
+        pubkeys.sort_unstable();
+        pubkeys
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8; 32], Trust)> {
+        self.scores.iter().map(|(pubkey, trust)| (pubkey, *trust))
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    fn compute(graph: &GraphState, root: &[u8; 32], options: &TrustOptions) -> Self {
+        let mut scores = HashMap::new();
+        scores.insert(
+            *root,
+            Trust {
+                hops: 0,
+                score: 1.0,
+            },
+        );
+
+        let mut frontier = vec![*root];
+        for hops in 1..=options.max_hops {
+            let weight = if hops == 1 { 1.0 } else { options.attenuation };
+            let mut next: HashMap<[u8; 32], f64> = HashMap::new();
+            for pubkey in &frontier {
+                let passed = scores[pubkey].score * weight;
+                for followed in graph.follows(pubkey).into_iter().flatten() {
+                    if !scores.contains_key(followed) {
+                        *next.entry(*followed).or_default() += passed;
+                    }
+                }
+            }
+
+            frontier = next.keys().copied().collect();
+            for (pubkey, score) in next {
+                let score = score.min(1.0);
+                scores.insert(pubkey, Trust { hops, score });
+            }
+        }
+
+        TrustScores {
+            root: *root,
+            options: *options,
+            scores,
+        }
+    }
+
+    /// Whether a change to `author`'s contact list can change these scores.
+    /// Only the lists of pubkeys that pass on trust matter.
+    fn depends_on(&self, author: &[u8; 32]) -> bool {
+        self.get(author)
+            .is_some_and(|trust| trust.hops < self.options.max_hops)
+    }
+}
+
+#[derive(Debug)]
+struct Cached {
+    generation: u64,
+    scores: Arc<TrustScores>,
+}
+
+/// Recently computed scores, kept until a contact list they depend on
+/// changes
+#[derive(Debug, Default)]
+pub(crate) struct TrustCache {
+    cached: Mutex<Vec<Cached>>,
+}
+
+impl TrustCache {
+    pub(crate) fn scores(
+        &self,
+        ndb: &Ndb,
+        follows: &FollowGraph,
+        txn: &Transaction,
+        root: &[u8; 32],
+        options: &TrustOptions,
+    ) -> Result<Arc<TrustScores>> {
+        let mut cached = lock(&self.cached);
+        follows.with(ndb, txn, |graph| {
+            let generation = graph.generation();
+            let found = cached
+                .iter()
+                .position(|c| c.scores.root == *root && c.scores.options == *options);
+
+            if let Some(i) = found {
+                let entry = &mut cached[i];
+                let unaffected = graph
+                    .changed_since(entry.generation)
+                    .is_some_and(|authors| !authors.iter().any(|a| entry.scores.depends_on(a)));
+                if unaffected {
+                    entry.generation = generation;
+                    return entry.scores.clone();
+                }
+                cached.remove(i);
+            }
+
+            let scores = Arc::new(TrustScores::compute(graph, root, options));
+            if cached.len() == CACHED_ROOTS {
+                cached.remove(0);
+            }
+            cached.push(Cached {
+                generation,
+                scores: scores.clone(),
+            });
+            scores
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util, Config, Filter, Note, NoteBuilder};
+
+    fn contacts(seckey: &[u8; 32], follows: &[[u8; 32]]) -> Note<'static> {
+        let mut builder = NoteBuilder::new().kind(3).content("").created_at(1);
+        for pubkey in follows {
+            builder = builder
+                .start_tag()
+                .tag_str("p")
+                .tag_str(&hex::encode(pubkey));
+        }
+        builder.sign(seckey).build().expect("contacts")
+    }
+
+    fn pubkey(seckey: &[u8; 32]) -> [u8; 32] {
+        *contacts(seckey, &[]).pubkey()
+    }
+
+    #[test]
+    fn trust_scores_work() {
+        let db = "target/testdbs/trust_scores";
+        test_util::cleanup_db(db);
+
+        let keys: Vec<[u8; 32]> = (1..=5).map(|i| [i as u8; 32]).collect();
+        let pks: Vec<[u8; 32]> = keys.iter().map(pubkey).collect();
+        let (me, b, c, d, far) = (pks[0], pks[1], pks[2], pks[3], pks[4]);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            for (seckey, follows) in [
+                (&keys[0], vec![b, c]),
+                (&keys[1], vec![d]),
+                (&keys[2], vec![d]),
+                (&keys[3], vec![far]),
+            ] {
+                let json = contacts(seckey, &follows).json().expect("json");
+                ndb.process_event(&format!("[\"EVENT\",\"wot\",{json}]"))
+                    .expect("process ok");
+            }
+        }
+
+        let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+        let txn = Transaction::new(&ndb).expect("txn");
+        let scores = ndb
+            .trust_scores(&txn, &me, &TrustOptions::new())
+            .expect("scores");
+        assert_eq!(
+            scores.get(&me),
+            Some(Trust {
+                hops: 0,
+                score: 1.0
+            })
+        );
+        assert_eq!(scores.score(&b), 1.0);
+        assert_eq!(
+            scores.get(&d),
+            Some(Trust {
+                hops: 2,
+                score: 1.0
+            })
+        );
+        assert_eq!(
+            scores.get(&far),
+            Some(Trust {
+                hops: 3,
+                score: 0.5
+            })
+        );
+
+        let mut close = vec![me, b, c, d];
+        close.sort();
+        assert_eq!(scores.at_least(0.75), close);
+
+        // same graph, same scores
+        let again = ndb
+            .trust_scores(&txn, &me, &TrustOptions::new())
+            .expect("scores");
+        assert!(Arc::ptr_eq(&scores, &again));
+
+        let mut options = TrustOptions::new();
+        options.set_max_hops(1);
+        let near = ndb.trust_scores(&txn, &me, &options).expect("scores");
+        assert_eq!(near.len(), 3);
+
+        let filter = Filter::new()
+            .kinds([1])
+            .min_trust(&near, 1.0)
+            .expect("min trust")
+            .build();
+        let other = NoteBuilder::new()
+            .kind(1)
+            .content("hi")
+            .sign(&keys[3])
+            .build()
+            .expect("note");
+        assert!(!filter.matches(&other));
+        let note = NoteBuilder::new()
+            .kind(1)
+            .content("hi")
+            .sign(&keys[1])
+            .build()
+            .expect("note");
+        assert!(filter.matches(&note));
+    }
+}